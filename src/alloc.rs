@@ -0,0 +1,131 @@
+// 统计分配字节数的全局分配器，供ExeState的内存限制使用。
+// 宿主程序需要用#[global_allocator]安装它，否则allocated()返回None，内存限制不起作用。
+// 超出上限的分配在try_reserve中失败；其他分配无法失败，只记下超限，由虚拟机随后报错
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::collections::TryReserveError;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub struct CountingAlloc;
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);  // 是否经过了CountingAlloc的分配
+
+thread_local! {
+    // 本线程分配减去释放的字节数。内存可能在别的线程释放，所以可以为负
+    static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+    static BUDGET: Cell<Option<isize>> = const { Cell::new(None) };  // ALLOCATED的上限
+    static FALLIBLE: Cell<bool> = const { Cell::new(false) };  // 正在try_reserve中，超限的分配返回空指针
+    static EXCEEDED: Cell<bool> = const { Cell::new(false) };  // 有分配超出了上限
+}
+
+// 再分配size字节是否超出上限
+fn over_budget(size: isize) -> bool {
+    BUDGET.try_with(|b| b.get())
+        .ok()
+        .flatten()
+        .is_some_and(|budget| ALLOCATED.try_with(|a| a.get()).unwrap_or(0).saturating_add(size) > budget)
+}
+
+// 分配前检查上限，返回false时这次分配应当失败
+fn admit(size: isize) -> bool {
+    if !over_budget(size) {
+        return true;
+    }
+    if FALLIBLE.try_with(|f| f.get()).unwrap_or(false) {
+        return false;
+    }
+    let _ = EXCEEDED.try_with(|e| e.set(true));
+    true
+}
+
+fn account(delta: isize) {
+    if !INSTALLED.load(Ordering::Relaxed) {
+        INSTALLED.store(true, Ordering::Relaxed);
+    }
+    // 线程退出时thread_local已销毁，忽略
+    let _ = ALLOCATED.try_with(|a| a.set(a.get().wrapping_add(delta)));
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if !admit(layout.size() as isize) {
+            return std::ptr::null_mut();
+        }
+        let p = System.alloc(layout);
+        if !p.is_null() {
+            account(layout.size() as isize);
+        }
+        p
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if !admit(layout.size() as isize) {
+            return std::ptr::null_mut();
+        }
+        let p = System.alloc_zeroed(layout);
+        if !p.is_null() {
+            account(layout.size() as isize);
+        }
+        p
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        account(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if new_size > layout.size() && !admit((new_size - layout.size()) as isize) {
+            return std::ptr::null_mut();
+        }
+        let p = System.realloc(ptr, layout, new_size);
+        if !p.is_null() {
+            account(new_size as isize - layout.size() as isize);
+        }
+        p
+    }
+}
+
+impl CountingAlloc {
+    pub const fn new() -> Self {
+        CountingAlloc
+    }
+}
+
+impl Default for CountingAlloc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 当前线程净分配的字节数；未安装CountingAlloc时返回None
+pub fn allocated() -> Option<usize> {
+    if !INSTALLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    Some(ALLOCATED.try_with(|a| a.get()).unwrap_or(0).max(0) as usize)
+}
+
+// 从现在起再净分配limit字节时的上限
+pub fn budget_after(limit: usize) -> isize {
+    ALLOCATED.with(|a| a.get()).saturating_add(limit.min(isize::MAX as usize) as isize)
+}
+
+// 设置本线程的分配上限，None为不限制，返回原来的上限。
+// 每个ExeState执行期间换上自己的上限，结束后恢复
+pub fn set_budget(budget: Option<isize>) -> Option<isize> {
+    BUDGET.with(|b| b.replace(budget))
+}
+
+// 上次调用以来有分配超出了上限，且现在仍然超出
+pub fn take_exceeded() -> bool {
+    EXCEEDED.with(|e| e.replace(false)) && over_budget(0)
+}
+
+// 为v预留空间，超出上限或系统内存不足时返回错误而不是终止程序
+pub fn try_reserve<T>(v: &mut Vec<T>, additional: usize) -> Result<(), TryReserveError> {
+    let saved = FALLIBLE.with(|f| f.replace(true));
+    let result = v.try_reserve(additional);
+    FALLIBLE.with(|f| f.set(saved));
+    result
+}
@@ -0,0 +1,108 @@
+use std::io::Read;
+
+use my_lua::dump;
+use my_lua::error::LuaError;
+use my_lua::lex::Lex;
+use my_lua::parse::ParseProto;
+
+const VERSION: &str = concat!("myLuac ", env!("CARGO_PKG_VERSION"), "  (Lua 5.4 compatible)");
+
+fn usage(progname: &str, msg: &str) -> ! {
+    eprintln!("{}: {}", progname, msg);
+    eprintln!(
+        "usage: {} [options] [filenames]
+Available options are:
+  -l       list (use -l -l for full listing)
+  -o name  output to file 'name' (default is \"luac.out\")
+  -p       parse only
+  -s       strip debug information
+  -v       show version information
+  --       stop handling options
+  -        stop handling options and process stdin",
+        progname
+    );
+    std::process::exit(1);
+}
+
+// 编译源码文件，或读入已编译的二进制chunk
+fn load(filename: &str) -> Result<ParseProto, LuaError> {
+    let (code, chunkname) = if filename == "-" {
+        let mut code = Vec::new();
+        std::io::stdin().read_to_end(&mut code).map(|_| (code, "=stdin".to_string()))
+    } else {
+        std::fs::read(filename).map(|code| (code, format!("@{}", filename)))
+    }
+    .map_err(|e| LuaError::runtime(format!("cannot open {}: {}", filename, e)))?;
+
+    if code.starts_with(&dump::SIGNATURE[..1]) {
+        return dump::undump(&code, &chunkname);
+    }
+    let mut proto = ParseProto::new(Lex::from_bytes(&code, &chunkname));
+    proto.compile()?;
+    Ok(proto)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let progname = &args[0];
+    let (mut list, mut parse_only, mut strip, mut version) = (false, false, false, false);
+    let mut output = "luac.out".to_string();
+
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--" => {
+                i += 1;
+                break;
+            }
+            "-" => break,
+            "-l" => list = true,
+            "-p" => parse_only = true,
+            "-s" => strip = true,
+            "-v" => version = true,
+            "-o" => {
+                i += 1;
+                match args.get(i) {
+                    Some(name) if name != "-" && !name.is_empty() => output = name.clone(),
+                    _ => usage(progname, "'-o' needs argument"),
+                }
+            }
+            a if a.starts_with('-') => usage(progname, &format!("unrecognized option '{}'", a)),
+            _ => break,
+        }
+        i += 1;
+    }
+
+    if version {
+        println!("{}", VERSION);
+        if i == args.len() {
+            return;
+        }
+    }
+    let files = &args[i..];
+    if files.is_empty() {
+        usage(progname, "no input files given");
+    }
+
+    let mut chunks = Vec::new();
+    for f in files {
+        match load(f) {
+            Ok(p) => chunks.push(p),
+            Err(e) => {
+                eprintln!("{}: {}", progname, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let proto = if chunks.len() == 1 { chunks.pop().unwrap() } else { ParseProto::combine(chunks) };
+
+    if list {
+        print!("{}", proto.listing());
+    }
+    if !parse_only {
+        if let Err(e) = std::fs::write(&output, dump::dump(&proto, strip)) {
+            eprintln!("{}: cannot write {}: {}", progname, output, e);
+            std::process::exit(1);
+        }
+    }
+}
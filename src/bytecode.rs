@@ -1,16 +1,75 @@
-#[derive(Debug)]
+// 操作数约定：
+//  - 寄存器均为当前函数栈帧内的相对位置
+//  - Call/Return/VarArgs等的计数参数使用"个数+1"编码，0表示"直到栈顶"（个数可变）
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ByteCode {  // 中间代码
-    GetGlobal(u8, u8), // 获取全局变量, 参数1: 栈位置, 参数2: 常量表索引
-    LoadConstant(u8, u8), // 加载常量，参数1: 栈位置, 参数2: 常量表索引
-    LoadNil(u8), // 加载nil，参数1: 栈位置
+    GetGlobal(u8, u8, u16), // 获取全局变量, 参数1: 栈位置, 参数2: _ENV上值, 参数3: 常量表索引
+    LoadConstant(u8, u16), // 加载常量，参数1: 栈位置, 参数2: 常量表索引
+    LoadNil(u8, u8), // 加载nil，参数1: 栈位置, 参数2: 个数
     LoadBool(u8, bool), // 加载bool，参数1: 栈位置, 参数2: 布尔值
     LoadInt(u8, i16), // 加载整数，参数1: 栈位置, 参数2: 整数值
 
-    SetGlobal(u8, u8),  // global = local
-    SetGlobalConst(u8, u8),  // global = const
-    SetGlobalGlobal(u8, u8),  // global = global
+    // 全局变量即_ENV上值的字段，参数1为_ENV上值
+    SetGlobal(u8, u16, u8),  // global = local
+    SetGlobalConst(u8, u16, u16),  // global = const
+    SetGlobalGlobal(u8, u16, u16),  // global = global
 
     Move(u8, u8),
 
-    Call(u8, u8), // 调用函数
-}
\ No newline at end of file
+    // 上值
+    GetUpvalue(u8, u8),  // 栈位置 = 上值
+    SetUpvalue(u8, u8),  // 上值 = 栈位置
+    Close(u8),  // 关闭从该栈位置起被捕获的局部变量和待关闭变量
+    Tbc(u8),    // 把该栈位置的局部变量登记为待关闭变量
+
+    // 表
+    NewTable(u8, u8, u8),  // 栈位置, 数组部分大小, 哈希部分大小
+    GetTable(u8, u8, u8),  // R[a] = R[b][R[c]]
+    GetField(u8, u8, u16),  // R[a] = R[b][K[c]]
+    GetInt(u8, u8, u8),    // R[a] = R[b][c]
+    SetTable(u8, u8, u8),  // R[a][R[b]] = R[c]
+    SetField(u8, u16, u8),  // R[a][K[b]] = R[c]
+    SetInt(u8, u8, u8),    // R[a][b] = R[c]
+    SetList(u8, u8, u16),  // 表构造：R[a][c+i] = R[a+i], i = 1..b（b为个数+1）
+    Method(u8, u8, u16),    // R[a+1] = R[b]; R[a] = R[b][K[c]]
+
+    // 运算
+    Add(u8, u8, u8),
+    Sub(u8, u8, u8),
+    Mul(u8, u8, u8),
+    Div(u8, u8, u8),
+    Idiv(u8, u8, u8),
+    Mod(u8, u8, u8),
+    Pow(u8, u8, u8),
+    BitAnd(u8, u8, u8),
+    BitOr(u8, u8, u8),
+    BitXor(u8, u8, u8),
+    ShiftL(u8, u8, u8),
+    ShiftR(u8, u8, u8),
+    Concat(u8, u8, u8),
+    Eq(u8, u8, u8),
+    Lt(u8, u8, u8),
+    Le(u8, u8, u8),
+    Neg(u8, u8),
+    Not(u8, u8),
+    BitNot(u8, u8),
+    Len(u8, u8),
+
+    // 跳转
+    Jump(i32),
+    TestAndJump(u8, i32),  // 假则跳转
+    TestOrJump(u8, i32),   // 真则跳转
+
+    // 循环
+    ForPrepare(u8, i32),  // 数值for初始化，不执行循环体时跳过
+    ForLoop(u8, i32),     // 数值for步进，继续则跳回
+    ForCall(u8, u8),      // 泛型for：以R[a+1],R[a+2]调用R[a]，结果放到R[a+4]起，参数2为变量个数
+    ForGenLoop(u8, i32),  // 泛型for：R[a+4]非nil则更新控制变量并跳回
+
+    // 函数
+    Closure(u8, u16),  // 栈位置, 子函数原型索引
+    Call(u8, u8, u8), // 调用函数，参数1: 函数位置, 参数2: 参数个数+1, 参数3: 返回值个数+1
+    TailCall(u8, u8), // 尾调用
+    Return(u8, u8),   // 返回，参数1: 起始位置, 参数2: 返回值个数+1
+    VarArgs(u8, u8),  // 加载可变参数，参数2: 个数+1
+}
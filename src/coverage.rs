@@ -0,0 +1,308 @@
+// 行和分支覆盖率：line钩子统计每行执行的次数，count钩子逐条指令观察条件跳转的去向。
+// 函数原型第一次执行时登记它和所有子函数的可执行行与条件跳转，所以没有执行过的函数也计入。
+// 结果以LCOV格式输出，可以与之前运行的结果合并，另可生成标注执行次数的源码报告
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::bytecode::ByteCode;
+use crate::debug::{HookEvent, HOOK_COUNT, HOOK_LINE};
+use crate::parse::ParseProto;
+use crate::vm::ExeState;
+
+// 一个源文件的覆盖数据
+#[derive(Default)]
+struct FileCoverage {
+    lines: BTreeMap<u32, u64>,  // 可执行行的执行次数
+    // (行, 块, 分支)的执行次数，块为该行第几个条件跳转，分支0为跳转、1为不跳转。
+    // 条件跳转没有执行过时为None
+    branches: BTreeMap<(u32, u32, u32), Option<u64>>,
+}
+
+impl FileCoverage {
+    fn add_branch(&mut self, key: (u32, u32, u32), taken: Option<u64>) {
+        let count = self.branches.entry(key).or_insert(None);
+        if let Some(n) = taken {
+            *count = Some(count.unwrap_or(0) + n);
+        }
+    }
+}
+
+// 各文件的覆盖数据，键为规范化的路径
+#[derive(Default)]
+pub struct CoverageData {
+    files: BTreeMap<String, FileCoverage>,
+}
+
+// 条件跳转指令
+#[derive(Clone, Copy)]
+struct Cond {
+    line: u32,
+    block: u32,
+    target: usize,
+}
+
+struct ProtoCoverage {
+    _proto: Rc<ParseProto>,  // 保持原型存活，使地址不被复用
+    file: Option<usize>,     // 不是从文件加载的chunk不统计
+    conds: Vec<Option<Cond>>,
+}
+
+struct Tracker {
+    files: Vec<(String, FileCoverage)>,
+    file_ids: HashMap<String, usize>,
+    protos: Vec<ProtoCoverage>,
+    proto_ids: HashMap<*const ParseProto, usize>,
+    cached: (*const ParseProto, usize),
+    last_cond: Option<(usize, usize)>,  // 上一条执行的指令是条件跳转时为(原型, pc)
+}
+
+pub struct Coverage(Rc<RefCell<Tracker>>);
+
+impl Coverage {
+    // 设置钩子开始统计，会替换已有的钩子
+    pub fn start(state: &mut ExeState) -> Coverage {
+        let tracker = Rc::new(RefCell::new(Tracker {
+            files: Vec::new(),
+            file_ids: HashMap::new(),
+            protos: Vec::new(),
+            proto_ids: HashMap::new(),
+            cached: (std::ptr::null(), 0),
+            last_cond: None,
+        }));
+        let hook_tracker = tracker.clone();
+        let hook = move |state: &mut ExeState, event| {
+            hook_tracker.borrow_mut().on_event(state, event);
+            Ok(())
+        };
+        state.set_hook(Some(Rc::new(hook)), HOOK_LINE | HOOK_COUNT, 1);
+        Coverage(tracker)
+    }
+
+    pub fn stop(self, state: &mut ExeState) -> CoverageData {
+        state.set_hook(None, 0, 0);
+        let tracker = Rc::try_unwrap(self.0).ok().expect("coverage hook still installed").into_inner();
+        CoverageData { files: tracker.files.into_iter().collect() }
+    }
+}
+
+// 条件跳转指令的跳转偏移
+fn cond_jump(code: ByteCode) -> Option<i32> {
+    match code {
+        ByteCode::TestAndJump(_, offset) | ByteCode::TestOrJump(_, offset) | ByteCode::ForPrepare(_, offset)
+            | ByteCode::ForLoop(_, offset) | ByteCode::ForGenLoop(_, offset) => Some(offset),
+        _ => None,
+    }
+}
+
+impl Tracker {
+    fn on_event(&mut self, state: &ExeState, event: HookEvent) {
+        let Some((proto, pc)) = state.current_instruction() else {
+            self.last_cond = None;
+            return;
+        };
+        let id = self.proto_id(proto);
+        let Some(file) = self.protos[id].file else {
+            self.last_cond = None;
+            return;
+        };
+        match event {
+            HookEvent::Line(line) => *self.files[file].1.lines.entry(line).or_insert(0) += 1,
+            HookEvent::Count => {
+                // 条件跳转之后执行的下一条指令一定在同一个函数中，由它判断去向
+                if let Some((cid, cpc)) = self.last_cond.take() {
+                    let cond = self.protos[cid].conds[cpc].unwrap();
+                    let branch = if cid != id {
+                        None
+                    } else if pc == cond.target {
+                        Some(0)
+                    } else if pc == cpc + 1 {
+                        Some(1)
+                    } else {
+                        None
+                    };
+                    if let Some(branch) = branch {
+                        let cov = &mut self.files[file].1;
+                        cov.add_branch((cond.line, cond.block, 0), Some(0));
+                        cov.add_branch((cond.line, cond.block, 1), Some(0));
+                        cov.add_branch((cond.line, cond.block, branch), Some(1));
+                    }
+                }
+                if self.protos[id].conds[pc].is_some() {
+                    self.last_cond = Some((id, pc));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn proto_id(&mut self, proto: &Rc<ParseProto>) -> usize {
+        let ptr = Rc::as_ptr(proto);
+        if self.cached.0 == ptr {
+            return self.cached.1;
+        }
+        let id = match self.proto_ids.get(&ptr) {
+            Some(&id) => id,
+            None => {
+                let file = proto.source.strip_prefix('@').map(|path| self.file_id(path));
+                self.register(proto, file, &mut HashMap::new());
+                self.proto_ids[&ptr]
+            }
+        };
+        self.cached = (ptr, id);
+        id
+    }
+
+    fn file_id(&mut self, path: &str) -> usize {
+        let path = std::fs::canonicalize(path).map_or(path.to_string(), |p| p.to_string_lossy().into_owned());
+        if let Some(&id) = self.file_ids.get(&path) {
+            return id;
+        }
+        self.files.push((path.clone(), FileCoverage::default()));
+        self.file_ids.insert(path, self.files.len() - 1);
+        self.files.len() - 1
+    }
+
+    // 登记原型和它的子函数，块号按先序遍历中该行的条件跳转依次编号，同一文件每次加载都相同
+    fn register(&mut self, proto: &Rc<ParseProto>, file: Option<usize>, blocks: &mut HashMap<u32, u32>) {
+        let mut conds = vec![None; proto.instructions.len()];
+        if let Some(file) = file {
+            let cov = &mut self.files[file].1;
+            for (pc, &code) in proto.instructions.iter().enumerate() {
+                let line = proto.lineinfo.get(pc).copied().unwrap_or(0);
+                if line == 0 {
+                    continue;
+                }
+                cov.lines.entry(line).or_insert(0);
+                match cond_jump(code) {
+                    Some(offset) if offset != 0 => {
+                        let block = blocks.entry(line).or_insert(0);
+                        cov.add_branch((line, *block, 0), None);
+                        cov.add_branch((line, *block, 1), None);
+                        conds[pc] = Some(Cond { line, block: *block, target: (pc as i64 + 1 + offset as i64) as usize });
+                        *block += 1;
+                    }
+                    _ => (),
+                }
+            }
+        }
+        self.protos.push(ProtoCoverage { _proto: proto.clone(), file, conds });
+        self.proto_ids.insert(Rc::as_ptr(proto), self.protos.len() - 1);
+        for child in &proto.protos {
+            if !self.proto_ids.contains_key(&Rc::as_ptr(child)) {
+                self.register(child, file, blocks);
+            }
+        }
+    }
+}
+
+fn percent(hit: usize, total: usize) -> String {
+    if total == 0 {
+        "-".to_string()
+    } else {
+        format!("{:.1}%", hit as f64 * 100.0 / total as f64)
+    }
+}
+
+impl CoverageData {
+    // 合并之前写出的LCOV数据
+    pub fn merge_lcov(&mut self, text: &str) {
+        let mut current = None;
+        for line in text.lines() {
+            if let Some(path) = line.strip_prefix("SF:") {
+                current = Some(self.files.entry(path.to_string()).or_default());
+            } else if line == "end_of_record" {
+                current = None;
+            } else if let Some(cov) = current.as_mut() {
+                if let Some(da) = line.strip_prefix("DA:") {
+                    let mut fields = da.split(',').map(|f| f.parse::<u64>().ok());
+                    if let (Some(Some(line)), Some(Some(count))) = (fields.next(), fields.next()) {
+                        *cov.lines.entry(line as u32).or_insert(0) += count;
+                    }
+                } else if let Some(brda) = line.strip_prefix("BRDA:") {
+                    let fields: Vec<_> = brda.split(',').collect();
+                    if let [line, block, branch, taken] = fields[..] {
+                        if let (Ok(line), Ok(block), Ok(branch)) = (line.parse(), block.parse(), branch.parse()) {
+                            cov.add_branch((line, block, branch), taken.parse().ok());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn write_lcov(&self, out: &mut impl Write) -> io::Result<()> {
+        for (path, cov) in &self.files {
+            writeln!(out, "TN:")?;
+            writeln!(out, "SF:{}", path)?;
+            for (&(line, block, branch), taken) in &cov.branches {
+                match taken {
+                    Some(n) => writeln!(out, "BRDA:{},{},{},{}", line, block, branch, n)?,
+                    None => writeln!(out, "BRDA:{},{},{},-", line, block, branch)?,
+                }
+            }
+            writeln!(out, "BRF:{}", cov.branches.len())?;
+            writeln!(out, "BRH:{}", cov.branches.values().filter(|n| n.is_some_and(|n| n > 0)).count())?;
+            for (line, count) in &cov.lines {
+                writeln!(out, "DA:{},{}", line, count)?;
+            }
+            writeln!(out, "LF:{}", cov.lines.len())?;
+            writeln!(out, "LH:{}", cov.lines.values().filter(|&&n| n > 0).count())?;
+            writeln!(out, "end_of_record")?;
+        }
+        out.flush()
+    }
+
+    // 标注了每行执行次数（未执行为#####）和分支覆盖的源码
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        let (mut lines_hit, mut lines_total, mut branches_hit, mut branches_total) = (0, 0, 0, 0);
+        for (path, cov) in &self.files {
+            let _ = writeln!(out, "{}\n{}\n{}", "=".repeat(78), path, "=".repeat(78));
+            let source = std::fs::read(path).map(|s| String::from_utf8_lossy(&s).into_owned());
+            let mut branches: BTreeMap<u32, (usize, usize)> = BTreeMap::new();
+            for (&(line, _, _), taken) in &cov.branches {
+                let b = branches.entry(line).or_default();
+                b.1 += 1;
+                if taken.is_some_and(|n| n > 0) {
+                    b.0 += 1;
+                }
+            }
+            let annotate = |out: &mut String, lineno: u32, text: &str| {
+                let count = match cov.lines.get(&lineno) {
+                    Some(0) => "#####".to_string(),
+                    Some(n) => n.to_string(),
+                    None => String::new(),
+                };
+                let branch = branches.get(&lineno).map_or(String::new(), |(hit, total)| format!("{}/{}", hit, total));
+                let _ = writeln!(out, "{:>9} {:>5} | {}", count, branch, text);
+            };
+            match &source {
+                Ok(source) => {
+                    for (i, text) in source.lines().enumerate() {
+                        annotate(&mut out, i as u32 + 1, text);
+                    }
+                }
+                Err(e) => {
+                    let _ = writeln!(out, "(cannot read source: {})", e);
+                    for &line in cov.lines.keys() {
+                        annotate(&mut out, line, &format!("<line {}>", line));
+                    }
+                }
+            }
+            let hit = cov.lines.values().filter(|&&n| n > 0).count();
+            let bhit: usize = branches.values().map(|b| b.0).sum();
+            let _ = writeln!(out, "\nlines: {}/{} ({}), branches: {}/{} ({})\n", hit, cov.lines.len(),
+                percent(hit, cov.lines.len()), bhit, cov.branches.len(), percent(bhit, cov.branches.len()));
+            lines_hit += hit;
+            lines_total += cov.lines.len();
+            branches_hit += bhit;
+            branches_total += cov.branches.len();
+        }
+        let _ = writeln!(out, "total lines: {}/{} ({}), branches: {}/{} ({})", lines_hit, lines_total,
+            percent(lines_hit, lines_total), branches_hit, branches_total, percent(branches_hit, branches_total));
+        out
+    }
+}
@@ -0,0 +1,690 @@
+// 调试适配器协议（Debug Adapter Protocol）服务，通过标准输入输出与编辑器通信。
+// 断点和单步都由行钩子实现；暂停时在钩子里处理请求，直到收到继续或单步请求
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::fd::FromRawFd;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::debug::{DebugInfo, HookEvent, HOOK_LINE};
+use crate::error::LuaError;
+use crate::json::{object, Json};
+use crate::value::{Table, Value};
+use crate::vm::ExeState;
+
+// 只报告一个线程，协程都在它上面运行
+const THREAD_ID: i64 = 1;
+
+// 协议输出，seq在所有消息间递增
+struct Writer {
+    out: File,
+    seq: i64,
+}
+
+type SharedWriter = Arc<Mutex<Writer>>;
+
+impl Writer {
+    fn send(&mut self, msg: Json) {
+        self.seq += 1;
+        let mut fields = vec![("seq".to_string(), Json::from(self.seq))];
+        if let Json::Object(rest) = msg {
+            fields.extend(rest);
+        }
+        let text = Json::Object(fields).to_string();
+        // 客户端已断开时写入失败，忽略
+        let _ = write!(self.out, "Content-Length: {}\r\n\r\n{}", text.len(), text);
+        let _ = self.out.flush();
+    }
+}
+
+// body为Null时省略
+fn send_event(writer: &SharedWriter, event: &str, body: Json) {
+    let mut msg = vec![("type".to_string(), Json::from("event")), ("event".to_string(), Json::from(event))];
+    if body != Json::Null {
+        msg.push(("body".to_string(), body));
+    }
+    writer.lock().unwrap().send(Json::Object(msg));
+}
+
+// 读取一条消息，输入结束时返回None
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut body = vec![0; length.unwrap()];
+    input.read_exact(&mut body)?;
+    Json::parse(&String::from_utf8_lossy(&body)).map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// 读取请求的线程，输入结束时关闭通道
+fn read_requests(input: File) -> Receiver<Json> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        loop {
+            match read_message(&mut input) {
+                Ok(Some(msg)) => {
+                    if tx.send(msg).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => eprintln!("dap: invalid message: {}", e),
+                Err(_) => break,
+            }
+        }
+    });
+    rx
+}
+
+// 把脚本的标准输出转为output事件，不完整的UTF-8序列留到下次
+fn forward_output(mut pipe: File, writer: SharedWriter) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        let mut pending = Vec::new();
+        while let Ok(n @ 1..) = pipe.read(&mut buf) {
+            pending.extend_from_slice(&buf[..n]);
+            let valid = match std::str::from_utf8(&pending) {
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                _ => pending.len(),
+            };
+            if valid > 0 {
+                let text = String::from_utf8_lossy(&pending[..valid]).into_owned();
+                pending.drain(..valid);
+                send_event(&writer, "output", object([("category", "stdout".into()), ("output", text.into())]));
+            }
+        }
+    })
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+// 协议使用原来的标准输入输出。脚本的标准输入改为/dev/null，标准输出改为管道，返回(输入, 输出, 管道读端)
+fn redirect_stdio() -> io::Result<(File, File, File)> {
+    unsafe {
+        let input = check(libc::fcntl(0, libc::F_DUPFD_CLOEXEC, 0))?;
+        let output = check(libc::fcntl(1, libc::F_DUPFD_CLOEXEC, 0))?;
+        let mut fds = [0; 2];
+        check(libc::pipe(fds.as_mut_ptr()))?;
+        check(libc::fcntl(fds[0], libc::F_SETFD, libc::FD_CLOEXEC))?;
+        let null = check(libc::open(c"/dev/null".as_ptr(), libc::O_RDONLY))?;
+        check(libc::dup2(null, 0))?;
+        check(libc::dup2(fds[1], 1))?;
+        libc::close(null);
+        libc::close(fds[1]);
+        Ok((File::from_raw_fd(input), File::from_raw_fd(output), File::from_raw_fd(fds[0])))
+    }
+}
+
+// 关闭管道写端，使转发线程读到文件结束
+fn detach_stdout() -> io::Result<()> {
+    let _ = io::stdout().flush();
+    unsafe {
+        let null = check(libc::open(c"/dev/null".as_ptr(), libc::O_WRONLY))?;
+        check(libc::dup2(null, 1))?;
+        libc::close(null);
+    }
+    Ok(())
+}
+
+fn command(req: &Json) -> &str {
+    req.get("command").as_str().unwrap_or("")
+}
+
+enum Step {
+    Entry,
+    In,
+    Over(usize),  // 调用栈深度不超过它时停下
+    Out(usize),   // 调用栈深度小于它时停下
+}
+
+// 变量引用指向的内容，只在一次暂停期间有效
+enum Scope {
+    Locals(usize),
+    Upvalues(usize),
+    Globals,
+    Table(Rc<RefCell<Table>>),
+}
+
+// evaluate中可以赋值回去的变量
+enum Binding {
+    Local(usize, i64),
+    Upvalue(Value, usize),
+}
+
+struct Session {
+    writer: SharedWriter,
+    requests: Receiver<Json>,
+    deferred: VecDeque<Json>,  // 运行期间收到、留到下次暂停时处理的请求
+    breakpoints: HashMap<PathBuf, HashSet<u32>>,
+    bp_lines: HashSet<u32>,  // 所有断点的行号，行钩子先用它快速过滤
+    paths: HashMap<String, Option<PathBuf>>,  // chunk名到规范化路径的缓存
+    step: Option<Step>,
+    pause: bool,
+    stopped: bool,
+    disconnected: bool,
+    refs: Vec<Scope>,
+}
+
+impl Session {
+    fn respond(&self, req: &Json, result: Result<Json, String>) {
+        let mut fields = vec![
+            ("type".to_string(), Json::from("response")),
+            ("request_seq".to_string(), req.get("seq").clone()),
+            ("success".to_string(), Json::from(result.is_ok())),
+            ("command".to_string(), Json::from(command(req))),
+        ];
+        match result {
+            Ok(Json::Null) => (),
+            Ok(body) => fields.push(("body".to_string(), body)),
+            Err(message) => fields.push(("message".to_string(), message.into())),
+        }
+        self.writer.lock().unwrap().send(Json::Object(fields));
+    }
+
+    fn disconnect(&mut self) -> LuaError {
+        self.disconnected = true;
+        LuaError::Interrupt("debug session terminated")
+    }
+
+    // 先处理留下的请求，再等待新的请求。输入结束时返回None
+    fn next_request(&mut self) -> Option<Json> {
+        self.deferred.pop_front().or_else(|| self.requests.recv().ok())
+    }
+
+    // 行钩子：先处理运行期间到达的请求，再判断是否需要停下。
+    // 运行中只处理暂停、断点设置和线程列表，以及要求立即结束的terminate；
+    // 控制执行和查看状态的请求（包括disconnect）按顺序留到下次暂停，使脚本化的会话也能按预期进行
+    fn on_line(&mut self, state: &mut ExeState, line: u32) -> Result<(), LuaError> {
+        loop {
+            match self.requests.try_recv() {
+                Ok(req) => match command(&req) {
+                    "pause" | "setBreakpoints" | "setExceptionBreakpoints" | "threads" => {
+                        let result = self.handle(state, &req);
+                        self.respond(&req, result);
+                    }
+                    "terminate" => {
+                        self.respond(&req, Ok(Json::Null));
+                        return Err(self.disconnect());
+                    }
+                    _ => self.deferred.push_back(req),
+                },
+                Err(TryRecvError::Empty) => break,
+                // 客户端已断开，但还有留下的请求要处理
+                Err(TryRecvError::Disconnected) if !self.deferred.is_empty() => break,
+                Err(TryRecvError::Disconnected) => return Err(self.disconnect()),
+            }
+        }
+        let reason = if self.pause {
+            "pause"
+        } else if self.bp_lines.contains(&line) && self.at_breakpoint(state, line) {
+            "breakpoint"
+        } else {
+            match self.step {
+                None => return Ok(()),
+                Some(Step::Entry) => "entry",
+                Some(Step::In) => "step",
+                Some(Step::Over(depth)) if state.call_depth(None) <= depth => "step",
+                Some(Step::Out(depth)) if state.call_depth(None) < depth => "step",
+                _ => return Ok(()),
+            }
+        };
+        self.stop(state, reason)
+    }
+
+    fn at_breakpoint(&mut self, state: &ExeState, line: u32) -> bool {
+        let Some(info) = state.get_info(None, 0) else {
+            return false;
+        };
+        let path = self.paths.entry(info.source.clone()).or_insert_with(|| {
+            info.source.strip_prefix('@').map(|p| std::fs::canonicalize(p).unwrap_or_else(|_| PathBuf::from(p)))
+        });
+        path.as_ref().and_then(|p| self.breakpoints.get(p)).is_some_and(|lines| lines.contains(&line))
+    }
+
+    // 暂停并处理请求，直到继续执行
+    fn stop(&mut self, state: &mut ExeState, reason: &str) -> Result<(), LuaError> {
+        self.pause = false;
+        self.step = None;
+        self.stopped = true;
+        let _ = io::stdout().flush();
+        let body = object([("reason", reason.into()), ("threadId", THREAD_ID.into()), ("allThreadsStopped", true.into())]);
+        send_event(&self.writer, "stopped", body);
+        let result = loop {
+            let Some(req) = self.next_request() else {
+                break Err(self.disconnect());
+            };
+            let depth = state.call_depth(None);
+            let (step, body) = match command(&req) {
+                "continue" => (None, object([("allThreadsContinued", true.into())])),
+                "next" => (Some(Step::Over(depth)), Json::Null),
+                "stepIn" => (Some(Step::In), Json::Null),
+                "stepOut" => (Some(Step::Out(depth)), Json::Null),
+                "disconnect" | "terminate" => {
+                    self.respond(&req, Ok(Json::Null));
+                    break Err(self.disconnect());
+                }
+                _ => {
+                    let result = self.handle(state, &req);
+                    self.respond(&req, result);
+                    continue;
+                }
+            };
+            self.respond(&req, Ok(body));
+            self.step = step;
+            break Ok(());
+        };
+        self.stopped = false;
+        self.refs.clear();
+        result
+    }
+
+    // 配置、查询类请求，返回响应的body
+    fn handle(&mut self, state: &mut ExeState, req: &Json) -> Result<Json, String> {
+        let args = req.get("arguments");
+        match command(req) {
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "setExceptionBreakpoints" => Ok(object([("breakpoints", Json::Array(Vec::new()))])),
+            "threads" => {
+                let thread = object([("id", THREAD_ID.into()), ("name", "main".into())]);
+                Ok(object([("threads", Json::Array(vec![thread]))]))
+            }
+            "pause" => {
+                self.pause = !self.stopped;
+                Ok(Json::Null)
+            }
+            "evaluate" => self.evaluate(state, args),
+            "stackTrace" | "scopes" | "variables" if !self.stopped => Err("program is not stopped".to_string()),
+            "stackTrace" => Ok(self.stack_trace(state, args)),
+            "scopes" => self.scopes(state, args),
+            "variables" => self.variables(state, args),
+            cmd => Err(format!("unsupported request '{}'", cmd)),
+        }
+    }
+
+    fn set_breakpoints(&mut self, args: &Json) -> Json {
+        let source = args.get("source");
+        let lines: Vec<i64> = match args.get("breakpoints") {
+            Json::Array(bps) => bps.iter().filter_map(|bp| bp.get("line").as_i64()).collect(),
+            _ => args.get("lines").as_array().iter().filter_map(Json::as_i64).collect(),
+        };
+        if let Some(path) = source.get("path").as_str() {
+            let path = std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+            self.breakpoints.insert(path, lines.iter().map(|&l| l as u32).collect());
+        }
+        self.bp_lines = self.breakpoints.values().flatten().copied().collect();
+        let bps = lines.iter().map(|&line| object([("verified", true.into()), ("line", line.into()), ("source", source.clone())]));
+        object([("breakpoints", Json::Array(bps.collect()))])
+    }
+
+    fn stack_trace(&self, state: &ExeState, args: &Json) -> Json {
+        let start = args.get("startFrame").as_i64().unwrap_or(0).max(0) as usize;
+        let levels = match args.get("levels").as_i64() {
+            Some(n) if n > 0 => n as usize,
+            _ => usize::MAX,
+        };
+        let mut frames = Vec::new();
+        let mut level = start;
+        while frames.len() < levels {
+            let Some(info) = state.get_info(None, level) else {
+                break;
+            };
+            level += 1;
+            let name = match (&info.name, info.what) {
+                (Some(name), _) => name.clone(),
+                (None, "main") => "main chunk".to_string(),
+                (None, "C") => "?".to_string(),
+                (None, _) => format!("function <{}:{}>", info.short_src, info.line_defined),
+            };
+            let line = info.current_line.max(0);
+            let mut frame = vec![
+                ("id".to_string(), Json::from(level as i64)),
+                ("name".to_string(), name.into()),
+                ("line".to_string(), line.into()),
+                ("column".to_string(), Json::from(if line > 0 { 1 } else { 0 })),
+            ];
+            if info.what != "C" {
+                frame.push(("source".to_string(), source_json(&info)));
+            }
+            frames.push(Json::Object(frame));
+        }
+        let total = state.call_depth(None) as i64;
+        object([("stackFrames", Json::Array(frames)), ("totalFrames", total.into())])
+    }
+
+    fn new_ref(&mut self, scope: Scope) -> i64 {
+        self.refs.push(scope);
+        self.refs.len() as i64
+    }
+
+    // frameId是调用栈层数加1
+    fn frame_level(state: &ExeState, args: &Json) -> Result<usize, String> {
+        match args.get("frameId").as_i64() {
+            Some(id) if id > 0 && state.get_info(None, id as usize - 1).is_some() => Ok(id as usize - 1),
+            _ => Err("invalid frameId".to_string()),
+        }
+    }
+
+    fn scopes(&mut self, state: &ExeState, args: &Json) -> Result<Json, String> {
+        let level = Self::frame_level(state, args)?;
+        let scope = |name: &str, hint: &str, reference: i64, expensive: bool| {
+            object([("name", name.into()), ("presentationHint", hint.into()), ("variablesReference", reference.into()), ("expensive", expensive.into())])
+        };
+        let scopes = vec![
+            scope("Locals", "locals", self.new_ref(Scope::Locals(level)), false),
+            scope("Upvalues", "upvalues", self.new_ref(Scope::Upvalues(level)), false),
+            scope("Globals", "globals", self.new_ref(Scope::Globals), true),
+        ];
+        Ok(object([("scopes", Json::Array(scopes))]))
+    }
+
+    fn variables(&mut self, state: &ExeState, args: &Json) -> Result<Json, String> {
+        let scope = args.get("variablesReference").as_i64()
+            .and_then(|r| self.refs.get((r as usize).wrapping_sub(1)))
+            .ok_or("invalid variablesReference")?;
+        let mut vars = Vec::new();
+        match scope {
+            Scope::Locals(level) => {
+                // 跳过"(temporary)"之类的内部变量
+                vars.extend((1..).map_while(|n| state.get_local(None, *level, n)).filter(|(name, _)| !name.starts_with('(')));
+                vars.extend((1..).map_while(|n| state.get_local(None, *level, -n)).enumerate().map(|(i, (_, v))| (format!("...[{}]", i + 1), v)));
+            }
+            Scope::Upvalues(level) => {
+                let func = state.get_info(None, *level).map_or(Value::Nil, |info| info.func);
+                vars.extend((1..).map_while(|n| state.function_upvalue(&func, n)));
+            }
+            Scope::Globals => {
+                vars = table_fields(&state.globals());
+                vars.sort_by(|a, b| a.0.cmp(&b.0));
+            }
+            Scope::Table(t) => vars = table_fields(t),
+        }
+        let start = args.get("start").as_i64().unwrap_or(0).max(0) as usize;
+        let count = match args.get("count").as_i64() {
+            Some(n) if n > 0 => n as usize,
+            _ => usize::MAX,
+        };
+        let vars: Vec<_> = vars.into_iter().skip(start).take(count).collect();
+        let vars = vars.into_iter().map(|(name, v)| {
+            let fields = [("name", name.into()), ("value", display_value(&v).into()),
+                ("type", v.type_name().into()), ("variablesReference", self.value_ref(&v).into())];
+            object(fields)
+        });
+        Ok(object([("variables", Json::Array(vars.collect()))]))
+    }
+
+    // 非空的表可以展开
+    fn value_ref(&mut self, v: &Value) -> i64 {
+        match v {
+            Value::Table(t) if t.borrow().next(&Value::Nil).is_ok_and(|kv| kv.is_some()) => self.new_ref(Scope::Table(t.clone())),
+            _ => 0,
+        }
+    }
+
+    // 在暂停的函数中求值：局部变量和上值放入一个环境表，其余名字访问函数的_ENV。
+    // 对这些变量的赋值在求值后写回。未暂停时在全局环境中求值
+    fn evaluate(&mut self, state: &mut ExeState, args: &Json) -> Result<Json, String> {
+        let expr = args.get("expression").as_str().ok_or("missing expression")?;
+        let mut bindings = HashMap::new();
+        let mut env = Value::Table(state.globals());
+        if self.stopped {
+            let level = match args.get("frameId") {
+                Json::Null => 0,
+                _ => Self::frame_level(state, args)?,
+            };
+            let func = state.get_info(None, level).map_or(Value::Nil, |info| info.func);
+            for (n, (name, v)) in (1..).map_while(|n| state.function_upvalue(&func, n).map(|up| (n, up))) {
+                if name == crate::parse::ENV {
+                    env = v;
+                } else {
+                    bindings.insert(name, (Binding::Upvalue(func.clone(), n), v));
+                }
+            }
+            // 同名的局部变量后面的遮蔽前面的
+            for (n, (name, v)) in (1..).map_while(|n| state.get_local(None, level, n).map(|local| (n, local))) {
+                if !name.starts_with('(') {
+                    bindings.insert(name, (Binding::Local(level, n), v));
+                }
+            }
+        }
+        let scope = if bindings.is_empty() {
+            env
+        } else {
+            let mut t = Table::new(0, bindings.len());
+            for (name, (_, v)) in &bindings {
+                t.set_str(name, v.clone());
+            }
+            let mut meta = Table::new(0, 2);
+            meta.set_str("__index", env.clone());
+            meta.set_str("__newindex", env);
+            t.metatable = Some(Rc::new(RefCell::new(meta)));
+            Value::Table(Rc::new(RefCell::new(t)))
+        };
+
+        // 先作为表达式，不行再作为语句
+        let f = state.load_bytes(format!("return {}", expr).as_bytes(), "=(eval)", Some(scope.clone()))
+            .or_else(|_| state.load_bytes(expr.as_bytes(), "=(eval)", Some(scope.clone())))
+            .map_err(|e| e.to_string())?;
+        let results = state.call_function(f, Vec::new()).map_err(|e| e.to_string())?;
+
+        if let Value::Table(t) = &scope {
+            for (name, (binding, old)) in bindings {
+                let v = t.borrow().get_str(&name);
+                if v != old {
+                    match binding {
+                        Binding::Local(level, n) => state.set_local(None, level, n, v),
+                        Binding::Upvalue(func, n) => state.set_function_upvalue(&func, n, v),
+                    };
+                }
+            }
+        }
+        let text = results.iter().map(display_value).collect::<Vec<_>>().join(", ");
+        let reference = match results.as_slice() {
+            [v] => self.value_ref(v),
+            _ => 0,
+        };
+        Ok(object([("result", text.into()), ("variablesReference", reference.into())]))
+    }
+}
+
+fn source_json(info: &DebugInfo) -> Json {
+    match info.source.strip_prefix('@') {
+        Some(path) => {
+            let full = std::fs::canonicalize(path).map_or(path.to_string(), |p| p.to_string_lossy().into_owned());
+            let name = std::path::Path::new(path).file_name().map_or(path.to_string(), |n| n.to_string_lossy().into_owned());
+            object([("name", name.into()), ("path", full.into())])
+        }
+        None => object([("name", info.short_src.clone().into())]),
+    }
+}
+
+// 表的原始内容，不触发元方法
+fn table_fields(t: &Rc<RefCell<Table>>) -> Vec<(String, Value)> {
+    let t = t.borrow();
+    let mut fields = Vec::new();
+    let mut key = Value::Nil;
+    while let Ok(Some((k, v))) = t.next(&key) {
+        fields.push((key_name(&k), v));
+        key = k;
+    }
+    fields
+}
+
+// 标识符形式的字符串键直接显示，其余键加方括号
+fn key_name(k: &Value) -> String {
+    if let Value::String(s) = k {
+        let is_name = s.first().is_some_and(|c| c.is_ascii_alphabetic() || *c == b'_')
+            && s.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_');
+        if is_name {
+            return String::from_utf8_lossy(s).into_owned();
+        }
+    }
+    format!("[{}]", display_value(k))
+}
+
+fn display_value(v: &Value) -> String {
+    match v {
+        Value::String(s) => {
+            let mut out = String::from("\"");
+            for c in String::from_utf8_lossy(s).chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    '\t' => out.push_str("\\t"),
+                    c if c.is_control() => out.push_str(&format!("\\{}", c as u32)),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+            out
+        }
+        _ => v.to_string(),
+    }
+}
+
+// 启动请求：读取并编译程序，返回(函数, 参数)
+fn launch(state: &mut ExeState, args: &Json) -> Result<(Value, Vec<Value>), String> {
+    let program = args.get("program").as_str().ok_or("missing 'program'")?;
+    if let Some(cwd) = args.get("cwd").as_str() {
+        std::env::set_current_dir(cwd).map_err(|e| format!("cannot change directory to {}: {}", cwd, e))?;
+    }
+    let code = std::fs::read(program).map_err(|e| format!("cannot open {}: {}", program, e))?;
+    let f = state.load_bytes(&code, &format!("@{}", program), None).map_err(|e| e.to_string())?;
+
+    // 全局arg表同命令行运行脚本时一致
+    let script_args: Vec<Value> = args.get("args").as_array().iter().filter_map(Json::as_str).map(Value::from).collect();
+    let mut arg = Table::new(script_args.len(), 0);
+    arg.set(Value::Integer(0), Value::from(program));
+    for (i, a) in script_args.iter().enumerate() {
+        arg.set(Value::Integer(i as i64 + 1), a.clone());
+    }
+    state.set_global("arg", Value::Table(Rc::new(RefCell::new(arg))));
+    Ok((f, script_args))
+}
+
+// 在标准输入输出上运行调试会话，直到客户端断开
+pub fn run(state: &mut ExeState) -> io::Result<()> {
+    let (input, output, pipe) = redirect_stdio()?;
+    let writer = Arc::new(Mutex::new(Writer { out: output, seq: 0 }));
+    let forwarder = forward_output(pipe, writer.clone());
+    let session = Rc::new(RefCell::new(Session {
+        writer: writer.clone(),
+        requests: read_requests(input),
+        deferred: VecDeque::new(),
+        breakpoints: HashMap::new(),
+        bp_lines: HashSet::new(),
+        paths: HashMap::new(),
+        step: None,
+        pause: false,
+        stopped: false,
+        disconnected: false,
+        refs: Vec::new(),
+    }));
+
+    // 配置阶段：收到launch和configurationDone后开始运行
+    let mut program = None;
+    let mut configured = false;
+    while program.is_none() || !configured {
+        let mut s = session.borrow_mut();
+        let Ok(req) = s.requests.recv() else {
+            return Ok(());
+        };
+        match command(&req) {
+            "initialize" => {
+                let caps = object([
+                    ("supportsConfigurationDoneRequest", true.into()),
+                    ("supportsEvaluateForHovers", true.into()),
+                    ("supportsTerminateRequest", true.into()),
+                ]);
+                s.respond(&req, Ok(caps));
+                send_event(&writer, "initialized", Json::Null);
+            }
+            "launch" => {
+                let result = launch(state, req.get("arguments"));
+                if result.is_ok() && req.get("arguments").get("stopOnEntry").as_bool() == Some(true) {
+                    s.step = Some(Step::Entry);
+                }
+                s.respond(&req, result.as_ref().map(|_| Json::Null).map_err(Clone::clone));
+                program = result.ok();
+            }
+            "configurationDone" => {
+                configured = true;
+                s.respond(&req, Ok(Json::Null));
+            }
+            "disconnect" | "terminate" => {
+                s.respond(&req, Ok(Json::Null));
+                return Ok(());
+            }
+            _ => {
+                let result = s.handle(state, &req);
+                s.respond(&req, result);
+            }
+        }
+    }
+    let (f, args) = program.unwrap();
+
+    let hook_session = session.clone();
+    let hook = move |state: &mut ExeState, event| match event {
+        HookEvent::Line(line) => hook_session.borrow_mut().on_line(state, line),
+        _ => Ok(()),
+    };
+    state.set_hook(Some(Rc::new(hook)), HOOK_LINE, 0);
+    let result = state.call_function(f, args);
+    state.set_hook(None, 0, 0);
+    detach_stdout()?;
+    let _ = forwarder.join();
+
+    let mut s = session.borrow_mut();
+    if s.disconnected {
+        return Ok(());
+    }
+    let exit_code = match result {
+        Ok(_) => 0,
+        Err(e) => {
+            send_event(&writer, "output", object([("category", "stderr".into()), ("output", format!("{}\n", e).into())]));
+            1
+        }
+    };
+    send_event(&writer, "exited", object([("exitCode", Json::from(exit_code))]));
+    send_event(&writer, "terminated", Json::Null);
+
+    // 程序结束后继续应答，直到客户端断开
+    while let Some(req) = s.next_request() {
+        if matches!(command(&req), "disconnect" | "terminate") {
+            s.respond(&req, Ok(Json::Null));
+            break;
+        }
+        let result = s.handle(state, &req);
+        s.respond(&req, result);
+    }
+    Ok(())
+}
@@ -0,0 +1,228 @@
+// 调试接口的公共类型，以及从字节码推断函数名
+use std::rc::Rc;
+
+use crate::bytecode::ByteCode;
+use crate::error::LuaError;
+use crate::parse::{ParseProto, ENV};
+use crate::value::Value;
+use crate::vm::ExeState;
+
+// 钩子事件掩码，对应LUA_MASKCALL等
+pub const HOOK_CALL: u8 = 1;
+pub const HOOK_RETURN: u8 = 2;
+pub const HOOK_LINE: u8 = 4;
+pub const HOOK_COUNT: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    Call,
+    TailCall,
+    Return,
+    Line(u32),  // 将要执行的新行
+    Count,
+}
+
+impl HookEvent {
+    pub fn mask(self) -> u8 {
+        match self {
+            HookEvent::Call | HookEvent::TailCall => HOOK_CALL,
+            HookEvent::Return => HOOK_RETURN,
+            HookEvent::Line(_) => HOOK_LINE,
+            HookEvent::Count => HOOK_COUNT,
+        }
+    }
+
+    // debug.sethook的钩子函数收到的事件名
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::Call => "call",
+            HookEvent::TailCall => "tail call",
+            HookEvent::Return => "return",
+            HookEvent::Line(_) => "line",
+            HookEvent::Count => "count",
+        }
+    }
+}
+
+// 钩子回调：触发事件的函数位于调用栈第0层（钩子为Lua函数时它自己在第1层之上，见debug.getinfo）。
+// 钩子执行期间不再触发钩子，也不能让出
+pub type Hook = Rc<dyn Fn(&mut ExeState, HookEvent) -> Result<(), LuaError>>;
+
+// 函数或调用栈中一层的信息，对应lua_Debug
+pub struct DebugInfo {
+    pub func: Value,
+    pub source: String,
+    pub short_src: String,
+    pub what: &'static str,  // "Lua"、"C"或"main"
+    pub line_defined: i64,
+    pub last_line_defined: i64,
+    pub current_line: i64,  // 没有行号信息时为-1
+    pub name: Option<String>,
+    pub namewhat: &'static str,  // "global"、"local"、"method"、"field"、"upvalue"等，未知时为空
+    pub nups: usize,
+    pub nparams: usize,
+    pub is_vararg: bool,
+    pub is_tail_call: bool,
+    pub ftransfer: usize,  // call和return钩子中，传递的第一个值的局部变量序号
+    pub ntransfer: usize,
+}
+
+impl DebugInfo {
+    // 函数本身的信息，不含调用相关的字段
+    pub fn of_function(func: &Value) -> Self {
+        let mut info = DebugInfo {
+            func: func.clone(),
+            source: "=[C]".to_string(),
+            short_src: "[C]".to_string(),
+            what: "C",
+            line_defined: -1,
+            last_line_defined: -1,
+            current_line: -1,
+            name: None,
+            namewhat: "",
+            nups: 0,
+            nparams: 0,
+            is_vararg: true,
+            is_tail_call: false,
+            ftransfer: 0,
+            ntransfer: 0,
+        };
+        match func {
+            Value::LuaFunction(f) => {
+                let proto = &f.proto;
+                info.source = proto.source.clone();
+                info.short_src = crate::lex::chunkid(&proto.source);
+                info.what = if proto.line_defined == 0 { "main" } else { "Lua" };
+                info.line_defined = proto.line_defined as i64;
+                info.last_line_defined = proto.last_line_defined as i64;
+                info.nups = f.upvalues.borrow().len();
+                info.nparams = proto.num_params;
+                info.is_vararg = proto.is_vararg;
+            }
+            Value::RustClosure(c) => info.nups = c.upvalues.borrow().len(),
+            _ => (),
+        }
+        info
+    }
+}
+
+// 调用者在pc处的指令调用了函数，推断被调用函数的名字和种类
+pub(crate) fn func_name_from_code(proto: &ParseProto, pc: usize) -> Option<(String, &'static str)> {
+    let event = match proto.instructions[pc] {
+        ByteCode::Call(a, _, _) | ByteCode::TailCall(a, _) => return obj_name(proto, pc, a),
+        ByteCode::ForCall(..) => return Some(("for iterator".to_string(), "for iterator")),
+        // 其余为指令触发的元方法
+        ByteCode::GetGlobal(..) | ByteCode::SetGlobalGlobal(..) | ByteCode::GetTable(..)
+            | ByteCode::GetField(..) | ByteCode::GetInt(..) | ByteCode::Method(..) => "index",
+        ByteCode::SetGlobal(..) | ByteCode::SetGlobalConst(..) | ByteCode::SetTable(..)
+            | ByteCode::SetField(..) | ByteCode::SetInt(..) => "newindex",
+        ByteCode::Add(..) => "add",
+        ByteCode::Sub(..) => "sub",
+        ByteCode::Mul(..) => "mul",
+        ByteCode::Div(..) => "div",
+        ByteCode::Idiv(..) => "idiv",
+        ByteCode::Mod(..) => "mod",
+        ByteCode::Pow(..) => "pow",
+        ByteCode::BitAnd(..) => "band",
+        ByteCode::BitOr(..) => "bor",
+        ByteCode::BitXor(..) => "bxor",
+        ByteCode::ShiftL(..) => "shl",
+        ByteCode::ShiftR(..) => "shr",
+        ByteCode::Neg(..) => "unm",
+        ByteCode::BitNot(..) => "bnot",
+        ByteCode::Len(..) => "len",
+        ByteCode::Concat(..) => "concat",
+        ByteCode::Eq(..) => "eq",
+        ByteCode::Lt(..) => "lt",
+        ByteCode::Le(..) => "le",
+        ByteCode::Close(..) | ByteCode::Return(..) => "close",
+        _ => return None,
+    };
+    Some((event.to_string(), "metamethod"))
+}
+
+// 在lastpc处寄存器reg中的值的名字，类似Lua的getobjname
+fn obj_name(proto: &ParseProto, lastpc: usize, reg: u8) -> Option<(String, &'static str)> {
+    if let Some(name) = proto.local_name(reg as usize, lastpc) {
+        return Some((name.to_string(), "local"));
+    }
+    let pc = find_set_reg(proto, lastpc, reg)?;
+    match proto.instructions[pc] {
+        ByteCode::Move(_, src) if src < reg => obj_name(proto, pc, src),
+        ByteCode::GetGlobal(_, env, k) => {
+            let is_env = proto.upvalues.get(env as usize).is_some_and(|up| up.name == ENV);
+            Some((const_name(proto, k as usize), if is_env { "global" } else { "field" }))
+        }
+        ByteCode::GetField(_, t, k) => {
+            let is_env = proto.local_name(t as usize, pc) == Some(ENV);
+            Some((const_name(proto, k as usize), if is_env { "global" } else { "field" }))
+        }
+        ByteCode::GetTable(_, _, k) => match obj_name(proto, pc, k) {
+            Some((name, "constant")) => Some((name, "field")),
+            _ => Some(("?".to_string(), "field")),
+        },
+        ByteCode::GetInt(..) => Some(("integer index".to_string(), "field")),
+        ByteCode::GetUpvalue(_, i) => Some((proto.upvalues[i as usize].name.clone(), "upvalue")),
+        ByteCode::Method(_, _, k) => Some((const_name(proto, k as usize), "method")),
+        ByteCode::LoadConstant(_, k) => match &proto.constants[k as usize] {
+            Value::String(s) => Some((String::from_utf8_lossy(s).into_owned(), "constant")),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn const_name(proto: &ParseProto, k: usize) -> String {
+    match &proto.constants[k] {
+        Value::String(s) => String::from_utf8_lossy(s).into_owned(),
+        _ => "?".to_string(),
+    }
+}
+
+// lastpc之前最后一条给reg赋值的指令。赋值位于条件分支中时（被跳过的区间内）无法确定，返回None
+fn find_set_reg(proto: &ParseProto, lastpc: usize, reg: u8) -> Option<usize> {
+    let reg = reg as usize;
+    let mut setreg = None;
+    let mut jmptarget = 0;
+    for (pc, &code) in proto.instructions[..lastpc].iter().enumerate() {
+        let target = match code {
+            ByteCode::Jump(offset) | ByteCode::TestAndJump(_, offset)
+                | ByteCode::TestOrJump(_, offset) | ByteCode::ForPrepare(_, offset) => Some(pc as i64 + 1 + offset as i64),
+            _ => None,
+        };
+        if let Some(target) = target {
+            if (pc as i64) < target && target <= lastpc as i64 && target as usize > jmptarget {
+                jmptarget = target as usize;
+            }
+        }
+        if sets_reg(code, reg) {
+            setreg = if pc < jmptarget { None } else { Some(pc) };
+        }
+    }
+    setreg
+}
+
+// 指令是否改写寄存器reg
+fn sets_reg(code: ByteCode, reg: usize) -> bool {
+    let a = match code {
+        ByteCode::LoadNil(a, n) => return (a as usize..a as usize + n as usize).contains(&reg),
+        ByteCode::Method(a, ..) => return reg == a as usize || reg == a as usize + 1,
+        ByteCode::Call(a, ..) | ByteCode::TailCall(a, ..) => return reg >= a as usize,
+        ByteCode::VarArgs(a, n) => return reg >= a as usize && (n == 0 || reg < a as usize + n as usize - 1),
+        ByteCode::ForCall(a, _) => return reg >= a as usize + 4,
+        ByteCode::ForPrepare(a, _) | ByteCode::ForLoop(a, _) => return (a as usize..a as usize + 4).contains(&reg),
+        ByteCode::ForGenLoop(a, _) => return reg == a as usize + 2,
+        ByteCode::GetGlobal(a, ..) | ByteCode::LoadConstant(a, _) | ByteCode::LoadBool(a, _)
+            | ByteCode::LoadInt(a, _) | ByteCode::Move(a, _) | ByteCode::GetUpvalue(a, _)
+            | ByteCode::NewTable(a, ..) | ByteCode::GetTable(a, ..) | ByteCode::GetField(a, ..)
+            | ByteCode::GetInt(a, ..) | ByteCode::Add(a, ..) | ByteCode::Sub(a, ..)
+            | ByteCode::Mul(a, ..) | ByteCode::Div(a, ..) | ByteCode::Idiv(a, ..)
+            | ByteCode::Mod(a, ..) | ByteCode::Pow(a, ..) | ByteCode::BitAnd(a, ..)
+            | ByteCode::BitOr(a, ..) | ByteCode::BitXor(a, ..) | ByteCode::ShiftL(a, ..)
+            | ByteCode::ShiftR(a, ..) | ByteCode::Concat(a, ..) | ByteCode::Eq(a, ..)
+            | ByteCode::Lt(a, ..) | ByteCode::Le(a, ..) | ByteCode::Neg(a, _) | ByteCode::Not(a, _)
+            | ByteCode::BitNot(a, _) | ByteCode::Len(a, _) | ByteCode::Closure(a, _) => a,
+        _ => return false,
+    };
+    reg == a as usize
+}
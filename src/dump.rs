@@ -0,0 +1,557 @@
+use std::rc::Rc;
+
+use crate::bytecode::ByteCode;
+use crate::error::LuaError;
+use crate::lex::chunkid;
+use crate::parse::{LocalInfo, ParseProto, UpvalueDesc};
+use crate::value::Value;
+
+// 二进制chunk格式：
+//   头部：签名、版本、格式、校验数据、整数和浮点数大小、校验用的整数和浮点数
+//   主函数上值个数，然后递归地保存函数原型
+pub const SIGNATURE: &[u8] = b"\x1bLua";
+const VERSION: u8 = 0x54;
+const FORMAT: u8 = b'M';  // 与官方luac格式不兼容，单独编号
+const DATA: &[u8] = b"\x19\x93\r\n\x1a\n";
+const CHECK_INTEGER: i64 = 0x5678;
+const CHECK_FLOAT: f64 = 370.5;
+const MAX_NESTING: usize = 200;
+
+// 常量类型标记
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_INTEGER: u8 = 3;
+const TAG_FLOAT: u8 = 4;
+const TAG_STRING: u8 = 5;
+
+struct Writer {
+    buf: Vec<u8>,
+    strip: bool,
+}
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+    fn bool(&mut self, v: bool) {
+        self.buf.push(v as u8);
+    }
+    fn u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+    fn i16(&mut self, v: i16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+    fn i32(&mut self, v: i32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+    // 无符号变长整数，每字节7位，高位表示后面还有
+    fn size(&mut self, mut v: usize) {
+        loop {
+            let b = (v & 0x7f) as u8;
+            v >>= 7;
+            if v == 0 {
+                self.buf.push(b);
+                return;
+            }
+            self.buf.push(b | 0x80);
+        }
+    }
+    fn bytes(&mut self, s: &[u8]) {
+        self.size(s.len());
+        self.buf.extend_from_slice(s);
+    }
+    // 可缺省的字符串：长度+1，0表示没有
+    fn opt_bytes(&mut self, s: Option<&[u8]>) {
+        match s {
+            Some(s) => {
+                self.size(s.len() + 1);
+                self.buf.extend_from_slice(s);
+            }
+            None => self.size(0),
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+type ReadResult<T> = Result<T, String>;
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> ReadResult<&[u8]> {
+        if self.data.len() - self.pos < n {
+            return Err("truncated chunk".to_string());
+        }
+        let s = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(s)
+    }
+    fn u8(&mut self) -> ReadResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn bool(&mut self) -> ReadResult<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err("corrupted chunk".to_string()),
+        }
+    }
+    fn u16(&mut self) -> ReadResult<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    fn i16(&mut self) -> ReadResult<i16> {
+        Ok(i16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    fn i32(&mut self) -> ReadResult<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn i64(&mut self) -> ReadResult<i64> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    fn f64(&mut self) -> ReadResult<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    fn size(&mut self) -> ReadResult<usize> {
+        let mut v: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.u8()?;
+            if shift > 56 {
+                return Err("integer overflow".to_string());
+            }
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return usize::try_from(v).map_err(|_| "integer overflow".to_string());
+            }
+            shift += 7;
+        }
+    }
+    // 元素个数，每个元素至少占min字节，据此排除明显错误的长度
+    fn count(&mut self, min: usize) -> ReadResult<usize> {
+        let n = self.size()?;
+        if n.saturating_mul(min) > self.data.len() - self.pos {
+            return Err("truncated chunk".to_string());
+        }
+        Ok(n)
+    }
+    fn bytes(&mut self) -> ReadResult<Vec<u8>> {
+        let n = self.size()?;
+        Ok(self.take(n)?.to_vec())
+    }
+    fn opt_bytes(&mut self) -> ReadResult<Option<Vec<u8>>> {
+        match self.size()? {
+            0 => Ok(None),
+            n => Ok(Some(self.take(n - 1)?.to_vec())),
+        }
+    }
+    fn string(&mut self) -> ReadResult<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| "corrupted chunk".to_string())
+    }
+}
+
+// 指令编码：操作码一个字节，之后按顺序存放各操作数（小端）
+macro_rules! opcodes {
+    ($($op:literal => $name:ident($($arg:ident: $ty:ident),*),)*) => {
+        fn write_code(w: &mut Writer, code: ByteCode) {
+            match code {
+                $(ByteCode::$name($($arg),*) => {
+                    w.u8($op);
+                    $(w.$ty($arg);)*
+                })*
+            }
+        }
+
+        fn read_code(r: &mut Reader) -> ReadResult<ByteCode> {
+            Ok(match r.u8()? {
+                $($op => ByteCode::$name($(r.$ty()?),*),)*
+                op => return Err(format!("invalid opcode {}", op)),
+            })
+        }
+    };
+}
+
+opcodes! {
+    0 => GetGlobal(a: u8, b: u8, c: u16),
+    1 => LoadConstant(a: u8, b: u16),
+    2 => LoadNil(a: u8, b: u8),
+    3 => LoadBool(a: u8, b: bool),
+    4 => LoadInt(a: u8, b: i16),
+    5 => SetGlobal(a: u8, b: u16, c: u8),
+    6 => SetGlobalConst(a: u8, b: u16, c: u16),
+    7 => SetGlobalGlobal(a: u8, b: u16, c: u16),
+    8 => Move(a: u8, b: u8),
+    9 => GetUpvalue(a: u8, b: u8),
+    10 => SetUpvalue(a: u8, b: u8),
+    11 => Close(a: u8),
+    12 => NewTable(a: u8, b: u8, c: u8),
+    13 => GetTable(a: u8, b: u8, c: u8),
+    14 => GetField(a: u8, b: u8, c: u16),
+    15 => GetInt(a: u8, b: u8, c: u8),
+    16 => SetTable(a: u8, b: u8, c: u8),
+    17 => SetField(a: u8, b: u16, c: u8),
+    18 => SetInt(a: u8, b: u8, c: u8),
+    19 => SetList(a: u8, b: u8, c: u16),
+    20 => Method(a: u8, b: u8, c: u16),
+    21 => Add(a: u8, b: u8, c: u8),
+    22 => Sub(a: u8, b: u8, c: u8),
+    23 => Mul(a: u8, b: u8, c: u8),
+    24 => Div(a: u8, b: u8, c: u8),
+    25 => Idiv(a: u8, b: u8, c: u8),
+    26 => Mod(a: u8, b: u8, c: u8),
+    27 => Pow(a: u8, b: u8, c: u8),
+    28 => BitAnd(a: u8, b: u8, c: u8),
+    29 => BitOr(a: u8, b: u8, c: u8),
+    30 => BitXor(a: u8, b: u8, c: u8),
+    31 => ShiftL(a: u8, b: u8, c: u8),
+    32 => ShiftR(a: u8, b: u8, c: u8),
+    33 => Concat(a: u8, b: u8, c: u8),
+    34 => Eq(a: u8, b: u8, c: u8),
+    35 => Lt(a: u8, b: u8, c: u8),
+    36 => Le(a: u8, b: u8, c: u8),
+    37 => Neg(a: u8, b: u8),
+    38 => Not(a: u8, b: u8),
+    39 => BitNot(a: u8, b: u8),
+    40 => Len(a: u8, b: u8),
+    41 => Jump(a: i32),
+    42 => TestAndJump(a: u8, b: i32),
+    43 => TestOrJump(a: u8, b: i32),
+    44 => ForPrepare(a: u8, b: i32),
+    45 => ForLoop(a: u8, b: i32),
+    46 => ForCall(a: u8, b: u8),
+    47 => ForGenLoop(a: u8, b: i32),
+    48 => Closure(a: u8, b: u16),
+    49 => Call(a: u8, b: u8, c: u8),
+    50 => TailCall(a: u8, b: u8),
+    51 => Return(a: u8, b: u8),
+    52 => VarArgs(a: u8, b: u8),
+    53 => Tbc(a: u8),
+}
+
+// 把函数原型序列化为二进制chunk，strip为真时去掉调试信息
+pub fn dump(proto: &ParseProto, strip: bool) -> Vec<u8> {
+    let mut w = Writer { buf: Vec::new(), strip };
+    w.buf.extend_from_slice(SIGNATURE);
+    w.u8(VERSION);
+    w.u8(FORMAT);
+    w.buf.extend_from_slice(DATA);
+    w.u8(8);  // 整数大小
+    w.u8(8);  // 浮点数大小
+    w.buf.extend_from_slice(&CHECK_INTEGER.to_le_bytes());
+    w.buf.extend_from_slice(&CHECK_FLOAT.to_le_bytes());
+    w.size(proto.upvalues.len());
+    dump_function(&mut w, proto, None);
+    w.buf
+}
+
+fn dump_function(w: &mut Writer, proto: &ParseProto, parent_source: Option<&str>) {
+    // 与外层函数相同的源码名不重复保存
+    if w.strip || parent_source == Some(proto.source.as_str()) {
+        w.opt_bytes(None);
+    } else {
+        w.opt_bytes(Some(proto.source.as_bytes()));
+    }
+    w.size(proto.line_defined);
+    w.size(proto.last_line_defined);
+    w.size(proto.num_params);
+    w.bool(proto.is_vararg);
+    w.size(proto.max_stack);
+
+    w.size(proto.instructions.len());
+    for &code in &proto.instructions {
+        write_code(w, code);
+    }
+
+    w.size(proto.constants.len());
+    for k in &proto.constants {
+        match k {
+            Value::Nil => w.u8(TAG_NIL),
+            Value::Bool(false) => w.u8(TAG_FALSE),
+            Value::Bool(true) => w.u8(TAG_TRUE),
+            Value::Integer(i) => {
+                w.u8(TAG_INTEGER);
+                w.buf.extend_from_slice(&i.to_le_bytes());
+            }
+            Value::Float(f) => {
+                w.u8(TAG_FLOAT);
+                w.buf.extend_from_slice(&f.to_le_bytes());
+            }
+            Value::String(s) => {
+                w.u8(TAG_STRING);
+                w.bytes(s);
+            }
+            _ => unreachable!("invalid constant {:?}", k),
+        }
+    }
+
+    w.size(proto.upvalues.len());
+    for up in &proto.upvalues {
+        w.bool(up.in_stack);
+        w.u8(up.index);
+    }
+
+    w.size(proto.protos.len());
+    for p in &proto.protos {
+        dump_function(w, p, Some(&proto.source));
+    }
+
+    // 调试信息
+    if w.strip {
+        w.size(0);
+        w.size(0);
+        w.size(0);
+        return;
+    }
+    w.size(proto.lineinfo.len());
+    for &line in &proto.lineinfo {
+        w.size(line as usize);
+    }
+    w.size(proto.locals_info.len());
+    for l in &proto.locals_info {
+        w.bytes(l.name.as_bytes());
+        w.size(l.start_pc);
+        w.size(l.end_pc);
+    }
+    w.size(proto.upvalues.len());
+    for up in &proto.upvalues {
+        w.bytes(up.name.as_bytes());
+    }
+}
+
+// 从二进制chunk恢复函数原型，所有数据都经过校验，错误的输入只会返回错误
+pub fn undump(data: &[u8], chunkname: &str) -> Result<ParseProto, LuaError> {
+    let error = |why: &str| LuaError::Syntax(format!("{}: bad binary format ({})", chunkid(chunkname), why));
+
+    let mut r = Reader { data, pos: 0 };
+    let check = |r: &mut Reader, expect: &[u8], why: &str| match r.take(expect.len()) {
+        Ok(s) if s == expect => Ok(()),
+        Ok(_) => Err(error(why)),
+        Err(e) => Err(error(&e)),
+    };
+    check(&mut r, SIGNATURE, "not a binary chunk")?;
+    check(&mut r, &[VERSION], "version mismatch")?;
+    check(&mut r, &[FORMAT], "format mismatch")?;
+    check(&mut r, DATA, "corrupted chunk")?;
+    check(&mut r, &[8], "integer size mismatch")?;
+    check(&mut r, &[8], "float size mismatch")?;
+    if r.i64().map_err(|e| error(&e))? != CHECK_INTEGER {
+        return Err(error("integer format mismatch"));
+    }
+    if r.f64().map_err(|e| error(&e))? != CHECK_FLOAT {
+        return Err(error("float format mismatch"));
+    }
+    let nupvalues = r.size().map_err(|e| error(&e))?;
+
+    let proto = undump_function(&mut r, "=?", 0).map_err(|e| error(&e))?;
+    if proto.upvalues.len() != nupvalues || proto.upvalues.iter().any(|u| u.in_stack) {
+        return Err(error("corrupted chunk"));
+    }
+    if r.pos != data.len() {
+        return Err(error("extra bytes after chunk"));
+    }
+    Ok(proto)
+}
+
+fn undump_function(r: &mut Reader, parent_source: &str, depth: usize) -> ReadResult<ParseProto> {
+    if depth > MAX_NESTING {
+        return Err("too many nested functions".to_string());
+    }
+    let source = match r.opt_bytes()? {
+        Some(s) => String::from_utf8_lossy(&s).into_owned(),
+        None => parent_source.to_string(),
+    };
+    let mut proto = ParseProto::empty(source, 0);
+    proto.line_defined = r.size()?;
+    proto.last_line_defined = r.size()?;
+    proto.num_params = r.size()?;
+    proto.is_vararg = r.bool()?;
+    proto.max_stack = r.size()?;
+
+    let n = r.count(1)?;
+    for _ in 0..n {
+        proto.instructions.push(read_code(r)?);
+    }
+
+    let n = r.count(1)?;
+    for _ in 0..n {
+        let k = match r.u8()? {
+            TAG_NIL => Value::Nil,
+            TAG_FALSE => Value::Bool(false),
+            TAG_TRUE => Value::Bool(true),
+            TAG_INTEGER => Value::Integer(r.i64()?),
+            TAG_FLOAT => Value::Float(r.f64()?),
+            TAG_STRING => Value::from(r.bytes()?),
+            tag => return Err(format!("invalid constant tag {}", tag)),
+        };
+        proto.constants.push(k);
+    }
+
+    let n = r.count(2)?;
+    for _ in 0..n {
+        let in_stack = r.bool()?;
+        let index = r.u8()?;
+        proto.upvalues.push(UpvalueDesc { name: "?".to_string(), in_stack, index });
+    }
+
+    let n = r.count(1)?;
+    for _ in 0..n {
+        let p = undump_function(r, &proto.source, depth + 1)?;
+        proto.protos.push(Rc::new(p));
+    }
+
+    let n = r.count(1)?;
+    for _ in 0..n {
+        proto.lineinfo.push(u32::try_from(r.size()?).map_err(|_| "corrupted chunk".to_string())?);
+    }
+    let n = r.count(3)?;
+    for _ in 0..n {
+        let name = r.string()?;
+        let start_pc = r.size()?;
+        let end_pc = r.size()?;
+        proto.locals_info.push(LocalInfo { name, start_pc, end_pc });
+    }
+    let n = r.count(1)?;
+    if n != 0 && n != proto.upvalues.len() {
+        return Err("corrupted chunk".to_string());
+    }
+    for i in 0..n {
+        proto.upvalues[i].name = r.string()?;
+    }
+
+    verify(&proto)?;
+    Ok(proto)
+}
+
+// 检查指令的操作数，保证虚拟机执行时不会越界
+fn verify(proto: &ParseProto) -> ReadResult<()> {
+    let code = &proto.instructions;
+    if proto.max_stack > 256 || proto.num_params > proto.max_stack {
+        return Err("invalid stack size".to_string());
+    }
+    if !proto.lineinfo.is_empty() && proto.lineinfo.len() != code.len() {
+        return Err("invalid line information".to_string());
+    }
+    if !matches!(code.last(), Some(ByteCode::Return(..))) {
+        return Err("function does not end with a return".to_string());
+    }
+    // 子函数的上值捕获本函数的寄存器或上值
+    for p in &proto.protos {
+        for up in &p.upvalues {
+            let limit = if up.in_stack { proto.max_stack } else { proto.upvalues.len() };
+            if up.index as usize >= limit {
+                return Err("invalid upvalue".to_string());
+            }
+        }
+    }
+    for (pc, c) in code.iter().enumerate() {
+        verify_code(proto, pc, c).map_err(|why| format!("{} at instruction {}", why, pc + 1))?;
+    }
+    Ok(())
+}
+
+fn verify_code(proto: &ParseProto, pc: usize, c: &ByteCode) -> Result<(), &'static str> {
+    let code = &proto.instructions;
+    let string_const = |k: u16| matches!(proto.constants.get(k as usize), Some(Value::String(_)));
+    let any_const = |k: u16| (k as usize) < proto.constants.len();
+
+    if register_top(c) > proto.max_stack {
+        return Err("register out of range");
+    }
+    let consts_ok = match *c {
+        ByteCode::GetGlobal(_, _, k) | ByteCode::SetGlobal(_, k, _) => string_const(k),
+        ByteCode::SetGlobalConst(_, k, v) => string_const(k) && any_const(v),
+        ByteCode::SetGlobalGlobal(_, k, v) => string_const(k) && string_const(v),
+        ByteCode::LoadConstant(_, k) | ByteCode::GetField(_, _, k)
+        | ByteCode::SetField(_, k, _) | ByteCode::Method(_, _, k) => any_const(k),
+        _ => true,
+    };
+    if !consts_ok {
+        return Err("invalid constant");
+    }
+    match *c {
+        ByteCode::GetUpvalue(_, u) | ByteCode::SetUpvalue(u, _) | ByteCode::GetGlobal(_, u, _)
+        | ByteCode::SetGlobal(u, _, _) | ByteCode::SetGlobalConst(u, _, _) | ByteCode::SetGlobalGlobal(u, _, _)
+            if u as usize >= proto.upvalues.len() => {
+            return Err("invalid upvalue");
+        }
+        ByteCode::Closure(_, i) if i as usize >= proto.protos.len() => return Err("invalid function index"),
+        ByteCode::Jump(o) | ByteCode::TestAndJump(_, o) | ByteCode::TestOrJump(_, o)
+        | ByteCode::ForPrepare(_, o) | ByteCode::ForLoop(_, o) | ByteCode::ForGenLoop(_, o) => {
+            // 不能跳到消费可变个数结果的指令上
+            let target = pc as i64 + 1 + o as i64;
+            if target < 0 || target >= code.len() as i64 || open_consumer(&code[target as usize]).is_some() {
+                return Err("invalid jump");
+            }
+        }
+        _ => (),
+    }
+
+    // 产生可变个数结果的指令必须紧跟着消费它们的指令，后者的起始位置不能超过前者
+    if let Some(from) = open_consumer(c) {
+        match pc.checked_sub(1).and_then(|p| open_producer(&code[p])) {
+            Some(start) if start >= from => (),
+            _ => return Err("invalid variable results"),
+        }
+    }
+    if open_producer(c).is_some() && code.get(pc + 1).and_then(open_consumer).is_none() {
+        return Err("invalid variable results");
+    }
+    Ok(())
+}
+
+// 指令访问的寄存器上界（不含）
+fn register_top(c: &ByteCode) -> usize {
+    let top = |a: u8, n: usize| a as usize + n;
+    match *c {
+        ByteCode::LoadNil(a, n) => top(a, n as usize),
+        ByteCode::GetGlobal(a, _, _) | ByteCode::LoadConstant(a, _) | ByteCode::LoadBool(a, _)
+        | ByteCode::LoadInt(a, _) | ByteCode::SetGlobal(_, _, a) | ByteCode::GetUpvalue(a, _)
+        | ByteCode::SetUpvalue(_, a) | ByteCode::NewTable(a, _, _) | ByteCode::TestAndJump(a, _)
+        | ByteCode::TestOrJump(a, _) | ByteCode::Closure(a, _) | ByteCode::Tbc(a) => top(a, 1),
+        ByteCode::SetGlobalConst(..) | ByteCode::SetGlobalGlobal(..) | ByteCode::Jump(_) => 0,
+        ByteCode::Close(a) => a as usize,
+        ByteCode::Move(a, b) | ByteCode::Neg(a, b) | ByteCode::Not(a, b) | ByteCode::BitNot(a, b)
+        | ByteCode::Len(a, b) | ByteCode::GetField(a, b, _) | ByteCode::GetInt(a, b, _)
+        | ByteCode::SetField(a, _, b) | ByteCode::SetInt(a, _, b) => top(a.max(b), 1),
+        ByteCode::GetTable(a, b, c) | ByteCode::SetTable(a, b, c)
+        | ByteCode::Add(a, b, c) | ByteCode::Sub(a, b, c) | ByteCode::Mul(a, b, c)
+        | ByteCode::Div(a, b, c) | ByteCode::Idiv(a, b, c) | ByteCode::Mod(a, b, c)
+        | ByteCode::Pow(a, b, c) | ByteCode::BitAnd(a, b, c) | ByteCode::BitOr(a, b, c)
+        | ByteCode::BitXor(a, b, c) | ByteCode::ShiftL(a, b, c) | ByteCode::ShiftR(a, b, c)
+        | ByteCode::Concat(a, b, c) | ByteCode::Eq(a, b, c) | ByteCode::Lt(a, b, c)
+        | ByteCode::Le(a, b, c) => top(a.max(b).max(c), 1),
+        ByteCode::SetList(a, n, _) => top(a, (n as usize).max(1)),
+        ByteCode::Method(a, b, _) => top(a, 2).max(top(b, 1)),
+        ByteCode::ForPrepare(a, _) | ByteCode::ForLoop(a, _) => top(a, 4),
+        ByteCode::ForCall(a, n) => top(a, 4 + (n as usize).max(3)),
+        ByteCode::ForGenLoop(a, _) => top(a, 5),
+        // 计数参数为"个数+1"：函数和参数共占narg个寄存器，返回值占nret-1个
+        ByteCode::Call(f, narg, nret) => top(f, (narg as usize).max(nret as usize).max(1)),
+        ByteCode::TailCall(f, narg) => top(f, (narg as usize).max(1)),
+        ByteCode::Return(a, n) => top(a, (n as usize).saturating_sub(1)),
+        // 个数可变时结果直接压到栈顶
+        ByteCode::VarArgs(a, n) => top(a, (n as usize).saturating_sub(1)),
+    }
+}
+
+// 产生可变个数结果的指令，返回结果的起始寄存器
+fn open_producer(c: &ByteCode) -> Option<usize> {
+    match *c {
+        ByteCode::Call(f, _, 0) | ByteCode::TailCall(f, _) => Some(f as usize),
+        ByteCode::VarArgs(a, 0) => Some(a as usize),
+        _ => None,
+    }
+}
+
+// 消费可变个数结果的指令，返回其要求的最小起始寄存器
+fn open_consumer(c: &ByteCode) -> Option<usize> {
+    match *c {
+        ByteCode::Call(f, 0, _) | ByteCode::TailCall(f, 0) => Some(f as usize + 1),
+        ByteCode::Return(a, 0) => Some(a as usize),
+        ByteCode::SetList(a, 0, _) => Some(a as usize + 1),
+        _ => None,
+    }
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+
+use crate::value::Value;
+
+const YIELD_ACROSS: &str = "attempt to yield across a C-call boundary";
+
+#[derive(Clone, Debug)]
+pub enum LuaError {
+    Syntax(String),   // 词法/语法错误，已带有"chunk:line:"前缀
+    Runtime(Value),   // 运行时错误，携带任意错误对象
+    Yield,            // 协程让出：沿Rust调用栈传递到resume，不是真正的错误
+    Interrupt(&'static str),  // 宿主设置的限制使执行中止，pcall不能捕获
+}
+
+impl LuaError {
+    pub fn runtime(msg: impl Into<String>) -> Self {
+        LuaError::Runtime(Value::from(msg.into()))
+    }
+
+    // pcall、coroutine.resume等能否把错误作为值返回给Lua代码
+    pub fn is_catchable(&self) -> bool {
+        !matches!(self, LuaError::Interrupt(_))
+    }
+
+    // 作为Lua值返回给pcall/load等调用者
+    pub fn into_value(self) -> Value {
+        match self {
+            LuaError::Syntax(msg) => Value::from(msg),
+            LuaError::Runtime(v) => v,
+            LuaError::Yield => Value::from(YIELD_ACROSS),
+            LuaError::Interrupt(msg) => Value::from(msg),
+        }
+    }
+}
+
+impl fmt::Display for LuaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LuaError::Syntax(msg) => write!(f, "{}", msg),
+            LuaError::Runtime(Value::String(msg)) => write!(f, "{}", String::from_utf8_lossy(msg)),
+            LuaError::Runtime(Value::Nil) => write!(f, "nil"),
+            LuaError::Runtime(v) => write!(f, "(error object is a {} value)", v.type_name()),
+            LuaError::Yield => write!(f, "{}", YIELD_ACROSS),
+            LuaError::Interrupt(msg) => write!(f, "{}", msg),
+        }
+    }
+}
@@ -1,169 +1,222 @@
-use std::{fs, io::{Read, Seek, SeekFrom}};
+use std::{fs, io::{self, Read}};
 
-#[derive(Debug)]
+use crate::error::LuaError;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Token {
 
     // lua关键字
-    And, Break, Do, Else, ElseIf, End, 
-    False, For, Function, Goto, If, In, 
-    Local, Nil, Not, Or, Repeat, Return, 
+    And, Break, Do, Else, ElseIf, End,
+    False, For, Function, Goto, If, In,
+    Local, Nil, Not, Or, Repeat, Return,
     Then, True, Until, While,
 
     // lua符号
     // +  -    *    /    %    ^    #
     Add, Sub, Mul, Div, Mod, Pow, Len,
+    // &       ~       |       <<      >>      //
+    BitAnd, BitXor, BitOr, ShiftL, ShiftR, Idiv,
     // ==    ~=    <=    <    >=    >
     Eq,     Ne,    Le,   Lt,  Ge,  Gt,
     // =    (    )  {    }  [    ]
     Assign, Lp, Rp, Lb, Rb, Ls, Rs,
-    //  ;        :      ,     .    ..      ...
-    Semicolon, Colon, Comma, Dot, Concat, Vararg,
+    //  ;        :      ::        ,     .    ..      ...
+    Semicolon, Colon, DoubColon, Comma, Dot, Concat, Vararg,
 
     // lua常量
     Integer(i64),
     Float(f64),
-    String(String),
+    String(Vec<u8>),
 
     // lua标识符
     Name(String),
-    
+
     // 文件结束
     Eos,
 }
 
 pub struct Lex {
     // input: fs::File,
-    code: Vec<u8>, 
+    code: Vec<u8>,
     idx: usize,
-    ahead: Option<Token>
+    ahead: Vec<(Token, usize)>,  // 预读的token及其所在行，后进先出
+    line: usize,
+    last_line: usize,  // 最近一个被消耗的token所在行
+    pub chunkname: String,  // "@文件名"、"=stdin" 或源码字符串本身
 }
 
 impl Lex {
-    pub fn new(filename: &str) -> Self {
-        let mut input = fs::File::open(filename).expect("Could not open file");
+    // 从文件读取源码，文件名为"-"时读取标准输入
+    pub fn new(filename: &str) -> io::Result<Self> {
+        if filename == "-" {
+            return Self::from_reader(io::stdin(), "=stdin");
+        }
+        let input = fs::File::open(filename)?;
+        Self::from_reader(input, &format!("@{}", filename))
+    }
+
+    pub fn from_reader<R: Read>(mut input: R, chunkname: &str) -> io::Result<Self> {
         let mut code = Vec::new();
-        input.read_to_end(&mut code).expect("Could not read file");
+        input.read_to_end(&mut code)?;
+        Ok(Self::from_vec(code, chunkname))
+    }
+
+    pub fn from_bytes(code: &[u8], chunkname: &str) -> Self {
+        Self::from_vec(code.to_vec(), chunkname)
+    }
+
+    pub fn from_string(code: String, chunkname: &str) -> Self {
+        Self::from_vec(code.into_bytes(), chunkname)
+    }
+
+    fn from_vec(code: Vec<u8>, chunkname: &str) -> Self {
+        let mut lex = Lex { code, idx: 0, ahead: Vec::new(), line: 1, last_line: 1, chunkname: chunkname.to_string() };
+        // 跳过首行的 #! 注释
+        if lex.code.first() == Some(&b'#') {
+            while lex.idx < lex.code.len() && lex.code[lex.idx] != b'\n' {
+                lex.idx += 1;
+            }
+        }
+        lex
+    }
 
-        Lex { code, idx: 0, ahead: None }
+    pub fn line(&self) -> usize {
+        self.line
     }
 
-    pub fn peek(&mut self) -> &Token {
-        if self.ahead.is_none()  {
-            let t = self.next();
-            self.ahead = Some(t);
-            
-        } 
+    pub fn last_line(&self) -> usize {
+        self.last_line
+    }
+
+    // 供错误信息使用的chunk名，规则同Lua的luaO_chunkid
+    pub fn source_name(&self) -> String {
+        chunkid(&self.chunkname)
+    }
 
-        self.ahead.as_ref().unwrap()
+    pub fn error(&self, msg: &str) -> LuaError {
+        LuaError::Syntax(format!("{}:{}: {}", self.source_name(), self.line, msg))
     }
 
-    pub fn next(&mut self) -> Token {
-        let ahead = self.ahead.take();
-        if let Some(t) = ahead {
-            return t;
-        } 
+    pub fn peek(&mut self) -> Result<&Token, LuaError> {
+        if self.ahead.is_empty()  {
+            let t = self.scan()?;
+            self.ahead.push((t, self.line));
+        }
+
+        Ok(&self.ahead.last().unwrap().0)
+    }
+
+    // 退回一个已读取的token
+    pub fn ahead_push(&mut self, t: Token) {
+        self.ahead.push((t, self.last_line));
+    }
 
+    // 期望下一个token为t
+    pub fn expect(&mut self, t: Token, what: &str) -> Result<(), LuaError> {
+        let token = self.next()?;
+        if token != t {
+            return Err(self.error(&format!("'{}' expected near {}", what, token_text(&token))));
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Token, LuaError> {
+        if let Some((t, line)) = self.ahead.pop() {
+            self.last_line = line;
+            return Ok(t);
+        }
+        let t = self.scan()?;
+        self.last_line = self.line;
+        Ok(t)
+    }
+
+    fn scan(&mut self) -> Result<Token, LuaError> {
         let c = self.read_char();
 
-        match c {
-            ' ' | '\n' | '\r' | '\t' => self.next(),
-            '\0' => Token::Eos,
-            '"' => {
-                let mut s = String::new();
-                loop {
-                    let c = self.read_char();
-                    match c {
-                        '"' => break,
-                        '\0' => panic!("Unterminated string"),
-                        _ => s.push(c),
-                        
-                    }
-                }
-                Token::String(s)
-            },
+        let token = match c {
+            ' ' | '\r' | '\t' | '\x0b' | '\x0c' => return self.scan(),
+            '\n' => {
+                self.line += 1;
+                return self.scan();
+            }
+            '\0' if self.idx > self.code.len() => Token::Eos,
+            '"' | '\'' => self.read_string(c)?,
             'a'..='z' | 'A'..='Z' | '_' => {
                 self.read_name(c)
             },
 
-            '0'..='9' => {
-                let mut s = String::new();
-                s.push(c);
-                let mut is_integer = true;
-                loop {
-                    let c = self.read_char();
-                    match c {
-                        '0'..='9' => s.push(c),
-                        '.' => {
-                            s.push(c);
-                            is_integer = false;
-                        },
-                        '\0' => break,
-                        _ => {
-                            // self.input.seek(SeekFrom::Current(-1)).unwrap();
-                            self.idx -= 1;
-                            break;
-                        }
-                        
-                    }
-                }
-                if is_integer {
-                    Token::Integer(s.parse().unwrap())
-                } else {
-                    Token::Float(s.parse().unwrap())
-                }
-            },
+            '0'..='9' => self.read_number(c)?,
 
             '(' => Token::Lp,
             ')' => Token::Rp,
             '{' => Token::Lb,
             '}' => Token::Rb,
-            '[' => Token::Ls,
+            '[' => {
+                match self.long_bracket_level() {
+                    Some(level) => Token::String(self.read_long_string(level)?),
+                    None => Token::Ls,
+                }
+            }
             ']' => Token::Rs,
 
             '+' => Token::Add,
-            '-' => Token::Sub,
+            '-' => {
+                if self.check_char('-') {
+                    self.read_comment()?;
+                    return self.scan();
+                }
+                Token::Sub
+            }
             '*' => Token::Mul,
-            '/' => Token::Div,
+            '/' => if self.check_char('/') { Token::Idiv } else { Token::Div },
             '%' => Token::Mod,
             '^' => Token::Pow,
             '#' => Token::Len,
+            '&' => Token::BitAnd,
+            '|' => Token::BitOr,
+            '~' => if self.check_char('=') { Token::Ne } else { Token::BitXor },
+            '<' => {
+                if self.check_char('<') {
+                    Token::ShiftL
+                } else if self.check_char('=') {
+                    Token::Le
+                } else {
+                    Token::Lt
+                }
+            }
+            '>' => {
+                if self.check_char('>') {
+                    Token::ShiftR
+                } else if self.check_char('=') {
+                    Token::Ge
+                } else {
+                    Token::Gt
+                }
+            }
 
             ';' => Token::Semicolon,
-            ':' => Token::Colon,
+            ':' => if self.check_char(':') { Token::DoubColon } else { Token::Colon },
             ',' => Token::Comma,
-            
+
             '.' => {
-                let c = self.read_char();
-                if c == '.' {
-                    let c = self.read_char();
-                    if c == '.' {
+                if self.check_char('.') {
+                    if self.check_char('.') {
                         Token::Vararg
                     } else {
-                        // self.input.seek(SeekFrom::Current(-1)).unwrap();
-                        self.idx -= 1;
                         Token::Concat
                     }
+                } else if self.peek_char().is_ascii_digit() {
+                    self.read_number('.')?
                 } else {
-                    // self.input.seek(SeekFrom::Current(-1)).unwrap();
-                    self.idx -= 1;
                     Token::Dot
                 }
             },
-            '=' => {
-                let c = self.read_char();
-                if c == '=' {
-                    Token::Eq
-                } else {
-                    self.idx -= 1;
-                    Token::Assign
-
-                }
-            }
+            '=' => if self.check_char('=') { Token::Eq } else { Token::Assign },
 
-            _ => panic!("Unexpected character: {}", c),
-        }
-        
-        
+            _ => return Err(self.error(&format!("unexpected symbol near '{}'", c))),
+        };
+        Ok(token)
     }
 
     fn read_char(&mut self) -> char {
@@ -171,14 +224,28 @@ impl Lex {
         // match self.input.read(&mut buf) {
         //     Ok(1) => buf[0] as char,
         //     _ => '\0',
-            
+
         // }
 
-        if let Some(&ch) = self.code.get(self.idx) {
+        let ch = self.peek_char();
+        // 越过结尾时idx仍然递增，以便区分源码中的'\0'和文件结束
+        self.idx += 1;
+        ch
+    }
+
+    fn peek_char(&self) -> char {
+        match self.code.get(self.idx) {
+            Some(&ch) => ch as char,
+            None => '\0',
+        }
+    }
+
+    fn check_char(&mut self, ch: char) -> bool {
+        if self.peek_char() == ch && self.idx < self.code.len() {
             self.idx += 1;
-            return ch as char;
+            true
         } else {
-            return '\0';
+            false
         }
     }
 
@@ -186,19 +253,16 @@ impl Lex {
         let mut s = String::new();
         s.push(ch);
         loop {
-            let c = self.read_char();
+            let c = self.peek_char();
             match c {
-                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => s.push(c),
-                '\0' => break,
-                _ => {
-                    // self.input.seek(SeekFrom::Current(-1)).unwrap();
-                    self.idx -= 1;
-                    break;
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => {
+                    self.idx += 1;
+                    s.push(c);
                 }
-                
+                _ => break,
             }
         }
-        match &s as &str {     
+        match &s as &str {
             "and"      => Token::And,
             "break"    => Token::Break,
             "do"       => Token::Do,
@@ -224,5 +288,340 @@ impl Lex {
             _ => Token::Name(s)
         }
     }
-    
-}
\ No newline at end of file
+
+    // 十进制或十六进制数，可带小数和指数部分
+    fn read_number(&mut self, first: char) -> Result<Token, LuaError> {
+        let mut s = String::new();
+        s.push(first);
+        let mut exponent = ['e', 'E'];
+        if first == '0' && matches!(self.peek_char(), 'x' | 'X') {
+            s.push(self.read_char());
+            exponent = ['p', 'P'];
+        }
+        loop {
+            let c = self.peek_char();
+            if exponent.contains(&c) {
+                self.idx += 1;
+                s.push(c);
+                if matches!(self.peek_char(), '+' | '-') {
+                    s.push(self.read_char());
+                }
+            } else if c.is_ascii_hexdigit() || c == '.' {
+                self.idx += 1;
+                s.push(c);
+            } else {
+                break;
+            }
+        }
+        if matches!(self.peek_char(), 'a'..='z' | 'A'..='Z' | '_') {
+            s.push(self.peek_char());
+            return Err(self.error(&format!("malformed number near '{}'", s)));
+        }
+
+        match str_to_number(&s) {
+            Some(crate::value::Value::Integer(i)) => Ok(Token::Integer(i)),
+            Some(crate::value::Value::Float(f)) => Ok(Token::Float(f)),
+            _ => Err(self.error(&format!("malformed number near '{}'", s))),
+        }
+    }
+
+    fn read_string(&mut self, quote: char) -> Result<Token, LuaError> {
+        let mut s = Vec::new();
+        loop {
+            let c = self.read_char();
+            match c {
+                _ if c == quote => break,
+                '\0' if self.idx > self.code.len() => return Err(self.error("unfinished string near <eof>")),
+                '\n' => return Err(self.error(&format!("unfinished string near '{}{}'", quote, String::from_utf8_lossy(&s)))),
+                '\\' => self.read_escape(&mut s)?,
+                _ => s.push(c as u8),
+            }
+        }
+        Ok(Token::String(s))
+    }
+
+    fn read_escape(&mut self, s: &mut Vec<u8>) -> Result<(), LuaError> {
+        let c = self.read_char();
+        match c {
+            'n' => s.push(b'\n'),
+            't' => s.push(b'\t'),
+            'r' => s.push(b'\r'),
+            'a' => s.push(7),
+            'b' => s.push(8),
+            'f' => s.push(12),
+            'v' => s.push(11),
+            '\\' => s.push(b'\\'),
+            '"' => s.push(b'"'),
+            '\'' => s.push(b'\''),
+            '\n' => {
+                self.line += 1;
+                s.push(b'\n');
+            }
+            'x' => {
+                let mut n = 0;
+                for _ in 0..2 {
+                    let d = self.read_char();
+                    n = n * 16 + d.to_digit(16).ok_or_else(|| self.error("hexadecimal digit expected"))?;
+                }
+                s.push(n as u8);
+            }
+            'z' => {
+                while self.peek_char().is_ascii_whitespace() {
+                    if self.read_char() == '\n' {
+                        self.line += 1;
+                    }
+                }
+            }
+            'u' => {
+                self.expect_char('{', "missing '{' in \\u{xxxx}")?;
+                let mut n: u32 = 0;
+                loop {
+                    let d = self.read_char();
+                    if d == '}' {
+                        break;
+                    }
+                    let d = d.to_digit(16).ok_or_else(|| self.error("hexadecimal digit expected"))?;
+                    n = n.checked_mul(16).filter(|n| *n < 0x8000_0000)
+                        .ok_or_else(|| self.error("UTF-8 value too large"))? + d;
+                }
+                utf8_encode(n, s);
+            }
+            '0'..='9' => {
+                let mut n = c.to_digit(10).unwrap();
+                for _ in 0..2 {
+                    match self.peek_char().to_digit(10) {
+                        Some(d) => {
+                            self.idx += 1;
+                            n = n * 10 + d;
+                        }
+                        None => break,
+                    }
+                }
+                if n > 255 {
+                    return Err(self.error("decimal escape too large"));
+                }
+                s.push(n as u8);
+            }
+            _ => return Err(self.error("invalid escape sequence")),
+        }
+        Ok(())
+    }
+
+    fn expect_char(&mut self, ch: char, msg: &str) -> Result<(), LuaError> {
+        if self.read_char() == ch {
+            Ok(())
+        } else {
+            Err(self.error(msg))
+        }
+    }
+
+    // 已读入'['，判断是否为长括号 [==[ ，返回等号个数
+    fn long_bracket_level(&mut self) -> Option<usize> {
+        let start = self.idx;
+        let mut level = 0;
+        while self.check_char('=') {
+            level += 1;
+        }
+        if self.check_char('[') {
+            Some(level)
+        } else {
+            self.idx = start;
+            None
+        }
+    }
+
+    fn read_long_string(&mut self, level: usize) -> Result<Vec<u8>, LuaError> {
+        // 紧跟开括号的换行被忽略
+        if self.check_char('\r') {
+            self.check_char('\n');
+            self.line += 1;
+        } else if self.check_char('\n') {
+            self.check_char('\r');
+            self.line += 1;
+        }
+
+        let mut s = Vec::new();
+        loop {
+            let c = self.read_char();
+            match c {
+                ']' => {
+                    let start = self.idx;
+                    let mut l = 0;
+                    while self.check_char('=') {
+                        l += 1;
+                    }
+                    if l == level && self.check_char(']') {
+                        return Ok(s);
+                    }
+                    self.idx = start;
+                    s.push(b']');
+                }
+                '\n' => {
+                    self.line += 1;
+                    s.push(b'\n');
+                }
+                '\0' if self.idx > self.code.len() => return Err(self.error("unfinished long string near <eof>")),
+                _ => s.push(c as u8),
+            }
+        }
+    }
+
+    fn read_comment(&mut self) -> Result<(), LuaError> {
+        if self.check_char('[') {
+            if let Some(level) = self.long_bracket_level() {
+                self.read_long_string(level)?;
+                return Ok(());
+            }
+        }
+        while self.idx < self.code.len() && self.code[self.idx] != b'\n' {
+            self.idx += 1;
+        }
+        Ok(())
+    }
+}
+
+pub fn token_text(token: &Token) -> String {
+    match token {
+        Token::Eos => "<eof>".to_string(),
+        Token::Name(name) => format!("'{}'", name),
+        Token::String(s) => format!("'{}'", String::from_utf8_lossy(s)),
+        Token::Integer(i) => format!("'{}'", i),
+        Token::Float(f) => format!("'{}'", f),
+        t => {
+            let s = match t {
+                Token::And => "and", Token::Break => "break", Token::Do => "do", Token::Else => "else",
+                Token::ElseIf => "elseif", Token::End => "end", Token::False => "false", Token::For => "for",
+                Token::Function => "function", Token::Goto => "goto", Token::If => "if", Token::In => "in",
+                Token::Local => "local", Token::Nil => "nil", Token::Not => "not", Token::Or => "or",
+                Token::Repeat => "repeat", Token::Return => "return", Token::Then => "then", Token::True => "true",
+                Token::Until => "until", Token::While => "while",
+                Token::Add => "+", Token::Sub => "-", Token::Mul => "*", Token::Div => "/", Token::Mod => "%",
+                Token::Pow => "^", Token::Len => "#", Token::BitAnd => "&", Token::BitXor => "~",
+                Token::BitOr => "|", Token::ShiftL => "<<", Token::ShiftR => ">>", Token::Idiv => "//",
+                Token::Eq => "==", Token::Ne => "~=", Token::Le => "<=", Token::Lt => "<", Token::Ge => ">=",
+                Token::Gt => ">", Token::Assign => "=", Token::Lp => "(", Token::Rp => ")", Token::Lb => "{",
+                Token::Rb => "}", Token::Ls => "[", Token::Rs => "]", Token::Semicolon => ";",
+                Token::Colon => ":", Token::DoubColon => "::", Token::Comma => ",", Token::Dot => ".",
+                Token::Concat => "..", Token::Vararg => "...",
+                _ => unreachable!(),
+            };
+            format!("'{}'", s)
+        }
+    }
+}
+
+// chunk名转为错误信息中的来源名
+pub fn chunkid(chunkname: &str) -> String {
+    if let Some(name) = chunkname.strip_prefix('=').or_else(|| chunkname.strip_prefix('@')) {
+        name.to_string()
+    } else {
+        let line = chunkname.lines().next().unwrap_or("");
+        if line.len() < chunkname.len() || line.len() > 40 {
+            let mut end = line.len().min(40);
+            while !line.is_char_boundary(end) {
+                end -= 1;
+            }
+            format!("[string \"{}...\"]", &line[..end])
+        } else {
+            format!("[string \"{}\"]", line)
+        }
+    }
+}
+
+pub fn utf8_encode(mut n: u32, s: &mut Vec<u8>) {
+    if n < 0x80 {
+        s.push(n as u8);
+        return;
+    }
+    // 与Lua一致，最多支持6字节的扩展编码
+    let mut buf = Vec::new();
+    let mut mfb = 0x3f;
+    loop {
+        buf.push(0x80 | (n & 0x3f) as u8);
+        n >>= 6;
+        mfb >>= 1;
+        if n <= mfb {
+            break;
+        }
+    }
+    buf.push(((!mfb << 1) | n) as u8);
+    s.extend(buf.iter().rev());
+}
+
+// 按Lua规则把字符串转为数字，失败返回None
+pub fn str_to_number(s: &str) -> Option<crate::value::Value> {
+    use crate::value::Value;
+
+    let s = s.trim_matches(|c: char| c.is_ascii_whitespace());
+    let (neg, body) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.strip_prefix('+').unwrap_or(s)),
+    };
+
+    if let Some(hex) = body.strip_prefix("0x").or_else(|| body.strip_prefix("0X")) {
+        return parse_hex(hex, neg);
+    }
+
+    if !body.is_empty() && body.bytes().all(|b| b.is_ascii_digit()) {
+        // 十进制整数溢出时转为浮点数
+        return match body.parse::<i64>() {
+            Ok(i) => Some(Value::Integer(if neg { i.wrapping_neg() } else { i })),
+            Err(_) if neg && body == "9223372036854775808" => Some(Value::Integer(i64::MIN)),
+            Err(_) => body.parse::<f64>().ok().map(|f| Value::Float(if neg { -f } else { f })),
+        };
+    }
+
+    let valid = !body.is_empty()
+        && body.bytes().all(|b| b.is_ascii_digit() || matches!(b, b'.' | b'e' | b'E' | b'+' | b'-'))
+        && body.bytes().any(|b| b.is_ascii_digit())
+        && !body.starts_with(['e', 'E']);
+    if !valid {
+        return None;
+    }
+    body.parse::<f64>().ok().map(|f| Value::Float(if neg { -f } else { f }))
+}
+
+fn parse_hex(hex: &str, neg: bool) -> Option<crate::value::Value> {
+    use crate::value::Value;
+
+    let (mantissa, exp) = match hex.find(['p', 'P']) {
+        Some(i) => (&hex[..i], Some(&hex[i+1..])),
+        None => (hex, None),
+    };
+    let (int_part, frac_part) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], Some(&mantissa[i+1..])),
+        None => (mantissa, None),
+    };
+    if int_part.is_empty() && frac_part.is_none_or(|f| f.is_empty()) {
+        return None;
+    }
+    if !int_part.bytes().all(|b| b.is_ascii_hexdigit())
+        || !frac_part.unwrap_or("").bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    if frac_part.is_none() && exp.is_none() {
+        // 十六进制整数按模2^64回绕
+        let mut n: u64 = 0;
+        for b in int_part.bytes() {
+            n = n.wrapping_mul(16).wrapping_add((b as char).to_digit(16).unwrap() as u64);
+        }
+        let i = n as i64;
+        return Some(Value::Integer(if neg { i.wrapping_neg() } else { i }));
+    }
+
+    let mut f = 0.0;
+    for b in int_part.bytes() {
+        f = f * 16.0 + (b as char).to_digit(16).unwrap() as f64;
+    }
+    let mut scale = 1.0 / 16.0;
+    for b in frac_part.unwrap_or("").bytes() {
+        f += (b as char).to_digit(16).unwrap() as f64 * scale;
+        scale /= 16.0;
+    }
+    if let Some(exp) = exp {
+        let e: i32 = exp.parse().ok()?;
+        f *= 2f64.powi(e);
+    }
+    Some(Value::Float(if neg { -f } else { f }))
+}
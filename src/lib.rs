@@ -0,0 +1,18 @@
+pub mod alloc;
+pub mod value;
+pub mod bytecode;
+pub mod error;
+pub mod lex;
+pub mod parse;
+mod listing;
+pub mod dump;
+pub mod debug;
+mod json;
+pub mod dap;
+pub mod profile;
+pub mod coverage;
+pub mod test_runner;
+pub mod vm;
+pub mod stdlib;
+pub mod readline;
+pub mod repl;
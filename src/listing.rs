@@ -0,0 +1,117 @@
+use std::fmt::Write;
+
+use crate::bytecode::ByteCode;
+use crate::lex::chunkid;
+use crate::parse::ParseProto;
+use crate::value::Value;
+
+// 类似`luac -l -l`的字节码清单
+impl ParseProto {
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        self.list_function(&mut out, true);
+        out
+    }
+
+    fn list_function(&self, out: &mut String, is_main: bool) {
+        let source = chunkid(&self.source);
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+
+        if is_main {
+            let _ = writeln!(out, "main <{}:0,0> ({} instruction{})", source, self.instructions.len(), plural(self.instructions.len()));
+        } else {
+            let _ = writeln!(out, "function <{}:{},{}> ({} instruction{})", source, self.line_defined, self.last_line_defined,
+                self.instructions.len(), plural(self.instructions.len()));
+        }
+        let _ = writeln!(out, "{}{} param{}, {} slot{}, {} upvalue{}, {} local{}, {} constant{}, {} function{}",
+            self.num_params, if self.is_vararg { "+" } else { "" }, plural(self.num_params),
+            self.max_stack, plural(self.max_stack),
+            self.upvalues.len(), plural(self.upvalues.len()),
+            self.locals_info.len(), plural(self.locals_info.len()),
+            self.constants.len(), plural(self.constants.len()),
+            self.protos.len(), plural(self.protos.len()));
+
+        for (pc, code) in self.instructions.iter().enumerate() {
+            let line = self.lineinfo.get(pc).copied().unwrap_or(0);
+            let (name, operands) = split_op(code);
+            let _ = write!(out, "\t{}\t[{}]\t{:<14}\t{}", pc + 1, line, name, operands);
+            if let Some(comment) = self.comment(pc, code) {
+                let _ = write!(out, "\t; {}", comment);
+            }
+            out.push('\n');
+        }
+
+        let _ = writeln!(out, "constants ({}):", self.constants.len());
+        for (i, k) in self.constants.iter().enumerate() {
+            let tag = match k {
+                Value::Nil => "N",
+                Value::Bool(_) => "B",
+                Value::Integer(_) => "I",
+                Value::Float(_) => "F",
+                Value::String(_) => "S",
+                _ => "?",
+            };
+            let _ = writeln!(out, "\t{}\t{}\t{}", i, tag, constant_text(k));
+        }
+
+        let _ = writeln!(out, "locals ({}):", self.locals_info.len());
+        for (i, l) in self.locals_info.iter().enumerate() {
+            let _ = writeln!(out, "\t{}\t{}\t{}\t{}", i, l.name, l.start_pc + 1, l.end_pc + 1);
+        }
+
+        let _ = writeln!(out, "upvalues ({}):", self.upvalues.len());
+        for (i, u) in self.upvalues.iter().enumerate() {
+            let _ = writeln!(out, "\t{}\t{}\t{}\t{}", i, u.name, u8::from(u.in_stack), u.index);
+        }
+
+        for p in &self.protos {
+            out.push('\n');
+            p.list_function(out, false);
+        }
+    }
+
+    // 解码操作数中引用的常量、上值和跳转目标
+    fn comment(&self, pc: usize, code: &ByteCode) -> Option<String> {
+        let k = |i: u16| self.constants.get(i as usize).map(constant_text).unwrap_or_else(|| "?".to_string());
+        let name = |i: u16| match self.constants.get(i as usize) {
+            Some(Value::String(s)) => String::from_utf8_lossy(s).into_owned(),
+            _ => "?".to_string(),
+        };
+        let up = |i: u8| self.upvalues.get(i as usize).map(|u| u.name.clone()).unwrap_or_else(|| "?".to_string());
+        let to = |offset: i32| format!("to {}", pc as i64 + 2 + offset as i64);
+
+        Some(match *code {
+            ByteCode::GetGlobal(_, e, i) => format!("{} {}", up(e), name(i)),
+            ByteCode::LoadConstant(_, i) => k(i),
+            ByteCode::SetGlobal(e, i, _) => format!("{} {}", up(e), name(i)),
+            ByteCode::SetGlobalConst(e, i, c) => format!("{} {} {}", up(e), name(i), k(c)),
+            ByteCode::SetGlobalGlobal(e, i, j) => format!("{} {} {}", up(e), name(i), name(j)),
+            ByteCode::GetUpvalue(_, i) | ByteCode::SetUpvalue(i, _) => up(i),
+            ByteCode::GetField(_, _, i) | ByteCode::SetField(_, i, _) | ByteCode::Method(_, _, i) => k(i),
+            ByteCode::Jump(o) | ByteCode::TestAndJump(_, o) | ByteCode::TestOrJump(_, o)
+            | ByteCode::ForPrepare(_, o) | ByteCode::ForLoop(_, o) | ByteCode::ForGenLoop(_, o) => to(o),
+            ByteCode::Closure(_, i) => match self.protos.get(i as usize) {
+                Some(p) => format!("function <{}:{}>", chunkid(&p.source), p.line_defined),
+                None => "?".to_string(),
+            },
+            _ => return None,
+        })
+    }
+}
+
+// 由Debug输出得到大写的指令名和以空格分隔的操作数
+fn split_op(code: &ByteCode) -> (String, String) {
+    let text = format!("{:?}", code);
+    match text.split_once('(') {
+        Some((name, args)) => (name.to_uppercase(), args.trim_end_matches(')').replace(',', "")),
+        None => (text.to_uppercase(), String::new()),
+    }
+}
+
+fn constant_text(v: &Value) -> String {
+    match v {
+        Value::String(s) => format!("{:?}", String::from_utf8_lossy(s)),
+        Value::Float(f) if f.fract() == 0.0 && f.is_finite() => format!("{:.1}", f),
+        _ => v.to_string(),
+    }
+}
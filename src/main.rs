@@ -1,28 +1,332 @@
-mod value;
-mod bytecode;
-mod lex;
-mod parse;
-mod vm;
+use std::cell::RefCell;
+use std::io::{IsTerminal, Read};
+use std::rc::Rc;
 
+use my_lua::error::LuaError;
+use my_lua::lex::Lex;
+use my_lua::parse::ParseProto;
+use my_lua::value::{Table, Value};
+use my_lua::{coverage, dap, dump, profile, repl, test_runner, vm};
 
+// 统计分配的内存，使ExeState::set_memory_limit和collectgarbage("count")可用
+#[global_allocator]
+static ALLOC: my_lua::alloc::CountingAlloc = my_lua::alloc::CountingAlloc::new();
+
+const VERSION: &str = concat!("myLua ", env!("CARGO_PKG_VERSION"), "  (Lua 5.4 compatible)");
+
+// 命令行中按顺序执行的 -e / -l 选项
+enum Action {
+    Exec(String),
+    Require(String),
+}
+
+struct Options {
+    actions: Vec<Action>,
+    interactive: bool,  // -i
+    version: bool,      // -v
+    no_env: bool,       // -E
+    warnings: bool,     // -W
+    list: bool,         // --list：只编译并输出字节码清单
+    dap: bool,          // --dap：作为调试适配器运行，脚本由launch请求指定
+    profile: Option<String>,  // --profile file：分析脚本，折叠栈写入file，报告输出到标准错误
+    coverage: bool,     // --coverage：统计脚本的覆盖率，合并到lcov.info并生成coverage.txt
+    script: Option<usize>,  // 脚本名在参数中的位置
+}
+
+fn usage(progname: &str, badoption: &str) -> ! {
+    if badoption == "-e" || badoption == "-l" || badoption == "--profile" {
+        eprintln!("{}: '{}' needs argument", progname, badoption);
+    } else {
+        eprintln!("{}: unrecognized option '{}'", progname, badoption);
+    }
+    eprintln!(
+        "usage: {} [options] [script [args]]
+       {} test [--junit file] [path ...]
+Available options are:
+  -e stat   execute string 'stat'
+  -i        enter interactive mode after executing 'script'
+  -l mod    require library 'mod' into global 'mod'
+  -l g=mod  require library 'mod' into global 'g'
+  -v        show version information
+  -E        ignore environment variables
+  -W        turn warnings on
+  --list    list the bytecode of 'script' instead of running it
+  --dap     run as a Debug Adapter Protocol server on stdio
+  --profile file  profile 'script', writing folded stacks to 'file'
+  --coverage  record coverage of 'script' into lcov.info and coverage.txt
+  --        stop handling options
+  -         stop handling options and execute stdin",
+        progname, progname
+    );
+    std::process::exit(1);
+}
+
+fn parse_args(args: &[String]) -> Options {
+    let progname = &args[0];
+    let mut opts = Options { actions: Vec::new(), interactive: false, version: false, no_env: false, warnings: false, list: false, dap: false, profile: None, coverage: false, script: None };
+    let mut i = 1;
+    while i < args.len() {
+        let arg = &args[i];
+        if !arg.starts_with('-') || arg == "-" {
+            opts.script = Some(i);
+            break;
+        }
+        match arg.as_str() {
+            "--" => {
+                if i + 1 < args.len() {
+                    opts.script = Some(i + 1);
+                }
+                break;
+            }
+            "-i" => {
+                opts.interactive = true;
+                opts.version = true;
+            }
+            "-v" => opts.version = true,
+            "-E" => opts.no_env = true,
+            "-W" => opts.warnings = true,
+            "--list" => opts.list = true,
+            "--dap" => opts.dap = true,
+            "--coverage" => opts.coverage = true,
+            "--profile" => {
+                i += 1;
+                match args.get(i) {
+                    Some(file) => opts.profile = Some(file.clone()),
+                    None => usage(progname, arg),
+                }
+            }
+            _ if arg.starts_with("-e") || arg.starts_with("-l") => {
+                // 参数可以紧跟选项，也可以是下一个命令行参数
+                let value = if arg.len() > 2 {
+                    arg[2..].to_string()
+                } else {
+                    i += 1;
+                    match args.get(i) {
+                        Some(v) if !v.starts_with('-') => v.clone(),
+                        _ => usage(progname, arg),
+                    }
+                };
+                opts.actions.push(if arg.starts_with("-e") { Action::Exec(value) } else { Action::Require(value) });
+            }
+            _ => usage(progname, arg),
+        }
+        i += 1;
+    }
+    opts
+}
+
+// 创建全局arg表：脚本名为arg[0]，其后参数为正索引，解释器和选项为负索引
+fn create_arg_table(state: &mut vm::ExeState, args: &[String], script: Option<usize>) {
+    let script = script.unwrap_or(0);
+    let mut table = Table::new(args.len().saturating_sub(script + 1), 0);
+    for (i, a) in args.iter().enumerate() {
+        table.set(Value::Integer(i as i64 - script as i64), Value::from(a.as_str()));
+    }
+    state.set_global("arg", Value::Table(Rc::new(RefCell::new(table))));
+}
+
+// 与lua.c的msghandler相同：在错误信息后附加调用栈；
+// 不是字符串的错误对象先尝试__tostring，不能转换的给出类型
+fn msghandler(state: &mut vm::ExeState) -> Result<i32, LuaError> {
+    let msg = match state.get_arg(1).clone() {
+        v @ (Value::String(_) | Value::Integer(_) | Value::Float(_)) => v,
+        v => {
+            if state.get_metamethod(&v, "__tostring") != Value::Nil {
+                if let s @ Value::String(_) = state.tostring(&v)? {
+                    state.push(s);
+                    return Ok(1);
+                }
+            }
+            Value::from(format!("(error object is a {} value)", v.type_name()))
+        }
+    };
+    let mut msg = state.tostring(&msg)?.as_bytes().unwrap().to_vec();
+    msg.push(b'\n');
+    msg.extend_from_slice(my_lua::stdlib::debug::traceback(state, None, 1).as_bytes());
+    state.push(Value::from(msg));
+    Ok(1)
+}
+
+// 以msghandler为消息处理函数调用，未捕获的错误带上调用栈
+fn docall(state: &mut vm::ExeState, f: Value, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+    let saved = state.set_error_handler(Value::Function(msghandler));
+    let result = state.call_function(f, args);
+    state.set_error_handler(saved);
+    result
+}
+
+fn do_chunk(state: &mut vm::ExeState, lex: Lex, args: Vec<Value>) -> Result<(), LuaError> {
+    let f = state.load(lex, None)?;
+    docall(state, f, args).map(|_| ())
+}
+
+fn do_string(state: &mut vm::ExeState, code: &str, chunkname: &str) -> Result<(), LuaError> {
+    do_chunk(state, Lex::from_bytes(code.as_bytes(), chunkname), Vec::new())
+}
+
+// 读取脚本文件，"-"为标准输入，返回内容和chunk名
+fn read_script(filename: &str) -> Result<(Vec<u8>, String), LuaError> {
+    let result = if filename == "-" {
+        let mut code = Vec::new();
+        std::io::stdin().read_to_end(&mut code).map(|_| (code, "=stdin".to_string()))
+    } else {
+        std::fs::read(filename).map(|code| (code, format!("@{}", filename)))
+    };
+    result.map_err(|e| LuaError::runtime(format!("cannot open {}: {}", filename, e)))
+}
+
+fn do_file(state: &mut vm::ExeState, filename: &str, args: Vec<Value>) -> Result<(), LuaError> {
+    let (code, chunkname) = read_script(filename)?;
+    let f = state.load_bytes(&code, &chunkname, None)?;
+    docall(state, f, args).map(|_| ())
+}
+
+// -l mod 或 -l g=mod：调用require并把结果存入全局变量
+fn do_require(state: &mut vm::ExeState, spec: &str) -> Result<(), LuaError> {
+    let (global, module) = match spec.split_once('=') {
+        Some((g, m)) => (g, m),
+        None => (spec, spec),
+    };
+    let require = state.get_global("require");
+    let result = docall(state, require, vec![Value::from(module)])?;
+    state.set_global(global, result.into_iter().next().unwrap_or(Value::Nil));
+    Ok(())
+}
+
+// LUA_INIT_5_4 或 LUA_INIT：以'@'开头为文件名，否则为代码
+fn handle_init(state: &mut vm::ExeState) -> Result<(), LuaError> {
+    let (name, init) = match std::env::var("LUA_INIT_5_4") {
+        Ok(v) => ("=LUA_INIT_5_4", v),
+        Err(_) => match std::env::var("LUA_INIT") {
+            Ok(v) => ("=LUA_INIT", v),
+            Err(_) => return Ok(()),
+        },
+    };
+    match init.strip_prefix('@') {
+        Some(filename) => do_file(state, filename, Vec::new()),
+        None => do_string(state, &init, name),
+    }
+}
+
+fn list_file(filename: &str) -> Result<(), LuaError> {
+    let (code, chunkname) = read_script(filename)?;
+    let proto = if code.starts_with(&dump::SIGNATURE[..1]) {
+        dump::undump(&code, &chunkname)?
+    } else {
+        let mut proto = ParseProto::new(Lex::from_bytes(&code, &chunkname));
+        proto.compile()?;
+        proto
+    };
+    print!("{}", proto.listing());
+    Ok(())
+}
+
+fn write_profile(profile: &profile::Profile, file: &str) -> Result<(), LuaError> {
+    std::fs::File::create(file)
+        .and_then(|f| profile.write_folded(&mut std::io::BufWriter::new(f)))
+        .map_err(|e| LuaError::runtime(format!("cannot write {}: {}", file, e)))
+}
+
+const LCOV_FILE: &str = "lcov.info";
+const COVERAGE_REPORT: &str = "coverage.txt";
+
+// 与之前运行的结果合并后写出
+fn write_coverage(mut data: coverage::CoverageData) -> Result<(), LuaError> {
+    if let Ok(old) = std::fs::read_to_string(LCOV_FILE) {
+        data.merge_lcov(&old);
+    }
+    let write_error = |file: &str, e: std::io::Error| LuaError::runtime(format!("cannot write {}: {}", file, e));
+    std::fs::File::create(LCOV_FILE)
+        .and_then(|f| data.write_lcov(&mut std::io::BufWriter::new(f)))
+        .map_err(|e| write_error(LCOV_FILE, e))?;
+    std::fs::write(COVERAGE_REPORT, data.report()).map_err(|e| write_error(COVERAGE_REPORT, e))
+}
 
 fn main() {
-    let mut args = std::env::args();
-    let project_name = args.next().unwrap(); // Get the name of the project
+    let args: Vec<String> = std::env::args().collect();
+    let progname = args[0].clone();
+    // 子命令`test`运行测试文件
+    if args.get(1).is_some_and(|a| a == "test") {
+        std::process::exit(test_runner::main(&progname, &args[2..]));
+    }
+    let opts = parse_args(&args);
+
+    if opts.coverage && opts.profile.is_some() {
+        eprintln!("{}: --profile and --coverage cannot be used together", progname);
+        std::process::exit(1);
+    }
 
-    let filename = match args.next() {
-        Some(filename) => filename,
-        None => {
-            eprintln!("Usage: {} <filename>", project_name);
+    let report = |result: Result<(), LuaError>| {
+        if let Err(e) = result {
+            eprintln!("{}: {}", progname, e);
             std::process::exit(1);
         }
     };
 
-    let lex = lex::Lex::new(&filename);  // 词法分析, 生成tokens
-    let mut proto = parse::ParseProto::new(lex);   
-    proto.compile();        // 语法分析，生成字节码
-    let mut state = vm::ExeState::new();  
-    state.run(&proto);  // 执行
+    if opts.list {
+        report(list_file(opts.script.map_or("-", |i| &args[i])));
+        return;
+    }
+
+    let mut state = vm::ExeState::new();
+    if opts.warnings {
+        let warn = state.get_global("warn");
+        if let Value::Function(_) = warn {
+            report(state.call_function(warn, vec![Value::from("@on")]).map(|_| ()));
+        }
+    }
+    create_arg_table(&mut state, &args, opts.script);
 
-    
+    if opts.version {
+        println!("{}", VERSION);
+    }
+    if opts.no_env {
+        my_lua::stdlib::package::set_paths(&mut state, true);
+    } else {
+        report(handle_init(&mut state));
+    }
+
+    for action in &opts.actions {
+        report(match action {
+            Action::Exec(code) => do_string(&mut state, code, "=(command line)"),
+            Action::Require(spec) => do_require(&mut state, spec),
+        });
+    }
+
+    if opts.dap {
+        if let Err(e) = dap::run(&mut state) {
+            eprintln!("{}: {}", progname, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(i) = opts.script {
+        // 脚本之后的参数同时作为main chunk的可变参数
+        let script_args = args[i + 1..].iter().map(|a| Value::from(a.as_str())).collect();
+        let profiler = opts.profile.as_ref().map(|_| profile::Profiler::start(&mut state));
+        let coverage = opts.coverage.then(|| coverage::Coverage::start(&mut state));
+        let result = do_file(&mut state, &args[i], script_args);
+        if let Some(coverage) = coverage {
+            report(write_coverage(coverage.stop(&mut state)));
+        }
+        if let (Some(profiler), Some(file)) = (profiler, &opts.profile) {
+            let profile = profiler.stop(&mut state);
+            report(write_profile(&profile, file));
+            eprint!("{}", profile.report());
+        }
+        report(result);
+    }
+
+    if opts.interactive {
+        repl::Repl::new().run(&mut state);
+    } else if opts.script.is_none() && opts.actions.is_empty() && !opts.version {
+        // 无脚本：终端下进入交互模式，否则执行标准输入
+        if std::io::stdin().is_terminal() {
+            println!("{}", VERSION);
+            repl::Repl::new().run(&mut state);
+        } else {
+            report(do_file(&mut state, "-", Vec::new()));
+        }
+    }
 }
@@ -1,68 +1,132 @@
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use crate::value::Value;
 use crate::bytecode::ByteCode;
-use crate::lex::{Lex, Token};
+use crate::error::LuaError;
+use crate::lex::{Lex, Token, token_text};
+
+// 全局变量所在环境的名字，是每个chunk主函数的第一个上值
+pub const ENV: &str = "_ENV";
+
+// 上值描述：in_stack为真表示捕获外层函数的局部变量(index为寄存器)，否则捕获外层函数的上值
+#[derive(Debug, Clone)]
+pub struct UpvalueDesc {
+    pub name: String,
+    pub in_stack: bool,
+    pub index: u8,
+}
+
+// 局部变量调试信息，作用域为指令区间 [start_pc, end_pc)
+#[derive(Debug, Clone)]
+pub struct LocalInfo {
+    pub name: String,
+    pub start_pc: usize,
+    pub end_pc: usize,
+}
 
 pub struct ParseProto {
     pub constants: Vec<Value>,
     pub instructions: Vec<ByteCode>,
+    pub protos: Vec<Rc<ParseProto>>,  // 子函数原型
+    pub upvalues: Vec<UpvalueDesc>,
+    pub lineinfo: Vec<u32>,  // 每条指令对应的源码行号
+    pub locals_info: Vec<LocalInfo>,
+    pub num_params: usize,
+    pub is_vararg: bool,
+    pub max_stack: usize,
+    pub source: String,  // chunk名
+    pub line_defined: usize,
+    pub last_line_defined: usize,
     constants_pos: HashMap<Value, usize>,  // 键字符串（字符串型Value），值常量表位置
 
-    locals: Vec<String>,
-    lex: Lex
+    lex: Option<Lex>
 }
 
 impl ParseProto {
     pub fn new(lex: Lex) -> Self {
+        let mut proto = Self::empty(lex.chunkname.clone(), 0);
+        proto.lex = Some(lex);
+        proto
+    }
+
+    pub(crate) fn empty(source: String, line_defined: usize) -> Self {
         ParseProto {
             constants: Vec::new(),
             instructions: Vec::new(),
+            protos: Vec::new(),
+            upvalues: Vec::new(),
+            lineinfo: Vec::new(),
+            locals_info: Vec::new(),
+            num_params: 0,
+            is_vararg: false,
+            max_stack: 2,
+            source,
+            line_defined,
+            last_line_defined: 0,
             constants_pos: HashMap::new(),
-            locals: Vec::new(),
-            lex
+            lex: None,
         }
-    } 
+    }
 
-    pub fn compile(&mut self) {
-        loop {
-            let token = self.lex.next();
-            match token {
-                Token::Eos => break,
-                Token::Name(name) => {
-                    let next_token = self.lex.peek();
-                    if let Token::Assign = next_token {
-                        self.assignment(name);
-                    } else {
-                        self.function_call(name);
-                    }
-                },
-
-                // local Name = exp
-                Token::Local => {
-                    if let Token::Name(name) = self.lex.next() {
-                        if let Token::Assign = self.lex.next() {
-                            self.load_exp(self.locals.len());
-                            self.locals.push(name);
-                        } else {
-                            panic!("expected `=`");
-                        }
-                    } else {
-                        panic!("Expect variable name")
-                    }
-                }
-    
-                _ => panic!("Unexpected token: {:?}", token),
-            }
+    // 编译整个chunk，chunk本身是一个可变参数函数
+    pub fn compile(&mut self) -> Result<(), LuaError> {
+        self.compile_chunk(&[], false)
+    }
+
+    // 交互模式下编译：names为之前各行留下的局部变量，作为chunk的上值依次排列在_ENV之后；
+    // 本chunk顶层新声明的局部变量也追加为上值，执行后由调用者保存以供后续行使用
+    pub fn compile_repl(&mut self, names: &[String]) -> Result<(), LuaError> {
+        self.compile_chunk(names, true)
+    }
+
+    // 把多个chunk合并为依次调用它们的主函数，供luac处理多个输入文件。
+    // 各chunk的_ENV上值都捕获主函数的_ENV
+    pub fn combine(chunks: Vec<ParseProto>) -> ParseProto {
+        let mut main = Self::empty("=(luac)".to_string(), 0);
+        main.is_vararg = true;
+        main.upvalues.push(UpvalueDesc { name: ENV.to_string(), in_stack: false, index: 0 });
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            main.instructions.push(ByteCode::Closure(0, i as u16));
+            main.instructions.push(ByteCode::Call(0, 1, 1));
+            main.lineinfo.extend([0, 0]);
+            main.protos.push(Rc::new(chunk));
+        }
+        main.instructions.push(ByteCode::Return(0, 1));
+        main.lineinfo.push(0);
+        main
+    }
+
+    fn compile_chunk(&mut self, names: &[String], repl: bool) -> Result<(), LuaError> {
+        let lex = self.lex.take().expect("chunk already compiled");
+        let mut main = Self::empty(self.source.clone(), 0);
+        main.is_vararg = true;
+        for (i, name) in std::iter::once(ENV).chain(names.iter().map(String::as_str)).enumerate() {
+            main.upvalues.push(UpvalueDesc { name: name.to_string(), in_stack: false, index: i as u8 });
         }
 
-        dbg!(&self.constants);
-        dbg!(&self.instructions);
+        let mut parser = Parser { lex, funcs: vec![FuncState::new(main)], repl };
+        parser.block()?;
+        if parser.lex.peek()? != &Token::Eos {
+            let t = parser.lex.next()?;
+            return Err(parser.lex.error(&format!("'<eof>' expected near {}", token_text(&t))));
+        }
+        let main = parser.close_func()?;
+        *self = main;
+        Ok(())
+    }
+
+    // 在pc处第reg个寄存器对应的局部变量名
+    pub fn local_name(&self, reg: usize, pc: usize) -> Option<&str> {
+        self.locals_info.iter()
+            .filter(|l| l.start_pc <= pc && pc < l.end_pc)
+            .nth(reg)
+            .map(|l| l.name.as_str())
     }
 
     fn add_const(&mut self, const_var: Value) -> usize {  // Value添加到constants_pos中
         if let Some(i) = self.constants_pos.get(&const_var) {
-            return *i;
+            *i
         } else {
             let i = self.constants.len();
             self.constants_pos.insert(const_var.clone(), i);
@@ -70,121 +134,1700 @@ impl ParseProto {
             i
         }
     }
+}
 
-    // local a = ...
-    fn load_exp(&mut self, dst: usize) {
-        let token = self.lex.next();
+#[derive(Debug, Clone)]
+enum ExpDesc {
+    Nil,
+    True,
+    False,
+    Integer(i64),
+    Float(f64),
+    String(Vec<u8>),
 
-        let byte_code = match token {
-            Token::String(s) => {
-                let i = self.add_const(Value::String(s));
-                ByteCode::LoadConstant(dst as u8, i as u8)
-            },
-            Token::Integer(i) => {
+    Local(usize),            // 局部变量寄存器
+    Upvalue(usize),
+    Global(usize, usize),    // _ENV上值, 名字在常量表中的位置
+    Index(usize, usize),     // 表寄存器, 键寄存器
+    IndexField(usize, usize),  // 表寄存器, 键常量
+    IndexInt(usize, u8),     // 表寄存器, 整数键
+
+    Call(usize),     // Call指令位置，结果在函数寄存器
+    VarArgs(usize),  // VarArgs指令位置
+    Reg(usize),      // 值已在临时寄存器中
+}
+
+struct LocalVar {
+    name: String,
+    is_const: bool,
+    info: usize,  // 在locals_info中的位置
+}
+
+struct Block {
+    nactvar: usize,
+    is_loop: bool,
+    has_upval: bool,   // 有局部变量被闭包捕获或待关闭，离开时需要Close
+    inside_tbc: bool,  // 在待关闭变量的作用域内，不能使用尾调用
+    breaks: Vec<usize>,
+    first_label: usize,
+    first_goto: usize,
+}
+
+struct Label {
+    name: String,
+    pc: usize,
+    nactvar: usize,
+}
+
+struct PendingGoto {
+    name: String,
+    pc: usize,
+    line: usize,
+    nactvar: usize,
+}
+
+struct FuncState {
+    proto: ParseProto,
+    locals: Vec<LocalVar>,   // 已声明的局部变量，第i个位于寄存器i
+    nactvar: usize,          // 其中已生效的个数
+    blocks: Vec<Block>,
+    free_reg: usize,
+    labels: Vec<Label>,
+    gotos: Vec<PendingGoto>,
+    inside_tbc: bool,  // 函数体最外层声明了待关闭变量
+}
+
+impl FuncState {
+    fn new(proto: ParseProto) -> Self {
+        FuncState {
+            proto, locals: Vec::new(), nactvar: 0, blocks: Vec::new(), free_reg: 0,
+            labels: Vec::new(), gotos: Vec::new(), inside_tbc: false,
+        }
+    }
+}
+
+struct Parser {
+    lex: Lex,
+    funcs: Vec<FuncState>,  // 正在编译的函数，最后一个为当前函数
+    repl: bool,  // 交互模式：顶层局部变量编译为chunk的上值
+}
+
+// 二元运算符优先级 (左, 右)
+fn binop_priority(t: &Token) -> Option<(u8, u8)> {
+    let p = match t {
+        Token::Or => (1, 1),
+        Token::And => (2, 2),
+        Token::Lt | Token::Gt | Token::Le | Token::Ge | Token::Ne | Token::Eq => (3, 3),
+        Token::BitOr => (4, 4),
+        Token::BitXor => (5, 5),
+        Token::BitAnd => (6, 6),
+        Token::ShiftL | Token::ShiftR => (7, 7),
+        Token::Concat => (9, 8),  // 右结合
+        Token::Add | Token::Sub => (10, 10),
+        Token::Mul | Token::Div | Token::Idiv | Token::Mod => (11, 11),
+        Token::Pow => (14, 13),  // 右结合
+        _ => return None,
+    };
+    Some(p)
+}
+
+const UNARY_PRIORITY: u8 = 12;
+const FIELDS_PER_FLUSH: usize = 50;
+
+impl Parser {
+    fn fs(&mut self) -> &mut FuncState {
+        self.funcs.last_mut().unwrap()
+    }
+
+    fn error<T>(&self, msg: &str) -> Result<T, LuaError> {
+        Err(self.lex.error(msg))
+    }
+
+    fn error_near<T>(&mut self, msg: &str) -> Result<T, LuaError> {
+        let t = self.lex.peek()?.clone();
+        self.error(&format!("{} near {}", msg, token_text(&t)))
+    }
+
+    fn check(&mut self, t: Token, what: &str) -> Result<(), LuaError> {
+        self.lex.expect(t, what)
+    }
+
+    fn test_next(&mut self, t: &Token) -> Result<bool, LuaError> {
+        if self.lex.peek()? == t {
+            self.lex.next()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // 期望与第line行的`what`匹配的`t`
+    fn check_match(&mut self, t: Token, what: &str, who: &str, line: usize) -> Result<(), LuaError> {
+        if self.lex.peek()? == &t {
+            self.lex.next()?;
+            return Ok(());
+        }
+        if line == self.lex.line() {
+            self.error_near(&format!("'{}' expected", what))
+        } else {
+            self.error_near(&format!("'{}' expected (to close '{}' at line {})", what, who, line))
+        }
+    }
+
+    fn read_name(&mut self) -> Result<String, LuaError> {
+        match self.lex.next()? {
+            Token::Name(name) => Ok(name),
+            t => self.error(&format!("<name> expected near {}", token_text(&t))),
+        }
+    }
+
+    // ---------- 代码生成 ----------
+
+    fn emit(&mut self, code: ByteCode) -> usize {
+        let line = self.lex.last_line() as u32;
+        let proto = &mut self.fs().proto;
+        proto.instructions.push(code);
+        proto.lineinfo.push(line);
+        proto.instructions.len() - 1
+    }
+
+    fn pc(&mut self) -> usize {
+        self.fs().proto.instructions.len()
+    }
+
+    fn add_const(&mut self, v: Value) -> Result<usize, LuaError> {
+        let i = self.fs().proto.add_const(v);
+        if i > u16::MAX as usize {
+            return self.error("too many constants");
+        }
+        Ok(i)
+    }
+
+    fn str_const(&mut self, s: &[u8]) -> Result<usize, LuaError> {
+        self.add_const(Value::from(s))
+    }
+
+    fn reserve_regs(&mut self, n: usize) -> Result<(), LuaError> {
+        let fs = self.fs();
+        fs.free_reg += n;
+        if fs.free_reg > 255 {
+            return self.error("function or expression needs too many registers");
+        }
+        if fs.free_reg > fs.proto.max_stack {
+            fs.proto.max_stack = fs.free_reg;
+        }
+        Ok(())
+    }
+
+    fn nactvar(&mut self) -> usize {
+        self.fs().nactvar
+    }
+
+    // 释放位于栈顶的临时寄存器
+    // 释放表达式占用的临时寄存器（索引表达式的表和键也可能在临时寄存器中）
+    fn free_exp(&mut self, desc: &ExpDesc) {
+        match *desc {
+            ExpDesc::Reg(r) | ExpDesc::IndexField(r, _) | ExpDesc::IndexInt(r, _) => self.free_reg(r),
+            ExpDesc::Index(t, k) => {
+                self.free_reg(t.max(k));
+                self.free_reg(t.min(k));
+            }
+            ExpDesc::Call(pc) => {
+                if let ByteCode::Call(f, _, _) = self.fs().proto.instructions[pc] {
+                    self.free_reg(f as usize);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn free_reg(&mut self, r: usize) {
+        let nactvar = self.nactvar();
+        let fs = self.fs();
+        if r >= nactvar && r + 1 == fs.free_reg {
+            fs.free_reg -= 1;
+        }
+    }
+
+    // 跳转指令的偏移量相对于下一条指令
+    fn patch_jump(&mut self, pc: usize, target: usize) {
+        let offset = target as i32 - pc as i32 - 1;
+        let code = &mut self.fs().proto.instructions[pc];
+        *code = match *code {
+            ByteCode::Jump(_) => ByteCode::Jump(offset),
+            ByteCode::TestAndJump(a, _) => ByteCode::TestAndJump(a, offset),
+            ByteCode::TestOrJump(a, _) => ByteCode::TestOrJump(a, offset),
+            ByteCode::ForPrepare(a, _) => ByteCode::ForPrepare(a, offset),
+            ByteCode::ForLoop(a, _) => ByteCode::ForLoop(a, offset),
+            ByteCode::ForGenLoop(a, _) => ByteCode::ForGenLoop(a, offset),
+            c => panic!("patch non-jump instruction {:?}", c),
+        };
+    }
+
+    fn patch_here(&mut self, pc: usize) {
+        let here = self.pc();
+        self.patch_jump(pc, here);
+    }
+
+    // 调用和可变参数表达式改为返回多个结果
+    fn set_multret(&mut self, desc: &ExpDesc, nret: u8) {
+        let fs = self.fs();
+        match *desc {
+            ExpDesc::Call(pc) => {
+                if let ByteCode::Call(f, narg, _) = fs.proto.instructions[pc] {
+                    fs.proto.instructions[pc] = ByteCode::Call(f, narg, nret);
+                }
+            }
+            ExpDesc::VarArgs(pc) => {
+                if let ByteCode::VarArgs(a, _) = fs.proto.instructions[pc] {
+                    fs.proto.instructions[pc] = ByteCode::VarArgs(a, nret);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn is_multret(desc: &ExpDesc) -> bool {
+        matches!(desc, ExpDesc::Call(_) | ExpDesc::VarArgs(_))
+    }
+
+    // 把表达式的值放到寄存器dst
+    fn discharge(&mut self, dst: usize, desc: ExpDesc) -> Result<(), LuaError> {
+        let d = dst as u8;
+        let code = match desc {
+            ExpDesc::Nil => ByteCode::LoadNil(d, 1),
+            ExpDesc::True => ByteCode::LoadBool(d, true),
+            ExpDesc::False => ByteCode::LoadBool(d, false),
+            ExpDesc::Integer(i) => {
                 if let Ok(ii) = i16::try_from(i) {
-                    ByteCode::LoadInt(dst as u8, ii)
+                    ByteCode::LoadInt(d, ii)
                 } else {
-                    let constant_index = self.constants.len();
-                    self.constants.push(Value::Integer(i));
-                    ByteCode::LoadConstant(dst as u8, constant_index as u8)
+                    ByteCode::LoadConstant(d, self.add_const(Value::Integer(i))? as u16)
                 }
+            }
+            ExpDesc::Float(f) => ByteCode::LoadConstant(d, self.add_const(Value::Float(f))? as u16),
+            ExpDesc::String(s) => ByteCode::LoadConstant(d, self.str_const(&s)? as u16),
+            ExpDesc::Local(r) | ExpDesc::Reg(r) => {
+                if r == dst {
+                    return Ok(());
+                }
+                ByteCode::Move(d, r as u8)
+            }
+            ExpDesc::Upvalue(i) => ByteCode::GetUpvalue(d, i as u8),
+            ExpDesc::Global(e, k) => ByteCode::GetGlobal(d, e as u8, k as u16),
+            ExpDesc::Index(t, k) => ByteCode::GetTable(d, t as u8, k as u8),
+            ExpDesc::IndexField(t, k) => ByteCode::GetField(d, t as u8, k as u16),
+            ExpDesc::IndexInt(t, i) => ByteCode::GetInt(d, t as u8, i),
+            ExpDesc::Call(pc) => {
+                let f = match self.fs().proto.instructions[pc] {
+                    ByteCode::Call(f, _, _) => f as usize,
+                    _ => unreachable!(),
+                };
+                return self.discharge(dst, ExpDesc::Reg(f));
+            }
+            ExpDesc::VarArgs(pc) => {
+                let fs = self.fs();
+                if let ByteCode::VarArgs(a, n) = fs.proto.instructions[pc] {
+                    if a as usize == dst {
+                        return Ok(());
+                    }
+                    // 直接改写目标位置
+                    fs.proto.instructions[pc] = ByteCode::VarArgs(d, n);
+                }
+                return Ok(());
+            }
+        };
+        self.emit(code);
+        Ok(())
+    }
+
+    // 把表达式放到下一个空闲寄存器
+    fn exp2nextreg(&mut self, desc: ExpDesc) -> Result<usize, LuaError> {
+        self.free_exp(&desc);
+        let dst = self.fs().free_reg;
+        self.reserve_regs(1)?;
+        self.discharge(dst, desc)?;
+        Ok(dst)
+    }
+
+    // 把表达式放到任意寄存器（局部变量直接使用其寄存器）
+    fn exp2anyreg(&mut self, desc: ExpDesc) -> Result<usize, LuaError> {
+        match desc {
+            ExpDesc::Local(r) | ExpDesc::Reg(r) => Ok(r),
+            ExpDesc::Call(pc) => match self.fs().proto.instructions[pc] {
+                ByteCode::Call(f, _, _) => Ok(f as usize),
+                _ => unreachable!(),
             },
-            Token::Float(f) => {
-                let constant_index = self.constants.len();
-                self.constants.push(Value::Float(f));
-                ByteCode::LoadConstant(dst as u8, constant_index as u8)
-            },
-            Token::True => {
-                ByteCode::LoadBool(dst as u8, true)
-            },
-            Token::False => {
-                ByteCode::LoadBool(dst as u8, false)
-            },
-            Token::Nil => {
-                ByteCode::LoadNil(dst as u8)
-            },
-            Token::Name(name) => self.load_var(name, dst),
-            _ => panic!("Unexpected token: {:?}", token),
-            
+            _ => self.exp2nextreg(desc),
+        }
+    }
+
+    // ---------- 变量 ----------
+
+    fn new_local(&mut self, name: String, is_const: bool) {
+        let fs = self.fs();
+        fs.proto.locals_info.push(LocalInfo { name: name.clone(), start_pc: usize::MAX, end_pc: 0 });
+        let info = fs.proto.locals_info.len() - 1;
+        fs.locals.push(LocalVar { name, is_const, info });
+    }
+
+    // 新声明的最后n个局部变量从当前位置开始生效
+    fn activate_locals(&mut self, n: usize) {
+        let pc = self.pc();
+        let fs = self.fs();
+        let len = fs.locals.len();
+        for var in &fs.locals[len - n..] {
+            fs.proto.locals_info[var.info].start_pc = pc;
+        }
+        fs.nactvar = len;
+    }
+
+    fn remove_locals(&mut self, nactvar: usize) {
+        let pc = self.pc();
+        let fs = self.fs();
+        while fs.locals.len() > nactvar {
+            let var = fs.locals.pop().unwrap();
+            fs.proto.locals_info[var.info].end_pc = pc;
+        }
+        fs.nactvar = nactvar;
+        fs.free_reg = nactvar;
+    }
+
+    // 在第level个函数中查找局部变量
+    fn find_local(&self, level: usize, name: &str) -> Option<usize> {
+        let fs = &self.funcs[level];
+        fs.locals.iter().rposition(|v| v.name == name && fs.proto.locals_info[v.info].start_pc != usize::MAX)
+    }
+
+    fn find_upvalue(&mut self, level: usize, name: &str) -> Option<usize> {
+        let upvalues = &self.funcs[level].proto.upvalues;
+        // 交互模式下同名上值可能被后声明的遮蔽，取最后一个
+        if let Some(i) = upvalues.iter().rposition(|u| u.name == name) {
+            return Some(i);
+        }
+        if level == 0 {
+            return None;
+        }
+
+        let desc = if let Some(reg) = self.find_local(level - 1, name) {
+            // 外层函数的局部变量被捕获，所在块离开时需要Close
+            let outer = &mut self.funcs[level - 1];
+            if let Some(block) = outer.blocks.iter_mut().rev().find(|b| b.nactvar <= reg) {
+                block.has_upval = true;
+            }
+            UpvalueDesc { name: name.to_string(), in_stack: true, index: reg as u8 }
+        } else {
+            let index = self.find_upvalue(level - 1, name)?;
+            UpvalueDesc { name: name.to_string(), in_stack: false, index: index as u8 }
         };
 
-        self.instructions.push(byte_code);
+        let upvalues = &mut self.funcs[level].proto.upvalues;
+        upvalues.push(desc);
+        Some(upvalues.len() - 1)
     }
 
-    // 加载变量： local a = b
-    fn load_var(&mut self, name: String, dst: usize) -> ByteCode {
-        if let Some(i) = self.locals.iter().rposition(|x| *x==name) {
-            ByteCode::Move(dst as u8, i as u8)
+    fn single_var(&mut self, name: String) -> Result<ExpDesc, LuaError> {
+        let level = self.funcs.len() - 1;
+        if let Some(reg) = self.find_local(level, &name) {
+            Ok(ExpDesc::Local(reg))
+        } else if let Some(i) = self.find_upvalue(level, &name) {
+            Ok(ExpDesc::Upvalue(i))
         } else {
-            let i = self.add_const(Value::String(name));
-            ByteCode::GetGlobal(dst as u8, i as u8)
+            // 全局变量即_ENV.name，主函数的第一个上值总是_ENV
+            let k = self.str_const(name.as_bytes())?;
+            if let Some(reg) = self.find_local(level, ENV) {
+                Ok(ExpDesc::IndexField(reg, k))
+            } else {
+                let env = self.find_upvalue(level, ENV).expect("_ENV not found");
+                Ok(ExpDesc::Global(env, k))
+            }
         }
-        
+    }
 
+    fn check_readonly(&mut self, desc: &ExpDesc) -> Result<(), LuaError> {
+        let name = match *desc {
+            ExpDesc::Local(reg) => {
+                let fs = self.fs();
+                let var = &fs.locals[reg];
+                if !var.is_const {
+                    return Ok(());
+                }
+                var.name.clone()
+            }
+            ExpDesc::Upvalue(i) => {
+                // 沿上值链找到原始的局部变量
+                let mut level = self.funcs.len() - 1;
+                let mut index = i;
+                loop {
+                    let up = self.funcs[level].proto.upvalues[index].clone();
+                    if level == 0 {
+                        return Ok(());  // 交互模式中保存的局部变量
+                    }
+                    level -= 1;
+                    if up.in_stack {
+                        let var = &self.funcs[level].locals[up.index as usize];
+                        if !var.is_const {
+                            return Ok(());
+                        }
+                        break var.name.clone();
+                    }
+                    index = up.index as usize;
+                }
+            }
+            _ => return Ok(()),
+        };
+        self.error(&format!("attempt to assign to const variable '{}'", name))
     }
 
-    fn function_call(&mut self, name: String) {
-        let constant_idx = self.add_const(Value::String(name));
-        let func_idx = self.locals.len();
-        self.instructions.push(ByteCode::GetGlobal(func_idx as u8, constant_idx as u8));
+    // ---------- 块和语句 ----------
 
-        let next_token = self.lex.next();
-        match next_token {
-            Token::String(s) => {
-                let i = self.add_const(Value::String(s));
-                self.instructions.push(ByteCode::LoadConstant((func_idx+1) as u8, i as u8));
-            },
-            Token::Lp => {  // '('
-                self.load_exp(func_idx+1);
+    fn enter_block(&mut self, is_loop: bool) {
+        let fs = self.fs();
+        let block = Block {
+            nactvar: fs.nactvar,
+            is_loop,
+            has_upval: false,
+            inside_tbc: fs.blocks.last().map_or(fs.inside_tbc, |b| b.inside_tbc),
+            breaks: Vec::new(),
+            first_label: fs.labels.len(),
+            first_goto: fs.gotos.len(),
+        };
+        fs.blocks.push(block);
+    }
+
+    // 登记待关闭变量，离开作用域时调用其`__close`元方法
+    fn mark_tbc(&mut self, reg: usize) {
+        self.emit(ByteCode::Tbc(reg as u8));
+        let fs = self.fs();
+        match fs.blocks.last_mut() {
+            Some(block) => {
+                block.has_upval = true;
+                block.inside_tbc = true;
+            }
+            None => fs.inside_tbc = true,
+        }
+    }
+
+    fn inside_tbc(&mut self) -> bool {
+        let fs = self.fs();
+        fs.blocks.last().map_or(fs.inside_tbc, |b| b.inside_tbc)
+    }
 
-                if let Token::Rp = self.lex.next() {
+    fn leave_block(&mut self) -> Result<(), LuaError> {
+        let block = self.fs().blocks.pop().unwrap();
+        self.remove_locals(block.nactvar);
+        if block.has_upval {
+            self.emit(ByteCode::Close(block.nactvar as u8));
+        }
+
+        // 本块中的标签失效，未解决的goto移交外层块
+        let fs = self.fs();
+        fs.labels.truncate(block.first_label);
+        for g in &mut fs.gotos[block.first_goto..] {
+            if g.nactvar > block.nactvar {
+                g.nactvar = block.nactvar;
+            }
+        }
+
+        for pc in block.breaks {
+            self.patch_here(pc);
+        }
+        Ok(())
+    }
 
+    fn block_follow(&mut self, with_until: bool) -> Result<bool, LuaError> {
+        Ok(match self.lex.peek()? {
+            Token::Else | Token::ElseIf | Token::End | Token::Eos => true,
+            Token::Until => with_until,
+            _ => false,
+        })
+    }
+
+    // 语句序列，不新建块
+    fn block(&mut self) -> Result<(), LuaError> {
+        loop {
+            if self.block_follow(true)? {
+                return Ok(());
+            }
+            if self.lex.peek()? == &Token::Return {
+                self.lex.next()?;
+                return self.ret_stat();
+            }
+            self.statement()?;
+        }
+    }
+
+    fn scoped_block(&mut self, is_loop: bool) -> Result<(), LuaError> {
+        self.enter_block(is_loop);
+        self.block()?;
+        self.leave_block()
+    }
+
+    fn statement(&mut self) -> Result<(), LuaError> {
+        let line = self.lex.line();
+        let token = self.lex.peek()?.clone();
+        match token {
+            Token::Semicolon => {
+                self.lex.next()?;
+            }
+            Token::If => self.if_stat(line)?,
+            Token::While => self.while_stat(line)?,
+            Token::Do => {
+                self.lex.next()?;
+                self.scoped_block(false)?;
+                self.check_match(Token::End, "end", "do", line)?;
+            }
+            Token::For => self.for_stat(line)?,
+            Token::Repeat => self.repeat_stat(line)?,
+            Token::Function => self.function_stat(line)?,
+            Token::Local => {
+                self.lex.next()?;
+                if self.test_next(&Token::Function)? {
+                    self.local_function()?;
                 } else {
-                    panic!("expect ')'");
+                    self.local_stat()?;
                 }
-            },
+            }
+            Token::DoubColon => {
+                self.lex.next()?;
+                let name = self.read_name()?;
+                self.check(Token::DoubColon, "::")?;
+                self.label_stat(name)?;
+            }
+            Token::Break => {
+                self.lex.next()?;
+                self.break_stat()?;
+            }
+            Token::Goto => {
+                self.lex.next()?;
+                let name = self.read_name()?;
+                self.goto_stat(name, line)?;
+            }
+            _ => self.exp_stat()?,
+        }
+
+        // 语句结束后释放所有临时寄存器
+        let nactvar = self.nactvar();
+        self.fs().free_reg = nactvar;
+        Ok(())
+    }
+
+    fn test_then_block(&mut self) -> Result<usize, LuaError> {
+        self.lex.next()?;  // 'if' 或 'elseif'
+        let cond = self.exp()?;
+        self.check(Token::Then, "then")?;
+        let reg = self.exp2anyreg(cond)?;
+        let jmp = self.emit(ByteCode::TestAndJump(reg as u8, 0));
+        let nactvar = self.nactvar();
+        self.fs().free_reg = nactvar;
+        self.scoped_block(false)?;
+        Ok(jmp)
+    }
+
+    fn if_stat(&mut self, line: usize) -> Result<(), LuaError> {
+        let mut exits = Vec::new();
+        let mut jmp = self.test_then_block()?;
+        while self.lex.peek()? == &Token::ElseIf {
+            exits.push(self.emit(ByteCode::Jump(0)));
+            self.patch_here(jmp);
+            jmp = self.test_then_block()?;
+        }
+        if self.test_next(&Token::Else)? {
+            exits.push(self.emit(ByteCode::Jump(0)));
+            self.patch_here(jmp);
+            self.scoped_block(false)?;
+        } else {
+            self.patch_here(jmp);
+        }
+        self.check_match(Token::End, "end", "if", line)?;
+        for pc in exits {
+            self.patch_here(pc);
+        }
+        Ok(())
+    }
+
+    fn while_stat(&mut self, line: usize) -> Result<(), LuaError> {
+        self.lex.next()?;
+        let start = self.pc();
+        let cond = self.exp()?;
+        self.check(Token::Do, "do")?;
+        let reg = self.exp2anyreg(cond)?;
+        let exit = self.emit(ByteCode::TestAndJump(reg as u8, 0));
+        let nactvar = self.nactvar();
+        self.fs().free_reg = nactvar;
+
+        self.enter_block(true);
+        self.scoped_block(false)?;
+        self.check_match(Token::End, "end", "while", line)?;
+        let back = self.emit(ByteCode::Jump(0));
+        self.patch_jump(back, start);
+        self.patch_here(exit);
+        self.leave_block()
+    }
+
+    fn repeat_stat(&mut self, line: usize) -> Result<(), LuaError> {
+        self.lex.next()?;
+        let start = self.pc();
+        self.enter_block(true);  // 循环块
+        self.enter_block(false);  // 循环体，until条件可以访问其中的局部变量
+        self.block()?;
+        self.check_match(Token::Until, "until", "repeat", line)?;
+        let cond = self.exp()?;
+        let reg = self.exp2anyreg(cond)?;
+
+        let has_upval = self.fs().blocks.last().unwrap().has_upval;
+        if has_upval {
+            // 先关闭上值再判断是否继续
+            let exit = self.emit(ByteCode::TestOrJump(reg as u8, 0));
+            let nactvar = self.fs().blocks.last().unwrap().nactvar;
+            self.emit(ByteCode::Close(nactvar as u8));
+            let back = self.emit(ByteCode::Jump(0));
+            self.patch_jump(back, start);
+            self.patch_here(exit);
+        } else {
+            let back = self.emit(ByteCode::TestAndJump(reg as u8, 0));
+            self.patch_jump(back, start);
+        }
+        self.leave_block()?;
+        self.leave_block()
+    }
+
+    fn for_stat(&mut self, line: usize) -> Result<(), LuaError> {
+        self.lex.next()?;
+        let name = self.read_name()?;
+        match self.lex.peek()? {
+            Token::Assign => self.for_num(name, line),
+            Token::Comma | Token::In => self.for_list(name, line),
+            _ => self.error_near("'=' or 'in' expected"),
+        }
+    }
+
+    fn for_num(&mut self, name: String, line: usize) -> Result<(), LuaError> {
+        self.lex.next()?;  // '='
+        self.enter_block(true);
+        let base = self.fs().free_reg;
+
+        let init = self.exp()?;
+        self.exp2nextreg(init)?;
+        self.check(Token::Comma, ",")?;
+        let limit = self.exp()?;
+        self.exp2nextreg(limit)?;
+        if self.test_next(&Token::Comma)? {
+            let step = self.exp()?;
+            self.exp2nextreg(step)?;
+        } else {
+            let dst = self.fs().free_reg;
+            self.reserve_regs(1)?;
+            self.emit(ByteCode::LoadInt(dst as u8, 1));
+        }
+
+        // 3个内部变量，名字不会与用户变量冲突
+        for internal in ["(for state)", "(for state)", "(for state)"] {
+            self.new_local(internal.to_string(), false);
+        }
+        self.activate_locals(3);
+        self.check(Token::Do, "do")?;
+
+        let prep = self.emit(ByteCode::ForPrepare(base as u8, 0));
+        self.enter_block(false);
+        self.new_local(name, false);
+        self.reserve_regs(1)?;
+        self.activate_locals(1);
+        self.block()?;
+        self.leave_block()?;
+        self.check_match(Token::End, "end", "for", line)?;
+
+        let lp = self.emit(ByteCode::ForLoop(base as u8, 0));
+        self.patch_jump(lp, prep + 1);
+        self.patch_here(prep);
+        self.leave_block()
+    }
+
+    fn for_list(&mut self, first: String, line: usize) -> Result<(), LuaError> {
+        self.enter_block(true);
+        let base = self.fs().free_reg;
+
+        let mut names = vec![first];
+        while self.test_next(&Token::Comma)? {
+            names.push(self.read_name()?);
+        }
+        self.check(Token::In, "in")?;
+
+        // 迭代函数、状态、控制变量、关闭值
+        self.explist_adjust(4)?;
+        for _ in 0..4 {
+            self.new_local("(for state)".to_string(), false);
+        }
+        self.activate_locals(4);
+        self.mark_tbc(base + 3);
+        self.check(Token::Do, "do")?;
+
+        let prep = self.emit(ByteCode::Jump(0));
+        let body = self.pc();
+        self.enter_block(false);
+        let nvars = names.len();
+        for name in names {
+            self.new_local(name, false);
+        }
+        self.reserve_regs(nvars.max(3))?;
+        self.fs().free_reg = base + 4 + nvars;
+        self.activate_locals(nvars);
+        self.block()?;
+        self.leave_block()?;
+        self.check_match(Token::End, "end", "for", line)?;
+
+        self.patch_here(prep);
+        self.emit(ByteCode::ForCall(base as u8, nvars as u8));
+        let lp = self.emit(ByteCode::ForGenLoop(base as u8, 0));
+        self.patch_jump(lp, body);
+        self.leave_block()
+    }
 
-            _ => panic!("expect constant")
+    fn break_stat(&mut self) -> Result<(), LuaError> {
+        let fs = self.fs();
+        let Some(i) = fs.blocks.iter().rposition(|b| b.is_loop) else {
+            return self.error("break outside a loop");
+        };
+        // 跳出的块中若有被捕获的局部变量，先关闭
+        let nactvar = fs.blocks[i].nactvar;
+        if fs.blocks[i..].iter().any(|b| b.has_upval) {
+            self.emit(ByteCode::Close(nactvar as u8));
+        }
+        let pc = self.emit(ByteCode::Jump(0));
+        self.fs().blocks[i].breaks.push(pc);
+        Ok(())
+    }
+
+    fn goto_stat(&mut self, name: String, line: usize) -> Result<(), LuaError> {
+        let nactvar = self.nactvar();
+        let fs = self.fs();
+        if let Some(label) = fs.labels.iter().rev().find(|l| l.name == name) {
+            // 向后跳转到可见标签
+            let (target, label_nactvar) = (label.pc, label.nactvar);
+            if nactvar > label_nactvar {
+                self.emit(ByteCode::Close(label_nactvar as u8));
+            }
+            let pc = self.emit(ByteCode::Jump(0));
+            self.patch_jump(pc, target);
+        } else {
+            let pc = self.emit(ByteCode::Jump(0));
+            self.fs().gotos.push(PendingGoto { name, pc, line, nactvar });
+        }
+        Ok(())
+    }
+
+    fn label_stat(&mut self, name: String) -> Result<(), LuaError> {
+        let fs = self.fs();
+        let first_label = fs.blocks.last().map_or(0, |b| b.first_label);
+        if fs.labels[first_label..].iter().any(|l| l.name == name) {
+            return self.error(&format!("label '{}' already defined", name));
+        }
+
+        // 跳过紧随其后的空语句，块末尾的标签视为在局部变量作用域之外
+        while self.lex.peek()? == &Token::Semicolon {
+            self.lex.next()?;
+        }
+        let at_end = self.block_follow(false)?;
+
+        let pc = self.pc();
+        let fs = self.fs();
+        let mut nactvar = fs.nactvar;
+        if at_end {
+            if let Some(b) = fs.blocks.last() {
+                nactvar = b.nactvar;
+            }
+        }
+        let first_goto = fs.blocks.last().map_or(0, |b| b.first_goto);
+
+        // 解决本块中挂起的同名goto
+        let mut i = first_goto;
+        while i < self.fs().gotos.len() {
+            if self.fs().gotos[i].name == name {
+                let g = self.fs().gotos.remove(i);
+                if g.nactvar < nactvar {
+                    let var = self.fs().locals[g.nactvar].name.clone();
+                    return self.error(&format!("<goto {}> at line {} jumps into the scope of local '{}'", g.name, g.line, var));
+                }
+                self.patch_jump(g.pc, pc);
+                continue;
+            }
+            i += 1;
+        }
+        self.fs().labels.push(Label { name, pc, nactvar });
+        Ok(())
+    }
+
+    fn function_stat(&mut self, line: usize) -> Result<(), LuaError> {
+        self.lex.next()?;  // 'function'
+
+        // funcname: Name {'.' Name} [':' Name]
+        let name = self.read_name()?;
+        let mut var = self.single_var(name)?;
+        let mut is_method = false;
+        loop {
+            match self.lex.peek()? {
+                Token::Dot => {
+                    self.lex.next()?;
+                    let key = self.read_name()?;
+                    var = self.index_field(var, key.as_bytes())?;
+                }
+                Token::Colon => {
+                    self.lex.next()?;
+                    let key = self.read_name()?;
+                    var = self.index_field(var, key.as_bytes())?;
+                    is_method = true;
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        let f = self.body(is_method, line)?;
+        self.store_var(var, f)
+    }
+
+    fn local_function(&mut self) -> Result<(), LuaError> {
+        let line = self.lex.line();
+        let name = self.read_name()?;
+        if self.repl_toplevel() {
+            let up = self.new_repl_upvalue(name)?;
+            let f = self.body(false, line)?;
+            let reg = self.exp2anyreg(f)?;
+            self.emit(ByteCode::SetUpvalue(up as u8, reg as u8));
+            self.fs().free_reg = self.fs().nactvar;
+            return Ok(());
         }
+        // 先声明变量，以便函数体内递归引用
+        self.new_local(name, false);
+        let reg = self.fs().free_reg;
+        self.reserve_regs(1)?;
+        self.activate_locals(1);
+        let f = self.body(false, line)?;
+        self.discharge(reg, f)
+    }
 
-        self.instructions.push(ByteCode::Call(func_idx as u8, 1));
+    fn local_stat(&mut self) -> Result<(), LuaError> {
+        let mut nvars = 0;
+        let mut has_close = false;
+        let mut close_var = 0;
+        loop {
+            let name = self.read_name()?;
+            let mut is_const = false;
+            if self.test_next(&Token::Lt)? {
+                match self.read_name()?.as_str() {
+                    "const" => is_const = true,
+                    "close" => {
+                        if has_close {
+                            return self.error("multiple to-be-closed variables in local list");
+                        }
+                        has_close = true;
+                        close_var = nvars;
+                        is_const = true;
+                    }
+                    attr => return self.error(&format!("unknown attribute '{}'", attr)),
+                }
+                self.check(Token::Gt, ">")?;
+            }
+            self.new_local(name, is_const);
+            nvars += 1;
+            if !self.test_next(&Token::Comma)? {
+                break;
+            }
+        }
+
+        if self.test_next(&Token::Assign)? {
+            self.explist_adjust(nvars)?;
+        } else {
+            let reg = self.fs().free_reg;
+            self.reserve_regs(nvars)?;
+            self.emit(ByteCode::LoadNil(reg as u8, nvars as u8));
+        }
+        let close_reg = self.fs().free_reg - nvars + close_var;
+        if self.repl_toplevel() {
+            // 交互模式下在本行代码结束时关闭
+            if has_close {
+                self.mark_tbc(close_reg);
+            }
+            return self.store_repl_locals(nvars);
+        }
+        self.activate_locals(nvars);
+        if has_close {
+            self.mark_tbc(close_reg);
+        }
+        Ok(())
     }
 
-    fn assignment(&mut self, var_name: String) {
-        self.lex.next();  // '='
+    fn repl_toplevel(&mut self) -> bool {
+        self.repl && self.funcs.len() == 1 && self.fs().blocks.is_empty()
+    }
+
+    fn new_repl_upvalue(&mut self, name: String) -> Result<usize, LuaError> {
+        let upvalues = &mut self.fs().proto.upvalues;
+        if upvalues.len() > u8::MAX as usize {
+            return self.error("too many local variables");
+        }
+        upvalues.push(UpvalueDesc { name, in_stack: false, index: upvalues.len() as u8 });
+        Ok(upvalues.len() - 1)
+    }
+
+    // 交互模式：把刚声明的nvars个局部变量（值已在寄存器中）转存为上值
+    fn store_repl_locals(&mut self, nvars: usize) -> Result<(), LuaError> {
+        let base = self.fs().free_reg - nvars;
+        let start = self.fs().locals.len() - nvars;
+        let vars: Vec<LocalVar> = self.fs().locals.drain(start..).collect();
+        for (i, var) in vars.into_iter().enumerate() {
+            self.fs().proto.locals_info.remove(var.info - i);
+            let up = self.new_repl_upvalue(var.name)?;
+            self.emit(ByteCode::SetUpvalue(up as u8, (base + i) as u8));
+        }
+        self.fs().free_reg = base;
+        Ok(())
+    }
 
-        if let Some(i) = self.locals.iter().rposition(|x| *x == var_name) {
-            // 局部变量赋值
-            self.load_exp(i);
+    // 表达式列表，调整为want个值并依次放入从free_reg开始的寄存器
+    fn explist_adjust(&mut self, want: usize) -> Result<(), LuaError> {
+        let base = self.fs().free_reg;
+        let mut n = 0;
+        let mut last = self.exp()?;
+        while self.test_next(&Token::Comma)? {
+            if n < want {
+                self.exp2nextreg(last)?;
+            } else {
+                // 多余的表达式求值后丢弃
+                self.exp2nextreg(last)?;
+                self.fs().free_reg -= 1;
+            }
+            n += 1;
+            last = self.exp()?;
+        }
+        n += 1;
+
+        if Self::is_multret(&last) && n <= want {
+            let extra = want + 1 - n;
+            self.set_multret(&last, extra as u8 + 1);
+            if let ExpDesc::VarArgs(pc) = last {
+                let dst = self.fs().free_reg;
+                if let ByteCode::VarArgs(_, k) = self.fs().proto.instructions[pc] {
+                    self.fs().proto.instructions[pc] = ByteCode::VarArgs(dst as u8, k);
+                }
+            }
+        } else if n <= want {
+            self.exp2nextreg(last)?;
+            if n < want {
+                let reg = self.fs().free_reg;
+                self.emit(ByteCode::LoadNil(reg as u8, (want - n) as u8));
+            }
         } else {
-            // 添加全局变量
-            let dst = self.add_const(Value::String(var_name)) as u8;
-
-            let next_token = self.lex.next();
-            let code = match next_token {
-                // 常数
-                Token::String(s) => ByteCode::SetGlobalConst(dst, self.add_const(Value::String(s)) as u8),
-                Token::Integer(i) => ByteCode::SetGlobalConst(dst, self.add_const(Value::Integer(i)) as u8),
-                Token::Float(f) => ByteCode::SetGlobalConst(dst, self.add_const(Value::Float(f)) as u8),
-                Token::True => ByteCode::SetGlobalConst(dst, self.add_const(Value::Bool(true)) as u8),
-                Token::False => ByteCode::SetGlobalConst(dst, self.add_const(Value::Bool(false)) as u8),
-                Token::Nil => ByteCode::SetGlobalConst(dst, self.add_const(Value::Nil) as u8),
-
-                // 变量
+            self.exp2nextreg(last)?;
+        }
+        self.fs().free_reg = base;
+        self.reserve_regs(want)?;
+        Ok(())
+    }
+
+    // 表达式语句：函数调用或赋值
+    fn exp_stat(&mut self) -> Result<(), LuaError> {
+        let desc = self.suffixed_exp()?;
+        let next = self.lex.peek()?.clone();
+        if next == Token::Assign || next == Token::Comma {
+            let mut vars = vec![desc];
+            while self.test_next(&Token::Comma)? {
+                let var = self.suffixed_exp()?;
+                vars.push(var);
+            }
+            self.check(Token::Assign, "=")?;
+            self.assignment(vars)
+        } else if let ExpDesc::Call(pc) = desc {
+            // 语句调用不需要返回值
+            self.set_multret(&ExpDesc::Call(pc), 1);
+            Ok(())
+        } else {
+            self.error_near("syntax error")
+        }
+    }
+
+    fn assignment(&mut self, vars: Vec<ExpDesc>) -> Result<(), LuaError> {
+        for var in &vars {
+            match var {
+                ExpDesc::Local(_) | ExpDesc::Upvalue(_) | ExpDesc::Global(..)
+                | ExpDesc::Index(..) | ExpDesc::IndexField(..) | ExpDesc::IndexInt(..) => self.check_readonly(var)?,
+                _ => return self.error_near("syntax error"),
+            }
+        }
+
+        if vars.len() == 1 {
+            let exp = self.exp()?;
+            if self.lex.peek()? != &Token::Comma {
+                let var = vars.into_iter().next().unwrap();
+                return self.store_var(var, exp);
+            }
+            // 右侧有多个表达式
+            let base = self.fs().free_reg;
+            self.exp2nextreg(exp)?;
+            while self.test_next(&Token::Comma)? {
+                let e = self.exp()?;
+                self.exp2nextreg(e)?;
+            }
+            let var = vars.into_iter().next().unwrap();
+            return self.store_var(var, ExpDesc::Reg(base));
+        }
+
+        // 左侧变量若被后面的索引表达式用作表或键，先复制一份
+        let vars = self.check_conflict(vars)?;
+        let base = self.fs().free_reg;
+        self.explist_adjust(vars.len())?;
+        for (i, var) in vars.into_iter().enumerate().rev() {
+            self.store_var(var, ExpDesc::Reg(base + i))?;
+        }
+        Ok(())
+    }
+
+    fn check_conflict(&mut self, mut vars: Vec<ExpDesc>) -> Result<Vec<ExpDesc>, LuaError> {
+        for i in 0..vars.len() {
+            let ExpDesc::Local(reg) = vars[i] else { continue };
+            let conflict = vars[..i].iter().any(|v| match *v {
+                ExpDesc::Index(t, k) => t == reg || k == reg,
+                ExpDesc::IndexField(t, _) | ExpDesc::IndexInt(t, _) => t == reg,
+                _ => false,
+            });
+            if conflict {
+                let copy = self.fs().free_reg;
+                self.reserve_regs(1)?;
+                self.emit(ByteCode::Move(copy as u8, reg as u8));
+                for v in &mut vars[..i] {
+                    *v = match *v {
+                        ExpDesc::Index(t, k) => ExpDesc::Index(if t == reg { copy } else { t }, if k == reg { copy } else { k }),
+                        ExpDesc::IndexField(t, k) if t == reg => ExpDesc::IndexField(copy, k),
+                        ExpDesc::IndexInt(t, k) if t == reg => ExpDesc::IndexInt(copy, k),
+                        ref other => other.clone(),
+                    };
+                }
+            }
+        }
+        Ok(vars)
+    }
+
+    // 赋值：var = exp
+    fn store_var(&mut self, var: ExpDesc, exp: ExpDesc) -> Result<(), LuaError> {
+        match var {
+            ExpDesc::Local(reg) => {
+                self.free_exp(&exp);
+                self.discharge(reg, exp)
+            }
+            ExpDesc::Upvalue(i) => {
+                let reg = self.exp2anyreg(exp)?;
+                self.emit(ByteCode::SetUpvalue(i as u8, reg as u8));
+                Ok(())
+            }
+            ExpDesc::Global(env, name) => {
+                let (e, name) = (env as u8, name as u16);
+                let code = match exp {
+                    ExpDesc::String(ref s) => ByteCode::SetGlobalConst(e, name, self.str_const(s)? as u16),
+                    ExpDesc::Integer(i) => ByteCode::SetGlobalConst(e, name, self.add_const(Value::Integer(i))? as u16),
+                    ExpDesc::Float(f) => ByteCode::SetGlobalConst(e, name, self.add_const(Value::Float(f))? as u16),
+                    ExpDesc::True => ByteCode::SetGlobalConst(e, name, self.add_const(Value::Bool(true))? as u16),
+                    ExpDesc::False => ByteCode::SetGlobalConst(e, name, self.add_const(Value::Bool(false))? as u16),
+                    ExpDesc::Nil => ByteCode::SetGlobalConst(e, name, self.add_const(Value::Nil)? as u16),
+                    ExpDesc::Global(src_env, src) if src_env == env => ByteCode::SetGlobalGlobal(e, name, src as u16),
+                    _ => {
+                        let reg = self.exp2anyreg(exp)?;
+                        ByteCode::SetGlobal(e, name, reg as u8)
+                    }
+                };
+                self.emit(code);
+                Ok(())
+            }
+            ExpDesc::Index(t, k) => {
+                let reg = self.exp2anyreg(exp)?;
+                self.emit(ByteCode::SetTable(t as u8, k as u8, reg as u8));
+                Ok(())
+            }
+            ExpDesc::IndexField(t, k) => {
+                let reg = self.exp2anyreg(exp)?;
+                self.emit(ByteCode::SetField(t as u8, k as u16, reg as u8));
+                Ok(())
+            }
+            ExpDesc::IndexInt(t, i) => {
+                let reg = self.exp2anyreg(exp)?;
+                self.emit(ByteCode::SetInt(t as u8, i, reg as u8));
+                Ok(())
+            }
+            _ => self.error("syntax error"),
+        }
+    }
+
+    fn ret_stat(&mut self) -> Result<(), LuaError> {
+        let base = self.fs().free_reg;
+        let nret;
+        if self.block_follow(true)? || self.lex.peek()? == &Token::Semicolon {
+            nret = 1;
+        } else {
+            let mut n = 1;
+            let mut last = self.exp()?;
+            while self.test_next(&Token::Comma)? {
+                self.exp2nextreg(last)?;
+                last = self.exp()?;
+                n += 1;
+            }
+            if let (1, ExpDesc::Call(pc), false) = (n, &last, self.inside_tbc()) {
+                // 尾调用，待关闭变量的作用域内不能使用
+                if let ByteCode::Call(f, narg, _) = self.fs().proto.instructions[*pc] {
+                    self.fs().proto.instructions[*pc] = ByteCode::TailCall(f, narg);
+                    // 被调用的是Rust函数时不复用帧，由这条指令返回其结果
+                    self.emit(ByteCode::Return(f, 0));
+                    self.test_next(&Token::Semicolon)?;
+                    return self.check_block_end();
+                }
+            }
+            if Self::is_multret(&last) {
+                self.set_multret(&last, 0);
+                if let ExpDesc::VarArgs(pc) = last {
+                    let dst = self.fs().free_reg;
+                    if let ByteCode::VarArgs(_, k) = self.fs().proto.instructions[pc] {
+                        self.fs().proto.instructions[pc] = ByteCode::VarArgs(dst as u8, k);
+                    }
+                }
+                nret = 0;
+            } else if n == 1 {
+                // 单个局部变量直接返回，不需要复制
+                let reg = self.exp2anyreg(last)?;
+                self.emit(ByteCode::Return(reg as u8, 2));
+                self.test_next(&Token::Semicolon)?;
+                return self.check_block_end();
+            } else {
+                self.exp2nextreg(last)?;
+                nret = n + 1;
+            }
+        }
+        self.emit(ByteCode::Return(base as u8, nret as u8));
+        self.test_next(&Token::Semicolon)?;
+        self.check_block_end()
+    }
+
+    fn check_block_end(&mut self) -> Result<(), LuaError> {
+        if self.block_follow(true)? {
+            Ok(())
+        } else {
+            self.error_near("'<eof>' expected")
+        }
+    }
+
+    // ---------- 函数 ----------
+
+    fn body(&mut self, is_method: bool, line: usize) -> Result<ExpDesc, LuaError> {
+        let source = self.fs().proto.source.clone();
+        self.funcs.push(FuncState::new(ParseProto::empty(source, line)));
+
+        if is_method {
+            self.new_local("self".to_string(), false);
+        }
+        self.check(Token::Lp, "(")?;
+        if self.lex.peek()? != &Token::Rp {
+            loop {
+                match self.lex.next()? {
+                    Token::Name(name) => self.new_local(name, false),
+                    Token::Vararg => {
+                        self.fs().proto.is_vararg = true;
+                        break;
+                    }
+                    t => return self.error(&format!("<name> expected near {}", token_text(&t))),
+                }
+                if !self.test_next(&Token::Comma)? {
+                    break;
+                }
+            }
+        }
+        let nparams = self.fs().locals.len();
+        self.fs().proto.num_params = nparams;
+        self.reserve_regs(nparams)?;
+        self.activate_locals(nparams);
+        self.check(Token::Rp, ")")?;
+
+        self.block()?;
+        self.fs().proto.last_line_defined = self.lex.line();
+        self.check_match(Token::End, "end", "function", line)?;
+
+        let proto = self.close_func()?;
+        let fs = self.fs();
+        fs.proto.protos.push(Rc::new(proto));
+        let index = fs.proto.protos.len() - 1;
+        if index > u16::MAX as usize {
+            return self.error("too many functions");
+        }
+        let dst = self.fs().free_reg;
+        self.reserve_regs(1)?;
+        self.emit(ByteCode::Closure(dst as u8, index as u16));
+        Ok(ExpDesc::Reg(dst))
+    }
+
+    fn close_func(&mut self) -> Result<ParseProto, LuaError> {
+        self.emit(ByteCode::Return(0, 1));
+        self.remove_locals(0);
+        let mut fs = self.funcs.pop().unwrap();
+        if let Some(g) = fs.gotos.first() {
+            return self.error(&format!("no visible label '{}' for <goto> at line {}", g.name, g.line));
+        }
+        fs.proto.constants_pos.clear();
+        Ok(fs.proto)
+    }
+
+    // ---------- 表达式 ----------
+
+    fn exp(&mut self) -> Result<ExpDesc, LuaError> {
+        self.subexp(0)
+    }
+
+    fn subexp(&mut self, limit: u8) -> Result<ExpDesc, LuaError> {
+        let token = self.lex.peek()?.clone();
+        let mut desc = match token {
+            Token::Not | Token::Sub | Token::Len | Token::BitXor => {
+                self.lex.next()?;
+                let operand = self.subexp(UNARY_PRIORITY)?;
+                self.unop(token, operand)?
+            }
+            _ => self.simple_exp()?,
+        };
+
+        loop {
+            let op = self.lex.peek()?.clone();
+            let Some((left, right)) = binop_priority(&op) else { break };
+            if left <= limit {
+                break;
+            }
+            self.lex.next()?;
+            desc = self.binop(op, desc, right)?;
+        }
+        Ok(desc)
+    }
+
+    fn simple_exp(&mut self) -> Result<ExpDesc, LuaError> {
+        let token = self.lex.peek()?.clone();
+        let desc = match token {
+            Token::Nil => ExpDesc::Nil,
+            Token::True => ExpDesc::True,
+            Token::False => ExpDesc::False,
+            Token::Integer(i) => ExpDesc::Integer(i),
+            Token::Float(f) => ExpDesc::Float(f),
+            Token::String(s) => ExpDesc::String(s),
+            Token::Vararg => {
+                if !self.fs().proto.is_vararg {
+                    return self.error("cannot use '...' outside a vararg function near '...'");
+                }
+                self.lex.next()?;
+                let dst = self.fs().free_reg;
+                let pc = self.emit(ByteCode::VarArgs(dst as u8, 2));
+                return Ok(ExpDesc::VarArgs(pc));
+            }
+            Token::Lb => return self.table_constructor(),
+            Token::Function => {
+                let line = self.lex.line();
+                self.lex.next()?;
+                return self.body(false, line);
+            }
+            _ => return self.suffixed_exp(),
+        };
+        self.lex.next()?;
+        Ok(desc)
+    }
+
+    fn primary_exp(&mut self) -> Result<ExpDesc, LuaError> {
+        match self.lex.next()? {
+            Token::Name(name) => self.single_var(name),
+            Token::Lp => {
+                let line = self.lex.line();
+                let desc = self.exp()?;
+                self.check_match(Token::Rp, ")", "(", line)?;
+                // 括号把多返回值截断为一个
+                match desc {
+                    ExpDesc::Call(_) | ExpDesc::VarArgs(_) => Ok(ExpDesc::Reg(self.exp2anyreg_vararg(desc)?)),
+                    _ => Ok(desc),
+                }
+            }
+            t => self.error(&format!("unexpected symbol near {}", token_text(&t))),
+        }
+    }
+
+    fn exp2anyreg_vararg(&mut self, desc: ExpDesc) -> Result<usize, LuaError> {
+        if let ExpDesc::VarArgs(pc) = desc {
+            let dst = self.fs().free_reg;
+            self.reserve_regs(1)?;
+            if let ByteCode::VarArgs(_, n) = self.fs().proto.instructions[pc] {
+                self.fs().proto.instructions[pc] = ByteCode::VarArgs(dst as u8, n);
+            }
+            return Ok(dst);
+        }
+        self.exp2anyreg(desc)
+    }
+
+    fn suffixed_exp(&mut self) -> Result<ExpDesc, LuaError> {
+        let mut desc = self.primary_exp()?;
+        loop {
+            match self.lex.peek()?.clone() {
+                Token::Dot => {
+                    self.lex.next()?;
+                    let key = self.read_name()?;
+                    desc = self.index_field(desc, key.as_bytes())?;
+                }
+                Token::Ls => {
+                    self.lex.next()?;
+                    let table = self.exp2anyreg_vararg(desc)?;
+                    let key = self.exp()?;
+                    self.check(Token::Rs, "]")?;
+                    desc = self.index(table, key)?;
+                }
+                Token::Colon => {
+                    self.lex.next()?;
+                    let name = self.read_name()?;
+                    let obj = self.exp2anyreg_vararg(desc)?;
+                    let obj_desc = ExpDesc::Reg(obj);
+                    self.free_exp(&obj_desc);
+                    let func = self.fs().free_reg;
+                    self.reserve_regs(2)?;
+                    let k = self.str_const(name.as_bytes())?;
+                    self.emit(ByteCode::Method(func as u8, obj as u8, k as u16));
+                    desc = self.call_args(func, 1)?;
+                }
+                Token::Lp | Token::String(_) | Token::Lb => {
+                    let func = self.exp2nextreg_vararg(desc)?;
+                    desc = self.call_args(func, 0)?;
+                }
+                _ => return Ok(desc),
+            }
+        }
+    }
+
+    fn exp2nextreg_vararg(&mut self, desc: ExpDesc) -> Result<usize, LuaError> {
+        if let ExpDesc::VarArgs(_) = desc {
+            let r = self.exp2anyreg_vararg(desc)?;
+            return Ok(r);
+        }
+        self.exp2nextreg(desc)
+    }
+
+    fn index_field(&mut self, desc: ExpDesc, key: &[u8]) -> Result<ExpDesc, LuaError> {
+        let table = self.exp2anyreg_vararg(desc)?;
+        let k = self.str_const(key)?;
+        Ok(ExpDesc::IndexField(table, k))
+    }
+
+    fn index(&mut self, table: usize, key: ExpDesc) -> Result<ExpDesc, LuaError> {
+        Ok(match key {
+            ExpDesc::String(s) => ExpDesc::IndexField(table, self.str_const(&s)?),
+            ExpDesc::Integer(i) if (0..=255).contains(&i) => ExpDesc::IndexInt(table, i as u8),
+            _ => {
+                let k = self.exp2anyreg_vararg(key)?;
+                ExpDesc::Index(table, k)
+            }
+        })
+    }
+
+    // 函数调用参数，func为函数所在寄存器，前面已放入nfixed个参数(方法调用的self)
+    fn call_args(&mut self, func: usize, nfixed: usize) -> Result<ExpDesc, LuaError> {
+        let line = self.lex.line();
+        let narg = match self.lex.next()? {
+            Token::String(s) => {
+                self.exp2nextreg(ExpDesc::String(s))?;
+                nfixed + 1 + 1
+            }
+            Token::Lb => {
+                self.lex.ahead_push(Token::Lb);
+                let t = self.table_constructor()?;
+                self.exp2nextreg(t)?;
+                nfixed + 1 + 1
+            }
+            Token::Lp => {
+                if self.test_next(&Token::Rp)? {
+                    nfixed + 1
+                } else {
+                    let mut n = nfixed + 1;
+                    let mut last = self.exp()?;
+                    while self.test_next(&Token::Comma)? {
+                        self.exp2nextreg(last)?;
+                        n += 1;
+                        last = self.exp()?;
+                    }
+                    self.check_match(Token::Rp, ")", "(", line)?;
+                    if Self::is_multret(&last) {
+                        // 结果已经位于参数位置
+                        self.set_multret(&last, 0);
+                        0
+                    } else {
+                        self.exp2nextreg(last)?;
+                        n + 1
+                    }
+                }
+            }
+            t => return self.error(&format!("function arguments expected near {}", token_text(&t))),
+        };
+        if narg > 255 {
+            return self.error("too many arguments");
+        }
+        let pc = self.emit(ByteCode::Call(func as u8, narg as u8, 2));
+        // 调用后函数位置存放第一个返回值
+        self.fs().free_reg = func + 1;
+        Ok(ExpDesc::Call(pc))
+    }
+
+    fn table_constructor(&mut self) -> Result<ExpDesc, LuaError> {
+        let line = self.lex.line();
+        self.check(Token::Lb, "{")?;
+        let table = self.fs().free_reg;
+        self.reserve_regs(1)?;
+        let pc = self.emit(ByteCode::NewTable(table as u8, 0, 0));
+
+        let mut narray = 0;  // 已写入的数组项
+        let mut pending = 0;  // 在寄存器中等待SetList的数组项
+        let mut nmap = 0;
+        loop {
+            if self.lex.peek()? == &Token::Rb {
+                break;
+            }
+            let saved = self.fs().free_reg;
+            match self.lex.peek()?.clone() {
+                Token::Ls => {
+                    self.lex.next()?;
+                    let k = self.exp()?;
+                    self.check(Token::Rs, "]")?;
+                    self.check(Token::Assign, "=")?;
+                    self.table_set(table, k)?;
+                    self.fs().free_reg = saved;
+                    nmap += 1;
+                }
                 Token::Name(name) => {
-                    if let Some(i) = self.locals.iter().rposition(|x| *x == name) {
-                        ByteCode::SetGlobal(dst, i as u8)
+                    self.lex.next()?;
+                    if self.lex.peek()? == &Token::Assign {
+                        self.lex.next()?;
+                        self.table_set(table, ExpDesc::String(name.into_bytes()))?;
+                        self.fs().free_reg = saved;
+                        nmap += 1;
                     } else {
-                        ByteCode::SetGlobalGlobal(dst, self.add_const(Value::String(name)) as u8)
+                        self.lex.ahead_push(Token::Name(name));
+                        pending = self.array_item(table, &mut narray, pending)?;
                     }
-                },
+                }
+                _ => pending = self.array_item(table, &mut narray, pending)?,
+            }
+            if !self.test_next(&Token::Comma)? && !self.test_next(&Token::Semicolon)? {
+                break;
+            }
+        }
+        self.check_match(Token::Rb, "}", "{", line)?;
+
+        if pending > 0 {
+            self.flush_list(table, pending, narray, false)?;
+            narray += pending;
+        }
+        self.fs().proto.instructions[pc] = ByteCode::NewTable(table as u8, narray.min(255) as u8, nmap.min(255) as u8);
+        self.fs().free_reg = table + 1;
+        Ok(ExpDesc::Reg(table))
+    }
+
+    fn table_set(&mut self, table: usize, key: ExpDesc) -> Result<(), LuaError> {
+        let key = self.index(table, key)?;
+        let value = self.exp()?;
+        match key {
+            ExpDesc::Index(t, k) => {
+                let v = self.exp2anyreg_vararg(value)?;
+                self.emit(ByteCode::SetTable(t as u8, k as u8, v as u8));
+                Ok(())
+            }
+            _ => self.store_var(key, value),
+        }
+    }
+
+    fn array_item(&mut self, table: usize, narray: &mut usize, pending: usize) -> Result<usize, LuaError> {
+        let value = self.exp()?;
+        // 最后一项的多返回值全部加入数组部分
+        if Self::is_multret(&value) && self.is_last_item()? {
+            self.set_multret(&value, 0);
+            self.flush_list(table, pending + 1, *narray, true)?;
+            *narray += pending;
+            return Ok(0);
+        }
+        self.exp2nextreg(value)?;
+        let pending = pending + 1;
+        if pending == FIELDS_PER_FLUSH {
+            self.flush_list(table, pending, *narray, false)?;
+            *narray += pending;
+            return Ok(0);
+        }
+        Ok(pending)
+    }
+
+    // 判断当前项是否为构造器的最后一项（后面只剩可选的分隔符和'}'）
+    fn is_last_item(&mut self) -> Result<bool, LuaError> {
+        match self.lex.peek()? {
+            Token::Rb => Ok(true),
+            Token::Comma | Token::Semicolon => {
+                let sep = self.lex.next()?;
+                let last = self.lex.peek()? == &Token::Rb;
+                self.lex.ahead_push(sep);
+                Ok(last)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    fn flush_list(&mut self, table: usize, n: usize, offset: usize, multret: bool) -> Result<(), LuaError> {
+        if offset > u16::MAX as usize {
+            return self.error("constructor too long");
+        }
+        let count = if multret { 0 } else { n + 1 };
+        self.emit(ByteCode::SetList(table as u8, count as u8, offset as u16));
+        self.fs().free_reg = table + 1;
+        Ok(())
+    }
 
-                _ => panic!("Unexpected token: {:?}", next_token),
+    fn unop(&mut self, op: Token, operand: ExpDesc) -> Result<ExpDesc, LuaError> {
+        // 常量折叠
+        match (&op, &operand) {
+            (Token::Sub, ExpDesc::Integer(i)) => return Ok(ExpDesc::Integer(i.wrapping_neg())),
+            (Token::Sub, ExpDesc::Float(f)) => return Ok(ExpDesc::Float(-f)),
+            (Token::Not, ExpDesc::Nil | ExpDesc::False) => return Ok(ExpDesc::True),
+            (Token::Not, ExpDesc::True | ExpDesc::Integer(_) | ExpDesc::Float(_) | ExpDesc::String(_)) => return Ok(ExpDesc::False),
+            (Token::BitXor, ExpDesc::Integer(i)) => return Ok(ExpDesc::Integer(!i)),
+            _ => (),
+        }
+
+        let src = self.exp2anyreg_vararg(operand)?;
+        self.free_reg(src);
+        let dst = self.fs().free_reg;
+        self.reserve_regs(1)?;
+        let code = match op {
+            Token::Sub => ByteCode::Neg(dst as u8, src as u8),
+            Token::Not => ByteCode::Not(dst as u8, src as u8),
+            Token::Len => ByteCode::Len(dst as u8, src as u8),
+            Token::BitXor => ByteCode::BitNot(dst as u8, src as u8),
+            _ => unreachable!(),
+        };
+        self.emit(code);
+        Ok(ExpDesc::Reg(dst))
+    }
+
+    fn binop(&mut self, op: Token, left: ExpDesc, right_priority: u8) -> Result<ExpDesc, LuaError> {
+        // and/or 短路求值，结果放在同一个寄存器
+        if op == Token::And || op == Token::Or {
+            let dst = self.exp2nextreg_vararg_val(left)?;
+            let jmp = if op == Token::And {
+                self.emit(ByteCode::TestAndJump(dst as u8, 0))
+            } else {
+                self.emit(ByteCode::TestOrJump(dst as u8, 0))
+            };
+            let right = self.subexp(right_priority)?;
+            self.free_exp(&right);
+            let right = match right {
+                ExpDesc::VarArgs(_) => ExpDesc::Reg(self.exp2anyreg_vararg(right)?),
+                r => r,
             };
+            self.free_exp(&right);
+            self.discharge(dst, right)?;
+            self.fs().free_reg = dst + 1;
+            self.patch_here(jmp);
+            return Ok(ExpDesc::Reg(dst));
+        }
+
+        let left = match left {
+            ExpDesc::Integer(_) | ExpDesc::Float(_) if Self::foldable(&op) => left,
+            _ => ExpDesc::Reg(self.exp2anyreg_vararg(left)?),
+        };
+        let right = self.subexp(right_priority)?;
+
+        if let Some(folded) = Self::fold(&op, &left, &right) {
+            return Ok(folded);
+        }
+
+        // 常量左操作数在右操作数之后放入寄存器，使右操作数的临时寄存器（如g.x中的表）先被释放
+        let (l, r) = match left {
+            ExpDesc::Reg(l) => (l, self.exp2anyreg_vararg(right)?),
+            _ => {
+                let r = self.exp2anyreg_vararg(right)?;
+                (self.exp2nextreg(left)?, r)
+            }
+        };
 
-            self.instructions.push(code);
+        // 释放操作数占用的临时寄存器，结果放在第一个空闲位置
+        self.free_reg(r.max(l));
+        self.free_reg(r.min(l));
+        let dst = self.fs().free_reg;
+        self.reserve_regs(1)?;
+        let (d, l, r) = (dst as u8, l as u8, r as u8);
+        let code = match op {
+            Token::Add => ByteCode::Add(d, l, r),
+            Token::Sub => ByteCode::Sub(d, l, r),
+            Token::Mul => ByteCode::Mul(d, l, r),
+            Token::Div => ByteCode::Div(d, l, r),
+            Token::Idiv => ByteCode::Idiv(d, l, r),
+            Token::Mod => ByteCode::Mod(d, l, r),
+            Token::Pow => ByteCode::Pow(d, l, r),
+            Token::BitAnd => ByteCode::BitAnd(d, l, r),
+            Token::BitOr => ByteCode::BitOr(d, l, r),
+            Token::BitXor => ByteCode::BitXor(d, l, r),
+            Token::ShiftL => ByteCode::ShiftL(d, l, r),
+            Token::ShiftR => ByteCode::ShiftR(d, l, r),
+            Token::Concat => ByteCode::Concat(d, l, r),
+            Token::Eq => ByteCode::Eq(d, l, r),
+            Token::Lt => ByteCode::Lt(d, l, r),
+            Token::Le => ByteCode::Le(d, l, r),
+            Token::Gt => ByteCode::Lt(d, r, l),
+            Token::Ge => ByteCode::Le(d, r, l),
+            Token::Ne => {
+                self.emit(ByteCode::Eq(d, l, r));
+                ByteCode::Not(d, d)
+            }
+            _ => unreachable!(),
+        };
+        self.emit(code);
+        Ok(ExpDesc::Reg(dst))
+    }
+
+    fn exp2nextreg_vararg_val(&mut self, desc: ExpDesc) -> Result<usize, LuaError> {
+        match desc {
+            ExpDesc::VarArgs(_) => self.exp2anyreg_vararg(desc),
+            ExpDesc::Reg(r) if r + 1 == self.fs().free_reg && r >= self.nactvar() => Ok(r),
+            ExpDesc::Call(pc) => {
+                let r = self.exp2anyreg(ExpDesc::Call(pc))?;
+                Ok(r)
+            }
+            _ => self.exp2nextreg(desc),
         }
+    }
 
+    fn foldable(op: &Token) -> bool {
+        matches!(op, Token::Add | Token::Sub | Token::Mul | Token::Div | Token::Idiv | Token::Mod | Token::Pow)
     }
-}
 
+    // 数值常量的算术折叠，只折叠不会出错且结果与运行时一致的情况
+    fn fold(op: &Token, left: &ExpDesc, right: &ExpDesc) -> Option<ExpDesc> {
+        let num = |d: &ExpDesc| match d {
+            ExpDesc::Integer(i) => Some(Value::Integer(*i)),
+            ExpDesc::Float(f) => Some(Value::Float(*f)),
+            _ => None,
+        };
+        let (a, b) = (num(left)?, num(right)?);
+        if !Self::foldable(op) {
+            return None;
+        }
+        let result = match op {
+            Token::Add => crate::vm::arith_add(&a, &b),
+            Token::Sub => crate::vm::arith_sub(&a, &b),
+            Token::Mul => crate::vm::arith_mul(&a, &b),
+            Token::Div => crate::vm::arith_div(&a, &b),
+            Token::Pow => crate::vm::arith_pow(&a, &b),
+            Token::Idiv | Token::Mod => {
+                // 除数为0的整数运算留到运行时报错
+                if let Value::Integer(0) = b {
+                    return None;
+                }
+                if *op == Token::Idiv { crate::vm::arith_idiv(&a, &b) } else { crate::vm::arith_mod(&a, &b) }
+            }
+            _ => None,
+        }?;
+        match result {
+            Value::Integer(i) => Some(ExpDesc::Integer(i)),
+            // NaN和-0不折叠，避免常量表去重问题
+            Value::Float(f) if f.is_nan() || f == 0.0 => None,
+            Value::Float(f) => Some(ExpDesc::Float(f)),
+            _ => None,
+        }
+    }
+}
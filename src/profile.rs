@@ -0,0 +1,332 @@
+// 性能分析：用call、return、line和count钩子统计每个函数、每行的时间和执行的指令数，
+// 输出火焰图工具使用的折叠栈格式和按自身时间排序的文本报告
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::debug::{HookEvent, HOOK_CALL, HOOK_COUNT, HOOK_LINE, HOOK_RETURN};
+use crate::vm::ExeState;
+
+// 报告中列出的最热的行数
+const TOP_LINES: usize = 20;
+
+// 函数的标识：Lua函数为所在chunk和定义行，Rust函数为函数地址
+#[derive(Hash, PartialEq, Eq)]
+enum FuncKey {
+    Lua(String, i64),
+    Rust(usize),
+}
+
+struct FuncStats {
+    label: String,
+    named: bool,
+    short_src: String,
+    calls: u64,
+    self_time: Duration,
+    total_time: Duration,  // 递归调用只计最外层，挂起的协程中的调用不计挂起的时间
+    instructions: u64,
+    active: u32,  // 在运行中或等待resume返回的调用栈上的次数
+}
+
+#[derive(Default)]
+struct LineStats {
+    time: Duration,
+    instructions: u64,
+}
+
+// 调用树的节点，每条从根开始的路径对应折叠栈的一行
+struct Node {
+    parent: Option<usize>,
+    func: usize,
+    self_time: Duration,
+}
+
+struct Frame {
+    func: usize,
+    node: usize,
+    depth: usize,  // 进入时的调用栈深度
+    line: u32,
+    entered: Instant,
+}
+
+struct Data {
+    funcs: Vec<FuncStats>,
+    func_ids: HashMap<FuncKey, usize>,
+    lines: HashMap<(usize, u32), LineStats>,
+    nodes: Vec<Node>,
+    children: HashMap<(Option<usize>, usize), usize>,
+    thread: usize,  // 当前线程（协程地址），其调用栈在stack中
+    stack: Vec<Frame>,
+    other_stacks: HashMap<usize, Vec<Frame>>,
+    resumers: Vec<usize>,  // 恢复了其他协程、等待其让出的线程，由外到内
+    pending: u64,  // 上次事件之后执行的指令数
+    last: Instant,
+    started: Instant,
+    instructions: u64,
+}
+
+pub struct Profiler(Rc<RefCell<Data>>);
+
+// 分析结果
+pub struct Profile {
+    data: Data,
+    elapsed: Duration,
+}
+
+impl Profiler {
+    // 设置钩子开始分析，会替换已有的钩子
+    pub fn start(state: &mut ExeState) -> Profiler {
+        let now = Instant::now();
+        let data = Rc::new(RefCell::new(Data {
+            funcs: Vec::new(),
+            func_ids: HashMap::new(),
+            lines: HashMap::new(),
+            nodes: Vec::new(),
+            children: HashMap::new(),
+            thread: Rc::as_ptr(&state.running()) as usize,
+            stack: Vec::new(),
+            other_stacks: HashMap::new(),
+            resumers: Vec::new(),
+            pending: 0,
+            last: now,
+            started: now,
+            instructions: 0,
+        }));
+        let hook_data = data.clone();
+        let hook = move |state: &mut ExeState, event| {
+            hook_data.borrow_mut().on_event(state, event);
+            Ok(())
+        };
+        state.set_hook(Some(Rc::new(hook)), HOOK_CALL | HOOK_RETURN | HOOK_LINE | HOOK_COUNT, 1);
+        Profiler(data)
+    }
+
+    pub fn stop(self, state: &mut ExeState) -> Profile {
+        state.set_hook(None, 0, 0);
+        let now = Instant::now();
+        let mut data = Rc::try_unwrap(self.0).ok().expect("profiler hook still installed").into_inner();
+        data.charge(now);
+        data.unwind(0, now);
+        for thread in std::mem::take(&mut data.resumers) {
+            let stack = data.other_stacks.remove(&thread).unwrap_or_default();
+            data.suspend(&stack, now);
+        }
+        Profile { elapsed: now - data.started, data }
+    }
+}
+
+impl Data {
+    fn on_event(&mut self, state: &ExeState, event: HookEvent) {
+        if event == HookEvent::Count {
+            self.pending += 1;
+            return;
+        }
+        let now = Instant::now();
+        let thread = Rc::as_ptr(&state.running()) as usize;
+        if thread != self.thread {
+            self.switch_thread(thread, now);
+        }
+        // 出错时函数没有return事件，按调用深度弹出已经离开的函数。call事件时新函数已在栈上
+        let depth = state.call_depth(None);
+        self.unwind(if event == HookEvent::Call { depth - 1 } else { depth }, now);
+
+        // count事件在同一条指令的line事件之前，这条指令属于新的一行
+        let carried = matches!(event, HookEvent::Line(_)) && self.pending > 0;
+        if carried {
+            self.pending -= 1;
+        }
+        self.charge(now);
+        if carried {
+            self.pending = 1;
+        }
+
+        match event {
+            HookEvent::Call => self.enter(state, depth, now),
+            HookEvent::TailCall => {
+                self.unwind(depth - 1, now);
+                self.enter(state, depth, now);
+            }
+            HookEvent::Return => self.unwind(depth - 1, now),
+            HookEvent::Line(line) => {
+                if let Some(top) = self.stack.last_mut() {
+                    top.line = line;
+                }
+            }
+            HookEvent::Count => unreachable!(),
+        }
+        // 钩子自身的开销不计入
+        self.last = Instant::now();
+        if matches!(event, HookEvent::Call | HookEvent::TailCall) {
+            if let Some(top) = self.stack.last_mut() {
+                top.entered = self.last;
+            }
+        }
+    }
+
+    // 上次事件以来的时间和指令计入栈顶的函数和行
+    fn charge(&mut self, now: Instant) {
+        let elapsed = now - self.last;
+        let instructions = std::mem::take(&mut self.pending);
+        self.last = now;
+        self.instructions += instructions;
+        let Some(top) = self.stack.last() else {
+            return;
+        };
+        let func = &mut self.funcs[top.func];
+        func.self_time += elapsed;
+        func.instructions += instructions;
+        self.nodes[top.node].self_time += elapsed;
+        if top.line != 0 {
+            let line = self.lines.entry((top.func, top.line)).or_default();
+            line.time += elapsed;
+            line.instructions += instructions;
+        }
+    }
+
+    // 回到恢复者时原来的线程已让出或结束，其中的调用停止计时；
+    // 否则原来的线程在等待resume返回，其中的调用继续计时
+    fn switch_thread(&mut self, thread: usize, now: Instant) {
+        let old = std::mem::take(&mut self.stack);
+        let back = self.resumers.last() == Some(&thread);
+        if back {
+            self.resumers.pop();
+            self.suspend(&old, now);
+        } else {
+            self.resumers.push(self.thread);
+        }
+        self.other_stacks.insert(std::mem::replace(&mut self.thread, thread), old);
+        self.stack = self.other_stacks.remove(&thread).unwrap_or_default();
+        // 被恢复的协程中的调用重新开始计时
+        if !back {
+            for frame in &mut self.stack {
+                frame.entered = now;
+                self.funcs[frame.func].active += 1;
+            }
+        }
+    }
+
+    fn suspend(&mut self, stack: &[Frame], now: Instant) {
+        for frame in stack.iter().rev() {
+            let func = &mut self.funcs[frame.func];
+            func.active -= 1;
+            if func.active == 0 {
+                func.total_time += now - frame.entered;
+            }
+        }
+    }
+
+    fn unwind(&mut self, depth: usize, now: Instant) {
+        while self.stack.last().is_some_and(|top| top.depth > depth) {
+            let frame = self.stack.pop().unwrap();
+            let func = &mut self.funcs[frame.func];
+            func.active -= 1;
+            if func.active == 0 {
+                func.total_time += now - frame.entered;
+            }
+        }
+    }
+
+    fn enter(&mut self, state: &ExeState, depth: usize, now: Instant) {
+        let Some(info) = state.get_info(None, 0) else {
+            return;
+        };
+        let key = if info.what == "C" {
+            FuncKey::Rust(info.func.to_pointer() as usize)
+        } else {
+            FuncKey::Lua(info.source.clone(), info.line_defined)
+        };
+        let label = |name: Option<&str>| match (name, info.what) {
+            (_, "main") => format!("main chunk ({})", info.short_src),
+            (name, "C") => format!("{} [C]", name.unwrap_or("?")),
+            (Some(name), _) => format!("{} ({}:{})", name, info.short_src, info.line_defined),
+            (None, _) => format!("function <{}:{}>", info.short_src, info.line_defined),
+        };
+        let func = match self.func_ids.get(&key) {
+            Some(&func) => {
+                // 先前的调用推断不出名字时换用这次的
+                if !self.funcs[func].named && info.name.is_some() {
+                    self.funcs[func].label = label(info.name.as_deref());
+                    self.funcs[func].named = true;
+                }
+                func
+            }
+            None => {
+                self.funcs.push(FuncStats {
+                    label: label(info.name.as_deref()),
+                    named: info.name.is_some(),
+                    short_src: info.short_src.clone(),
+                    calls: 0,
+                    self_time: Duration::ZERO,
+                    total_time: Duration::ZERO,
+                    instructions: 0,
+                    active: 0,
+                });
+                self.func_ids.insert(key, self.funcs.len() - 1);
+                self.funcs.len() - 1
+            }
+        };
+        self.funcs[func].calls += 1;
+        self.funcs[func].active += 1;
+
+        let parent = self.stack.last().map(|top| top.node);
+        let nodes = &mut self.nodes;
+        let node = *self.children.entry((parent, func)).or_insert_with(|| {
+            nodes.push(Node { parent, func, self_time: Duration::ZERO });
+            nodes.len() - 1
+        });
+        self.stack.push(Frame { func, node, depth, line: 0, entered: now });
+    }
+}
+
+fn ms(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+impl Profile {
+    // 折叠栈格式：每行为以';'分隔的调用路径和自身时间（微秒）
+    pub fn write_folded(&self, out: &mut impl Write) -> io::Result<()> {
+        let funcs = &self.data.funcs;
+        for node in &self.data.nodes {
+            let micros = node.self_time.as_micros();
+            if micros == 0 {
+                continue;
+            }
+            let mut path = vec![funcs[node.func].label.as_str()];
+            let mut parent = node.parent;
+            while let Some(p) = parent {
+                path.push(&funcs[self.data.nodes[p].func].label);
+                parent = self.data.nodes[p].parent;
+            }
+            path.reverse();
+            writeln!(out, "{} {}", path.join(";"), micros)?;
+        }
+        out.flush()
+    }
+
+    // 按自身时间排序的函数表和最热的行
+    pub fn report(&self) -> String {
+        let data = &self.data;
+        let mut out = String::new();
+        let _ = writeln!(out, "profile: {:.3} ms, {} instructions", ms(self.elapsed), data.instructions);
+
+        let mut funcs: Vec<_> = data.funcs.iter().collect();
+        funcs.sort_by(|a, b| b.self_time.cmp(&a.self_time).then(b.instructions.cmp(&a.instructions)));
+        let _ = writeln!(out, "\n{:>12} {:>12} {:>10} {:>14}  function", "self ms", "total ms", "calls", "instructions");
+        for f in funcs {
+            let _ = writeln!(out, "{:>12.3} {:>12.3} {:>10} {:>14}  {}",
+                ms(f.self_time), ms(f.total_time), f.calls, f.instructions, f.label);
+        }
+
+        let mut lines: Vec<_> = data.lines.iter().collect();
+        lines.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(b.1.instructions.cmp(&a.1.instructions)));
+        let _ = writeln!(out, "\n{:>12} {:>14}  line", "self ms", "instructions");
+        for ((func, line), stats) in lines.into_iter().take(TOP_LINES) {
+            let func = &data.funcs[*func];
+            let _ = writeln!(out, "{:>12.3} {:>14}  {}:{}  {}", ms(stats.time), stats.instructions, func.short_src, line, func.label);
+        }
+        out
+    }
+}
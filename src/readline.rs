@@ -0,0 +1,298 @@
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::PathBuf;
+
+const MAX_HISTORY: usize = 1000;
+
+// 简单的行编辑器：终端下进入raw模式，支持光标移动、删除和历史记录；
+// 非终端（管道、文件）时退化为逐行读取
+pub struct LineEditor {
+    history: Vec<String>,
+    history_file: Option<PathBuf>,
+}
+
+impl LineEditor {
+    pub fn new(history_file: Option<PathBuf>) -> Self {
+        let history = match &history_file {
+            Some(path) => fs::read_to_string(path)
+                .map(|s| s.lines().map(str::to_string).collect())
+                .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        LineEditor { history, history_file }
+    }
+
+    // 默认历史文件：$MYLUA_HISTORY，否则 ~/.mylua_history
+    pub fn default_history_file() -> Option<PathBuf> {
+        if let Some(path) = std::env::var_os("MYLUA_HISTORY") {
+            return Some(PathBuf::from(path));
+        }
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".mylua_history"))
+    }
+
+    pub fn add_history(&mut self, line: &str) {
+        if line.trim().is_empty() || self.history.last().map(String::as_str) == Some(line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > MAX_HISTORY {
+            self.history.remove(0);
+        }
+        if let Some(path) = &self.history_file {
+            let mut content = self.history.join("\n");
+            content.push('\n');
+            let _ = fs::write(path, content);
+        }
+    }
+
+    // 读取一行（不含换行符），文件结束返回None
+    pub fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        match RawMode::enable() {
+            Some(raw) => {
+                let result = self.edit(prompt);
+                drop(raw);
+                println!();
+                result
+            }
+            None => {
+                print!("{}", prompt);
+                io::stdout().flush()?;
+                let mut line = String::new();
+                if io::stdin().lock().read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                while line.ends_with('\n') || line.ends_with('\r') {
+                    line.pop();
+                }
+                Ok(Some(line))
+            }
+        }
+    }
+
+    fn edit(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        let mut line: Vec<char> = Vec::new();
+        let mut pos = 0;
+        let mut hist_pos = self.history.len();
+        let mut saved = Vec::new();  // 浏览历史前正在编辑的内容
+        redraw(prompt, &line, pos)?;
+
+        loop {
+            let c = match read_char()? {
+                Some(c) => c,
+                None => return Ok(None),
+            };
+            match c {
+                '\r' | '\n' => return Ok(Some(line.into_iter().collect())),
+                '\x04' => {  // Ctrl-D
+                    if line.is_empty() {
+                        return Ok(None);
+                    }
+                    if pos < line.len() {
+                        line.remove(pos);
+                    }
+                }
+                '\x03' => {  // Ctrl-C：放弃当前行
+                    print!("^C");
+                    return Ok(Some(String::new()));
+                }
+                '\x7f' | '\x08' => {
+                    if pos > 0 {
+                        pos -= 1;
+                        line.remove(pos);
+                    }
+                }
+                '\x01' => pos = 0,           // Ctrl-A
+                '\x05' => pos = line.len(),  // Ctrl-E
+                '\x02' => pos = pos.saturating_sub(1),  // Ctrl-B
+                '\x06' => pos = (pos + 1).min(line.len()),  // Ctrl-F
+                '\x0b' => line.truncate(pos),  // Ctrl-K
+                '\x15' => {  // Ctrl-U
+                    line.drain(..pos);
+                    pos = 0;
+                }
+                '\x17' => {  // Ctrl-W：删除前一个单词
+                    let mut start = pos;
+                    while start > 0 && line[start - 1] == ' ' {
+                        start -= 1;
+                    }
+                    while start > 0 && line[start - 1] != ' ' {
+                        start -= 1;
+                    }
+                    line.drain(start..pos);
+                    pos = start;
+                }
+                '\x10' | '\x0e' => {  // Ctrl-P / Ctrl-N
+                    let up = c == '\x10';
+                    self.browse(up, &mut hist_pos, &mut saved, &mut line, &mut pos);
+                }
+                '\x1b' => match read_escape()? {
+                    Some(Key::Up) => self.browse(true, &mut hist_pos, &mut saved, &mut line, &mut pos),
+                    Some(Key::Down) => self.browse(false, &mut hist_pos, &mut saved, &mut line, &mut pos),
+                    Some(Key::Left) => pos = pos.saturating_sub(1),
+                    Some(Key::Right) => pos = (pos + 1).min(line.len()),
+                    Some(Key::Home) => pos = 0,
+                    Some(Key::End) => pos = line.len(),
+                    Some(Key::Delete) if pos < line.len() => {
+                        line.remove(pos);
+                    }
+                    _ => {}
+                },
+                '\t' => {
+                    line.insert(pos, ' ');
+                    pos += 1;
+                }
+                c if (c as u32) < 0x20 => {}
+                c => {
+                    line.insert(pos, c);
+                    pos += 1;
+                }
+            }
+            redraw(prompt, &line, pos)?;
+        }
+    }
+
+    fn browse(&self, up: bool, hist_pos: &mut usize, saved: &mut Vec<char>, line: &mut Vec<char>, pos: &mut usize) {
+        if up {
+            if *hist_pos == 0 {
+                return;
+            }
+            if *hist_pos == self.history.len() {
+                *saved = line.clone();
+            }
+            *hist_pos -= 1;
+            *line = self.history[*hist_pos].chars().collect();
+        } else {
+            if *hist_pos >= self.history.len() {
+                return;
+            }
+            *hist_pos += 1;
+            *line = match self.history.get(*hist_pos) {
+                Some(h) => h.chars().collect(),
+                None => saved.clone(),
+            };
+        }
+        *pos = line.len();
+    }
+}
+
+enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Delete,
+}
+
+fn redraw(prompt: &str, line: &[char], pos: usize) -> io::Result<()> {
+    let text: String = line.iter().collect();
+    let mut out = io::stdout();
+    write!(out, "\r{}{}\x1b[K", prompt, text)?;
+    if pos < line.len() {
+        write!(out, "\x1b[{}D", line.len() - pos)?;
+    }
+    out.flush()
+}
+
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut buf = [0u8; 1];
+    match io::stdin().lock().read(&mut buf)? {
+        0 => Ok(None),
+        _ => Ok(Some(buf[0])),
+    }
+}
+
+// 读取一个UTF-8字符
+fn read_char() -> io::Result<Option<char>> {
+    let first = match read_byte()? {
+        Some(b) => b,
+        None => return Ok(None),
+    };
+    let len = match first {
+        0xf0..=0xf7 => 4,
+        0xe0..=0xef => 3,
+        0xc0..=0xdf => 2,
+        _ => 1,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte()? {
+            Some(b) => bytes.push(b),
+            None => break,
+        }
+    }
+    Ok(std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()).or(Some('\0')))
+}
+
+// 解析ESC之后的控制序列，如 "[A"、"[3~"、"OH"
+fn read_escape() -> io::Result<Option<Key>> {
+    let kind = read_byte()?;
+    if kind != Some(b'[') && kind != Some(b'O') {
+        return Ok(None);
+    }
+    let mut param = 0;
+    loop {
+        let b = match read_byte()? {
+            Some(b) => b,
+            None => return Ok(None),
+        };
+        let key = match b {
+            b'0'..=b'9' => {
+                param = param * 10 + (b - b'0') as u32;
+                continue;
+            }
+            b';' => continue,
+            b'A' => Key::Up,
+            b'B' => Key::Down,
+            b'C' => Key::Right,
+            b'D' => Key::Left,
+            b'H' => Key::Home,
+            b'F' => Key::End,
+            b'~' => match param {
+                1 | 7 => Key::Home,
+                4 | 8 => Key::End,
+                3 => Key::Delete,
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        return Ok(Some(key));
+    }
+}
+
+// 终端raw模式，drop时恢复原设置
+struct RawMode {
+    orig: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> Option<RawMode> {
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) == 0 || libc::isatty(libc::STDOUT_FILENO) == 0 {
+                return None;
+            }
+            let mut orig: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut orig) != 0 {
+                return None;
+            }
+            let mut raw = orig;
+            raw.c_iflag &= !(libc::BRKINT | libc::ICRNL | libc::INPCK | libc::ISTRIP | libc::IXON);
+            raw.c_lflag &= !(libc::ECHO | libc::ICANON | libc::IEXTEN | libc::ISIG);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw) != 0 {
+                return None;
+            }
+            Some(RawMode { orig })
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.orig);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::LuaError;
+use crate::lex::Lex;
+use crate::parse::ParseProto;
+use crate::readline::LineEditor;
+use crate::value::{LuaClosure, Upvalue, Value};
+use crate::vm::ExeState;
+
+const CHUNKNAME: &str = "=stdin";
+
+// 交互式解释器，所有输入行共享同一个ExeState
+pub struct Repl {
+    editor: LineEditor,
+    // 各行顶层声明的局部变量，作为后续chunk的上值
+    local_names: Vec<String>,
+    local_values: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Repl {
+            editor: LineEditor::new(LineEditor::default_history_file()),
+            local_names: Vec::new(),
+            local_values: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self, state: &mut ExeState) {
+        while let Some(f) = self.read_chunk(state) {
+            let f = match f {
+                Ok(f) => f,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    continue;
+                }
+            };
+            match state.call_function(f, Vec::new()) {
+                Ok(results) => {
+                    if !results.is_empty() {
+                        let print = state.get_global("print");
+                        if let Err(e) = state.call_function(print, results) {
+                            eprintln!("error calling 'print' ({})", e);
+                        }
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        println!();
+    }
+
+    // 读入一个完整的chunk并编译，输入结束返回None
+    fn read_chunk(&mut self, state: &ExeState) -> Option<Result<Value, LuaError>> {
+        let line = self.read_line(state, true)?;
+        let first = match line.strip_prefix('=') {
+            Some(exp) => format!("return {}", exp),
+            None => line.clone(),
+        };
+
+        // 先尝试作为表达式，以便打印其值
+        if let Ok(f) = self.compile(state, &format!("return {};", first)) {
+            self.editor.add_history(&line);
+            return Some(Ok(f));
+        }
+
+        let mut code = first;
+        loop {
+            match self.compile(state, &code) {
+                Err(LuaError::Syntax(msg)) if msg.ends_with("<eof>") => {
+                    // 输入不完整，继续读取
+                    match self.read_line(state, false) {
+                        Some(line) => {
+                            code.push('\n');
+                            code.push_str(&line);
+                        }
+                        None => {
+                            self.editor.add_history(&code);
+                            return Some(Err(LuaError::Syntax(msg)));
+                        }
+                    }
+                }
+                result => {
+                    self.editor.add_history(&code);
+                    return Some(result);
+                }
+            }
+        }
+    }
+
+    fn read_line(&mut self, state: &ExeState, first: bool) -> Option<String> {
+        let prompt = match (first, state.get_global(if first { "_PROMPT" } else { "_PROMPT2" })) {
+            (_, Value::String(s)) => String::from_utf8_lossy(&s).into_owned(),
+            (true, _) => "> ".to_string(),
+            (false, _) => ">> ".to_string(),
+        };
+        match self.editor.read_line(&prompt) {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("{}", e);
+                None
+            }
+        }
+    }
+
+    fn compile(&mut self, state: &ExeState, code: &str) -> Result<Value, LuaError> {
+        let mut proto = ParseProto::new(Lex::from_bytes(code.as_bytes(), CHUNKNAME));
+        proto.compile_repl(&self.local_names)?;
+
+        // 本chunk新声明的顶层局部变量，排在_ENV和之前的局部变量之后
+        for up in &proto.upvalues[1 + self.local_names.len()..] {
+            self.local_names.push(up.name.clone());
+            self.local_values.push(Rc::new(RefCell::new(Upvalue::Closed(Value::Nil))));
+        }
+        let env = Rc::new(RefCell::new(Upvalue::Closed(Value::Table(state.globals()))));
+        let upvalues = std::iter::once(env).chain(self.local_values.iter().cloned()).collect();
+        Ok(Value::LuaFunction(Rc::new(LuaClosure { proto: Rc::new(proto), upvalues: RefCell::new(upvalues) })))
+    }
+}
@@ -0,0 +1,151 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::LuaError;
+use crate::value::{Table, Value};
+use crate::vm::ExeState;
+
+// 库函数共用的参数检查，错误信息与C Lua的luaL_check*一致
+impl ExeState {
+    // "bad argument #i to 'fname' (msg)"。以方法调用时不计self，与luaL_argerror一致
+    pub fn arg_error(&self, i: usize, fname: &str, msg: &str) -> LuaError {
+        if self.get_info(None, 0).is_some_and(|info| info.namewhat == "method") {
+            if i == 1 {
+                return self.error(&format!("calling '{}' on bad self ({})", fname, msg));
+            }
+            return self.error(&format!("bad argument #{} to '{}' ({})", i - 1, fname, msg));
+        }
+        self.error(&format!("bad argument #{} to '{}' ({})", i, fname, msg))
+    }
+
+    pub fn type_error(&self, i: usize, fname: &str, expected: &str) -> LuaError {
+        let got = if i > self.get_args_count() {
+            "no value".to_string()
+        } else {
+            let v = self.get_arg(i);
+            match v.metatable().map(|mt| mt.borrow().get_str("__name")) {
+                Some(Value::String(name)) => String::from_utf8_lossy(&name).into_owned(),
+                _ => v.type_name().to_string(),
+            }
+        };
+        self.arg_error(i, fname, &format!("{} expected, got {}", expected, got))
+    }
+
+    pub fn check_any(&self, i: usize, fname: &str) -> Result<Value, LuaError> {
+        if i > self.get_args_count() {
+            return Err(self.arg_error(i, fname, "value expected"));
+        }
+        Ok(self.get_arg(i).clone())
+    }
+
+    pub fn check_table(&self, i: usize, fname: &str) -> Result<Rc<RefCell<Table>>, LuaError> {
+        match self.get_arg(i) {
+            Value::Table(t) => Ok(t.clone()),
+            _ => Err(self.type_error(i, fname, "table")),
+        }
+    }
+
+    // 整数或可以转为整数的浮点数、字符串
+    pub fn check_integer(&self, i: usize, fname: &str) -> Result<i64, LuaError> {
+        let v = self.get_arg(i);
+        match v.to_integer() {
+            Some(n) => Ok(n),
+            None if v.to_number().is_some() => Err(self.arg_error(i, fname, "number has no integer representation")),
+            None => Err(self.type_error(i, fname, "number")),
+        }
+    }
+
+    // 数字或可以转为数字的字符串，返回Integer或Float
+    pub fn check_number(&self, i: usize, fname: &str) -> Result<Value, LuaError> {
+        self.get_arg(i).to_number().ok_or_else(|| self.type_error(i, fname, "number"))
+    }
+
+    pub fn check_float(&self, i: usize, fname: &str) -> Result<f64, LuaError> {
+        self.get_arg(i).to_float().ok_or_else(|| self.type_error(i, fname, "number"))
+    }
+
+    // 字符串，数字按Lua规则转为字符串
+    pub fn check_string(&self, i: usize, fname: &str) -> Result<Rc<[u8]>, LuaError> {
+        match self.get_arg(i) {
+            Value::String(s) => Ok(s.clone()),
+            v @ (Value::Integer(_) | Value::Float(_)) => Ok(v.to_string().into_bytes().into()),
+            _ => Err(self.type_error(i, fname, "string")),
+        }
+    }
+
+    pub fn opt_integer(&self, i: usize, fname: &str, default: i64) -> Result<i64, LuaError> {
+        match self.get_arg(i) {
+            Value::Nil => Ok(default),
+            _ => self.check_integer(i, fname),
+        }
+    }
+
+    pub fn opt_float(&self, i: usize, fname: &str, default: f64) -> Result<f64, LuaError> {
+        match self.get_arg(i) {
+            Value::Nil => Ok(default),
+            _ => self.check_float(i, fname),
+        }
+    }
+
+    pub fn opt_string(&self, i: usize, fname: &str, default: &str) -> Result<Rc<[u8]>, LuaError> {
+        match self.get_arg(i) {
+            Value::Nil => Ok(default.as_bytes().into()),
+            _ => self.check_string(i, fname),
+        }
+    }
+
+    // 在选项列表中查找字符串参数，返回其下标
+    pub fn check_option(&self, i: usize, fname: &str, default: Option<&str>, options: &[&str]) -> Result<usize, LuaError> {
+        let name = match default {
+            Some(d) => self.opt_string(i, fname, d)?,
+            None => self.check_string(i, fname)?,
+        };
+        options.iter().position(|o| o.as_bytes() == &*name).ok_or_else(|| {
+            self.arg_error(i, fname, &format!("invalid option '{}'", String::from_utf8_lossy(&name)))
+        })
+    }
+
+    // 文件操作失败时返回 nil, "fname: 错误信息", errno
+    pub fn push_file_error(&mut self, err: &std::io::Error, fname: Option<&str>) -> i32 {
+        let msg = match fname {
+            Some(fname) => format!("{}: {}", fname, os_error_string(err)),
+            None => os_error_string(err),
+        };
+        self.push(Value::Nil);
+        self.push(Value::from(msg));
+        self.push(Value::Integer(err.raw_os_error().unwrap_or(0) as i64));
+        3
+    }
+
+    // 子进程结束时返回 true或nil, "exit"或"signal", 退出码或信号
+    pub fn push_exec_result(&mut self, status: std::process::ExitStatus) -> i32 {
+        use std::os::unix::process::ExitStatusExt;
+        let (what, code) = match status.code() {
+            Some(code) => ("exit", code),
+            None => ("signal", status.signal().unwrap_or(0)),
+        };
+        self.push(if what == "exit" && code == 0 { Value::Bool(true) } else { Value::Nil });
+        self.push(Value::from(what));
+        self.push(Value::Integer(code as i64));
+        3
+    }
+
+    // 把多个返回值压栈，返回个数
+    pub fn push_all(&mut self, values: Vec<Value>) -> i32 {
+        let n = values.len();
+        for v in values {
+            self.push(v);
+        }
+        n as i32
+    }
+}
+
+// 与C的strerror相同的错误信息，不带Rust附加的"(os error N)"
+pub fn os_error_string(err: &std::io::Error) -> String {
+    match err.raw_os_error() {
+        Some(errno) => unsafe {
+            std::ffi::CStr::from_ptr(libc::strerror(errno)).to_string_lossy().into_owned()
+        },
+        None => err.to_string(),
+    }
+}
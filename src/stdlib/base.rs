@@ -0,0 +1,485 @@
+use std::io::Write;
+
+use crate::error::LuaError;
+use crate::value::{RustFn, Value};
+use crate::vm::ExeState;
+
+pub fn open(state: &mut ExeState) {
+    let functions: [(&str, RustFn); 24] = [
+        ("assert", lua_assert),
+        ("collectgarbage", lua_collectgarbage),
+        ("dofile", lua_dofile),
+        ("error", lua_error),
+        ("getmetatable", lua_getmetatable),
+        ("ipairs", lua_ipairs),
+        ("load", lua_load),
+        ("loadfile", lua_loadfile),
+        ("loadstring", lua_loadstring),
+        ("next", lua_next),
+        ("pairs", lua_pairs),
+        ("pcall", lua_pcall),
+        ("print", lua_print),
+        ("rawequal", lua_rawequal),
+        ("rawget", lua_rawget),
+        ("rawlen", lua_rawlen),
+        ("rawset", lua_rawset),
+        ("select", lua_select),
+        ("setmetatable", lua_setmetatable),
+        ("tonumber", lua_tonumber),
+        ("tostring", lua_tostring),
+        ("type", lua_type),
+        ("warn", lua_warn),
+        ("xpcall", lua_xpcall),
+    ];
+    for (name, f) in functions {
+        state.set_global(name, Value::Function(f));
+    }
+    state.set_global("_G", Value::Table(state.globals()));
+    state.set_global("_VERSION", Value::from("Lua 5.4"));
+}
+
+// print(...)：各参数经tostring转换，以制表符分隔
+fn lua_print(state: &mut ExeState) -> Result<i32, LuaError> {
+    let mut line = Vec::new();
+    for i in 1..=state.get_args_count() {
+        if i > 1 {
+            line.push(b'\t');
+        }
+        let v = state.get_arg(i).clone();
+        if let Value::String(s) = state.tostring(&v)? {
+            line.extend_from_slice(&s);
+        }
+    }
+    line.push(b'\n');
+    let _ = std::io::stdout().write_all(&line);
+    Ok(0)
+}
+
+fn lua_type(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.check_any(1, "type")?;
+    state.push(Value::from(v.type_name()));
+    Ok(1)
+}
+
+fn lua_tostring(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.check_any(1, "tostring")?;
+    let s = state.tostring(&v)?;
+    state.push(s);
+    Ok(1)
+}
+
+// tonumber(e [, base])：不能转换时返回nil
+fn lua_tonumber(state: &mut ExeState) -> Result<i32, LuaError> {
+    let result = if let Value::Nil = state.get_arg(2) {
+        let v = state.check_any(1, "tonumber")?;
+        v.to_number().unwrap_or(Value::Nil)
+    } else {
+        let base = state.check_integer(2, "tonumber")?;
+        let Value::String(s) = state.get_arg(1).clone() else {
+            return Err(state.type_error(1, "tonumber", "string"));
+        };
+        if !(2..=36).contains(&base) {
+            return Err(state.arg_error(2, "tonumber", "base out of range"));
+        }
+        str_to_int(&s, base as u32).map_or(Value::Nil, Value::Integer)
+    };
+    state.push(result);
+    Ok(1)
+}
+
+// 按指定进制解析整数，允许前后空白和负号，溢出时回绕
+fn str_to_int(s: &[u8], base: u32) -> Option<i64> {
+    let s = s.trim_ascii();
+    let (neg, digits) = match s.strip_prefix(b"-") {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let mut n: i64 = 0;
+    for &b in digits {
+        let d = (b as char).to_digit(base)?;
+        n = n.wrapping_mul(base as i64).wrapping_add(d as i64);
+    }
+    Some(if neg { n.wrapping_neg() } else { n })
+}
+
+// ipairs(t)：从1开始迭代到第一个nil，读取时遵守`__index`
+fn lua_ipairs(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.check_any(1, "ipairs")?;
+    state.push(Value::Function(ipairs_aux));
+    state.push(t);
+    state.push(Value::Integer(0));
+    Ok(3)
+}
+
+fn ipairs_aux(state: &mut ExeState) -> Result<i32, LuaError> {
+    let i = state.check_integer(2, "ipairs")?.wrapping_add(1);
+    let t = state.get_arg(1).clone();
+    let v = state.index(&t, &Value::Integer(i))?;
+    if v == Value::Nil {
+        state.push(Value::Nil);
+        return Ok(1);
+    }
+    state.push(Value::Integer(i));
+    state.push(v);
+    Ok(2)
+}
+
+// pairs(t)：有`__pairs`元方法时使用其前3个返回值，否则返回next, t, nil
+fn lua_pairs(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.check_any(1, "pairs")?;
+    let mm = state.get_metamethod(&t, "__pairs");
+    if mm != Value::Nil {
+        let mut results = state.call_function(mm, vec![t])?;
+        results.resize(3, Value::Nil);
+        return Ok(state.push_all(results));
+    }
+    if !matches!(t, Value::Table(_)) {
+        return Err(state.type_error(1, "pairs", "table"));
+    }
+    state.push(Value::Function(lua_next));
+    state.push(t);
+    state.push(Value::Nil);
+    Ok(3)
+}
+
+pub(crate) fn lua_next(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.check_table(1, "next")?;
+    let next = t.borrow().next(state.get_arg(2));
+    match next {
+        Ok(Some((k, v))) => {
+            state.push(k);
+            state.push(v);
+            Ok(2)
+        }
+        Ok(None) => {
+            state.push(Value::Nil);
+            Ok(1)
+        }
+        Err(()) => Err(state.error("invalid key to 'next'")),
+    }
+}
+
+// select(n, ...)：返回第n个之后的参数，负数从末尾算起；select('#', ...)返回参数个数
+fn lua_select(state: &mut ExeState) -> Result<i32, LuaError> {
+    let count = state.get_args_count() as i64 - 1;
+    if let Value::String(s) = state.get_arg(1) {
+        if &**s == b"#" {
+            state.push(Value::Integer(count));
+            return Ok(1);
+        }
+    }
+    let n = state.check_integer(1, "select")?;
+    let n = if n < 0 {
+        count + n
+    } else if n > count {
+        count
+    } else {
+        n - 1
+    };
+    if n < 0 {
+        return Err(state.arg_error(1, "select", "index out of range"));
+    }
+    let values: Vec<Value> = (n as usize + 2..=count as usize + 1).map(|i| state.get_arg(i).clone()).collect();
+    Ok(state.push_all(values))
+}
+
+fn lua_rawequal(state: &mut ExeState) -> Result<i32, LuaError> {
+    let a = state.check_any(1, "rawequal")?;
+    let b = state.check_any(2, "rawequal")?;
+    state.push(Value::Bool(raw_equal(&a, &b)));
+    Ok(1)
+}
+
+// 不触发元方法的相等比较，整数和浮点数按数值比较
+fn raw_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Integer(i), Value::Float(f)) | (Value::Float(f), Value::Integer(i)) => *i as f64 == *f && *f as i64 == *i,
+        _ => a == b,
+    }
+}
+
+fn lua_rawget(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.check_table(1, "rawget")?;
+    let k = state.check_any(2, "rawget")?;
+    let v = t.borrow().get(&k);
+    state.push(v);
+    Ok(1)
+}
+
+fn lua_rawset(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.check_table(1, "rawset")?;
+    let k = state.check_any(2, "rawset")?;
+    let v = state.check_any(3, "rawset")?;
+    state.raw_set(&t, k, v)?;
+    state.push(Value::Table(t));
+    Ok(1)
+}
+
+fn lua_rawlen(state: &mut ExeState) -> Result<i32, LuaError> {
+    let n = match state.get_arg(1) {
+        Value::Table(t) => t.borrow().len(),
+        Value::String(s) => s.len() as i64,
+        _ => return Err(state.arg_error(1, "rawlen", "table or string expected")),
+    };
+    state.push(Value::Integer(n));
+    Ok(1)
+}
+
+fn lua_setmetatable(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.check_table(1, "setmetatable")?;
+    let mt = match state.get_arg(2) {
+        Value::Nil => None,
+        Value::Table(mt) => Some(mt.clone()),
+        _ => return Err(state.type_error(2, "setmetatable", "nil or table")),
+    };
+    if let Some(old) = &t.borrow().metatable {
+        if old.borrow().get_str("__metatable") != Value::Nil {
+            return Err(state.error("cannot change a protected metatable"));
+        }
+    }
+    t.borrow_mut().metatable = mt;
+    state.push(Value::Table(t));
+    Ok(1)
+}
+
+// getmetatable(v)：元表有`__metatable`字段时返回该字段
+fn lua_getmetatable(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.check_any(1, "getmetatable")?;
+    let result = match state.get_metatable(&v) {
+        Some(mt) => match mt.borrow().get_str("__metatable") {
+            Value::Nil => Value::Table(mt.clone()),
+            protected => protected,
+        },
+        None => Value::Nil,
+    };
+    state.push(result);
+    Ok(1)
+}
+
+// error(msg [, level])：level大于0且msg为字符串时加上出错位置
+fn lua_error(state: &mut ExeState) -> Result<i32, LuaError> {
+    let msg = state.get_arg(1).clone();
+    let level = state.opt_integer(2, "error", 1)?;
+    if let (Value::String(s), true) = (&msg, level > 0) {
+        let mut full = state.where_(level as usize).into_bytes();
+        full.extend_from_slice(s);
+        return Err(LuaError::Runtime(Value::from(full)));
+    }
+    Err(LuaError::Runtime(msg))
+}
+
+// assert(v [, message, ...])：成功时返回全部参数
+fn lua_assert(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.check_any(1, "assert")?;
+    if !v.is_false() {
+        return Ok(state.get_args_count() as i32);
+    }
+    if state.get_args_count() >= 2 {
+        return Err(LuaError::Runtime(state.get_arg(2).clone()));
+    }
+    Err(state.error("assertion failed!"))
+}
+
+// pcall(f, ...)：返回true加f的返回值，或false加错误对象。f可以让出
+fn lua_pcall(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = state.check_any(1, "pcall")?;
+    let args = (2..=state.get_args_count()).map(|i| state.get_arg(i).clone()).collect();
+    // 外层xpcall的消息处理函数不处理这里捕获的错误
+    let prev = state.set_error_handler(Value::Nil);
+    state.call_function_k(f, args, finish_pcall, prev)
+}
+
+// pcall和xpcall的延续，ctx为调用前的消息处理函数
+fn finish_pcall(state: &mut ExeState, result: Result<Vec<Value>, LuaError>, prev: Value) -> Result<i32, LuaError> {
+    state.set_error_handler(prev);
+    match result {
+        Ok(results) => {
+            state.push(Value::Bool(true));
+            Ok(state.push_all(results) + 1)
+        }
+        Err(e) if !e.is_catchable() => Err(e),
+        Err(e) => {
+            state.push(Value::Bool(false));
+            state.push(e.into_value());
+            Ok(2)
+        }
+    }
+}
+
+// xpcall(f, msgh, ...)：出错时在出错处以错误对象调用msgh，返回false加msgh的结果
+fn lua_xpcall(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = state.get_arg(1).clone();
+    let handler = state.check_any(2, "xpcall")?;
+    let args = (3..=state.get_args_count()).map(|i| state.get_arg(i).clone()).collect();
+    let prev = state.set_error_handler(handler);
+    state.call_function_k(f, args, finish_pcall, prev)
+}
+
+// 内存由Rc管理，没有可控制的收集器；各选项返回与C Lua相同类型的值
+fn lua_collectgarbage(state: &mut ExeState) -> Result<i32, LuaError> {
+    const OPTIONS: [&str; 10] = ["collect", "stop", "restart", "count", "step", "setpause", "setstepmul",
+        "isrunning", "generational", "incremental"];
+    let result = match OPTIONS[state.check_option(1, "collectgarbage", Some("collect"), &OPTIONS)?] {
+        // 安装了alloc::CountingAlloc时为本线程分配的KB数
+        "count" => Value::Float(crate::alloc::allocated().unwrap_or(0) as f64 / 1024.0),
+        "step" | "isrunning" => Value::Bool(true),
+        "generational" | "incremental" => Value::from("incremental"),
+        _ => Value::Integer(0),
+    };
+    state.push(result);
+    Ok(1)
+}
+
+// warn(msg1, ...)：以'@'开头的单个参数是控制消息
+fn lua_warn(state: &mut ExeState) -> Result<i32, LuaError> {
+    state.check_string(1, "warn")?;
+    let mut msg = Vec::new();
+    for i in 1..=state.get_args_count() {
+        msg.extend_from_slice(&state.check_string(i, "warn")?);
+    }
+    if state.get_args_count() == 1 && msg.starts_with(b"@") {
+        match &msg[..] {
+            b"@on" => state.warnings = true,
+            b"@off" => state.warnings = false,
+            _ => (),
+        }
+    } else if state.warnings {
+        eprintln!("Lua warning: {}", String::from_utf8_lossy(&msg));
+    }
+    Ok(0)
+}
+
+// 失败时返回 nil, 错误信息
+fn load_result(state: &mut ExeState, result: Result<Value, LuaError>) -> Result<i32, LuaError> {
+    match result {
+        Ok(f) => {
+            state.push(f);
+            Ok(1)
+        }
+        Err(e) => {
+            state.push(Value::Nil);
+            state.push(e.into_value());
+            Ok(2)
+        }
+    }
+}
+
+// 给出了env参数（即使为nil）时，它成为chunk的_ENV上值
+fn env_arg(state: &ExeState, i: usize) -> Option<Value> {
+    (i <= state.get_args_count()).then(|| state.get_arg(i).clone())
+}
+
+// 按mode检查chunk是文本还是二进制
+fn check_mode(code: &[u8], mode: &str) -> Result<(), LuaError> {
+    let (kind, allowed) = if code.first() == Some(&0x1b) {
+        ("binary", mode.contains('b'))
+    } else {
+        ("text", mode.contains('t'))
+    };
+    if !allowed {
+        return Err(LuaError::runtime(format!("attempt to load a {} chunk (mode is '{}')", kind, mode)));
+    }
+    Ok(())
+}
+
+fn load_code(state: &mut ExeState, code: Vec<u8>, chunkname: &str, mode: &str, env: Option<Value>) -> Result<Value, LuaError> {
+    // 沙箱中不能加载二进制chunk
+    let mode = if super::sandbox::is_sandboxed(state) { mode.replace('b', "") } else { mode.to_string() };
+    check_mode(&code, &mode)?;
+    state.load_bytes(&code, chunkname, env)
+}
+
+fn string_arg(v: &Value) -> Option<String> {
+    match v {
+        Value::String(s) => Some(String::from_utf8_lossy(s).into_owned()),
+        Value::Integer(_) | Value::Float(_) => Some(v.to_string()),
+        _ => None,
+    }
+}
+
+// load(chunk [, chunkname [, mode [, env]]])
+fn lua_load(state: &mut ExeState) -> Result<i32, LuaError> {
+    let chunk = state.get_arg(1).clone();
+    let mode = string_arg(state.get_arg(3)).unwrap_or_else(|| "bt".to_string());
+    let env = env_arg(state, 4);
+
+    let (code, default_name) = match chunk {
+        Value::String(s) => (s.to_vec(), String::from_utf8_lossy(&s).into_owned()),
+        Value::Function(_) | Value::LuaFunction(_) | Value::RustClosure(_) => {
+            // 反复调用读取函数，直到返回nil或空串
+            let mut code = Vec::new();
+            loop {
+                let piece = state.call_function(chunk.clone(), Vec::new())?;
+                match piece.into_iter().next() {
+                    None | Some(Value::Nil) => break,
+                    Some(Value::String(s)) if s.is_empty() => break,
+                    Some(Value::String(s)) => code.extend_from_slice(&s),
+                    Some(_) => return load_result(state, Err(LuaError::runtime("reader function must return a string"))),
+                }
+            }
+            (code, "=(load)".to_string())
+        }
+        _ => return Err(state.type_error(1, "load", "string")),
+    };
+    let chunkname = string_arg(state.get_arg(2)).unwrap_or(default_name);
+
+    let result = load_code(state, code, &chunkname, &mode, env);
+    load_result(state, result)
+}
+
+// loadstring(s [, chunkname])：Lua 5.1的接口，只接受字符串
+fn lua_loadstring(state: &mut ExeState) -> Result<i32, LuaError> {
+    let Some(s) = string_arg(state.get_arg(1)) else {
+        return Err(state.type_error(1, "loadstring", "string"));
+    };
+    let chunkname = string_arg(state.get_arg(2)).unwrap_or_else(|| s.clone());
+    let result = load_code(state, s.into_bytes(), &chunkname, "bt", None);
+    load_result(state, result)
+}
+
+// 读取文件，文件名为nil时读取标准输入
+fn read_file(filename: &Value) -> Result<(Vec<u8>, String), LuaError> {
+    use std::io::Read;
+
+    match filename {
+        Value::Nil => {
+            let mut code = Vec::new();
+            std::io::stdin().read_to_end(&mut code)
+                .map_err(|e| LuaError::runtime(format!("cannot read stdin: {}", e)))?;
+            Ok((code, "=stdin".to_string()))
+        }
+        Value::String(name) => {
+            let name = String::from_utf8_lossy(name);
+            let code = std::fs::read(&*name)
+                .map_err(|e| LuaError::runtime(format!("cannot open {}: {}", name, e)))?;
+            Ok((code, format!("@{}", name)))
+        }
+        v => Err(LuaError::runtime(format!("bad argument #1 (string expected, got {})", v.type_name()))),
+    }
+}
+
+// loadfile([filename [, mode [, env]]])
+fn lua_loadfile(state: &mut ExeState) -> Result<i32, LuaError> {
+    let filename = state.get_arg(1).clone();
+    let mode = string_arg(state.get_arg(2)).unwrap_or_else(|| "bt".to_string());
+    let env = env_arg(state, 3);
+    let result = read_file(&filename)
+        .and_then(|(code, chunkname)| load_code(state, code, &chunkname, &mode, env));
+    load_result(state, result)
+}
+
+// dofile([filename])：错误直接抛出，返回chunk的全部返回值
+fn lua_dofile(state: &mut ExeState) -> Result<i32, LuaError> {
+    let filename = state.get_arg(1).clone();
+    let (code, chunkname) = read_file(&filename)?;
+    let f = load_code(state, code, &chunkname, "bt", None)?;
+    let results = state.call_function(f, Vec::new())?;
+    let n = results.len();
+    for v in results {
+        state.push(v);
+    }
+    Ok(n as i32)
+}
@@ -0,0 +1,167 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::LuaError;
+use crate::value::{RustFn, Table, Value};
+use crate::vm::{CoStatus, Coroutine, ExeState};
+
+pub fn open(state: &mut ExeState) {
+    let functions: [(&str, RustFn); 8] = [
+        ("close", co_close),
+        ("create", co_create),
+        ("isyieldable", co_isyieldable),
+        ("resume", co_resume),
+        ("running", co_running),
+        ("status", co_status),
+        ("wrap", co_wrap),
+        ("yield", co_yield),
+    ];
+    let mut co = Table::new(0, 8);
+    for (name, f) in functions {
+        co.set_str(name, Value::Function(f));
+    }
+    state.set_global("coroutine", Value::Table(Rc::new(RefCell::new(co))));
+}
+
+fn check_coroutine(state: &ExeState, i: usize, fname: &str) -> Result<Rc<Coroutine>, LuaError> {
+    match state.get_arg(i) {
+        Value::Thread(co) => Ok(co.clone()),
+        _ => Err(state.type_error(i, fname, "coroutine")),
+    }
+}
+
+fn create(state: &ExeState, fname: &str) -> Result<Rc<Coroutine>, LuaError> {
+    match state.get_arg(1) {
+        f @ (Value::Function(_) | Value::RustClosure(_) | Value::LuaFunction(_)) => Ok(state.new_coroutine(f.clone())),
+        _ => Err(state.type_error(1, fname, "function")),
+    }
+}
+
+fn status_name(state: &ExeState, co: &Rc<Coroutine>) -> &'static str {
+    if Rc::ptr_eq(co, &state.running()) {
+        return "running";
+    }
+    match co.status() {
+        CoStatus::Suspended => "suspended",
+        CoStatus::Running => "running",
+        CoStatus::Normal => "normal",
+        CoStatus::Dead => "dead",
+    }
+}
+
+fn args_from(state: &ExeState, first: usize) -> Vec<Value> {
+    (first..=state.get_args_count()).map(|i| state.get_arg(i).clone()).collect()
+}
+
+// coroutine.create(f)
+fn co_create(state: &mut ExeState) -> Result<i32, LuaError> {
+    let co = create(state, "create")?;
+    state.push(Value::Thread(co));
+    Ok(1)
+}
+
+// coroutine.resume(co, ...)：返回true加让出或返回的值，或false加错误对象
+fn co_resume(state: &mut ExeState) -> Result<i32, LuaError> {
+    let co = check_coroutine(state, 1, "resume")?;
+    let args = args_from(state, 2);
+    match state.resume(&co, args) {
+        Ok(values) => {
+            state.push(Value::Bool(true));
+            Ok(state.push_all(values) + 1)
+        }
+        Err(e) if !e.is_catchable() => Err(e),
+        Err(e) => {
+            state.push(Value::Bool(false));
+            state.push(e.into_value());
+            Ok(2)
+        }
+    }
+}
+
+// coroutine.wrap(f)：返回恢复协程的函数，出错时关闭协程并传播错误
+fn co_wrap(state: &mut ExeState) -> Result<i32, LuaError> {
+    let co = create(state, "wrap")?;
+    let f = state.new_rust_closure(aux_wrap, vec![Value::Thread(co)]);
+    state.push(f);
+    Ok(1)
+}
+
+fn aux_wrap(state: &mut ExeState) -> Result<i32, LuaError> {
+    let Value::Thread(co) = state.get_upvalue(1) else {
+        unreachable!()
+    };
+    let args = args_from(state, 1);
+    let started = co.status() == CoStatus::Suspended;
+    match state.resume(&co, args) {
+        Ok(values) => Ok(state.push_all(values)),
+        Err(e) if !e.is_catchable() => Err(e),
+        Err(e) => {
+            // 协程中的错误：先关闭其待关闭变量
+            let mut err = e.into_value();
+            if started {
+                if let Err(e) = state.close_coroutine(&co) {
+                    err = e;
+                }
+            }
+            if let Value::String(s) = &err {
+                let mut full = state.where_(1).into_bytes();
+                full.extend_from_slice(s);
+                err = Value::from(full);
+            }
+            Err(LuaError::Runtime(err))
+        }
+    }
+}
+
+// coroutine.yield(...)
+fn co_yield(state: &mut ExeState) -> Result<i32, LuaError> {
+    let values = args_from(state, 1);
+    Err(state.yield_values(values))
+}
+
+// coroutine.status(co)
+fn co_status(state: &mut ExeState) -> Result<i32, LuaError> {
+    let co = check_coroutine(state, 1, "status")?;
+    let name = status_name(state, &co);
+    state.push(Value::from(name));
+    Ok(1)
+}
+
+// coroutine.isyieldable([co])
+fn co_isyieldable(state: &mut ExeState) -> Result<i32, LuaError> {
+    let co = match state.get_arg(1) {
+        Value::Nil => state.running(),
+        _ => check_coroutine(state, 1, "isyieldable")?,
+    };
+    let yieldable = state.is_yieldable(&co);
+    state.push(Value::Bool(yieldable));
+    Ok(1)
+}
+
+// coroutine.running()：返回正在运行的协程，以及它是否为主线程
+fn co_running(state: &mut ExeState) -> Result<i32, LuaError> {
+    let co = state.running();
+    let is_main = state.is_main_thread(&co);
+    state.push(Value::Thread(co));
+    state.push(Value::Bool(is_main));
+    Ok(2)
+}
+
+// coroutine.close(co)：关闭挂起或已结束的协程，返回true，或false加错误对象
+fn co_close(state: &mut ExeState) -> Result<i32, LuaError> {
+    let co = check_coroutine(state, 1, "close")?;
+    match status_name(state, &co) {
+        "suspended" | "dead" => match state.close_coroutine(&co) {
+            Ok(()) => {
+                state.push(Value::Bool(true));
+                Ok(1)
+            }
+            Err(err) => {
+                state.push(Value::Bool(false));
+                state.push(err);
+                Ok(2)
+            }
+        },
+        name => Err(state.error(&format!("cannot close a {} coroutine", name))),
+    }
+}
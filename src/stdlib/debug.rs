@@ -0,0 +1,423 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::debug::{DebugInfo, HookEvent, HOOK_CALL, HOOK_COUNT, HOOK_LINE, HOOK_RETURN};
+use crate::error::LuaError;
+use crate::value::{RustFn, Table, Value};
+use crate::vm::{Coroutine, ExeState};
+
+const HOOK_KEY: &str = "_HOOK";  // 注册表中debug.sethook设置的钩子函数
+
+pub fn open(state: &mut ExeState) {
+    let functions: [(&str, RustFn); 13] = [
+        ("gethook", db_gethook),
+        ("getinfo", db_getinfo),
+        ("getlocal", db_getlocal),
+        ("getmetatable", db_getmetatable),
+        ("getregistry", db_getregistry),
+        ("getupvalue", db_getupvalue),
+        ("sethook", db_sethook),
+        ("setlocal", db_setlocal),
+        ("setmetatable", db_setmetatable),
+        ("setupvalue", db_setupvalue),
+        ("traceback", db_traceback),
+        ("upvalueid", db_upvalueid),
+        ("upvaluejoin", db_upvaluejoin),
+    ];
+    let mut debug = Table::new(0, functions.len());
+    for (name, f) in functions {
+        debug.set_str(name, Value::Function(f));
+    }
+    state.set_global("debug", Value::Table(Rc::new(RefCell::new(debug))));
+}
+
+// 可选的第一个参数为协程：返回协程（None为当前线程）和其余参数前的偏移
+fn thread_arg(state: &ExeState) -> (Option<Rc<Coroutine>>, usize) {
+    match state.get_arg(1) {
+        Value::Thread(co) => (Some(co.clone()), 1),
+        _ => (None, 0),
+    }
+}
+
+fn is_function(v: &Value) -> bool {
+    matches!(v, Value::Function(_) | Value::LuaFunction(_) | Value::RustClosure(_))
+}
+
+fn check_function(state: &ExeState, i: usize, fname: &str) -> Result<Value, LuaError> {
+    match state.get_arg(i) {
+        f if is_function(f) => Ok(f.clone()),
+        _ => Err(state.type_error(i, fname, "function")),
+    }
+}
+
+// 检查调用栈层数参数，超出范围时报错
+fn check_level(state: &ExeState, co: Option<&Rc<Coroutine>>, i: usize, fname: &str) -> Result<usize, LuaError> {
+    let level = state.check_integer(i, fname)?;
+    match usize::try_from(level) {
+        Ok(level) if state.get_info(co, level).is_some() => Ok(level),
+        _ => Err(state.arg_error(i, fname, "level out of range")),
+    }
+}
+
+// debug.getinfo([thread,] f, [what])：f为函数或调用栈层数，层数超出范围时返回nil
+fn db_getinfo(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (co, arg) = thread_arg(state);
+    let options = state.opt_string(arg + 2, "getinfo", "flnSrtu")?;
+    if options.first() == Some(&b'>') || options.iter().any(|c| !b"SlnurtfL".contains(c)) {
+        return Err(state.arg_error(arg + 2, "getinfo", "invalid option"));
+    }
+    let info = match state.get_arg(arg + 1) {
+        f if is_function(f) => DebugInfo::of_function(f),
+        _ => {
+            let level = state.check_integer(arg + 1, "getinfo")?;
+            match usize::try_from(level).ok().and_then(|level| state.get_info(co.as_ref(), level)) {
+                Some(info) => info,
+                None => {
+                    state.push(Value::Nil);
+                    return Ok(1);
+                }
+            }
+        }
+    };
+
+    let mut t = Table::new(0, 16);
+    for &option in options.iter() {
+        match option {
+            b'S' => {
+                t.set_str("source", Value::from(info.source.as_str()));
+                t.set_str("short_src", Value::from(info.short_src.as_str()));
+                t.set_str("linedefined", Value::Integer(info.line_defined));
+                t.set_str("lastlinedefined", Value::Integer(info.last_line_defined));
+                t.set_str("what", Value::from(info.what));
+            }
+            b'l' => t.set_str("currentline", Value::Integer(info.current_line)),
+            b'u' => {
+                t.set_str("nups", Value::Integer(info.nups as i64));
+                t.set_str("nparams", Value::Integer(info.nparams as i64));
+                t.set_str("isvararg", Value::Bool(info.is_vararg));
+            }
+            b'n' => {
+                t.set_str("name", info.name.as_deref().map_or(Value::Nil, Value::from));
+                t.set_str("namewhat", Value::from(info.namewhat));
+            }
+            b'r' => {
+                t.set_str("ftransfer", Value::Integer(info.ftransfer as i64));
+                t.set_str("ntransfer", Value::Integer(info.ntransfer as i64));
+            }
+            b't' => t.set_str("istailcall", Value::Bool(info.is_tail_call)),
+            b'f' => t.set_str("func", info.func.clone()),
+            b'L' => t.set_str("activelines", active_lines(&info.func)),
+            _ => unreachable!(),
+        }
+    }
+    state.push(Value::Table(Rc::new(RefCell::new(t))));
+    Ok(1)
+}
+
+// 有指令的行号集合，Rust函数为nil
+fn active_lines(f: &Value) -> Value {
+    let Value::LuaFunction(f) = f else {
+        return Value::Nil;
+    };
+    let mut lines = Table::new(0, f.proto.lineinfo.len());
+    for &line in f.proto.lineinfo.iter().filter(|&&line| line != 0) {
+        lines.set(Value::Integer(line as i64), Value::Bool(true));
+    }
+    Value::Table(Rc::new(RefCell::new(lines)))
+}
+
+// debug.getlocal([thread,] f, n)：f为函数时只返回第n个参数的名字
+fn db_getlocal(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (co, arg) = thread_arg(state);
+    let n = state.check_integer(arg + 2, "getlocal")?;
+    if is_function(state.get_arg(arg + 1)) {
+        let name = match state.get_arg(arg + 1) {
+            Value::LuaFunction(f) if n > 0 && (n as usize) <= f.proto.num_params => {
+                f.proto.local_name(n as usize - 1, 0).map_or(Value::Nil, Value::from)
+            }
+            _ => Value::Nil,
+        };
+        state.push(name);
+        return Ok(1);
+    }
+    let level = check_level(state, co.as_ref(), arg + 1, "getlocal")?;
+    match state.get_local(co.as_ref(), level, n) {
+        Some((name, v)) => {
+            state.push(Value::from(name));
+            state.push(v);
+            Ok(2)
+        }
+        None => {
+            state.push(Value::Nil);
+            Ok(1)
+        }
+    }
+}
+
+// debug.setlocal([thread,] level, n, value)：返回变量名，变量不存在时返回nil
+fn db_setlocal(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (co, arg) = thread_arg(state);
+    let level = check_level(state, co.as_ref(), arg + 1, "setlocal")?;
+    let n = state.check_integer(arg + 2, "setlocal")?;
+    let v = state.check_any(arg + 3, "setlocal")?;
+    let name = state.set_local(co.as_ref(), level, n, v);
+    state.push(name.map_or(Value::Nil, Value::from));
+    Ok(1)
+}
+
+// debug.getupvalue(f, n)：返回上值的名字和值，不存在时不返回值
+fn db_getupvalue(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = check_function(state, 1, "getupvalue")?;
+    let n = state.check_integer(2, "getupvalue")?;
+    match usize::try_from(n).ok().and_then(|n| state.function_upvalue(&f, n)) {
+        Some((name, v)) => {
+            state.push(Value::from(name));
+            state.push(v);
+            Ok(2)
+        }
+        None => Ok(0),
+    }
+}
+
+// debug.setupvalue(f, n, value)：返回上值的名字，不存在时不返回值
+fn db_setupvalue(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = check_function(state, 1, "setupvalue")?;
+    let n = state.check_integer(2, "setupvalue")?;
+    let v = state.check_any(3, "setupvalue")?;
+    match usize::try_from(n).ok().and_then(|n| state.set_function_upvalue(&f, n, v)) {
+        Some(name) => {
+            state.push(Value::from(name));
+            Ok(1)
+        }
+        None => Ok(0),
+    }
+}
+
+// 上值的唯一标识：Lua函数的上值为共享的上值对象，Rust闭包的上值为其所在的位置
+fn upvalue_id(f: &Value, n: i64) -> Option<*const ()> {
+    let i = usize::try_from(n).ok()?.checked_sub(1)?;
+    match f {
+        Value::LuaFunction(f) => f.upvalues.borrow().get(i).map(|up| Rc::as_ptr(up) as *const ()),
+        Value::RustClosure(c) => {
+            let upvalues = c.upvalues.borrow();
+            upvalues.get(i).map(|up| up as *const Value as *const ())
+        }
+        _ => None,
+    }
+}
+
+// debug.upvalueid(f, n)：上值不存在时返回nil
+fn db_upvalueid(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = check_function(state, 1, "upvalueid")?;
+    let n = state.check_integer(2, "upvalueid")?;
+    let id = match upvalue_id(&f, n) {
+        Some(p) => state.new_light_userdata(p as *mut std::ffi::c_void),
+        None => Value::Nil,
+    };
+    state.push(id);
+    Ok(1)
+}
+
+// debug.upvaluejoin(f1, n1, f2, n2)：让Lua函数f1的第n1个上值引用f2的第n2个上值
+fn db_upvaluejoin(state: &mut ExeState) -> Result<i32, LuaError> {
+    let mut ups = Vec::new();
+    for (fi, ni) in [(1, 2), (3, 4)] {
+        let f = match check_function(state, fi, "upvaluejoin")? {
+            Value::LuaFunction(f) => f,
+            _ => return Err(state.arg_error(fi, "upvaluejoin", "Lua function expected")),
+        };
+        let n = state.check_integer(ni, "upvaluejoin")?;
+        if upvalue_id(&Value::LuaFunction(f.clone()), n).is_none() {
+            return Err(state.arg_error(ni, "upvaluejoin", "invalid upvalue index"));
+        }
+        ups.push((f, n as usize - 1));
+    }
+    let up = ups[1].0.upvalues.borrow()[ups[1].1].clone();
+    ups[0].0.upvalues.borrow_mut()[ups[0].1] = up;
+    Ok(0)
+}
+
+// 调用debug.sethook设置的Lua函数：参数为事件名，line事件还有行号
+fn lua_hook(state: &mut ExeState, event: HookEvent) -> Result<(), LuaError> {
+    let f = state.registry().borrow().get_str(HOOK_KEY);
+    let mut args = vec![Value::from(event.name())];
+    if let HookEvent::Line(line) = event {
+        args.push(Value::Integer(line as i64));
+    }
+    state.call_function(f, args)?;
+    Ok(())
+}
+
+// debug.sethook([thread,] hook, mask [, count])：不带参数时关闭钩子。钩子对所有线程生效
+fn db_sethook(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (_, arg) = thread_arg(state);
+    if matches!(state.get_arg(arg + 1), Value::Nil) {
+        state.registry().borrow_mut().set_str(HOOK_KEY, Value::Nil);
+        state.set_hook(None, 0, 0);
+        return Ok(0);
+    }
+    let smask = state.check_string(arg + 2, "sethook")?;
+    let f = check_function(state, arg + 1, "sethook")?;
+    let count = state.opt_integer(arg + 3, "sethook", 0)?.clamp(0, u32::MAX as i64) as u32;
+    let mut mask = 0;
+    for (c, bit) in [(b'c', HOOK_CALL), (b'r', HOOK_RETURN), (b'l', HOOK_LINE)] {
+        if smask.contains(&c) {
+            mask |= bit;
+        }
+    }
+    if count > 0 {
+        mask |= HOOK_COUNT;
+    }
+    state.registry().borrow_mut().set_str(HOOK_KEY, f);
+    state.set_hook(Some(Rc::new(lua_hook)), mask, count);
+    Ok(0)
+}
+
+// debug.gethook([thread])：返回钩子函数、掩码和计数，没有钩子时返回nil
+fn db_gethook(state: &mut ExeState) -> Result<i32, LuaError> {
+    let mask = state.hook_mask();
+    if mask == 0 {
+        state.push(Value::Nil);
+        return Ok(1);
+    }
+    let f = match state.registry().borrow().get_str(HOOK_KEY) {
+        Value::Nil => Value::from("external hook"),
+        f => f,
+    };
+    let mut smask = String::new();
+    for (c, bit) in [('c', HOOK_CALL), ('r', HOOK_RETURN), ('l', HOOK_LINE)] {
+        if mask & bit != 0 {
+            smask.push(c);
+        }
+    }
+    state.push(f);
+    state.push(Value::from(smask));
+    state.push(Value::Integer(state.hook_count() as i64));
+    Ok(3)
+}
+
+// debug.getmetatable(v)：不理会`__metatable`字段
+fn db_getmetatable(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.check_any(1, "getmetatable")?;
+    let mt = state.get_metatable(&v).map_or(Value::Nil, Value::Table);
+    state.push(mt);
+    Ok(1)
+}
+
+// debug.setmetatable(v, mt)：可以设置任何类型的元表，返回v
+fn db_setmetatable(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.get_arg(1).clone();
+    let mt = match state.get_arg(2) {
+        Value::Nil => None,
+        Value::Table(mt) => Some(mt.clone()),
+        _ => return Err(state.type_error(2, "setmetatable", "nil or table")),
+    };
+    state.set_type_metatable(&v, mt);
+    state.push(v);
+    Ok(1)
+}
+
+fn db_getregistry(state: &mut ExeState) -> Result<i32, LuaError> {
+    state.push(Value::Table(state.registry()));
+    Ok(1)
+}
+
+// debug.traceback([thread,] [message [, level]])：message不是字符串或nil时原样返回
+fn db_traceback(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (co, arg) = thread_arg(state);
+    let msg = match state.get_arg(arg + 1) {
+        Value::Nil => None,
+        v @ (Value::String(_) | Value::Integer(_) | Value::Float(_)) => Some(v.clone()),
+        v => {
+            let v = v.clone();
+            state.push(v);
+            return Ok(1);
+        }
+    };
+    let is_current = co.as_ref().is_none_or(|co| Rc::ptr_eq(co, &state.running()));
+    let level = state.opt_integer(arg + 2, "traceback", if is_current { 1 } else { 0 })?;
+    let mut result = Vec::new();
+    if let Some(msg) = msg {
+        match msg {
+            Value::String(s) => result.extend_from_slice(&s),
+            v => result.extend_from_slice(v.to_string().as_bytes()),
+        }
+        result.push(b'\n');
+    }
+    result.extend_from_slice(traceback(state, co.as_ref(), level.max(0) as usize).as_bytes());
+    state.push(Value::from(result));
+    Ok(1)
+}
+
+const LEVELS1: usize = 10;  // 调用栈过深时只列出开头的层数
+const LEVELS2: usize = 11;  // 和结尾的层数
+
+// co的调用栈从level层起的"stack traceback:"列表，与luaL_traceback的格式相同
+pub fn traceback(state: &ExeState, co: Option<&Rc<Coroutine>>, level: usize) -> String {
+    let mut out = String::from("stack traceback:");
+    let last = state.call_depth(co).saturating_sub(1);
+    let mut limit2show = if last.saturating_sub(level) > LEVELS1 + LEVELS2 { Some(LEVELS1) } else { None };
+    let mut level = level;
+    while let Some(info) = state.get_info(co, level) {
+        level += 1;
+        if limit2show == Some(0) {
+            let n = last - level - LEVELS2 + 1;
+            out.push_str(&format!("\n\t...\t(skipping {} levels)", n));
+            level += n;
+            limit2show = None;
+            continue;
+        }
+        limit2show = limit2show.map(|l| l - 1);
+        if info.current_line <= 0 {
+            out.push_str(&format!("\n\t{}: in ", info.short_src));
+        } else {
+            out.push_str(&format!("\n\t{}:{}: in ", info.short_src, info.current_line));
+        }
+        out.push_str(&func_name(state, &info));
+        if info.is_tail_call {
+            out.push_str("\n\t(...tail calls...)");
+        }
+    }
+    out
+}
+
+fn func_name(state: &ExeState, info: &DebugInfo) -> String {
+    if let Some(name) = global_func_name(state, &info.func) {
+        format!("function '{}'", name)
+    } else if let (Some(name), false) = (&info.name, info.namewhat.is_empty()) {
+        format!("{} '{}'", info.namewhat, name)
+    } else if info.what == "main" {
+        "main chunk".to_string()
+    } else if info.what != "C" {
+        format!("function <{}:{}>", info.short_src, info.line_defined)
+    } else {
+        "?".to_string()
+    }
+}
+
+// 在package.loaded的各模块中查找函数，返回"模块.字段"，全局函数不带"_G."
+fn global_func_name(state: &ExeState, f: &Value) -> Option<String> {
+    let Value::Table(loaded) = state.registry().borrow().get_str("_LOADED") else {
+        return None;
+    };
+    let loaded = loaded.borrow();
+    let mut key = Value::Nil;
+    while let Ok(Some((modname, module))) = loaded.next(&key) {
+        if let (Value::String(modname), Value::Table(module)) = (&modname, &module) {
+            let module = module.borrow();
+            let mut field = Value::Nil;
+            while let Ok(Some((name, v))) = module.next(&field) {
+                if let (Value::String(name), true) = (&name, v == *f) {
+                    let name = String::from_utf8_lossy(name);
+                    return Some(match &modname[..] {
+                        b"_G" => name.into_owned(),
+                        modname => format!("{}.{}", String::from_utf8_lossy(modname), name),
+                    });
+                }
+                field = name;
+            }
+        }
+        key = modname;
+    }
+    None
+}
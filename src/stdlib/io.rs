@@ -0,0 +1,804 @@
+use std::cell::RefCell;
+use std::ffi::OsStr;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::process::{Child, ChildStdin, ChildStdout, Command, ExitStatus, Stdio};
+use std::rc::Rc;
+
+use crate::error::LuaError;
+use crate::value::{fmt_float, fmt_g, RustFn, Table, UserData, Value};
+use crate::vm::ExeState;
+
+const IO_INPUT: &str = "_IO_input";
+const IO_OUTPUT: &str = "_IO_output";
+
+const BUFFER_SIZE: usize = 8192;
+const MAX_ARG_LINE: usize = 250;  // lines()最多保存的格式个数
+const MAX_LEN_NUM: usize = 200;  // read("n")读入数字的最大长度
+
+pub fn open(state: &mut ExeState) {
+    let methods: [(&str, RustFn); 7] = [
+        ("close", io_close),
+        ("flush", f_flush),
+        ("lines", f_lines),
+        ("read", f_read),
+        ("seek", f_seek),
+        ("setvbuf", f_setvbuf),
+        ("write", f_write),
+    ];
+    let meta = state.register_userdata_type::<LuaFile>("FILE*", &methods);
+    meta.borrow_mut().set_str("__tostring", Value::Function(f_tostring));
+    meta.borrow_mut().set_str("__close", Value::Function(f_gc));
+
+    let functions: [(&str, RustFn); 11] = [
+        ("close", io_close),
+        ("flush", io_flush),
+        ("input", io_input),
+        ("lines", io_lines),
+        ("open", io_open),
+        ("output", io_output),
+        ("popen", io_popen),
+        ("read", io_read),
+        ("tmpfile", io_tmpfile),
+        ("type", io_type),
+        ("write", io_write),
+    ];
+    let mut io = Table::new(0, 16);
+    for (name, f) in functions {
+        io.set_str(name, Value::Function(f));
+    }
+
+    let stdin = state.new_userdata(LuaFile::new(Stream::Stdin));
+    let stdout = state.new_userdata(LuaFile::new(Stream::Stdout));
+    let stderr = state.new_userdata(LuaFile::new(Stream::Stderr));
+    io.set_str("stdin", stdin.clone());
+    io.set_str("stdout", stdout.clone());
+    io.set_str("stderr", stderr);
+    let registry = state.registry();
+    registry.borrow_mut().set_str(IO_INPUT, stdin);
+    registry.borrow_mut().set_str(IO_OUTPUT, stdout);
+
+    state.set_global("io", Value::Table(Rc::new(RefCell::new(io))));
+}
+
+// ---------- 文件句柄 ----------
+
+enum Stream {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(BufReader<File>),
+    ReadPipe(Child, BufReader<ChildStdout>),
+    WritePipe(Child, ChildStdin),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Buffering {
+    No,
+    Full,
+    Line,
+}
+
+// Lua的文件对象，作为用户数据保存；被回收时由Drop关闭
+pub struct LuaFile {
+    stream: Option<Stream>,  // None表示已关闭
+    wbuf: Vec<u8>,  // 普通文件的写缓冲，读缓冲由BufReader负责
+    buffering: Buffering,
+    bufsize: usize,
+}
+
+fn bad_fd() -> io::Error {
+    io::Error::from_raw_os_error(libc::EBADF)
+}
+
+impl LuaFile {
+    fn new(stream: Stream) -> Self {
+        LuaFile { stream: Some(stream), wbuf: Vec::new(), buffering: Buffering::Full, bufsize: BUFFER_SIZE }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.stream.is_none()
+    }
+
+    fn is_std(&self) -> bool {
+        matches!(self.stream, Some(Stream::Stdin | Stream::Stdout | Stream::Stderr))
+    }
+
+    fn flush_wbuf(&mut self) -> io::Result<()> {
+        if self.wbuf.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::take(&mut self.wbuf);
+        match &mut self.stream {
+            Some(Stream::File(r)) => r.get_mut().write_all(&data),
+            _ => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_wbuf()?;
+        match &mut self.stream {
+            Some(Stream::Stdout) => io::stdout().flush(),
+            Some(Stream::Stderr) => io::stderr().flush(),
+            Some(Stream::WritePipe(_, w)) => w.flush(),
+            _ => Ok(()),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.stream {
+            Some(Stream::Stdout) => {
+                let mut out = io::stdout();
+                out.write_all(data)?;
+                if self.buffering == Buffering::No {
+                    out.flush()?;
+                }
+                Ok(())
+            }
+            Some(Stream::Stderr) => io::stderr().write_all(data),
+            Some(Stream::WritePipe(_, w)) => w.write_all(data),
+            Some(Stream::File(r)) => {
+                // 读过之后写，先把文件位置退回到已读取的位置
+                if !r.buffer().is_empty() {
+                    let pos = r.stream_position()?;
+                    r.seek(SeekFrom::Start(pos))?;
+                }
+                self.wbuf.extend_from_slice(data);
+                let full = match self.buffering {
+                    Buffering::No => true,
+                    Buffering::Line => data.contains(&b'\n'),
+                    Buffering::Full => self.wbuf.len() >= self.bufsize,
+                };
+                if full { self.flush_wbuf() } else { Ok(()) }
+            }
+            _ => Err(bad_fd()),
+        }
+    }
+
+    fn with_reader<R>(&mut self, f: impl FnOnce(&mut dyn BufRead) -> io::Result<R>) -> io::Result<R> {
+        self.flush_wbuf()?;
+        match &mut self.stream {
+            Some(Stream::Stdin) => f(&mut io::stdin().lock()),
+            Some(Stream::File(r)) => f(r),
+            Some(Stream::ReadPipe(_, r)) => f(r),
+            _ => Err(bad_fd()),
+        }
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.flush_wbuf()?;
+        match &mut self.stream {
+            Some(Stream::File(r)) => r.seek(pos),
+            _ => Err(io::Error::from_raw_os_error(libc::ESPIPE)),
+        }
+    }
+
+    // 关闭文件，管道返回子进程的退出状态
+    fn close(&mut self) -> io::Result<Option<ExitStatus>> {
+        let flushed = self.flush();
+        let status = match self.stream.take() {
+            Some(Stream::ReadPipe(mut child, r)) => {
+                drop(r);
+                Some(child.wait()?)
+            }
+            Some(Stream::WritePipe(mut child, w)) => {
+                drop(w);
+                Some(child.wait()?)
+            }
+            _ => None,
+        };
+        flushed.map(|_| status)
+    }
+}
+
+impl Drop for LuaFile {
+    fn drop(&mut self) {
+        if self.is_std() {
+            let _ = self.flush();
+        } else {
+            let _ = self.close();
+        }
+    }
+}
+
+// 刷新默认输出文件的缓冲，供os.exit在退出前调用
+pub(crate) fn flush_output(state: &ExeState) {
+    if let Value::UserData(u) = state.registry().borrow().get_str(IO_OUTPUT) {
+        if let Some(mut f) = u.borrow_mut::<LuaFile>() {
+            let _ = f.flush();
+        }
+    }
+}
+
+// ---------- 读取 ----------
+
+enum Format {
+    Number,
+    Line(bool),  // 是否去掉换行符
+    All,
+    Chars(usize),
+}
+
+fn check_format(state: &ExeState, v: &Value, i: usize, fname: &str) -> Result<Format, LuaError> {
+    if let Value::Integer(_) | Value::Float(_) = v {
+        let n = v.to_integer().ok_or_else(|| state.arg_error(i, fname, "number has no integer representation"))?;
+        return Ok(Format::Chars(n.max(0) as usize));
+    }
+    let Value::String(p) = v else { return Err(state.type_error(i, fname, "string")) };
+    let p = p.strip_prefix(b"*").unwrap_or(p);  // 兼容旧版本的"*l"等写法
+    match p.first() {
+        Some(b'n') => Ok(Format::Number),
+        Some(b'l') => Ok(Format::Line(true)),
+        Some(b'L') => Ok(Format::Line(false)),
+        Some(b'a') => Ok(Format::All),
+        _ => Err(state.arg_error(i, fname, "invalid format")),
+    }
+}
+
+// 解析read的格式参数，first为第一个格式的参数位置
+fn check_formats(state: &ExeState, first: usize, fname: &str) -> Result<Vec<Format>, LuaError> {
+    let n = state.get_args_count();
+    if n < first {
+        return Ok(vec![Format::Line(true)]);
+    }
+    (first..=n).map(|i| check_format(state, state.get_arg(i), i, fname)).collect()
+}
+
+// 依次按格式读取，某个格式失败时以nil结束
+fn read_formats(file: &UserData, formats: &[Format]) -> io::Result<Vec<Value>> {
+    let mut f = file.borrow_mut::<LuaFile>().unwrap();
+    f.with_reader(|r| {
+        let mut results = Vec::with_capacity(formats.len());
+        for format in formats {
+            let v = match format {
+                Format::Number => read_number(r)?,
+                Format::Line(chop) => read_line(r, *chop)?,
+                Format::All => Some(Value::String(read_up_to(r, u64::MAX)?.into())),
+                Format::Chars(0) => {
+                    if r.fill_buf()?.is_empty() { None } else { Some(Value::from("")) }
+                }
+                Format::Chars(n) => {
+                    let buf = read_up_to(r, *n as u64)?;
+                    if buf.is_empty() { None } else { Some(Value::String(buf.into())) }
+                }
+            };
+            match v {
+                Some(v) => results.push(v),
+                None => {
+                    results.push(Value::Nil);
+                    break;
+                }
+            }
+        }
+        Ok(results)
+    })
+}
+
+// 读到文件尾或读满n字节。超出内存上限时以OutOfMemory失败，而不是终止程序
+fn read_up_to(r: &mut dyn BufRead, n: u64) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    while (buf.len() as u64) < n {
+        let chunk = r.fill_buf()?;
+        if chunk.is_empty() {
+            break;
+        }
+        let len = chunk.len().min((n - buf.len() as u64).min(usize::MAX as u64) as usize);
+        crate::alloc::try_reserve(&mut buf, len).map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        buf.extend_from_slice(&chunk[..len]);
+        r.consume(len);
+    }
+    Ok(buf)
+}
+
+fn read_line(r: &mut dyn BufRead, chop: bool) -> io::Result<Option<Value>> {
+    let mut buf = Vec::new();
+    if r.read_until(b'\n', &mut buf)? == 0 {
+        return Ok(None);
+    }
+    if chop && buf.last() == Some(&b'\n') {
+        buf.pop();
+    }
+    Ok(Some(Value::String(buf.into())))
+}
+
+// 读取一个数字：先按数字的语法读入尽量长的前缀，再整体转换
+struct NumReader<'a> {
+    r: &'a mut dyn BufRead,
+    buf: Vec<u8>,
+    overflow: bool,
+}
+
+impl NumReader<'_> {
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.r.fill_buf()?.first().copied())
+    }
+
+    // 接受当前字符
+    fn next(&mut self, c: u8) -> bool {
+        if self.buf.len() >= MAX_LEN_NUM {
+            self.overflow = true;
+            return false;
+        }
+        self.buf.push(c);
+        self.r.consume(1);
+        true
+    }
+
+    // 当前字符属于set时接受它
+    fn test2(&mut self, set: &[u8; 2]) -> io::Result<bool> {
+        match self.peek()? {
+            Some(c) if c == set[0] || c == set[1] => Ok(self.next(c)),
+            _ => Ok(false),
+        }
+    }
+
+    fn read_digits(&mut self, hex: bool) -> io::Result<usize> {
+        let mut count = 0;
+        while let Some(c) = self.peek()? {
+            let digit = if hex { c.is_ascii_hexdigit() } else { c.is_ascii_digit() };
+            if !digit || !self.next(c) {
+                break;
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+fn read_number(r: &mut dyn BufRead) -> io::Result<Option<Value>> {
+    let mut rn = NumReader { r, buf: Vec::new(), overflow: false };
+    while rn.peek()?.is_some_and(|c| c.is_ascii_whitespace() || c == 0x0b) {
+        rn.r.consume(1);
+    }
+    rn.test2(b"-+")?;
+    let mut count = 0;
+    let mut hex = false;
+    if rn.test2(b"00")? {
+        if rn.test2(b"xX")? {
+            hex = true;
+        } else {
+            count = 1;
+        }
+    }
+    count += rn.read_digits(hex)?;
+    if rn.test2(b"..")? {
+        count += rn.read_digits(hex)?;
+    }
+    if count > 0 && rn.test2(if hex { b"pP" } else { b"eE" })? {
+        rn.test2(b"-+")?;
+        rn.read_digits(false)?;
+    }
+    if rn.overflow {
+        return Ok(None);
+    }
+    Ok(std::str::from_utf8(&rn.buf).ok().and_then(crate::lex::str_to_number))
+}
+
+// ---------- 辅助函数 ----------
+
+// 方法的第一个参数：未关闭的文件
+fn to_file(state: &ExeState, fname: &str) -> Result<Rc<UserData>, LuaError> {
+    let u = state.get_userdata::<LuaFile>(1).ok_or_else(|| state.type_error(1, fname, "FILE*"))?;
+    if u.borrow::<LuaFile>().unwrap().is_closed() {
+        return Err(state.error("attempt to use a closed file"));
+    }
+    Ok(u)
+}
+
+// 默认输入或输出文件
+fn get_io_file(state: &ExeState, key: &str) -> Result<Rc<UserData>, LuaError> {
+    let Value::UserData(u) = state.registry().borrow().get_str(key) else { unreachable!() };
+    if u.borrow::<LuaFile>().unwrap().is_closed() {
+        let which = if key == IO_INPUT { "input" } else { "output" };
+        return Err(state.error(&format!("default {} file is closed", which)));
+    }
+    Ok(u)
+}
+
+fn open_file(filename: &[u8], mode: &[u8]) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    let update = mode.get(1) == Some(&b'+');
+    match mode[0] {
+        b'r' => options.read(true).write(update),
+        b'w' => options.write(true).create(true).truncate(true).read(update),
+        _ => options.append(true).create(true).read(update),
+    };
+    options.open(OsStr::from_bytes(filename))
+}
+
+// 打开文件，失败时报错（用于io.lines和io.input/io.output的文件名参数）
+fn open_check_file(state: &ExeState, filename: &[u8], mode: &[u8]) -> Result<Value, LuaError> {
+    match open_file(filename, mode) {
+        Ok(f) => Ok(state.new_userdata(LuaFile::new(Stream::File(BufReader::new(f))))),
+        Err(e) => Err(state.error(&format!("cannot open file '{}' ({})",
+            String::from_utf8_lossy(filename), super::auxlib::os_error_string(&e)))),
+    }
+}
+
+// 模式为[rwa]%+?b*
+fn check_mode(mode: &[u8]) -> bool {
+    let Some((&first, mut rest)) = mode.split_first() else { return false };
+    if !b"rwa".contains(&first) {
+        return false;
+    }
+    if rest.first() == Some(&b'+') {
+        rest = &rest[1..];
+    }
+    rest.iter().all(|&c| c == b'b')
+}
+
+fn close_file(state: &mut ExeState, file: &UserData) -> Result<i32, LuaError> {
+    let mut f = file.borrow_mut::<LuaFile>().unwrap();
+    if f.is_std() {
+        drop(f);
+        state.push(Value::Nil);
+        state.push(Value::from("cannot close standard file"));
+        return Ok(2);
+    }
+    let result = f.close();
+    drop(f);
+    match result {
+        Ok(Some(status)) => Ok(state.push_exec_result(status)),
+        Ok(None) => {
+            state.push(Value::Bool(true));
+            Ok(1)
+        }
+        Err(e) => Ok(state.push_file_error(&e, None)),
+    }
+}
+
+fn read_file(state: &mut ExeState, file: &UserData, first: usize, fname: &str) -> Result<i32, LuaError> {
+    let formats = check_formats(state, first, fname)?;
+    match read_formats(file, &formats) {
+        Ok(results) => Ok(state.push_all(results)),
+        Err(e) if e.kind() == io::ErrorKind::OutOfMemory => Err(LuaError::runtime("not enough memory")),
+        Err(e) => Ok(state.push_file_error(&e, None)),
+    }
+}
+
+// 依次写入字符串或数字参数，成功时返回文件本身
+fn write_file(state: &mut ExeState, file: Value, first: usize, fname: &str) -> Result<i32, LuaError> {
+    let Value::UserData(u) = &file else { unreachable!() };
+    let mut data = Vec::new();
+    for i in first..=state.get_args_count() {
+        match state.get_arg(i) {
+            Value::Integer(n) => data.extend(n.to_string().into_bytes()),
+            // 与C Lua的"%.14g"一致，不补".0"
+            &Value::Float(n) if n.is_finite() => data.extend(fmt_g(n, 14).into_bytes()),
+            &Value::Float(n) => data.extend(fmt_float(n).into_bytes()),
+            _ => data.extend_from_slice(&state.check_string(i, fname)?),
+        }
+    }
+    let result = u.borrow_mut::<LuaFile>().unwrap().write(&data);
+    match result {
+        Ok(()) => {
+            state.push(file);
+            Ok(1)
+        }
+        Err(e) => Ok(state.push_file_error(&e, None)),
+    }
+}
+
+fn flush_file(state: &mut ExeState, file: &UserData) -> Result<i32, LuaError> {
+    let result = file.borrow_mut::<LuaFile>().unwrap().flush();
+    match result {
+        Ok(()) => {
+            state.push(Value::Bool(true));
+            Ok(1)
+        }
+        Err(e) => Ok(state.push_file_error(&e, None)),
+    }
+}
+
+// lines的迭代器，上值为(文件, 结束时是否关闭, 格式个数, 格式...)
+fn lines_iterator(state: &ExeState, file: Value, toclose: bool, first: usize) -> Result<Value, LuaError> {
+    let n = state.get_args_count().saturating_sub(first - 1);
+    if n > MAX_ARG_LINE {
+        return Err(state.arg_error(MAX_ARG_LINE + first, "lines", "too many arguments"));
+    }
+    let mut upvalues = vec![file, Value::Bool(toclose), Value::Integer(n as i64)];
+    upvalues.extend((first..first + n).map(|i| state.get_arg(i).clone()));
+    Ok(state.new_rust_closure(io_readline, upvalues))
+}
+
+fn io_readline(state: &mut ExeState) -> Result<i32, LuaError> {
+    let Value::UserData(file) = state.get_upvalue(1) else { unreachable!() };
+    if file.borrow::<LuaFile>().unwrap().is_closed() {
+        return Err(state.error("file is already closed"));
+    }
+    let Value::Integer(n) = state.get_upvalue(3) else { unreachable!() };
+    let mut formats = vec![Format::Line(true)];
+    if n > 0 {
+        formats = (1..=n as usize)
+            .map(|i| check_format(state, &state.get_upvalue(3 + i), i + 1, "lines"))
+            .collect::<Result<_, _>>()?;
+    }
+    match read_formats(&file, &formats) {
+        Ok(results) if results.first().is_some_and(|v| !v.is_false()) => Ok(state.push_all(results)),
+        Ok(_) => {
+            if state.get_upvalue(2) == Value::Bool(true) {
+                let _ = file.borrow_mut::<LuaFile>().unwrap().close();
+            }
+            Ok(0)
+        }
+        Err(e) if e.kind() == io::ErrorKind::OutOfMemory => Err(LuaError::runtime("not enough memory")),
+        Err(e) => Err(state.error(&super::auxlib::os_error_string(&e))),
+    }
+}
+
+// ---------- io库函数 ----------
+
+// io.open(filename [, mode])
+fn io_open(state: &mut ExeState) -> Result<i32, LuaError> {
+    let filename = state.check_string(1, "open")?;
+    let mode = state.opt_string(2, "open", "r")?;
+    if !check_mode(&mode) {
+        return Err(state.arg_error(2, "open", "invalid mode"));
+    }
+    match open_file(&filename, &mode) {
+        Ok(f) => {
+            let file = state.new_userdata(LuaFile::new(Stream::File(BufReader::new(f))));
+            state.push(file);
+            Ok(1)
+        }
+        Err(e) => Ok(state.push_file_error(&e, Some(&String::from_utf8_lossy(&filename)))),
+    }
+}
+
+// io.close([file])：缺省关闭默认输出文件
+fn io_close(state: &mut ExeState) -> Result<i32, LuaError> {
+    let file = match state.get_arg(1) {
+        Value::Nil => {
+            let Value::UserData(u) = state.registry().borrow().get_str(IO_OUTPUT) else { unreachable!() };
+            u
+        }
+        _ => to_file(state, "close")?,
+    };
+    if file.borrow::<LuaFile>().unwrap().is_closed() {
+        return Err(state.error("attempt to use a closed file"));
+    }
+    close_file(state, &file)
+}
+
+fn io_iofile(state: &mut ExeState, key: &str, mode: &str, fname: &str) -> Result<i32, LuaError> {
+    let file = match state.get_arg(1) {
+        Value::Nil => None,
+        Value::String(name) => Some(open_check_file(state, &name.clone(), mode.as_bytes())?),
+        v @ (Value::Integer(_) | Value::Float(_)) => {
+            let name = v.to_string();
+            Some(open_check_file(state, name.as_bytes(), mode.as_bytes())?)
+        }
+        _ => {
+            to_file(state, fname)?;
+            Some(state.get_arg(1).clone())
+        }
+    };
+    let registry = state.registry();
+    if let Some(file) = file {
+        registry.borrow_mut().set_str(key, file);
+    }
+    let current = registry.borrow().get_str(key);
+    state.push(current);
+    Ok(1)
+}
+
+// io.input([file])
+fn io_input(state: &mut ExeState) -> Result<i32, LuaError> {
+    io_iofile(state, IO_INPUT, "r", "input")
+}
+
+// io.output([file])
+fn io_output(state: &mut ExeState) -> Result<i32, LuaError> {
+    io_iofile(state, IO_OUTPUT, "w", "output")
+}
+
+// io.lines([filename, ...])：指定文件名时读完后自动关闭文件
+fn io_lines(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (file, toclose) = match state.get_arg(1) {
+        Value::Nil => {
+            let file = get_io_file(state, IO_INPUT)?;
+            (Value::UserData(file), false)
+        }
+        _ => {
+            let name = state.check_string(1, "lines")?;
+            (open_check_file(state, &name, b"r")?, true)
+        }
+    };
+    let iter = lines_iterator(state, file.clone(), toclose, 2)?;
+    state.push(iter);
+    if toclose {
+        // 第4个返回值作为泛型for的待关闭变量
+        state.push(Value::Nil);
+        state.push(Value::Nil);
+        state.push(file);
+        return Ok(4);
+    }
+    Ok(1)
+}
+
+// io.read(...)
+fn io_read(state: &mut ExeState) -> Result<i32, LuaError> {
+    let file = get_io_file(state, IO_INPUT)?;
+    read_file(state, &file, 1, "read")
+}
+
+// io.write(...)
+fn io_write(state: &mut ExeState) -> Result<i32, LuaError> {
+    let file = get_io_file(state, IO_OUTPUT)?;
+    write_file(state, Value::UserData(file), 1, "write")
+}
+
+fn io_flush(state: &mut ExeState) -> Result<i32, LuaError> {
+    let file = get_io_file(state, IO_OUTPUT)?;
+    flush_file(state, &file)
+}
+
+// io.type(obj)："file"、"closed file"或nil
+fn io_type(state: &mut ExeState) -> Result<i32, LuaError> {
+    state.check_any(1, "type")?;
+    let v = match state.get_userdata::<LuaFile>(1) {
+        Some(u) if u.borrow::<LuaFile>().unwrap().is_closed() => Value::from("closed file"),
+        Some(_) => Value::from("file"),
+        None => Value::Nil,
+    };
+    state.push(v);
+    Ok(1)
+}
+
+// io.tmpfile()：创建后立即删除，关闭时自动消失
+fn io_tmpfile(state: &mut ExeState) -> Result<i32, LuaError> {
+    let dir = std::env::temp_dir();
+    let mut n = 0;
+    let result = loop {
+        let path = dir.join(format!("lua_{}_{:x}", std::process::id(), random_suffix() + n));
+        match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+            Ok(f) => {
+                let _ = std::fs::remove_file(&path);
+                break Ok(f);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && n < 100 => n += 1,
+            Err(e) => break Err(e),
+        }
+    };
+    match result {
+        Ok(f) => {
+            let file = state.new_userdata(LuaFile::new(Stream::File(BufReader::new(f))));
+            state.push(file);
+            Ok(1)
+        }
+        Err(e) => Ok(state.push_file_error(&e, None)),
+    }
+}
+
+fn random_suffix() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+}
+
+// io.popen(prog [, mode])：通过shell执行命令，读取其输出或写入其输入
+fn io_popen(state: &mut ExeState) -> Result<i32, LuaError> {
+    let prog = state.check_string(1, "popen")?;
+    let mode = state.opt_string(2, "popen", "r")?;
+    let reading = match &*mode {
+        b"r" => true,
+        b"w" => false,
+        _ => return Err(state.arg_error(2, "popen", "invalid mode")),
+    };
+    let _ = io::stdout().flush();
+    let mut command = Command::new("/bin/sh");
+    command.arg("-c").arg(OsStr::from_bytes(&prog));
+    if reading {
+        command.stdout(Stdio::piped());
+    } else {
+        command.stdin(Stdio::piped());
+    }
+    let stream = command.spawn().map(|mut child| {
+        if reading {
+            let out = child.stdout.take().unwrap();
+            Stream::ReadPipe(child, BufReader::new(out))
+        } else {
+            let input = child.stdin.take().unwrap();
+            Stream::WritePipe(child, input)
+        }
+    });
+    match stream {
+        Ok(stream) => {
+            let file = state.new_userdata(LuaFile::new(stream));
+            state.push(file);
+            Ok(1)
+        }
+        Err(e) => Ok(state.push_file_error(&e, Some(&String::from_utf8_lossy(&prog)))),
+    }
+}
+
+// ---------- 文件方法 ----------
+
+fn f_read(state: &mut ExeState) -> Result<i32, LuaError> {
+    let file = to_file(state, "read")?;
+    read_file(state, &file, 2, "read")
+}
+
+fn f_write(state: &mut ExeState) -> Result<i32, LuaError> {
+    let file = to_file(state, "write")?;
+    write_file(state, Value::UserData(file), 2, "write")
+}
+
+fn f_lines(state: &mut ExeState) -> Result<i32, LuaError> {
+    let file = to_file(state, "lines")?;
+    let iter = lines_iterator(state, Value::UserData(file), false, 2)?;
+    state.push(iter);
+    Ok(1)
+}
+
+fn f_flush(state: &mut ExeState) -> Result<i32, LuaError> {
+    let file = to_file(state, "flush")?;
+    flush_file(state, &file)
+}
+
+// file:seek([whence [, offset]])
+fn f_seek(state: &mut ExeState) -> Result<i32, LuaError> {
+    let file = to_file(state, "seek")?;
+    let whence = state.check_option(2, "seek", Some("cur"), &["set", "cur", "end"])?;
+    let offset = state.opt_integer(3, "seek", 0)?;
+    let pos = match whence {
+        0 if offset < 0 => {
+            state.push(Value::Nil);
+            state.push(Value::from(super::auxlib::os_error_string(&io::Error::from_raw_os_error(libc::EINVAL))));
+            state.push(Value::Integer(libc::EINVAL as i64));
+            return Ok(3);
+        }
+        0 => SeekFrom::Start(offset as u64),
+        1 => SeekFrom::Current(offset),
+        _ => SeekFrom::End(offset),
+    };
+    let result = file.borrow_mut::<LuaFile>().unwrap().seek(pos);
+    match result {
+        Ok(p) => {
+            state.push(Value::Integer(p as i64));
+            Ok(1)
+        }
+        Err(e) => Ok(state.push_file_error(&e, None)),
+    }
+}
+
+// file:setvbuf(mode [, size])
+fn f_setvbuf(state: &mut ExeState) -> Result<i32, LuaError> {
+    let file = to_file(state, "setvbuf")?;
+    let mode = state.check_option(2, "setvbuf", None, &["no", "full", "line"])?;
+    let size = state.opt_integer(3, "setvbuf", BUFFER_SIZE as i64)?;
+    let mut f = file.borrow_mut::<LuaFile>().unwrap();
+    f.buffering = [Buffering::No, Buffering::Full, Buffering::Line][mode];
+    f.bufsize = size.max(1) as usize;
+    let result = f.flush_wbuf();
+    drop(f);
+    match result {
+        Ok(()) => {
+            state.push(Value::Bool(true));
+            Ok(1)
+        }
+        Err(e) => Ok(state.push_file_error(&e, None)),
+    }
+}
+
+fn f_tostring(state: &mut ExeState) -> Result<i32, LuaError> {
+    let file = state.get_userdata::<LuaFile>(1).ok_or_else(|| state.type_error(1, "tostring", "FILE*"))?;
+    let s = if file.borrow::<LuaFile>().unwrap().is_closed() {
+        "file (closed)".to_string()
+    } else {
+        format!("file ({:p})", Rc::as_ptr(&file))
+    };
+    state.push(Value::from(s));
+    Ok(1)
+}
+
+// __close：关闭尚未关闭的非标准文件
+fn f_gc(state: &mut ExeState) -> Result<i32, LuaError> {
+    if let Some(file) = state.get_userdata::<LuaFile>(1) {
+        let mut f = file.borrow_mut::<LuaFile>().unwrap();
+        if !f.is_std() && !f.is_closed() {
+            let _ = f.close();
+        }
+    }
+    Ok(0)
+}
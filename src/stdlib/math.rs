@@ -0,0 +1,347 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::LuaError;
+use crate::value::{float_to_integer, RustFn, Table, Value};
+use crate::vm::ExeState;
+
+pub fn open(state: &mut ExeState) {
+    let functions: [(&str, RustFn); 23] = [
+        ("abs", math_abs),
+        ("acos", math_acos),
+        ("asin", math_asin),
+        ("atan", math_atan),
+        ("ceil", math_ceil),
+        ("cos", math_cos),
+        ("deg", math_deg),
+        ("exp", math_exp),
+        ("floor", math_floor),
+        ("fmod", math_fmod),
+        ("log", math_log),
+        ("max", math_max),
+        ("min", math_min),
+        ("modf", math_modf),
+        ("rad", math_rad),
+        ("sin", math_sin),
+        ("sqrt", math_sqrt),
+        ("tan", math_tan),
+        ("tointeger", math_tointeger),
+        ("type", math_type),
+        ("ult", math_ult),
+        ("random", math_random),
+        ("randomseed", math_randomseed),
+    ];
+    // random和randomseed共享以用户数据保存的生成器状态
+    let mut rng = Xoshiro256 { s: [0; 4] };
+    rng.seed(random_seed(), rng.s.as_ptr() as u64);
+    let rng = state.new_userdata(rng);
+
+    let mut math = Table::new(0, 32);
+    for (name, f) in functions {
+        let f = if name.starts_with("random") {
+            state.new_rust_closure(f, vec![rng.clone()])
+        } else {
+            Value::Function(f)
+        };
+        math.set_str(name, f);
+    }
+    math.set_str("pi", Value::Float(std::f64::consts::PI));
+    math.set_str("huge", Value::Float(f64::INFINITY));
+    math.set_str("maxinteger", Value::Integer(i64::MAX));
+    math.set_str("mininteger", Value::Integer(i64::MIN));
+    state.set_global("math", Value::Table(Rc::new(RefCell::new(math))));
+}
+
+// 浮点数能表示为整数时返回整数
+fn float_or_int(f: f64) -> Value {
+    match float_to_integer(f) {
+        Some(i) => Value::Integer(i),
+        None => Value::Float(f),
+    }
+}
+
+fn math_abs(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = match state.check_number(1, "abs")? {
+        Value::Integer(i) => Value::Integer(i.wrapping_abs()),
+        Value::Float(f) => Value::Float(f.abs()),
+        v => v,
+    };
+    state.push(v);
+    Ok(1)
+}
+
+fn math_floor(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = match state.check_number(1, "floor")? {
+        Value::Float(f) => float_or_int(f.floor()),
+        v => v,
+    };
+    state.push(v);
+    Ok(1)
+}
+
+fn math_ceil(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = match state.check_number(1, "ceil")? {
+        Value::Float(f) => float_or_int(f.ceil()),
+        v => v,
+    };
+    state.push(v);
+    Ok(1)
+}
+
+// math.fmod(x, y)：整数时结果向零取整，与C的'%'一致
+fn math_fmod(state: &mut ExeState) -> Result<i32, LuaError> {
+    let a = state.check_number(1, "fmod")?;
+    let b = state.check_number(2, "fmod")?;
+    let v = match (a, b) {
+        (Value::Integer(x), Value::Integer(y)) => {
+            if y == 0 {
+                return Err(state.arg_error(2, "fmod", "zero"));
+            }
+            Value::Integer(x.wrapping_rem(y))
+        }
+        (x, y) => Value::Float(x.to_float().unwrap() % y.to_float().unwrap()),
+    };
+    state.push(v);
+    Ok(1)
+}
+
+// math.modf(x)：整数部分（向零取整）和小数部分
+fn math_modf(state: &mut ExeState) -> Result<i32, LuaError> {
+    match state.check_number(1, "modf")? {
+        Value::Float(n) => {
+            let ip = if n < 0.0 { n.ceil() } else { n.floor() };
+            state.push(Value::Float(ip));
+            state.push(Value::Float(if n == ip { 0.0 } else { n - ip }));
+        }
+        v => {
+            state.push(v);
+            state.push(Value::Float(0.0));
+        }
+    }
+    Ok(2)
+}
+
+fn float_fn(state: &mut ExeState, fname: &str, f: fn(f64) -> f64) -> Result<i32, LuaError> {
+    let x = state.check_float(1, fname)?;
+    state.push(Value::Float(f(x)));
+    Ok(1)
+}
+
+fn math_sqrt(state: &mut ExeState) -> Result<i32, LuaError> {
+    float_fn(state, "sqrt", f64::sqrt)
+}
+
+fn math_exp(state: &mut ExeState) -> Result<i32, LuaError> {
+    float_fn(state, "exp", f64::exp)
+}
+
+fn math_sin(state: &mut ExeState) -> Result<i32, LuaError> {
+    float_fn(state, "sin", f64::sin)
+}
+
+fn math_cos(state: &mut ExeState) -> Result<i32, LuaError> {
+    float_fn(state, "cos", f64::cos)
+}
+
+fn math_tan(state: &mut ExeState) -> Result<i32, LuaError> {
+    float_fn(state, "tan", f64::tan)
+}
+
+fn math_asin(state: &mut ExeState) -> Result<i32, LuaError> {
+    float_fn(state, "asin", f64::asin)
+}
+
+fn math_acos(state: &mut ExeState) -> Result<i32, LuaError> {
+    float_fn(state, "acos", f64::acos)
+}
+
+fn math_deg(state: &mut ExeState) -> Result<i32, LuaError> {
+    float_fn(state, "deg", |x| x * (180.0 / std::f64::consts::PI))
+}
+
+fn math_rad(state: &mut ExeState) -> Result<i32, LuaError> {
+    float_fn(state, "rad", |x| x * (std::f64::consts::PI / 180.0))
+}
+
+// math.atan(y [, x])
+fn math_atan(state: &mut ExeState) -> Result<i32, LuaError> {
+    let y = state.check_float(1, "atan")?;
+    let x = state.opt_float(2, "atan", 1.0)?;
+    state.push(Value::Float(y.atan2(x)));
+    Ok(1)
+}
+
+// math.log(x [, base])
+fn math_log(state: &mut ExeState) -> Result<i32, LuaError> {
+    let x = state.check_float(1, "log")?;
+    let r = match state.get_arg(2) {
+        Value::Nil => x.ln(),
+        _ => match state.check_float(2, "log")? {
+            2.0 => x.log2(),
+            10.0 => x.log10(),
+            base => x.ln() / base.ln(),
+        },
+    };
+    state.push(Value::Float(r));
+    Ok(1)
+}
+
+// max和min保留参数原来的整数或浮点类型
+fn min_max(state: &mut ExeState, fname: &str, want_max: bool) -> Result<i32, LuaError> {
+    let n = state.get_args_count();
+    if n < 1 {
+        return Err(state.type_error(1, fname, "number"));
+    }
+    let mut best = state.check_number(1, fname)?;
+    for i in 2..=n {
+        let v = state.check_number(i, fname)?;
+        let better = if want_max { state.less_than(&best, &v)? } else { state.less_than(&v, &best)? };
+        if better {
+            best = v;
+        }
+    }
+    state.push(best);
+    Ok(1)
+}
+
+fn math_max(state: &mut ExeState) -> Result<i32, LuaError> {
+    min_max(state, "max", true)
+}
+
+fn math_min(state: &mut ExeState) -> Result<i32, LuaError> {
+    min_max(state, "min", false)
+}
+
+// math.tointeger(x)：不能转为整数时返回nil
+fn math_tointeger(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.check_any(1, "tointeger")?;
+    state.push(v.to_integer().map_or(Value::Nil, Value::Integer));
+    Ok(1)
+}
+
+fn math_type(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = match state.check_any(1, "type")? {
+        Value::Integer(_) => Value::from("integer"),
+        Value::Float(_) => Value::from("float"),
+        _ => Value::Nil,
+    };
+    state.push(v);
+    Ok(1)
+}
+
+// math.ult(m, n)：按无符号整数比较
+fn math_ult(state: &mut ExeState) -> Result<i32, LuaError> {
+    let a = state.check_integer(1, "ult")?;
+    let b = state.check_integer(2, "ult")?;
+    state.push(Value::Bool((a as u64) < (b as u64)));
+    Ok(1)
+}
+
+// ---------- 伪随机数 ----------
+
+// xoshiro256**，与Lua 5.4相同，种子相同时得到相同的序列
+struct Xoshiro256 {
+    s: [u64; 4],
+}
+
+impl Xoshiro256 {
+    fn next(&mut self) -> u64 {
+        let s = &mut self.s;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    fn seed(&mut self, n1: u64, n2: u64) {
+        self.s = [n1, 0xff, n2, 0];
+        // 丢弃最初的值，使种子充分扩散
+        for _ in 0..16 {
+            self.next();
+        }
+    }
+
+    // 把随机数投影到[0, n]，没有偏差
+    fn project(&mut self, mut ran: u64, n: u64) -> u64 {
+        if n & n.wrapping_add(1) == 0 {
+            return ran & n;
+        }
+        // 不小于n的最小的2^b-1
+        let mut lim = n;
+        lim |= lim >> 1;
+        lim |= lim >> 2;
+        lim |= lim >> 4;
+        lim |= lim >> 8;
+        lim |= lim >> 16;
+        lim |= lim >> 32;
+        loop {
+            ran &= lim;
+            if ran <= n {
+                return ran;
+            }
+            ran = self.next();
+        }
+    }
+}
+
+// 浮点数取高53位，范围[0, 1)
+fn to_float(rv: u64) -> f64 {
+    (rv >> 11) as f64 * (0.5 / (1u64 << 52) as f64)
+}
+
+fn random_seed() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+}
+
+fn with_rng<R>(state: &ExeState, f: impl FnOnce(&mut Xoshiro256) -> R) -> R {
+    let Value::UserData(u) = state.get_upvalue(1) else { unreachable!() };
+    let mut rng = u.borrow_mut::<Xoshiro256>().unwrap();
+    f(&mut rng)
+}
+
+// math.random([m [, n]])
+fn math_random(state: &mut ExeState) -> Result<i32, LuaError> {
+    let rv = with_rng(state, Xoshiro256::next);
+    let (low, up) = match state.get_args_count() {
+        0 => {
+            state.push(Value::Float(to_float(rv)));
+            return Ok(1);
+        }
+        1 => {
+            let up = state.check_integer(1, "random")?;
+            if up == 0 {
+                // math.random(0)返回所有位都随机的整数
+                state.push(Value::Integer(rv as i64));
+                return Ok(1);
+            }
+            (1, up)
+        }
+        2 => (state.check_integer(1, "random")?, state.check_integer(2, "random")?),
+        _ => return Err(state.error("wrong number of arguments")),
+    };
+    if low > up {
+        return Err(state.arg_error(1, "random", "interval is empty"));
+    }
+    let p = with_rng(state, |rng| rng.project(rv, (up as u64).wrapping_sub(low as u64)));
+    state.push(Value::Integer(p.wrapping_add(low as u64) as i64));
+    Ok(1)
+}
+
+// math.randomseed([x [, y]])：没有参数时使用随机的种子，返回实际使用的两个种子
+fn math_randomseed(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (n1, n2) = if state.get_args_count() == 0 {
+        (random_seed() as i64, with_rng(state, |rng| rng as *const Xoshiro256 as i64))
+    } else {
+        (state.check_integer(1, "randomseed")?, state.opt_integer(2, "randomseed", 0)?)
+    };
+    with_rng(state, |rng| rng.seed(n1 as u64, n2 as u64));
+    state.push(Value::Integer(n1));
+    state.push(Value::Integer(n2));
+    Ok(2)
+}
@@ -0,0 +1,90 @@
+// 标准库，每个子模块提供open函数把库函数注册到ExeState
+mod auxlib;
+pub mod base;
+pub mod coroutine;
+pub mod debug;
+pub mod io;
+pub mod math;
+pub mod os;
+pub mod package;
+mod pattern;
+mod sandbox;
+pub mod string;
+mod strpack;
+pub mod table;
+pub mod testlib;
+pub mod utf8;
+
+use crate::vm::ExeState;
+
+// 生成大字符串的函数每复制这么多字节检查一次中断请求和deadline
+pub(crate) const CHECK_BYTES: usize = 1 << 20;
+
+// 可以选择打开的标准库
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lib {
+    Base,
+    Coroutine,
+    Debug,
+    Io,
+    Math,
+    Os,
+    Package,
+    String,
+    Table,
+    Utf8,
+}
+
+impl Lib {
+    pub const ALL: [Lib; 10] = [Lib::Base, Lib::Coroutine, Lib::Debug, Lib::Io, Lib::Math, Lib::Os, Lib::Package, Lib::String, Lib::Table, Lib::Utf8];
+
+    // 库在全局表和package.loaded中的名字
+    pub fn name(self) -> &'static str {
+        match self {
+            Lib::Base => "_G",
+            Lib::Coroutine => "coroutine",
+            Lib::Debug => "debug",
+            Lib::Io => "io",
+            Lib::Math => "math",
+            Lib::Os => "os",
+            Lib::Package => "package",
+            Lib::String => "string",
+            Lib::Table => "table",
+            Lib::Utf8 => "utf8",
+        }
+    }
+
+    fn open(self, state: &mut ExeState) {
+        match self {
+            Lib::Base => base::open(state),
+            Lib::Coroutine => coroutine::open(state),
+            Lib::Debug => debug::open(state),
+            Lib::Io => io::open(state),
+            Lib::Math => math::open(state),
+            Lib::Os => os::open(state),
+            Lib::Package => package::open(state),
+            Lib::String => string::open(state),
+            Lib::Table => table::open(state),
+            Lib::Utf8 => utf8::open(state),
+        }
+    }
+}
+
+// 按Lib::ALL的顺序打开选中的库，相当于有选择的luaL_openlibs。
+// package最后打开，以便把其他库登记到package.loaded。
+// sandbox为真时不打开io和package，并把其余的库限制为沙箱环境
+pub fn open_libs(state: &mut ExeState, libs: &[Lib], sandbox: bool) {
+    let mut selected: Vec<Lib> = Lib::ALL.into_iter()
+        .filter(|lib| libs.contains(lib) && !(sandbox && sandbox::UNSAFE_LIBS.contains(lib)))
+        .collect();
+    if let Some(i) = selected.iter().position(|&lib| lib == Lib::Package) {
+        selected.remove(i);
+        selected.push(Lib::Package);
+    }
+    for lib in &selected {
+        lib.open(state);
+    }
+    if sandbox {
+        sandbox::apply(state, &selected);
+    }
+}
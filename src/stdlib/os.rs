@@ -0,0 +1,298 @@
+use std::cell::RefCell;
+use std::ffi::{CStr, CString, OsStr};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::process::Command;
+use std::rc::Rc;
+
+use crate::error::LuaError;
+use crate::value::{RustFn, Table, Value};
+use crate::vm::ExeState;
+
+// strftime允许的转换说明符，第i组的长度为i+1（带E、O修饰的说明符为2）
+const STRFTIME_OPTIONS: [&str; 2] = [
+    "aAbBcCdDeFgGhHIjmMnprRStTuUVwWxXyYzZ%",
+    "EcECExEXEyEYOdOeOHOIOmOMOSOuOUOVOwOWOy",
+];
+const MAX_DATE_ITEM: usize = 250;
+
+pub fn open(state: &mut ExeState) {
+    let functions: [(&str, RustFn); 11] = [
+        ("clock", os_clock),
+        ("date", os_date),
+        ("difftime", os_difftime),
+        ("execute", os_execute),
+        ("exit", os_exit),
+        ("getenv", os_getenv),
+        ("remove", os_remove),
+        ("rename", os_rename),
+        ("setlocale", os_setlocale),
+        ("time", os_time),
+        ("tmpname", os_tmpname),
+    ];
+    let mut os = Table::new(0, 16);
+    for (name, f) in functions {
+        os.set_str(name, Value::Function(f));
+    }
+    state.set_global("os", Value::Table(Rc::new(RefCell::new(os))));
+}
+
+// 沙箱：去掉可以执行命令、删除文件和结束进程的函数，其他库不受影响
+pub fn sandbox(state: &mut ExeState) {
+    if let Value::Table(os) = state.get_global("os") {
+        for name in ["execute", "exit", "remove"] {
+            os.borrow_mut().set_str(name, Value::Nil);
+        }
+    }
+}
+
+// os.exit([code [, close]])：true为成功，false为失败，缺省为成功；
+// close为true时先释放全局变量，使打开的文件等对象被关闭
+fn os_exit(state: &mut ExeState) -> Result<i32, LuaError> {
+    let code = match state.get_arg(1) {
+        Value::Nil | Value::Bool(true) => 0,
+        Value::Bool(false) => 1,
+        _ => state.opt_integer(1, "exit", 0)? as i32,
+    };
+    if !state.get_arg(2).is_false() {
+        let globals = state.globals();
+        let old = std::mem::replace(&mut *globals.borrow_mut(), Table::new(0, 0));
+        drop(old);
+    }
+    super::io::flush_output(state);
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+    std::process::exit(code);
+}
+
+// os.getenv(varname)
+fn os_getenv(state: &mut ExeState) -> Result<i32, LuaError> {
+    let name = state.check_string(1, "getenv")?;
+    let v = match std::env::var_os(OsStr::from_bytes(&name)) {
+        Some(v) => Value::String(v.as_bytes().into()),
+        None => Value::Nil,
+    };
+    state.push(v);
+    Ok(1)
+}
+
+fn to_cstring(state: &ExeState, i: usize, fname: &str) -> Result<(CString, String), LuaError> {
+    let s = state.check_string(i, fname)?;
+    let name = String::from_utf8_lossy(&s).into_owned();
+    let cs = CString::new(s.to_vec()).map_err(|_| state.arg_error(i, fname, "string contains zeros"))?;
+    Ok((cs, name))
+}
+
+fn push_result(state: &mut ExeState, ok: bool, fname: &str) -> i32 {
+    if ok {
+        state.push(Value::Bool(true));
+        1
+    } else {
+        state.push_file_error(&std::io::Error::last_os_error(), Some(fname))
+    }
+}
+
+// os.remove(filename)：文件或空目录
+fn os_remove(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (path, name) = to_cstring(state, 1, "remove")?;
+    let ok = unsafe { libc::remove(path.as_ptr()) } == 0;
+    Ok(push_result(state, ok, &name))
+}
+
+// os.rename(oldname, newname)
+fn os_rename(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (from, name) = to_cstring(state, 1, "rename")?;
+    let (to, _) = to_cstring(state, 2, "rename")?;
+    let ok = unsafe { libc::rename(from.as_ptr(), to.as_ptr()) } == 0;
+    Ok(push_result(state, ok, &name))
+}
+
+// os.tmpname()：创建一个空的临时文件并返回其名字
+fn os_tmpname(state: &mut ExeState) -> Result<i32, LuaError> {
+    let mut template = *b"/tmp/lua_XXXXXX\0";
+    let fd = unsafe { libc::mkstemp(template.as_mut_ptr() as *mut libc::c_char) };
+    if fd == -1 {
+        return Err(state.error("unable to generate a unique filename"));
+    }
+    unsafe { libc::close(fd) };
+    state.push(Value::String(template[..template.len() - 1].into()));
+    Ok(1)
+}
+
+// os.execute([command])：没有参数时返回是否有可用的shell
+fn os_execute(state: &mut ExeState) -> Result<i32, LuaError> {
+    if let Value::Nil = state.get_arg(1) {
+        state.push(Value::Bool(std::path::Path::new("/bin/sh").exists()));
+        return Ok(1);
+    }
+    let cmd = state.check_string(1, "execute")?;
+    let _ = std::io::stdout().flush();
+    match Command::new("/bin/sh").arg("-c").arg(OsStr::from_bytes(&cmd)).status() {
+        Ok(status) => Ok(state.push_exec_result(status)),
+        Err(e) => Ok(state.push_file_error(&e, None)),
+    }
+}
+
+// os.clock()：程序使用的CPU时间，单位为秒
+fn os_clock(state: &mut ExeState) -> Result<i32, LuaError> {
+    let mut ts: libc::timespec = unsafe { std::mem::zeroed() };
+    unsafe { libc::clock_gettime(libc::CLOCK_PROCESS_CPUTIME_ID, &mut ts) };
+    state.push(Value::Float(ts.tv_sec as f64 + ts.tv_nsec as f64 / 1e9));
+    Ok(1)
+}
+
+// os.difftime(t2 [, t1])
+fn os_difftime(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t2 = state.check_integer(1, "difftime")?;
+    let t1 = state.opt_integer(2, "difftime", 0)?;
+    state.push(Value::Float(t2 as f64 - t1 as f64));
+    Ok(1)
+}
+
+// os.setlocale([locale [, category]])
+fn os_setlocale(state: &mut ExeState) -> Result<i32, LuaError> {
+    const CATEGORIES: [libc::c_int; 6] =
+        [libc::LC_ALL, libc::LC_COLLATE, libc::LC_CTYPE, libc::LC_MONETARY, libc::LC_NUMERIC, libc::LC_TIME];
+    let locale = match state.get_arg(1) {
+        Value::Nil => None,
+        _ => Some(to_cstring(state, 1, "setlocale")?.0),
+    };
+    let op = state.check_option(2, "setlocale", Some("all"),
+        &["all", "collate", "ctype", "monetary", "numeric", "time"])?;
+    let result = unsafe {
+        libc::setlocale(CATEGORIES[op], locale.as_ref().map_or(std::ptr::null(), |l| l.as_ptr()))
+    };
+    let v = if result.is_null() {
+        Value::Nil
+    } else {
+        Value::String(unsafe { CStr::from_ptr(result) }.to_bytes().into())
+    };
+    state.push(v);
+    Ok(1)
+}
+
+// ---------- 日期和时间 ----------
+
+fn now() -> libc::time_t {
+    unsafe { libc::time(std::ptr::null_mut()) }
+}
+
+// 日期表中的整数字段，delta为Lua与C中取值的差
+fn get_field(state: &ExeState, t: &Table, key: &str, default: Option<i64>, delta: i64) -> Result<libc::c_int, LuaError> {
+    let v = t.get_str(key);
+    let res = match v.to_integer() {
+        Some(res) => {
+            let res = res.checked_sub(delta).filter(|r| i32::try_from(*r).is_ok());
+            res.ok_or_else(|| state.error(&format!("field '{}' is out-of-bound", key)))?
+        }
+        None if v != Value::Nil => return Err(state.error(&format!("field '{}' is not an integer", key))),
+        None => default.ok_or_else(|| state.error(&format!("field '{}' missing in date table", key)))?,
+    };
+    Ok(res as libc::c_int)
+}
+
+// 把tm的各字段写入日期表，os.time借此规范化传入的表
+fn set_all_fields(t: &mut Table, tm: &libc::tm) {
+    t.set_str("year", Value::Integer(tm.tm_year as i64 + 1900));
+    t.set_str("month", Value::Integer(tm.tm_mon as i64 + 1));
+    t.set_str("day", Value::Integer(tm.tm_mday as i64));
+    t.set_str("hour", Value::Integer(tm.tm_hour as i64));
+    t.set_str("min", Value::Integer(tm.tm_min as i64));
+    t.set_str("sec", Value::Integer(tm.tm_sec as i64));
+    t.set_str("yday", Value::Integer(tm.tm_yday as i64 + 1));
+    t.set_str("wday", Value::Integer(tm.tm_wday as i64 + 1));
+    if tm.tm_isdst >= 0 {
+        t.set_str("isdst", Value::Bool(tm.tm_isdst != 0));
+    }
+}
+
+// os.time([table])
+fn os_time(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = match state.get_arg(1) {
+        Value::Nil => now(),
+        _ => {
+            let table = state.check_table(1, "time")?;
+            let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+            {
+                let t = table.borrow();
+                tm.tm_year = get_field(state, &t, "year", None, 1900)?;
+                tm.tm_mon = get_field(state, &t, "month", None, 1)?;
+                tm.tm_mday = get_field(state, &t, "day", None, 0)?;
+                tm.tm_hour = get_field(state, &t, "hour", Some(12), 0)?;
+                tm.tm_min = get_field(state, &t, "min", Some(0), 0)?;
+                tm.tm_sec = get_field(state, &t, "sec", Some(0), 0)?;
+                tm.tm_isdst = match t.get_str("isdst") {
+                    Value::Nil => -1,
+                    v => !v.is_false() as libc::c_int,
+                };
+            }
+            let t = unsafe { libc::mktime(&mut tm) };
+            set_all_fields(&mut table.borrow_mut(), &tm);
+            t
+        }
+    };
+    if t == -1 {
+        return Err(state.error("time result cannot be represented in this installation"));
+    }
+    state.push(Value::Integer(t as i64));
+    Ok(1)
+}
+
+// 检查一个转换说明符，返回其长度（不含'%'）
+fn check_option(conv: &[u8]) -> Option<usize> {
+    (1..).zip(STRFTIME_OPTIONS).find_map(|(len, options)| {
+        let found = conv.len() >= len && options.as_bytes().chunks(len).any(|o| o == &conv[..len]);
+        found.then_some(len)
+    })
+}
+
+// os.date([format [, time]])："*t"返回日期表，以'!'开头时使用UTC
+fn os_date(state: &mut ExeState) -> Result<i32, LuaError> {
+    let format = state.opt_string(1, "date", "%c")?;
+    let t = match state.get_arg(2) {
+        Value::Nil => now(),
+        _ => state.check_integer(2, "date")? as libc::time_t,
+    };
+    let (utc, mut format) = match format.strip_prefix(b"!") {
+        Some(f) => (true, f),
+        None => (false, &format[..]),
+    };
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    let ok = unsafe {
+        if utc { !libc::gmtime_r(&t, &mut tm).is_null() } else { !libc::localtime_r(&t, &mut tm).is_null() }
+    };
+    if !ok {
+        return Err(state.error("date result cannot be represented in this installation"));
+    }
+    if format.starts_with(b"*t") {
+        let mut table = Table::new(0, 9);
+        set_all_fields(&mut table, &tm);
+        state.push(Value::Table(Rc::new(RefCell::new(table))));
+        return Ok(1);
+    }
+
+    let mut result = Vec::new();
+    while let Some((&c, rest)) = format.split_first() {
+        format = rest;
+        if c != b'%' {
+            result.push(c);
+            continue;
+        }
+        let Some(len) = check_option(format) else {
+            let conv = String::from_utf8_lossy(format).into_owned();
+            return Err(state.arg_error(1, "date", &format!("invalid conversion specifier '%{}'", conv)));
+        };
+        // 每个转换说明符单独交给strftime处理
+        let mut spec = vec![b'%'];
+        spec.extend_from_slice(&format[..len]);
+        spec.push(0);
+        format = &format[len..];
+        let mut buf = [0u8; MAX_DATE_ITEM];
+        let n = unsafe {
+            libc::strftime(buf.as_mut_ptr() as *mut libc::c_char, buf.len(), spec.as_ptr() as *const libc::c_char, &tm)
+        };
+        result.extend_from_slice(&buf[..n]);
+    }
+    state.push(Value::String(result.into()));
+    Ok(1)
+}
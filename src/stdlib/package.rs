@@ -0,0 +1,291 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::LuaError;
+use crate::value::{RustFn, Table, Value};
+use crate::vm::ExeState;
+
+use super::Lib;
+
+const LUA_DIR: &str = "/usr/local/share/lua/5.4/";
+const NATIVE_DIR: &str = "/usr/local/lib/lua/5.4/";
+const MSG_NO_DYNLIB: &str = "dynamic libraries not enabled; check your Lua installation";
+
+fn default_path() -> String {
+    format!("{0}?.lua;{0}?/init.lua;{1}?.lua;{1}?/init.lua;./?.lua;./?/init.lua", LUA_DIR, NATIVE_DIR)
+}
+
+fn default_cpath() -> String {
+    format!("{0}?.so;{0}loadall.so;./?.so", NATIVE_DIR)
+}
+
+// 须在其他标准库之后打开，以便把它们登记到package.loaded
+pub fn open(state: &mut ExeState) {
+    let loaded = table_field(state, "_LOADED");
+    let preload = table_field(state, "_PRELOAD");
+    table_field(state, "_NATIVE");
+
+    let mut package = Table::new(0, 10);
+    package.set_str("config", Value::from("/\n;\n?\n!\n-\n"));
+    package.set_str("loaded", Value::Table(loaded.clone()));
+    package.set_str("preload", Value::Table(preload));
+    package.set_str("loadlib", Value::Function(package_loadlib));
+    package.set_str("searchpath", Value::Function(package_searchpath));
+    let package = Rc::new(RefCell::new(package));
+
+    // 搜索器和require以package表为上值
+    let searchers: [RustFn; 3] = [searcher_preload, searcher_lua, searcher_native];
+    let mut list = Table::new(searchers.len(), 0);
+    for (i, f) in (1..).zip(searchers) {
+        list.set(Value::Integer(i), state.new_rust_closure(f, vec![Value::Table(package.clone())]));
+    }
+    package.borrow_mut().set_str("searchers", Value::Table(Rc::new(RefCell::new(list))));
+    let require = state.new_rust_closure(lua_require, vec![Value::Table(package.clone())]);
+    state.set_global("require", require);
+    state.set_global("package", Value::Table(package.clone()));
+
+    set_paths(state, false);
+    for lib in Lib::ALL.into_iter().filter(|&lib| lib != Lib::Package) {
+        let module = state.get_global(lib.name());
+        loaded.borrow_mut().set_str(lib.name(), module);
+    }
+    loaded.borrow_mut().set_str("package", Value::Table(package));
+}
+
+// 注册表中的子表，不存在时新建
+fn table_field(state: &ExeState, key: &str) -> Rc<RefCell<Table>> {
+    let registry = state.registry();
+    let v = registry.borrow().get_str(key);
+    match v {
+        Value::Table(t) => t,
+        _ => {
+            let t = Rc::new(RefCell::new(Table::new(0, 8)));
+            registry.borrow_mut().set_str(key, Value::Table(t.clone()));
+            t
+        }
+    }
+}
+
+// 设置package.path和package.cpath。环境变量中的";;"替换为默认路径，
+// no_env时（命令行-E）忽略环境变量
+pub fn set_paths(state: &mut ExeState, no_env: bool) {
+    let Value::Table(package) = state.get_global("package") else {
+        return;
+    };
+    let paths = [
+        ("path", "LUA_PATH_5_4", "LUA_PATH", default_path()),
+        ("cpath", "LUA_CPATH_5_4", "LUA_CPATH", default_cpath()),
+    ];
+    for (field, env1, env2, default) in paths {
+        let env = if no_env { None } else { std::env::var(env1).or_else(|_| std::env::var(env2)).ok() };
+        let path = match env {
+            None => default,
+            Some(path) => match path.find(";;") {
+                None => path,
+                Some(mark) => {
+                    let mut result = String::new();
+                    if mark > 0 {
+                        result.push_str(&path[..mark]);
+                        result.push(';');
+                    }
+                    result.push_str(&default);
+                    if mark + 2 < path.len() {
+                        result.push(';');
+                        result.push_str(&path[mark + 2..]);
+                    }
+                    result
+                }
+            },
+        };
+        package.borrow_mut().set_str(field, Value::from(path));
+    }
+}
+
+impl ExeState {
+    // 注册Rust实现的模块：require(name)时以(name, ":native:")调用loader，其返回值作为模块
+    pub fn register_module(&mut self, name: &str, loader: RustFn) {
+        table_field(self, "_NATIVE").borrow_mut().set_str(name, Value::Function(loader));
+    }
+}
+
+fn package_field(state: &ExeState, field: &str) -> Value {
+    match state.get_upvalue(1) {
+        Value::Table(package) => package.borrow().get_str(field),
+        _ => Value::Nil,
+    }
+}
+
+// 按分号分隔的模板逐个把'?'替换为名字，返回第一个可读的文件，
+// 否则返回列出所有尝试过的文件的错误信息
+fn search_path(name: &str, path: &str, sep: &str, rep: &str) -> Result<String, String> {
+    let name = if sep.is_empty() { name.to_string() } else { name.replace(sep, rep) };
+    let mut tried = Vec::new();
+    for template in path.split(';').filter(|t| !t.is_empty()) {
+        let filename = template.replace('?', &name);
+        if std::fs::File::open(&filename).is_ok() {
+            return Ok(filename);
+        }
+        tried.push(format!("no file '{}'", filename));
+    }
+    Err(tried.join("\n\t"))
+}
+
+fn check_path(state: &ExeState, field: &str) -> Result<String, LuaError> {
+    match package_field(state, field) {
+        Value::String(s) => Ok(String::from_utf8_lossy(&s).into_owned()),
+        _ => Err(state.error(&format!("'package.{}' must be a string", field))),
+    }
+}
+
+// package.searchpath(name, path [, sep [, rep]])
+fn package_searchpath(state: &mut ExeState) -> Result<i32, LuaError> {
+    let name = String::from_utf8_lossy(&state.check_string(1, "searchpath")?).into_owned();
+    let path = String::from_utf8_lossy(&state.check_string(2, "searchpath")?).into_owned();
+    let sep = String::from_utf8_lossy(&state.opt_string(3, "searchpath", ".")?).into_owned();
+    let rep = String::from_utf8_lossy(&state.opt_string(4, "searchpath", "/")?).into_owned();
+    match search_path(&name, &path, &sep, &rep) {
+        Ok(filename) => {
+            state.push(Value::from(filename));
+            Ok(1)
+        }
+        Err(msg) => {
+            state.push(Value::Nil);
+            state.push(Value::from(msg));
+            Ok(2)
+        }
+    }
+}
+
+// package.loadlib(path, funcname)：不支持动态库
+fn package_loadlib(state: &mut ExeState) -> Result<i32, LuaError> {
+    state.check_string(1, "loadlib")?;
+    state.check_string(2, "loadlib")?;
+    state.push(Value::Nil);
+    state.push(Value::from(MSG_NO_DYNLIB));
+    state.push(Value::from("absent"));
+    Ok(3)
+}
+
+// 在package.preload中查找加载函数
+fn searcher_preload(state: &mut ExeState) -> Result<i32, LuaError> {
+    let name = state.check_string(1, "searcher")?;
+    let loader = match package_field(state, "preload") {
+        Value::Table(preload) => preload.borrow().get(&Value::String(name.clone())),
+        _ => return Err(state.error("'package.preload' must be a table")),
+    };
+    if loader == Value::Nil {
+        let msg = format!("no field package.preload['{}']", String::from_utf8_lossy(&name));
+        state.push(Value::from(msg));
+        return Ok(1);
+    }
+    state.push(loader);
+    state.push(Value::from(":preload:"));
+    Ok(2)
+}
+
+// 按package.path查找并编译Lua源文件或预编译的chunk
+fn searcher_lua(state: &mut ExeState) -> Result<i32, LuaError> {
+    let name = String::from_utf8_lossy(&state.check_string(1, "searcher")?).into_owned();
+    let path = check_path(state, "path")?;
+    let filename = match search_path(&name, &path, ".", "/") {
+        Ok(filename) => filename,
+        Err(msg) => {
+            state.push(Value::from(msg));
+            return Ok(1);
+        }
+    };
+    let loaded = std::fs::read(&filename)
+        .map_err(|e| LuaError::runtime(format!("cannot read {}: {}", filename, super::auxlib::os_error_string(&e))))
+        .and_then(|code| state.load_bytes(&code, &format!("@{}", filename), None));
+    match loaded {
+        Ok(f) => {
+            state.push(f);
+            state.push(Value::from(filename));
+            Ok(2)
+        }
+        Err(e) => {
+            let msg = format!("error loading module '{}' from file '{}':\n\t{}", name, filename, e);
+            Err(state.error(&msg))
+        }
+    }
+}
+
+// 先查找register_module注册的模块，再按package.cpath查找动态库
+fn searcher_native(state: &mut ExeState) -> Result<i32, LuaError> {
+    let name = state.check_string(1, "searcher")?;
+    let loader = table_field(state, "_NATIVE").borrow().get(&Value::String(name.clone()));
+    if loader != Value::Nil {
+        state.push(loader);
+        state.push(Value::from(":native:"));
+        return Ok(2);
+    }
+    let name = String::from_utf8_lossy(&name).into_owned();
+    let cpath = check_path(state, "cpath")?;
+    match search_path(&name, &cpath, ".", "/") {
+        Ok(filename) => {
+            let msg = format!("error loading module '{}' from file '{}':\n\t{}", name, filename, MSG_NO_DYNLIB);
+            Err(state.error(&msg))
+        }
+        Err(msg) => {
+            state.push(Value::from(format!("no native module '{}'\n\t{}", name, msg)));
+            Ok(1)
+        }
+    }
+}
+
+// 依次调用package.searchers中的搜索器，返回加载函数及其附加值
+fn find_loader(state: &mut ExeState, name: &Value) -> Result<(Value, Value), LuaError> {
+    let Value::Table(searchers) = package_field(state, "searchers") else {
+        return Err(state.error("'package.searchers' must be a table"));
+    };
+    let mut msg = Vec::new();
+    for i in 1.. {
+        let searcher = searchers.borrow().get(&Value::Integer(i));
+        if searcher == Value::Nil {
+            break;
+        }
+        let mut results = state.call_function(searcher, vec![name.clone()])?.into_iter();
+        match (results.next().unwrap_or(Value::Nil), results.next().unwrap_or(Value::Nil)) {
+            (loader @ (Value::Function(_) | Value::RustClosure(_) | Value::LuaFunction(_)), extra) => return Ok((loader, extra)),
+            (Value::String(s), _) => {
+                msg.extend_from_slice(b"\n\t");
+                msg.extend_from_slice(&s);
+            }
+            (v, _) if v.to_number().is_some() => {
+                msg.extend_from_slice(b"\n\t");
+                msg.extend_from_slice(v.to_string().as_bytes());
+            }
+            _ => (),
+        }
+    }
+    let name = String::from_utf8_lossy(match name {
+        Value::String(s) => s,
+        _ => unreachable!(),
+    })
+    .into_owned();
+    Err(state.error(&format!("module '{}' not found:{}", name, String::from_utf8_lossy(&msg))))
+}
+
+// require(modname)：返回模块和加载函数的附加值（如文件名）
+fn lua_require(state: &mut ExeState) -> Result<i32, LuaError> {
+    let name = Value::String(state.check_string(1, "require")?);
+    let loaded = table_field(state, "_LOADED");
+    let module = loaded.borrow().get(&name);
+    if !module.is_false() {
+        state.push(module);
+        return Ok(1);
+    }
+    let (loader, extra) = find_loader(state, &name)?;
+    let result = state.call_function(loader, vec![name.clone(), extra.clone()])?;
+    if let Some(v) = result.into_iter().next().filter(|v| *v != Value::Nil) {
+        loaded.borrow_mut().set(name.clone(), v);
+    }
+    let mut module = loaded.borrow().get(&name);
+    if module == Value::Nil {
+        module = Value::Bool(true);
+        loaded.borrow_mut().set(name, module.clone());
+    }
+    state.push(module);
+    state.push(extra);
+    Ok(2)
+}
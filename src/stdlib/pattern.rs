@@ -0,0 +1,346 @@
+// Lua模式匹配，移植自C Lua的lstrlib.c
+
+const L_ESC: u8 = b'%';
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+const MAX_CAPTURES: usize = 32;
+const MAX_CALLS: usize = 200;
+
+const CAP_UNFINISHED: isize = -1;
+const CAP_POSITION: isize = -2;
+
+// 一个捕获：子串的起止位置，或位置捕获"()"对应的位置
+pub enum Capture {
+    Str(usize, usize),
+    Position(usize),
+}
+
+pub struct MatchState<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    level: usize,
+    capture: [(usize, isize); MAX_CAPTURES],  // (起点, 长度或CAP_*)
+    depth: usize,
+}
+
+type MatchResult = Result<Option<usize>, String>;
+
+// 模式中不含特殊字符时可以按普通子串查找
+pub fn no_specials(pat: &[u8]) -> bool {
+    !pat.iter().any(|c| SPECIALS.contains(c))
+}
+
+pub fn find_plain(s: &[u8], pat: &[u8]) -> Option<usize> {
+    if pat.is_empty() {
+        return Some(0);
+    }
+    s.windows(pat.len()).position(|w| w == pat)
+}
+
+impl<'a> MatchState<'a> {
+    pub fn new(src: &'a [u8], pat: &'a [u8]) -> Self {
+        MatchState { src, pat, level: 0, capture: [(0, 0); MAX_CAPTURES], depth: MAX_CALLS }
+    }
+
+    // 从src的位置s、模式的位置p开始匹配，成功时返回匹配的结束位置
+    pub fn find(&mut self, s: usize, p: usize) -> MatchResult {
+        self.level = 0;
+        self.depth = MAX_CALLS;
+        self.do_match(s, p)
+    }
+
+    fn do_match(&mut self, mut s: usize, mut p: usize) -> MatchResult {
+        if self.depth == 0 {
+            return Err("pattern too complex".to_string());
+        }
+        self.depth -= 1;
+        let result = loop {
+            if p == self.pat.len() {
+                break Some(s);
+            }
+            match self.pat[p] {
+                b'(' => {
+                    break if self.pat.get(p + 1) == Some(&b')') {
+                        self.start_capture(s, p + 2, CAP_POSITION)?
+                    } else {
+                        self.start_capture(s, p + 1, CAP_UNFINISHED)?
+                    };
+                }
+                b')' => break self.end_capture(s, p + 1)?,
+                b'$' if p + 1 == self.pat.len() => {
+                    break if s == self.src.len() { Some(s) } else { None };
+                }
+                L_ESC if self.pat.get(p + 1) == Some(&b'b') => match self.match_balance(s, p + 2)? {
+                    Some(e) => {
+                        s = e;
+                        p += 4;
+                    }
+                    None => break None,
+                },
+                L_ESC if self.pat.get(p + 1) == Some(&b'f') => {
+                    p += 2;
+                    if self.pat.get(p) != Some(&b'[') {
+                        return Err("missing '[' after '%f' in pattern".to_string());
+                    }
+                    let ep = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                    let current = self.src.get(s).copied().unwrap_or(0);
+                    if !self.match_bracket_class(previous, p, ep - 1) && self.match_bracket_class(current, p, ep - 1) {
+                        p = ep;
+                    } else {
+                        break None;
+                    }
+                }
+                L_ESC if self.pat.get(p + 1).is_some_and(u8::is_ascii_digit) => {
+                    match self.match_capture(s, self.pat[p + 1])? {
+                        Some(e) => {
+                            s = e;
+                            p += 2;
+                        }
+                        None => break None,
+                    }
+                }
+                _ => {
+                    let ep = self.class_end(p)?;
+                    let next = self.pat.get(ep).copied();
+                    if !self.single_match(s, p, ep) {
+                        if matches!(next, Some(b'*' | b'?' | b'-')) {
+                            // 允许零次匹配
+                            p = ep + 1;
+                            continue;
+                        }
+                        break None;
+                    }
+                    match next {
+                        Some(b'?') => match self.do_match(s + 1, ep + 1)? {
+                            Some(e) => break Some(e),
+                            None => p = ep + 1,
+                        },
+                        Some(b'+') => break self.max_expand(s + 1, p, ep)?,
+                        Some(b'*') => break self.max_expand(s, p, ep)?,
+                        Some(b'-') => break self.min_expand(s, p, ep)?,
+                        _ => {
+                            s += 1;
+                            p = ep;
+                        }
+                    }
+                }
+            }
+        };
+        self.depth += 1;
+        Ok(result)
+    }
+
+    // 单个字符类之后的位置
+    fn class_end(&self, mut p: usize) -> Result<usize, String> {
+        let c = self.pat[p];
+        p += 1;
+        if c == L_ESC {
+            if p >= self.pat.len() {
+                return Err("malformed pattern (ends with '%')".to_string());
+            }
+            return Ok(p + 1);
+        }
+        if c == b'[' {
+            if self.pat.get(p) == Some(&b'^') {
+                p += 1;
+            }
+            // 第一个字符即使是']'也属于集合
+            loop {
+                if p >= self.pat.len() {
+                    return Err("malformed pattern (missing ']')".to_string());
+                }
+                let c = self.pat[p];
+                p += 1;
+                if c == L_ESC && p < self.pat.len() {
+                    p += 1;
+                }
+                if self.pat.get(p) == Some(&b']') {
+                    return Ok(p + 1);
+                }
+                if p >= self.pat.len() {
+                    return Err("malformed pattern (missing ']')".to_string());
+                }
+            }
+        }
+        Ok(p)
+    }
+
+    fn single_match(&self, s: usize, p: usize, ep: usize) -> bool {
+        let Some(&c) = self.src.get(s) else { return false };
+        match self.pat[p] {
+            b'.' => true,
+            L_ESC => match_class(c, self.pat[p + 1]),
+            b'[' => self.match_bracket_class(c, p, ep - 1),
+            pc => pc == c,
+        }
+    }
+
+    // p指向'['，ec指向对应的']'
+    fn match_bracket_class(&self, c: u8, mut p: usize, ec: usize) -> bool {
+        let mut sig = true;
+        if self.pat[p + 1] == b'^' {
+            sig = false;
+            p += 1;
+        }
+        p += 1;
+        while p < ec {
+            if self.pat[p] == L_ESC {
+                p += 1;
+                if match_class(c, self.pat[p]) {
+                    return sig;
+                }
+            } else if self.pat[p + 1] == b'-' && p + 2 < ec {
+                if self.pat[p] <= c && c <= self.pat[p + 2] {
+                    return sig;
+                }
+                p += 2;
+            } else if self.pat[p] == c {
+                return sig;
+            }
+            p += 1;
+        }
+        !sig
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, ep: usize) -> MatchResult {
+        let mut i = 0;
+        while self.single_match(s + i, p, ep) {
+            i += 1;
+        }
+        // 尽量多地匹配，失败时逐个回退
+        loop {
+            if let Some(e) = self.do_match(s + i, ep + 1)? {
+                return Ok(Some(e));
+            }
+            if i == 0 {
+                return Ok(None);
+            }
+            i -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, ep: usize) -> MatchResult {
+        loop {
+            if let Some(e) = self.do_match(s, ep + 1)? {
+                return Ok(Some(e));
+            }
+            if self.single_match(s, p, ep) {
+                s += 1;
+            } else {
+                return Ok(None);
+            }
+        }
+    }
+
+    fn start_capture(&mut self, s: usize, p: usize, what: isize) -> MatchResult {
+        if self.level >= MAX_CAPTURES {
+            return Err("too many captures".to_string());
+        }
+        self.capture[self.level] = (s, what);
+        self.level += 1;
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.level -= 1;
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> MatchResult {
+        let l = self.capture_to_close()?;
+        self.capture[l].1 = (s - self.capture[l].0) as isize;
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.capture[l].1 = CAP_UNFINISHED;
+        }
+        Ok(result)
+    }
+
+    fn capture_to_close(&self) -> Result<usize, String> {
+        (0..self.level).rev()
+            .find(|&l| self.capture[l].1 == CAP_UNFINISHED)
+            .ok_or_else(|| "invalid pattern capture".to_string())
+    }
+
+    fn match_balance(&self, s: usize, p: usize) -> MatchResult {
+        if p + 1 >= self.pat.len() {
+            return Err("malformed pattern (missing arguments to '%b')".to_string());
+        }
+        let (b, e) = (self.pat[p], self.pat[p + 1]);
+        if self.src.get(s) != Some(&b) {
+            return Ok(None);
+        }
+        let mut count = 1;
+        for i in s + 1..self.src.len() {
+            let c = self.src[i];
+            if c == e {
+                count -= 1;
+                if count == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == b {
+                count += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    // 反向引用%1-%9
+    fn match_capture(&self, s: usize, l: u8) -> MatchResult {
+        let l = self.check_capture(l)?;
+        let (start, len) = self.capture[l];
+        let len = len as usize;
+        if self.src.len() - s >= len && self.src[start..start + len] == self.src[s..s + len] {
+            Ok(Some(s + len))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn check_capture(&self, l: u8) -> Result<usize, String> {
+        let i = l as isize - b'1' as isize;
+        if i < 0 || i as usize >= self.level || self.capture[i as usize].1 == CAP_UNFINISHED {
+            return Err(format!("invalid capture index %{}", i + 1));
+        }
+        Ok(i as usize)
+    }
+
+    // 第i个捕获；没有捕获时第0个为整个匹配s..e
+    pub fn get_capture(&self, i: usize, s: usize, e: usize) -> Result<Capture, String> {
+        if i >= self.level {
+            if i != 0 {
+                return Err(format!("invalid capture index %{}", i + 1));
+            }
+            return Ok(Capture::Str(s, e));
+        }
+        let (start, len) = self.capture[i];
+        match len {
+            CAP_UNFINISHED => Err("unfinished capture".to_string()),
+            CAP_POSITION => Ok(Capture::Position(start)),
+            len => Ok(Capture::Str(start, start + len as usize)),
+        }
+    }
+
+    // 全部捕获，whole为None时没有捕获就返回空
+    pub fn captures(&self, whole: Option<(usize, usize)>) -> Result<Vec<Capture>, String> {
+        let n = if self.level == 0 && whole.is_some() { 1 } else { self.level };
+        let (s, e) = whole.unwrap_or((0, 0));
+        (0..n).map(|i| self.get_capture(i, s, e)).collect()
+    }
+}
+
+fn match_class(c: u8, cl: u8) -> bool {
+    let result = match cl.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c.is_ascii_whitespace() || c == 0x0b,
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return cl == c,
+    };
+    if cl.is_ascii_uppercase() { !result } else { result }
+}
@@ -0,0 +1,112 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::LuaError;
+use crate::value::{Table, Value};
+use crate::vm::ExeState;
+
+use super::Lib;
+
+// 沙箱中保留的基础库函数：没有访问文件的dofile/loadfile，也没有可以绕过只读库表的rawset
+const SAFE_BASE: [&str; 22] = [
+    "_G", "_VERSION", "assert", "collectgarbage", "error", "getmetatable", "ipairs", "load",
+    "loadstring", "next", "pairs", "pcall", "print", "rawequal", "rawget", "rawlen", "select",
+    "setmetatable", "tonumber", "tostring", "type", "xpcall",
+];
+
+// 沙箱中保留的os函数：只有时间相关的
+const SAFE_OS: [&str; 4] = ["clock", "date", "difftime", "time"];
+
+// 沙箱中不能打开的库：可以访问文件系统或调试其他代码
+pub(crate) const UNSAFE_LIBS: [Lib; 3] = [Lib::Debug, Lib::Io, Lib::Package];
+
+const SANDBOX_KEY: &str = "_SANDBOX";
+
+// 把已打开的标准库限制为沙箱环境：过滤全局函数，库表换成只读代理，
+// load只接受文本chunk
+pub(crate) fn apply(state: &mut ExeState, libs: &[Lib]) {
+    state.registry().borrow_mut().set_str(SANDBOX_KEY, Value::Bool(true));
+
+    let lib_names: Vec<&str> = libs.iter().filter(|&&lib| lib != Lib::Base).map(|lib| lib.name()).collect();
+    let safe_globals: Vec<&str> = SAFE_BASE.iter().copied().chain(lib_names.iter().copied()).collect();
+    retain(&state.globals(), &safe_globals);
+    if let Value::Table(os) = state.get_global("os") {
+        retain(&os, &SAFE_OS);
+    }
+
+    for name in lib_names {
+        if let Value::Table(lib) = state.get_global(name) {
+            let proxy = read_only(state, lib);
+            state.set_global(name, proxy);
+        }
+    }
+    // 字符串方法也通过只读代理查找，且字符串的元表不能再被取得
+    if let Value::Table(string) = state.get_global("string") {
+        let mut meta = Table::new(0, 2);
+        meta.set_str("__index", Value::Table(string));
+        meta.set_str("__metatable", Value::Bool(false));
+        state.set_string_metatable(Some(Rc::new(RefCell::new(meta))));
+    }
+}
+
+// 删除表中名字不在白名单里的字段
+fn retain(t: &Rc<RefCell<Table>>, names: &[&str]) {
+    let mut removed = Vec::new();
+    let mut key = Value::Nil;
+    while let Ok(Some((k, _))) = t.borrow().next(&key) {
+        let keep = matches!(&k, Value::String(s) if names.iter().any(|name| name.as_bytes() == &s[..]));
+        if !keep {
+            removed.push(k.clone());
+        }
+        key = k;
+    }
+    for k in removed {
+        t.borrow_mut().set(k, Value::Nil);
+    }
+}
+
+pub(crate) fn is_sandboxed(state: &ExeState) -> bool {
+    state.registry().borrow().get_str(SANDBOX_KEY) == Value::Bool(true)
+}
+
+// 只读代理：读取转发到原表，赋值报错，元表被保护，pairs遍历原表
+fn read_only(state: &ExeState, t: Rc<RefCell<Table>>) -> Value {
+    let mut meta = Table::new(0, 4);
+    meta.set_str("__index", Value::Table(t.clone()));
+    meta.set_str("__newindex", Value::Function(read_only_newindex));
+    meta.set_str("__pairs", state.new_rust_closure(read_only_pairs, vec![Value::Table(t)]));
+    meta.set_str("__metatable", Value::Bool(false));
+    let mut proxy = Table::new(0, 0);
+    proxy.metatable = Some(Rc::new(RefCell::new(meta)));
+    Value::Table(Rc::new(RefCell::new(proxy)))
+}
+
+fn read_only_newindex(state: &mut ExeState) -> Result<i32, LuaError> {
+    Err(state.error("attempt to modify a read-only table"))
+}
+
+// 迭代函数以上值持有原表，状态是代理本身，原表不会交给脚本
+fn read_only_pairs(state: &mut ExeState) -> Result<i32, LuaError> {
+    let iter = state.new_rust_closure(read_only_next, vec![state.get_upvalue(1)]);
+    state.push(iter);
+    state.push(state.get_arg(1).clone());
+    state.push(Value::Nil);
+    Ok(3)
+}
+
+fn read_only_next(state: &mut ExeState) -> Result<i32, LuaError> {
+    let Value::Table(t) = state.get_upvalue(1) else { unreachable!() };
+    let next = t.borrow().next(state.get_arg(2));
+    match next {
+        Ok(Some((k, v))) => {
+            state.push(k);
+            state.push(v);
+            Ok(2)
+        }
+        Ok(None) => {
+            state.push(Value::Nil);
+            Ok(1)
+        }
+        Err(()) => Err(state.error("invalid key to 'next'")),
+    }
+}
@@ -0,0 +1,736 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::dump;
+use crate::error::LuaError;
+use crate::stdlib::pattern::{self, Capture, MatchState};
+use crate::stdlib::strpack;
+use crate::value::{fmt_g, RustFn, Table, Value};
+use crate::vm::ExeState;
+
+// 结果字符串的长度上限
+const MAX_SIZE: usize = i32::MAX as usize;
+
+pub fn open(state: &mut ExeState) {
+    let functions: [(&str, RustFn); 17] = [
+        ("byte", str_byte),
+        ("char", str_char),
+        ("dump", str_dump),
+        ("find", str_find),
+        ("format", str_format),
+        ("gmatch", str_gmatch),
+        ("gsub", str_gsub),
+        ("len", str_len),
+        ("lower", str_lower),
+        ("match", str_match),
+        ("pack", strpack::str_pack),
+        ("packsize", strpack::str_packsize),
+        ("rep", str_rep),
+        ("reverse", str_reverse),
+        ("sub", str_sub),
+        ("unpack", strpack::str_unpack),
+        ("upper", str_upper),
+    ];
+    let mut string = Table::new(0, 32);
+    for (name, f) in functions {
+        string.set_str(name, Value::Function(f));
+    }
+    let string = Rc::new(RefCell::new(string));
+
+    // 所有字符串共享的元表，使得 s:upper() 可用
+    let mut meta = Table::new(0, 1);
+    meta.set_str("__index", Value::Table(string.clone()));
+    state.set_string_metatable(Some(Rc::new(RefCell::new(meta))));
+    state.set_global("string", Value::Table(string));
+}
+
+// 负数位置从末尾算起，0视为1
+fn start_pos(pos: i64, len: usize) -> usize {
+    if pos > 0 {
+        pos as usize
+    } else if pos == 0 || pos < -(len as i64) {
+        1
+    } else {
+        (len as i64 + pos + 1) as usize
+    }
+}
+
+// 结束位置，超出范围时截断到[0, len]
+fn end_pos(pos: i64, len: usize) -> usize {
+    if pos > len as i64 {
+        len
+    } else if pos >= 0 {
+        pos as usize
+    } else if pos < -(len as i64) {
+        0
+    } else {
+        (len as i64 + pos + 1) as usize
+    }
+}
+
+fn str_len(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "len")?;
+    state.push(Value::Integer(s.len() as i64));
+    Ok(1)
+}
+
+// string.sub(s, i [, j])
+fn str_sub(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "sub")?;
+    let i = start_pos(state.check_integer(2, "sub")?, s.len());
+    let j = end_pos(state.opt_integer(3, "sub", -1)?, s.len());
+    state.push(if i > j { Value::from("") } else { Value::from(&s[i - 1..j]) });
+    Ok(1)
+}
+
+fn str_upper(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "upper")?;
+    state.push(Value::from(s.to_ascii_uppercase()));
+    Ok(1)
+}
+
+fn str_lower(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "lower")?;
+    state.push(Value::from(s.to_ascii_lowercase()));
+    Ok(1)
+}
+
+// string.rep(s, n [, sep])
+fn str_rep(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "rep")?;
+    let n = state.check_integer(2, "rep")?;
+    let sep = state.opt_string(3, "rep", "")?;
+    if n <= 0 {
+        state.push(Value::from(""));
+        return Ok(1);
+    }
+    let n = n as usize;
+    if (s.len() + sep.len()).checked_mul(n).is_none_or(|total| total > MAX_SIZE) {
+        return Err(state.error("resulting string too large"));
+    }
+    let total = (s.len() + sep.len()) * n - sep.len();
+    let mut result = Vec::new();
+    state.reserve(&mut result, total)?;
+    // 先放一份s和sep，再成倍复制已有的内容，每次复制整数份；最后一份不带sep
+    result.extend_from_slice(&s);
+    result.extend_from_slice(&sep);
+    let unit = s.len() + sep.len();
+    let step = (super::CHECK_BYTES / unit.max(1)).max(1) * unit;
+    let body = total - s.len();
+    while result.len() < body {
+        let len = result.len().min(step).min(body - result.len());
+        result.extend_from_within(..len);
+        state.check_interrupt()?;
+    }
+    result.truncate(body);
+    result.extend_from_slice(&s);
+    state.push(Value::from(result));
+    Ok(1)
+}
+
+fn str_reverse(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "reverse")?;
+    let mut r = s.to_vec();
+    r.reverse();
+    state.push(Value::from(r));
+    Ok(1)
+}
+
+// string.byte(s [, i [, j]])
+fn str_byte(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "byte")?;
+    let i = start_pos(state.opt_integer(2, "byte", 1)?, s.len());
+    let j = end_pos(state.opt_integer(3, "byte", i as i64)?, s.len());
+    if i > j {
+        return Ok(0);
+    }
+    if j - i >= i32::MAX as usize {
+        return Err(state.error("string slice too long"));
+    }
+    let bytes: Vec<Value> = s[i - 1..j].iter().map(|&b| Value::Integer(b as i64)).collect();
+    Ok(state.push_all(bytes))
+}
+
+fn str_char(state: &mut ExeState) -> Result<i32, LuaError> {
+    let mut result = Vec::with_capacity(state.get_args_count());
+    for i in 1..=state.get_args_count() {
+        let c = state.check_integer(i, "char")?;
+        if !(0..=255).contains(&c) {
+            return Err(state.arg_error(i, "char", "value out of range"));
+        }
+        result.push(c as u8);
+    }
+    state.push(Value::from(result));
+    Ok(1)
+}
+
+// string.dump(f [, strip])：把Lua函数序列化为二进制chunk
+fn str_dump(state: &mut ExeState) -> Result<i32, LuaError> {
+    let bytes = match state.get_arg(1) {
+        Value::LuaFunction(f) => dump::dump(&f.proto, !state.get_arg(2).is_false()),
+        Value::Function(_) | Value::RustClosure(_) => return Err(state.error("unable to dump given function")),
+        _ => return Err(state.type_error(1, "dump", "function")),
+    };
+    state.push(Value::from(bytes));
+    Ok(1)
+}
+
+// ---------- 模式匹配 ----------
+
+fn capture_value(src: &[u8], cap: Capture) -> Value {
+    match cap {
+        Capture::Str(s, e) => Value::from(&src[s..e]),
+        Capture::Position(p) => Value::Integer(p as i64 + 1),
+    }
+}
+
+fn push_captures(state: &mut ExeState, ms: &MatchState, src: &[u8], whole: Option<(usize, usize)>) -> Result<i32, LuaError> {
+    let caps = ms.captures(whole).map_err(|e| state.error(&e))?;
+    let values = caps.into_iter().map(|c| capture_value(src, c)).collect();
+    Ok(state.push_all(values))
+}
+
+// find和match的共同实现
+fn str_find_aux(state: &mut ExeState, find: bool) -> Result<i32, LuaError> {
+    let fname = if find { "find" } else { "match" };
+    let s = state.check_string(1, fname)?;
+    let p = state.check_string(2, fname)?;
+    let init = start_pos(state.opt_integer(3, fname, 1)?, s.len()) - 1;
+    if init > s.len() {
+        state.push(Value::Nil);
+        return Ok(1);
+    }
+
+    if find && (!state.get_arg(4).is_false() || pattern::no_specials(&p)) {
+        // 普通子串查找
+        if let Some(i) = pattern::find_plain(&s[init..], &p) {
+            state.push(Value::Integer((init + i + 1) as i64));
+            state.push(Value::Integer((init + i + p.len()) as i64));
+            return Ok(2);
+        }
+    } else {
+        let anchor = p.first() == Some(&b'^');
+        let pstart = anchor as usize;
+        let mut ms = MatchState::new(&s, &p);
+        let mut s1 = init;
+        loop {
+            if let Some(e) = ms.find(s1, pstart).map_err(|e| state.error(&e))? {
+                if find {
+                    state.push(Value::Integer(s1 as i64 + 1));
+                    state.push(Value::Integer(e as i64));
+                    return Ok(push_captures(state, &ms, &s, None)? + 2);
+                }
+                return push_captures(state, &ms, &s, Some((s1, e)));
+            }
+            s1 += 1;
+            if anchor || s1 > s.len() {
+                break;
+            }
+        }
+    }
+    state.push(Value::Nil);
+    Ok(1)
+}
+
+// string.find(s, pattern [, init [, plain]])
+fn str_find(state: &mut ExeState) -> Result<i32, LuaError> {
+    str_find_aux(state, true)
+}
+
+// string.match(s, pattern [, init])
+fn str_match(state: &mut ExeState) -> Result<i32, LuaError> {
+    str_find_aux(state, false)
+}
+
+// string.gmatch(s, pattern [, init])：迭代器的上值为字符串、模式、当前位置和上次匹配的结束位置
+fn str_gmatch(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "gmatch")?;
+    let p = state.check_string(2, "gmatch")?;
+    let init = start_pos(state.opt_integer(3, "gmatch", 1)?, s.len()) - 1;
+    let init = init.min(s.len() + 1);
+    let iter = state.new_rust_closure(gmatch_aux, vec![
+        Value::String(s),
+        Value::String(p),
+        Value::Integer(init as i64),
+        Value::Nil,
+    ]);
+    state.push(iter);
+    Ok(1)
+}
+
+fn gmatch_aux(state: &mut ExeState) -> Result<i32, LuaError> {
+    let (Value::String(s), Value::String(p)) = (state.get_upvalue(1), state.get_upvalue(2)) else {
+        return Ok(0);
+    };
+    let Value::Integer(pos) = state.get_upvalue(3) else { return Ok(0) };
+    let last = state.get_upvalue(4);
+
+    let mut ms = MatchState::new(&s, &p);
+    let mut src = pos as usize;
+    while src <= s.len() {
+        if let Some(e) = ms.find(src, 0).map_err(|e| state.error(&e))? {
+            // 不接受在上次匹配结束处的空匹配
+            if last != Value::Integer(e as i64) {
+                state.set_upvalue(3, Value::Integer(e as i64));
+                state.set_upvalue(4, Value::Integer(e as i64));
+                return push_captures(state, &ms, &s, Some((src, e)));
+            }
+        }
+        src += 1;
+    }
+    state.set_upvalue(3, Value::Integer(src as i64));
+    Ok(0)
+}
+
+// string.gsub(s, pattern, repl [, n])
+fn str_gsub(state: &mut ExeState) -> Result<i32, LuaError> {
+    let src = state.check_string(1, "gsub")?;
+    let p = state.check_string(2, "gsub")?;
+    let repl = state.get_arg(3).clone();
+    if !matches!(repl, Value::Integer(_) | Value::Float(_) | Value::String(_) | Value::Table(_)
+        | Value::Function(_) | Value::LuaFunction(_) | Value::RustClosure(_)) {
+        return Err(state.type_error(3, "gsub", "string/function/table"));
+    }
+    let max_n = state.opt_integer(4, "gsub", src.len() as i64 + 1)?;
+
+    let anchor = p.first() == Some(&b'^');
+    let pstart = anchor as usize;
+    let mut ms = MatchState::new(&src, &p);
+    let mut result = Vec::with_capacity(src.len());
+    let mut s = 0;
+    let mut last = None;
+    let mut n = 0;
+    while n < max_n {
+        match ms.find(s, pstart).map_err(|e| state.error(&e))? {
+            Some(e) if Some(e) != last => {
+                n += 1;
+                add_value(state, &ms, &mut result, &src, s, e, &repl)?;
+                s = e;
+                last = Some(e);
+            }
+            _ if s < src.len() => {
+                result.push(src[s]);
+                s += 1;
+            }
+            _ => break,
+        }
+        if anchor {
+            break;
+        }
+    }
+    result.extend_from_slice(&src[s..]);
+    state.push(Value::from(result));
+    state.push(Value::Integer(n));
+    Ok(2)
+}
+
+// 把一次匹配s..e的替换结果追加到result
+fn add_value(state: &mut ExeState, ms: &MatchState, result: &mut Vec<u8>, src: &[u8], s: usize, e: usize, repl: &Value) -> Result<(), LuaError> {
+    let value = match repl {
+        Value::String(r) => return add_string(state, ms, result, src, s, e, r),
+        Value::Integer(_) | Value::Float(_) => {
+            return add_string(state, ms, result, src, s, e, repl.to_string().as_bytes());
+        }
+        Value::Table(_) => {
+            let key = ms.get_capture(0, s, e).map_err(|m| state.error(&m))?;
+            state.index(repl, &capture_value(src, key))?
+        }
+        _ => {
+            let caps = ms.captures(Some((s, e))).map_err(|m| state.error(&m))?;
+            let args = caps.into_iter().map(|c| capture_value(src, c)).collect();
+            state.call_function(repl.clone(), args)?.into_iter().next().unwrap_or(Value::Nil)
+        }
+    };
+    match value {
+        Value::Nil | Value::Bool(false) => result.extend_from_slice(&src[s..e]),
+        Value::String(v) => result.extend_from_slice(&v),
+        v @ (Value::Integer(_) | Value::Float(_)) => result.extend_from_slice(v.to_string().as_bytes()),
+        v => return Err(state.error(&format!("invalid replacement value (a {})", v.type_name()))),
+    }
+    Ok(())
+}
+
+// 替换字符串中的%0-%9为捕获，%%为'%'
+fn add_string(state: &mut ExeState, ms: &MatchState, result: &mut Vec<u8>, src: &[u8], s: usize, e: usize, repl: &[u8]) -> Result<(), LuaError> {
+    let mut i = 0;
+    while i < repl.len() {
+        let c = repl[i];
+        i += 1;
+        if c != b'%' {
+            result.push(c);
+            continue;
+        }
+        match repl.get(i) {
+            Some(b'%') => result.push(b'%'),
+            Some(b'0') => result.extend_from_slice(&src[s..e]),
+            Some(d @ b'1'..=b'9') => {
+                let cap = ms.get_capture((d - b'1') as usize, s, e).map_err(|m| state.error(&m))?;
+                result.extend_from_slice(capture_value(src, cap).to_string().as_bytes());
+            }
+            _ => return Err(state.error("invalid use of '%' in replacement string")),
+        }
+        i += 1;
+    }
+    Ok(())
+}
+
+// ---------- string.format ----------
+
+// 一个格式说明的标志、宽度和精度
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+const FLAGS_F: &[u8] = b"-+ #0";
+const FLAGS_X: &[u8] = b"-#0";
+const FLAGS_I: &[u8] = b"-+ 0";
+const FLAGS_U: &[u8] = b"-0";
+const FLAGS_C: &[u8] = b"-";
+
+// 检查说明只包含允许的标志，宽度和精度都不超过2位
+fn check_spec(form: &[u8], flags: &[u8], precision: bool) -> bool {
+    let mut i = 1;
+    while i < form.len() && flags.contains(&form[i]) {
+        i += 1;
+    }
+    let digits2 = |mut i: usize| {
+        for _ in 0..2 {
+            if form.get(i).is_some_and(u8::is_ascii_digit) {
+                i += 1;
+            }
+        }
+        i
+    };
+    if form.get(i) != Some(&b'0') {
+        i = digits2(i);
+        if form.get(i) == Some(&b'.') && precision {
+            i = digits2(i + 1);
+        }
+    }
+    form.get(i).is_some_and(u8::is_ascii_alphabetic)
+}
+
+fn parse_spec(form: &[u8]) -> Spec {
+    let mut spec = Spec { left: false, plus: false, space: false, alt: false, zero: false, width: 0, precision: None };
+    let mut i = 1;
+    loop {
+        match form[i] {
+            b'-' => spec.left = true,
+            b'+' => spec.plus = true,
+            b' ' => spec.space = true,
+            b'#' => spec.alt = true,
+            b'0' => spec.zero = true,
+            _ => break,
+        }
+        i += 1;
+    }
+    while form[i].is_ascii_digit() {
+        spec.width = spec.width * 10 + (form[i] - b'0') as usize;
+        i += 1;
+    }
+    if form[i] == b'.' {
+        i += 1;
+        let mut p = 0;
+        while form[i].is_ascii_digit() {
+            p = p * 10 + (form[i] - b'0') as usize;
+            i += 1;
+        }
+        spec.precision = Some(p);
+    }
+    spec
+}
+
+// 按宽度填充；zero为真时在符号和前缀之后补0
+fn pad(spec: &Spec, prefix: &str, body: &[u8], zero: bool) -> Vec<u8> {
+    let len = prefix.len() + body.len();
+    let fill = spec.width.saturating_sub(len);
+    let mut out = Vec::with_capacity(len + fill);
+    if spec.left {
+        out.extend_from_slice(prefix.as_bytes());
+        out.extend_from_slice(body);
+        out.resize(out.len() + fill, b' ');
+    } else if zero {
+        out.extend_from_slice(prefix.as_bytes());
+        out.resize(out.len() + fill, b'0');
+        out.extend_from_slice(body);
+    } else {
+        out.resize(fill, b' ');
+        out.extend_from_slice(prefix.as_bytes());
+        out.extend_from_slice(body);
+    }
+    out
+}
+
+fn sign_prefix(spec: &Spec, negative: bool) -> &'static str {
+    if negative {
+        "-"
+    } else if spec.plus {
+        "+"
+    } else if spec.space {
+        " "
+    } else {
+        ""
+    }
+}
+
+fn format_int(spec: &Spec, conv: u8, n: i64) -> Vec<u8> {
+    let mut digits = match conv {
+        b'd' | b'i' => n.unsigned_abs().to_string(),
+        b'u' => (n as u64).to_string(),
+        b'o' => format!("{:o}", n as u64),
+        b'x' => format!("{:x}", n as u64),
+        _ => format!("{:X}", n as u64),
+    };
+    if let Some(p) = spec.precision {
+        if p == 0 && n == 0 {
+            digits.clear();
+        }
+        if digits.len() < p {
+            digits.insert_str(0, &"0".repeat(p - digits.len()));
+        }
+    }
+    let prefix = match conv {
+        b'd' | b'i' => sign_prefix(spec, n < 0),
+        b'o' if spec.alt && !digits.starts_with('0') => {
+            digits.insert(0, '0');
+            ""
+        }
+        b'x' if spec.alt && n != 0 => "0x",
+        b'X' if spec.alt && n != 0 => "0X",
+        _ => "",
+    };
+    pad(spec, prefix, digits.as_bytes(), spec.zero && spec.precision.is_none())
+}
+
+// "%e"格式：尾数和至少两位的指数
+fn fmt_e(f: f64, precision: usize, alt: bool) -> String {
+    let s = format!("{:.*e}", precision, f);
+    let (mantissa, exp) = s.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    let dot = if alt && precision == 0 { "." } else { "" };
+    format!("{}{}e{}{:02}", mantissa, dot, if exp < 0 { '-' } else { '+' }, exp.abs())
+}
+
+// "%#g"：保留末尾的0
+fn fmt_g_alt(f: f64, precision: usize) -> String {
+    let precision = precision.max(1);
+    let exp = if f == 0.0 {
+        0
+    } else {
+        let s = format!("{:.*e}", precision - 1, f);
+        s.split_once('e').unwrap().1.parse::<i32>().unwrap()
+    };
+    if exp < -4 || exp >= precision as i32 {
+        fmt_e(f, precision - 1, true)
+    } else {
+        let decimals = (precision as i32 - 1 - exp) as usize;
+        let s = format!("{:.*}", decimals, f);
+        if decimals == 0 { s + "." } else { s }
+    }
+}
+
+// "%a"：十六进制浮点数，与glibc的输出一致
+fn fmt_hex_float(f: f64, precision: Option<usize>) -> String {
+    if f == 0.0 {
+        return match precision {
+            Some(p) if p > 0 => format!("0x0.{}p+0", "0".repeat(p)),
+            _ => "0x0p+0".to_string(),
+        };
+    }
+    let bits = f.to_bits();
+    let biased = ((bits >> 52) & 0x7ff) as i64;
+    let mut mantissa = bits & ((1 << 52) - 1);
+    let (mut lead, exp) = if biased == 0 { (0, -1022) } else { (1, biased - 1023) };
+    let digits = match precision {
+        None => {
+            let s = format!("{:013x}", mantissa);
+            s.trim_end_matches('0').to_string()
+        }
+        Some(p) if p >= 13 => format!("{:013x}{}", mantissa, "0".repeat(p - 13)),
+        Some(p) => {
+            // 按舍入到最近偶数截断到p位
+            let shift = (13 - p) * 4;
+            let rem = mantissa & ((1 << shift) - 1);
+            let half = 1 << (shift - 1);
+            mantissa >>= shift;
+            if rem > half || (rem == half && mantissa & 1 == 1) {
+                mantissa += 1;
+                if p == 0 || mantissa >> (p * 4) != 0 {
+                    lead += 1;
+                    mantissa &= (1 << (p * 4)) - 1;
+                }
+            }
+            if p == 0 { String::new() } else { format!("{:0width$x}", mantissa, width = p) }
+        }
+    };
+    let dot = if digits.is_empty() { "" } else { "." };
+    format!("0x{}{}{}p{}{}", lead, dot, digits, if exp < 0 { '-' } else { '+' }, exp.abs())
+}
+
+fn format_float(spec: &Spec, conv: u8, f: f64) -> Vec<u8> {
+    let prefix = sign_prefix(spec, f.is_sign_negative());
+    let x = f.abs();
+    if !x.is_finite() {
+        let body = if x.is_nan() { "nan" } else { "inf" };
+        let body = if conv.is_ascii_uppercase() { body.to_ascii_uppercase() } else { body.to_string() };
+        return pad(spec, prefix, body.as_bytes(), false);
+    }
+    let body = match conv.to_ascii_lowercase() {
+        b'f' => {
+            let p = spec.precision.unwrap_or(6);
+            let s = format!("{:.*}", p, x);
+            if spec.alt && p == 0 { s + "." } else { s }
+        }
+        b'e' => fmt_e(x, spec.precision.unwrap_or(6), spec.alt),
+        b'g' if spec.alt => fmt_g_alt(x, spec.precision.unwrap_or(6)),
+        b'g' => fmt_g(x, spec.precision.unwrap_or(6)),
+        _ => fmt_hex_float(x, spec.precision),
+    };
+    let body = if conv.is_ascii_uppercase() { body.to_ascii_uppercase() } else { body };
+    pad(spec, prefix, body.as_bytes(), spec.zero)
+}
+
+// "%q"：可以被Lua读回的字面量
+fn add_quoted(out: &mut Vec<u8>, s: &[u8]) {
+    out.push(b'"');
+    for (i, &c) in s.iter().enumerate() {
+        match c {
+            b'"' | b'\\' | b'\n' => {
+                out.push(b'\\');
+                out.push(c);
+            }
+            c if c.is_ascii_control() => {
+                let next_is_digit = s.get(i + 1).is_some_and(u8::is_ascii_digit);
+                let esc = if next_is_digit { format!("\\{:03}", c) } else { format!("\\{}", c) };
+                out.extend_from_slice(esc.as_bytes());
+            }
+            c => out.push(c),
+        }
+    }
+    out.push(b'"');
+}
+
+fn add_literal(state: &ExeState, out: &mut Vec<u8>, arg: usize) -> Result<(), LuaError> {
+    match state.get_arg(arg) {
+        Value::String(s) => add_quoted(out, s),
+        Value::Integer(i) => {
+            let s = if *i == i64::MIN { format!("0x{:x}", i) } else { i.to_string() };
+            out.extend_from_slice(s.as_bytes());
+        }
+        Value::Float(f) => {
+            let s = if *f == f64::INFINITY {
+                "1e9999".to_string()
+            } else if *f == f64::NEG_INFINITY {
+                "-1e9999".to_string()
+            } else if f.is_nan() {
+                "(0/0)".to_string()
+            } else {
+                let hex = fmt_hex_float(f.abs(), None);
+                if f.is_sign_negative() { format!("-{}", hex) } else { hex }
+            };
+            out.extend_from_slice(s.as_bytes());
+        }
+        v @ (Value::Nil | Value::Bool(_)) => out.extend_from_slice(v.to_string().as_bytes()),
+        _ => return Err(state.arg_error(arg, "format", "value has no literal form")),
+    }
+    Ok(())
+}
+
+// string.format(formatstring, ...)
+fn str_format(state: &mut ExeState) -> Result<i32, LuaError> {
+    let fmt = state.check_string(1, "format")?;
+    let mut out = Vec::with_capacity(fmt.len());
+    let mut arg = 1;
+    let mut i = 0;
+    while i < fmt.len() {
+        let c = fmt[i];
+        i += 1;
+        if c != b'%' {
+            out.push(c);
+            continue;
+        }
+        if fmt.get(i) == Some(&b'%') {
+            out.push(b'%');
+            i += 1;
+            continue;
+        }
+
+        // 取出"%[flags][width][.precision]conv"
+        let start = i - 1;
+        while i < fmt.len() && b"-+ #0123456789.".contains(&fmt[i]) {
+            i += 1;
+        }
+        if i - start >= 22 {
+            return Err(state.error("invalid format string to 'format'"));
+        }
+        let conv = fmt.get(i).copied().unwrap_or(0);
+        let form = &fmt[start..(i + 1).min(fmt.len())];
+        i += 1;
+        let form_str = String::from_utf8_lossy(form).into_owned();
+
+        arg += 1;
+        if arg > state.get_args_count() {
+            return Err(state.arg_error(arg, "format", "no value"));
+        }
+        let (flags, precision) = match conv {
+            b'c' | b'p' => (FLAGS_C, false),
+            b's' => (FLAGS_C, true),
+            b'd' | b'i' => (FLAGS_I, true),
+            b'u' => (FLAGS_U, true),
+            b'o' | b'x' | b'X' => (FLAGS_X, true),
+            b'a' | b'A' | b'f' | b'F' | b'e' | b'E' | b'g' | b'G' => (FLAGS_F, true),
+            b'q' => {
+                if form.len() > 2 {
+                    return Err(state.error("specifier '%q' cannot have modifiers"));
+                }
+                add_literal(state, &mut out, arg)?;
+                continue;
+            }
+            _ => return Err(state.error(&format!("invalid conversion '{}' to 'format'", form_str))),
+        };
+        if !check_spec(form, flags, precision) {
+            return Err(state.error(&format!("invalid conversion specification: '{}'", form_str)));
+        }
+        let spec = parse_spec(form);
+
+        match conv {
+            b'c' => {
+                let c = state.check_integer(arg, "format")? as u8;
+                out.extend(pad(&spec, "", &[c], false));
+            }
+            b'd' | b'i' | b'u' | b'o' | b'x' | b'X' => {
+                let n = state.check_integer(arg, "format")?;
+                out.extend(format_int(&spec, conv, n));
+            }
+            b'p' => {
+                let p = match state.get_arg(arg) {
+                    Value::String(s) => s.as_ptr() as *const std::ffi::c_void,
+                    v => v.to_pointer(),
+                };
+                let body = if p.is_null() { "(null)".to_string() } else { format!("{:p}", p) };
+                out.extend(pad(&spec, "", body.as_bytes(), false));
+            }
+            b's' => {
+                let v = state.get_arg(arg).clone();
+                let Value::String(s) = state.tostring(&v)? else { unreachable!() };
+                let s = match spec.precision {
+                    Some(p) if p < s.len() => &s[..p],
+                    _ => &s[..],
+                };
+                out.extend(pad(&spec, "", s, false));
+            }
+            _ => {
+                let f = state.check_float(arg, "format")?;
+                out.extend(format_float(&spec, conv, f));
+            }
+        }
+    }
+    state.push(Value::from(out));
+    Ok(1)
+}
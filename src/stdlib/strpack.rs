@@ -0,0 +1,342 @@
+// string.pack/unpack/packsize，移植自C Lua的lstrlib.c
+
+use crate::error::LuaError;
+use crate::value::Value;
+use crate::vm::ExeState;
+
+const MAX_INT_SIZE: usize = 16;
+const SZINT: usize = 8;  // lua_Integer的字节数
+const MAX_ALIGN: usize = 8;
+const MAX_SIZE: usize = i64::MAX as usize;
+
+#[derive(Clone, Copy, PartialEq)]
+enum KOption {
+    Int,       // 有符号整数
+    Uint,      // 无符号整数
+    Float,     // f：单精度浮点数
+    Double,    // d和n：双精度浮点数
+    Char,      // c[n]：定长字符串
+    Str,       // s[n]：带长度前缀的字符串
+    Zstr,      // z：以'\0'结尾的字符串
+    Padding,   // x：一个填充字节
+    PaddAlign, // X：按下一个选项对齐
+    Nop,       // 空格和设置选项
+}
+
+// 格式字符串的解析状态
+struct Format<'a> {
+    fmt: &'a [u8],
+    pos: usize,
+    little: bool,
+    max_align: usize,
+    fname: &'static str,
+}
+
+impl<'a> Format<'a> {
+    fn new(fmt: &'a [u8], fname: &'static str) -> Self {
+        Format { fmt, pos: 0, little: cfg!(target_endian = "little"), max_align: 1, fname }
+    }
+
+    fn done(&self) -> bool {
+        self.pos >= self.fmt.len()
+    }
+
+    fn get_num(&mut self, default: usize) -> usize {
+        if !self.fmt.get(self.pos).is_some_and(u8::is_ascii_digit) {
+            return default;
+        }
+        let mut a = 0;
+        while let Some(d) = self.fmt.get(self.pos).filter(|d| d.is_ascii_digit()) {
+            if a > (i32::MAX as usize - 9) / 10 {
+                break;
+            }
+            a = a * 10 + (d - b'0') as usize;
+            self.pos += 1;
+        }
+        a
+    }
+
+    fn get_num_limit(&mut self, state: &ExeState, default: usize) -> Result<usize, LuaError> {
+        let size = self.get_num(default);
+        if size > MAX_INT_SIZE || size == 0 {
+            return Err(state.error(&format!("integral size ({}) out of limits [1,{}]", size, MAX_INT_SIZE)));
+        }
+        Ok(size)
+    }
+
+    // 读取一个选项，返回选项和它的大小
+    fn get_option(&mut self, state: &ExeState) -> Result<(KOption, usize), LuaError> {
+        let opt = self.fmt[self.pos];
+        self.pos += 1;
+        Ok(match opt {
+            b'b' => (KOption::Int, 1),
+            b'B' => (KOption::Uint, 1),
+            b'h' => (KOption::Int, 2),
+            b'H' => (KOption::Uint, 2),
+            b'l' | b'j' => (KOption::Int, 8),
+            b'L' | b'J' | b'T' => (KOption::Uint, 8),
+            b'f' => (KOption::Float, 4),
+            b'n' | b'd' => (KOption::Double, 8),
+            b'i' => (KOption::Int, self.get_num_limit(state, 4)?),
+            b'I' => (KOption::Uint, self.get_num_limit(state, 4)?),
+            b's' => (KOption::Str, self.get_num_limit(state, 8)?),
+            b'c' => {
+                if !self.fmt.get(self.pos).is_some_and(u8::is_ascii_digit) {
+                    return Err(state.error("missing size for format option 'c'"));
+                }
+                (KOption::Char, self.get_num(0))
+            }
+            b'z' => (KOption::Zstr, 0),
+            b'x' => (KOption::Padding, 1),
+            b'X' => (KOption::PaddAlign, 0),
+            b' ' => (KOption::Nop, 0),
+            b'<' => {
+                self.little = true;
+                (KOption::Nop, 0)
+            }
+            b'>' => {
+                self.little = false;
+                (KOption::Nop, 0)
+            }
+            b'=' => {
+                self.little = cfg!(target_endian = "little");
+                (KOption::Nop, 0)
+            }
+            b'!' => {
+                self.max_align = self.get_num_limit(state, MAX_ALIGN)?;
+                (KOption::Nop, 0)
+            }
+            c => return Err(state.error(&format!("invalid format option '{}'", c as char))),
+        })
+    }
+
+    // 读取一个选项，并计算在当前位置total之前需要的对齐字节数
+    fn get_details(&mut self, state: &ExeState, total: usize) -> Result<(KOption, usize, usize), LuaError> {
+        let (opt, size) = self.get_option(state)?;
+        let mut align = size;
+        if opt == KOption::PaddAlign {
+            // 'X'的对齐由下一个选项决定，该选项本身被忽略
+            let next = if self.done() { None } else { Some(self.get_option(state)?) };
+            match next {
+                Some((o, a)) if o != KOption::Char && a != 0 => align = a,
+                _ => return Err(state.arg_error(1, self.fname, "invalid next option for option 'X'")),
+            }
+        }
+        let ntoalign = if align <= 1 || opt == KOption::Char {
+            0
+        } else {
+            let align = align.min(self.max_align);
+            if !align.is_power_of_two() {
+                return Err(state.arg_error(1, self.fname, "format asks for alignment not power of 2"));
+            }
+            (align - (total & (align - 1))) & (align - 1)
+        };
+        Ok((opt, size, ntoalign))
+    }
+}
+
+fn pack_int(out: &mut Vec<u8>, n: u64, little: bool, size: usize, negative: bool) {
+    let mut bytes = vec![if negative { 0xff } else { 0 }; size];
+    for (i, b) in bytes.iter_mut().take(SZINT).enumerate() {
+        *b = (n >> (i * 8)) as u8;
+    }
+    if !little {
+        bytes.reverse();
+    }
+    out.extend_from_slice(&bytes);
+}
+
+fn pack_bytes(out: &mut Vec<u8>, bytes: &[u8], little: bool) {
+    // bytes为本机字节序
+    if little == cfg!(target_endian = "little") {
+        out.extend_from_slice(bytes);
+    } else {
+        out.extend(bytes.iter().rev());
+    }
+}
+
+// string.pack(fmt, v1, v2, ...)
+pub fn str_pack(state: &mut ExeState) -> Result<i32, LuaError> {
+    let fmt = state.check_string(1, "pack")?;
+    let mut f = Format::new(&fmt, "pack");
+    let mut out = Vec::new();
+    let mut arg = 1;
+    while !f.done() {
+        let (opt, size, ntoalign) = f.get_details(state, out.len())?;
+        out.resize(out.len() + ntoalign, 0);
+        arg += 1;
+        match opt {
+            KOption::Int => {
+                let n = state.check_integer(arg, "pack")?;
+                if size < SZINT {
+                    let lim = 1i64 << (size * 8 - 1);
+                    if !(-lim..lim).contains(&n) {
+                        return Err(state.arg_error(arg, "pack", "integer overflow"));
+                    }
+                }
+                pack_int(&mut out, n as u64, f.little, size, n < 0);
+            }
+            KOption::Uint => {
+                let n = state.check_integer(arg, "pack")?;
+                if size < SZINT && (n as u64) >= 1u64 << (size * 8) {
+                    return Err(state.arg_error(arg, "pack", "unsigned overflow"));
+                }
+                pack_int(&mut out, n as u64, f.little, size, false);
+            }
+            KOption::Float => {
+                let x = state.check_float(arg, "pack")? as f32;
+                pack_bytes(&mut out, &x.to_ne_bytes(), f.little);
+            }
+            KOption::Double => {
+                let x = state.check_float(arg, "pack")?;
+                pack_bytes(&mut out, &x.to_ne_bytes(), f.little);
+            }
+            KOption::Char => {
+                let s = state.check_string(arg, "pack")?;
+                if s.len() > size {
+                    return Err(state.arg_error(arg, "pack", "string longer than given size"));
+                }
+                out.extend_from_slice(&s);
+                out.resize(out.len() + size - s.len(), 0);
+            }
+            KOption::Str => {
+                let s = state.check_string(arg, "pack")?;
+                if size < SZINT && s.len() as u64 >= 1u64 << (size * 8) {
+                    return Err(state.arg_error(arg, "pack", "string length does not fit in given size"));
+                }
+                pack_int(&mut out, s.len() as u64, f.little, size, false);
+                out.extend_from_slice(&s);
+            }
+            KOption::Zstr => {
+                let s = state.check_string(arg, "pack")?;
+                if s.contains(&0) {
+                    return Err(state.arg_error(arg, "pack", "string contains zeros"));
+                }
+                out.extend_from_slice(&s);
+                out.push(0);
+            }
+            KOption::Padding => {
+                out.push(0);
+                arg -= 1;
+            }
+            KOption::PaddAlign | KOption::Nop => arg -= 1,
+        }
+    }
+    state.push(Value::from(out));
+    Ok(1)
+}
+
+// string.packsize(fmt)：格式中不能有变长选项
+pub fn str_packsize(state: &mut ExeState) -> Result<i32, LuaError> {
+    let fmt = state.check_string(1, "packsize")?;
+    let mut f = Format::new(&fmt, "packsize");
+    let mut total: usize = 0;
+    while !f.done() {
+        let (opt, size, ntoalign) = f.get_details(state, total)?;
+        if opt == KOption::Str || opt == KOption::Zstr {
+            return Err(state.arg_error(1, "packsize", "variable-length format"));
+        }
+        let size = size + ntoalign;
+        if total > MAX_SIZE - size {
+            return Err(state.arg_error(1, "packsize", "format result too large"));
+        }
+        total += size;
+    }
+    state.push(Value::Integer(total as i64));
+    Ok(1)
+}
+
+fn unpack_int(state: &ExeState, bytes: &[u8], little: bool, signed: bool) -> Result<i64, LuaError> {
+    let size = bytes.len();
+    let at = |i: usize| if little { bytes[i] } else { bytes[size - 1 - i] };
+    let limit = size.min(SZINT);
+    let mut res: u64 = 0;
+    for i in (0..limit).rev() {
+        res = (res << 8) | at(i) as u64;
+    }
+    if size < SZINT {
+        if signed {
+            let mask = 1u64 << (size * 8 - 1);
+            res = (res ^ mask).wrapping_sub(mask);
+        }
+    } else if size > SZINT {
+        // 多出的字节必须是符号扩展
+        let mask = if !signed || (res as i64) >= 0 { 0 } else { 0xff };
+        if (limit..size).any(|i| at(i) != mask) {
+            return Err(state.error(&format!("{}-byte integer does not fit into Lua Integer", size)));
+        }
+    }
+    Ok(res as i64)
+}
+
+fn native_bytes<const N: usize>(bytes: &[u8], little: bool) -> [u8; N] {
+    let mut buf: [u8; N] = bytes.try_into().unwrap();
+    if little != cfg!(target_endian = "little") {
+        buf.reverse();
+    }
+    buf
+}
+
+// string.unpack(fmt, s [, pos])：返回各个值和之后的位置
+pub fn str_unpack(state: &mut ExeState) -> Result<i32, LuaError> {
+    let fmt = state.check_string(1, "unpack")?;
+    let data = state.check_string(2, "unpack")?;
+    let len = data.len();
+    let init = state.opt_integer(3, "unpack", 1)?;
+    let mut pos = if init > 0 {
+        init as usize - 1
+    } else if init == 0 || init < -(len as i64) {
+        0
+    } else {
+        (len as i64 + init) as usize
+    };
+    if pos > len {
+        return Err(state.arg_error(3, "unpack", "initial position out of string"));
+    }
+
+    let mut f = Format::new(&fmt, "unpack");
+    let mut values = Vec::new();
+    while !f.done() {
+        let (opt, size, ntoalign) = f.get_details(state, pos)?;
+        if ntoalign + size > len - pos {
+            return Err(state.arg_error(2, "unpack", "data string too short"));
+        }
+        pos += ntoalign;
+        let bytes = &data[pos..pos + size];
+        match opt {
+            KOption::Int | KOption::Uint => {
+                let n = unpack_int(state, bytes, f.little, opt == KOption::Int)?;
+                values.push(Value::Integer(n));
+            }
+            KOption::Float => {
+                let x = f32::from_ne_bytes(native_bytes(bytes, f.little));
+                values.push(Value::Float(x as f64));
+            }
+            KOption::Double => {
+                let x = f64::from_ne_bytes(native_bytes(bytes, f.little));
+                values.push(Value::Float(x));
+            }
+            KOption::Char => values.push(Value::from(bytes)),
+            KOption::Str => {
+                let n = unpack_int(state, bytes, f.little, false)? as u64;
+                if n > (len - pos - size) as u64 {
+                    return Err(state.arg_error(2, "unpack", "data string too short"));
+                }
+                let n = n as usize;
+                values.push(Value::from(&data[pos + size..pos + size + n]));
+                pos += n;
+            }
+            KOption::Zstr => {
+                let Some(n) = data[pos..].iter().position(|&b| b == 0) else {
+                    return Err(state.arg_error(2, "unpack", "unfinished string for format 'z'"));
+                };
+                values.push(Value::from(&data[pos..pos + n]));
+                pos += n + 1;
+            }
+            KOption::Padding | KOption::PaddAlign | KOption::Nop => (),
+        }
+        pos += size;
+    }
+    values.push(Value::Integer(pos as i64 + 1));
+    Ok(state.push_all(values))
+}
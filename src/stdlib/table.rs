@@ -0,0 +1,357 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::LuaError;
+use crate::value::{RustFn, Table, Value};
+use crate::vm::ExeState;
+
+// table.unpack一次最多返回的值个数
+const MAX_UNPACK: i64 = 1_000_000;
+// 区间小于此值时用中点作为快速排序的枢轴
+const RANLIMIT: i64 = 100;
+
+pub fn open(state: &mut ExeState) {
+    let functions: [(&str, RustFn); 7] = [
+        ("concat", tab_concat),
+        ("insert", tab_insert),
+        ("move", tab_move),
+        ("pack", tab_pack),
+        ("remove", tab_remove),
+        ("sort", tab_sort),
+        ("unpack", tab_unpack),
+    ];
+    let mut table = Table::new(0, 8);
+    for (name, f) in functions {
+        table.set_str(name, Value::Function(f));
+    }
+    state.set_global("table", Value::Table(Rc::new(RefCell::new(table))));
+}
+
+const TAB_R: u8 = 1;  // 需要读
+const TAB_W: u8 = 2;  // 需要写
+const TAB_L: u8 = 4;  // 需要长度
+
+// 参数必须是表，或者元表中有所需的元方法
+fn check_table(state: &ExeState, i: usize, fname: &str, what: u8) -> Result<Value, LuaError> {
+    let v = state.get_arg(i).clone();
+    if let Value::Table(_) = v {
+        return Ok(v);
+    }
+    if let Some(mt) = state.get_metatable(&v) {
+        let mt = mt.borrow();
+        let has = |event: &str| mt.get_str(event) != Value::Nil;
+        if (what & TAB_R == 0 || has("__index")) && (what & TAB_W == 0 || has("__newindex")) && (what & TAB_L == 0 || has("__len")) {
+            return Ok(v);
+        }
+    }
+    Err(state.type_error(i, fname, "table"))
+}
+
+// #t，遵守`__len`，结果必须是整数
+fn length(state: &mut ExeState, t: &Value) -> Result<i64, LuaError> {
+    match state.len(t)? {
+        Value::Integer(n) => Ok(n),
+        Value::Float(f) => crate::value::float_to_integer(f).ok_or_else(|| state.error("object length is not an integer")),
+        _ => Err(state.error("object length is not an integer")),
+    }
+}
+
+fn geti(state: &mut ExeState, t: &Value, i: i64) -> Result<Value, LuaError> {
+    state.index(t, &Value::Integer(i))
+}
+
+fn seti(state: &mut ExeState, t: &Value, i: i64, v: Value) -> Result<(), LuaError> {
+    state.set_index(t, Value::Integer(i), v)
+}
+
+// table.insert(t, [pos,] value)
+fn tab_insert(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = check_table(state, 1, "insert", TAB_R | TAB_W | TAB_L)?;
+    let e = length(state, &t)?.wrapping_add(1);  // 第一个空位
+    let pos = match state.get_args_count() {
+        2 => e,
+        3 => {
+            let pos = state.check_integer(2, "insert")?;
+            // 把pos转为无符号数，同时检查 1 <= pos <= e
+            if (pos as u64).wrapping_sub(1) >= e as u64 {
+                return Err(state.arg_error(2, "insert", "position out of bounds"));
+            }
+            for i in (pos + 1..=e).rev() {
+                let v = geti(state, &t, i - 1)?;
+                seti(state, &t, i, v)?;
+            }
+            pos
+        }
+        _ => return Err(state.error("wrong number of arguments to 'insert'")),
+    };
+    let v = state.get_arg(state.get_args_count()).clone();
+    seti(state, &t, pos, v)?;
+    Ok(0)
+}
+
+// table.remove(t [, pos])
+fn tab_remove(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = check_table(state, 1, "remove", TAB_R | TAB_W | TAB_L)?;
+    let size = length(state, &t)?;
+    let mut pos = state.opt_integer(2, "remove", size)?;
+    if pos != size && (pos as u64).wrapping_sub(1) > size as u64 {
+        return Err(state.arg_error(2, "remove", "position out of bounds"));
+    }
+    let result = geti(state, &t, pos)?;
+    while pos < size {
+        let v = geti(state, &t, pos + 1)?;
+        seti(state, &t, pos, v)?;
+        pos += 1;
+    }
+    seti(state, &t, pos, Value::Nil)?;
+    state.push(result);
+    Ok(1)
+}
+
+// table.move(a1, f, e, t [, a2])
+fn tab_move(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = state.check_integer(2, "move")?;
+    let e = state.check_integer(3, "move")?;
+    let t = state.check_integer(4, "move")?;
+    let tt = if state.get_args_count() >= 5 && *state.get_arg(5) != Value::Nil { 5 } else { 1 };
+    let src = check_table(state, 1, "move", TAB_R)?;
+    let dst = check_table(state, tt, "move", TAB_W)?;
+    if e >= f {
+        if !(f > 0 || e < i64::MAX + f) {
+            return Err(state.arg_error(3, "move", "too many elements to move"));
+        }
+        let n = e - f + 1;
+        if t > i64::MAX - n + 1 {
+            return Err(state.arg_error(4, "move", "destination wrap around"));
+        }
+        if t > e || t <= f || (tt != 1 && !state.equals(&src, &dst)?) {
+            for i in 0..n {
+                let v = geti(state, &src, f + i)?;
+                seti(state, &dst, t + i, v)?;
+            }
+        } else {
+            // 区间重叠时从后向前复制
+            for i in (0..n).rev() {
+                let v = geti(state, &src, f + i)?;
+                seti(state, &dst, t + i, v)?;
+            }
+        }
+    }
+    state.push(dst);
+    Ok(1)
+}
+
+// table.concat(list [, sep [, i [, j]]])
+fn tab_concat(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = check_table(state, 1, "concat", TAB_R | TAB_L)?;
+    let sep = state.opt_string(2, "concat", "")?;
+    let i = state.opt_integer(3, "concat", 1)?;
+    let last = match state.get_arg(4) {
+        Value::Nil => length(state, &t)?,
+        _ => state.check_integer(4, "concat")?,
+    };
+    let mut out = Vec::new();
+    let mut checked = 0;
+    let mut k = i;
+    while k <= last {
+        match geti(state, &t, k)? {
+            Value::String(s) => {
+                state.reserve(&mut out, s.len() + sep.len())?;
+                out.extend_from_slice(&s);
+            }
+            v @ (Value::Integer(_) | Value::Float(_)) => out.extend_from_slice(v.to_string().as_bytes()),
+            _ => return Err(state.error(&format!("invalid value (at index {}) in table for 'concat'", k))),
+        }
+        if k != last {
+            state.reserve(&mut out, sep.len())?;
+            out.extend_from_slice(&sep);
+        }
+        if out.len() - checked >= super::CHECK_BYTES {
+            checked = out.len();
+            state.check_interrupt()?;
+        }
+        if k == i64::MAX {
+            break;
+        }
+        k += 1;
+    }
+    state.push(Value::from(out));
+    Ok(1)
+}
+
+// table.pack(...)：返回包含全部参数和字段n的新表
+fn tab_pack(state: &mut ExeState) -> Result<i32, LuaError> {
+    let n = state.get_args_count();
+    let mut t = Table::new(n, 1);
+    for i in 1..=n {
+        t.set(Value::Integer(i as i64), state.get_arg(i).clone());
+    }
+    t.set_str("n", Value::Integer(n as i64));
+    state.push(Value::Table(Rc::new(RefCell::new(t))));
+    Ok(1)
+}
+
+// table.unpack(list [, i [, j]])
+fn tab_unpack(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = state.get_arg(1).clone();
+    let i = state.opt_integer(2, "unpack", 1)?;
+    let e = match state.get_arg(3) {
+        Value::Nil => length(state, &t)?,
+        _ => state.check_integer(3, "unpack")?,
+    };
+    if i > e {
+        return Ok(0);
+    }
+    let n = (e as u64).wrapping_sub(i as u64);
+    if n >= MAX_UNPACK as u64 {
+        return Err(state.error("too many results to unpack"));
+    }
+    let mut values = Vec::with_capacity(n as usize + 1);
+    for k in 0..=n as i64 {
+        values.push(geti(state, &t, i + k)?);
+    }
+    Ok(state.push_all(values))
+}
+
+// ---------- table.sort ----------
+
+// table.sort(list [, comp])：原地快速排序
+fn tab_sort(state: &mut ExeState) -> Result<i32, LuaError> {
+    let t = check_table(state, 1, "sort", TAB_R | TAB_W | TAB_L)?;
+    let n = length(state, &t)?;
+    if n > 1 {
+        if n >= i32::MAX as i64 {
+            return Err(state.arg_error(1, "sort", "array too big"));
+        }
+        let comp = state.get_arg(2).clone();
+        if !matches!(comp, Value::Nil | Value::Function(_) | Value::LuaFunction(_) | Value::RustClosure(_)) {
+            return Err(state.type_error(2, "sort", "function"));
+        }
+        let mut sorter = Sorter { t, comp };
+        sorter.sort(state, 1, n, 0)?;
+    }
+    Ok(0)
+}
+
+struct Sorter {
+    t: Value,
+    comp: Value,
+}
+
+impl Sorter {
+    fn get(&self, state: &mut ExeState, i: i64) -> Result<Value, LuaError> {
+        geti(state, &self.t, i)
+    }
+
+    fn set(&self, state: &mut ExeState, i: i64, v: Value) -> Result<(), LuaError> {
+        seti(state, &self.t, i, v)
+    }
+
+    // a < b，使用比较函数或`<`运算
+    fn less(&self, state: &mut ExeState, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match &self.comp {
+            Value::Nil => state.less_than(a, b),
+            comp => {
+                let r = state.call_function(comp.clone(), vec![a.clone(), b.clone()])?;
+                Ok(r.first().is_some_and(|v| !v.is_false()))
+            }
+        }
+    }
+
+    // t[i] = a; t[j] = b
+    fn set2(&self, state: &mut ExeState, i: i64, a: Value, j: i64, b: Value) -> Result<(), LuaError> {
+        self.set(state, i, a)?;
+        self.set(state, j, b)
+    }
+
+    fn sort(&mut self, state: &mut ExeState, mut lo: i64, mut up: i64, mut rnd: u64) -> Result<(), LuaError> {
+        while lo < up {
+            // 先排好 lo、p、up 三个位置
+            let a_lo = self.get(state, lo)?;
+            let a_up = self.get(state, up)?;
+            if self.less(state, &a_up, &a_lo)? {
+                self.set2(state, lo, a_up, up, a_lo)?;
+            }
+            if up - lo == 1 {
+                break;
+            }
+            let p = if up - lo < RANLIMIT || rnd == 0 {
+                lo + (up - lo) / 2
+            } else {
+                let r4 = (up - lo) / 4;
+                (rnd % (r4 as u64 * 2)) as i64 + lo + r4
+            };
+            let a_p = self.get(state, p)?;
+            let a_lo = self.get(state, lo)?;
+            if self.less(state, &a_p, &a_lo)? {
+                self.set2(state, p, a_lo, lo, a_p)?;
+            } else {
+                let a_up = self.get(state, up)?;
+                if self.less(state, &a_up, &a_p)? {
+                    self.set2(state, p, a_up, up, a_p)?;
+                }
+            }
+            if up - lo == 2 {
+                break;
+            }
+            // 把枢轴放到up-1
+            let pivot = self.get(state, p)?;
+            let a_up1 = self.get(state, up - 1)?;
+            self.set2(state, p, a_up1, up - 1, pivot.clone())?;
+            let p = self.partition(state, lo, up, &pivot)?;
+
+            // 递归处理较短的一边，较长的一边继续循环
+            let n;
+            if p - lo < up - p {
+                self.sort(state, lo, p - 1, rnd)?;
+                n = p - lo;
+                lo = p + 1;
+            } else {
+                self.sort(state, p + 1, up, rnd)?;
+                n = up - p;
+                up = p - 1;
+            }
+            if (up - lo) / 128 > n {
+                // 划分太不均匀，改用随机枢轴
+                rnd = random_pivot();
+            }
+        }
+        Ok(())
+    }
+
+    // 不变式：a[lo..i] <= P <= a[j..up]，a[up-1] == P
+    fn partition(&mut self, state: &mut ExeState, lo: i64, up: i64, pivot: &Value) -> Result<i64, LuaError> {
+        let mut i = lo;
+        let mut j = up - 1;
+        loop {
+            i += 1;
+            let mut a_i = self.get(state, i)?;
+            while self.less(state, &a_i, pivot)? {
+                if i == up - 1 {
+                    return Err(state.error("invalid order function for sorting"));
+                }
+                i += 1;
+                a_i = self.get(state, i)?;
+            }
+            j -= 1;
+            let mut a_j = self.get(state, j)?;
+            while self.less(state, pivot, &a_j)? {
+                if j < i {
+                    return Err(state.error("invalid order function for sorting"));
+                }
+                j -= 1;
+                a_j = self.get(state, j)?;
+            }
+            if j < i {
+                self.set2(state, up - 1, a_i, i, pivot.clone())?;
+                return Ok(i);
+            }
+            self.set2(state, i, a_j, j, a_i)?;
+        }
+    }
+}
+
+fn random_pivot() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+}
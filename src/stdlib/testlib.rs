@@ -0,0 +1,346 @@
+// 测试框架（类似busted）：describe/it/pending/before_each/after_each收集测试，
+// 并把全局assert扩展为带有equals、same、error_matches的表。只由`myLua test`打开
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use super::pattern::MatchState;
+use crate::error::LuaError;
+use crate::value::{RustFn, Table, Value};
+use crate::vm::ExeState;
+
+// 注册表中保存收集到的测试的键
+const TESTS: &str = "_TESTS";
+
+pub enum TestStatus {
+    Pass,
+    Fail(String),
+    Skip,
+}
+
+pub struct TestResult {
+    pub name: String,  // 外层describe的名字和测试名，以空格连接
+    pub status: TestStatus,
+    pub time: Duration,
+}
+
+enum Item {
+    Block(Block),
+    Test(String, Option<Value>),  // 没有函数的为pending
+}
+
+struct Block {
+    name: String,
+    before_each: Vec<Value>,
+    after_each: Vec<Value>,
+    items: Vec<Item>,
+}
+
+impl Block {
+    fn new(name: String) -> Self {
+        Block { name, before_each: Vec::new(), after_each: Vec::new(), items: Vec::new() }
+    }
+}
+
+// 正在收集的describe块，第一个为文件的顶层
+struct Suite {
+    blocks: Vec<Block>,
+    running: bool,
+}
+
+pub fn open(state: &mut ExeState) {
+    let functions: [(&str, RustFn); 5] = [
+        ("after_each", test_after_each),
+        ("before_each", test_before_each),
+        ("describe", test_describe),
+        ("it", test_it),
+        ("pending", test_pending),
+    ];
+    for (name, f) in functions {
+        state.set_global(name, Value::Function(f));
+    }
+    let suite = state.new_userdata(Suite { blocks: vec![Block::new(String::new())], running: false });
+    state.registry().borrow_mut().set_str(TESTS, suite);
+
+    // assert仍可以像函数一样调用
+    let asserts: [(&str, RustFn); 3] = [
+        ("equals", assert_equals),
+        ("error_matches", assert_error_matches),
+        ("same", assert_same),
+    ];
+    let mut assert = Table::new(0, asserts.len());
+    for (name, f) in asserts {
+        assert.set_str(name, Value::Function(f));
+    }
+    let mut meta = Table::new(0, 1);
+    meta.set_str("__call", Value::Function(assert_call));
+    assert.metatable = Some(Rc::new(RefCell::new(meta)));
+    state.set_global("assert", Value::Table(Rc::new(RefCell::new(assert))));
+}
+
+fn with_suite<R>(state: &ExeState, f: impl FnOnce(&mut Suite) -> R) -> R {
+    let Value::UserData(u) = state.registry().borrow().get_str(TESTS) else {
+        panic!("test library not opened");
+    };
+    let mut suite = u.borrow_mut::<Suite>().unwrap();
+    f(&mut suite)
+}
+
+fn check_function(state: &ExeState, i: usize, fname: &str) -> Result<Value, LuaError> {
+    match state.get_arg(i) {
+        f @ (Value::Function(_) | Value::RustClosure(_) | Value::LuaFunction(_)) => Ok(f.clone()),
+        _ => Err(state.type_error(i, fname, "function")),
+    }
+}
+
+// 测试运行时不能再定义测试
+fn add_item(state: &ExeState, fname: &str, f: impl FnOnce(&mut Block)) -> Result<(), LuaError> {
+    let added = with_suite(state, |suite| {
+        if !suite.running {
+            f(suite.blocks.last_mut().unwrap());
+        }
+        !suite.running
+    });
+    if added {
+        Ok(())
+    } else {
+        Err(state.error(&format!("'{}' cannot be called inside a test", fname)))
+    }
+}
+
+// describe(name, f)：立即执行f收集其中的测试
+fn test_describe(state: &mut ExeState) -> Result<i32, LuaError> {
+    let name = String::from_utf8_lossy(&state.check_string(1, "describe")?).into_owned();
+    let f = check_function(state, 2, "describe")?;
+    add_item(state, "describe", |_| ())?;
+    with_suite(state, |suite| suite.blocks.push(Block::new(name)));
+    let result = state.call_function(f, Vec::new());
+    with_suite(state, |suite| {
+        let block = suite.blocks.pop().unwrap();
+        suite.blocks.last_mut().unwrap().items.push(Item::Block(block));
+    });
+    result.map(|_| 0)
+}
+
+// it(name [, f])：没有f时同pending
+fn test_it(state: &mut ExeState) -> Result<i32, LuaError> {
+    let name = String::from_utf8_lossy(&state.check_string(1, "it")?).into_owned();
+    let f = match state.get_arg(2) {
+        Value::Nil => None,
+        _ => Some(check_function(state, 2, "it")?),
+    };
+    add_item(state, "it", |block| block.items.push(Item::Test(name, f)))?;
+    Ok(0)
+}
+
+fn test_pending(state: &mut ExeState) -> Result<i32, LuaError> {
+    let name = String::from_utf8_lossy(&state.check_string(1, "pending")?).into_owned();
+    add_item(state, "pending", |block| block.items.push(Item::Test(name, None)))?;
+    Ok(0)
+}
+
+fn test_before_each(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = check_function(state, 1, "before_each")?;
+    add_item(state, "before_each", |block| block.before_each.push(f))?;
+    Ok(0)
+}
+
+fn test_after_each(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = check_function(state, 1, "after_each")?;
+    add_item(state, "after_each", |block| block.after_each.push(f))?;
+    Ok(0)
+}
+
+// 按定义顺序运行收集到的测试。外层的before_each先执行，after_each总会执行且内层的先执行
+pub fn run_tests(state: &mut ExeState) -> Result<Vec<TestResult>, LuaError> {
+    let root = with_suite(state, |suite| {
+        suite.running = true;
+        std::mem::replace(&mut suite.blocks[0], Block::new(String::new()))
+    });
+    let mut results = Vec::new();
+    run_block(state, &root, &mut Vec::new(), &mut results)?;
+    Ok(results)
+}
+
+fn run_block<'a>(state: &mut ExeState, block: &'a Block, path: &mut Vec<&'a Block>, results: &mut Vec<TestResult>) -> Result<(), LuaError> {
+    path.push(block);
+    for item in &block.items {
+        match item {
+            Item::Block(inner) => run_block(state, inner, path, results)?,
+            Item::Test(name, f) => {
+                let mut names: Vec<&str> = path.iter().map(|b| b.name.as_str()).filter(|n| !n.is_empty()).collect();
+                names.push(name);
+                let start = Instant::now();
+                let status = match f {
+                    Some(f) => match run_test(state, path, f) {
+                        Ok(()) => TestStatus::Pass,
+                        // 中断（如超时）终止整个文件
+                        Err(e) if !e.is_catchable() => return Err(e),
+                        Err(e) => TestStatus::Fail(e.to_string()),
+                    },
+                    None => TestStatus::Skip,
+                };
+                results.push(TestResult { name: names.join(" "), status, time: start.elapsed() });
+            }
+        }
+    }
+    path.pop();
+    Ok(())
+}
+
+fn run_test(state: &mut ExeState, path: &[&Block], f: &Value) -> Result<(), LuaError> {
+    let mut result = Ok(());
+    for hook in path.iter().flat_map(|b| &b.before_each) {
+        result = state.call_function(hook.clone(), Vec::new()).map(|_| ());
+        if result.is_err() {
+            break;
+        }
+    }
+    if result.is_ok() {
+        result = state.call_function(f.clone(), Vec::new()).map(|_| ());
+    }
+    for hook in path.iter().rev().flat_map(|b| b.after_each.iter().rev()) {
+        let r = state.call_function(hook.clone(), Vec::new()).map(|_| ());
+        if result.is_ok() {
+            result = r;
+        }
+    }
+    result
+}
+
+// ---------- assert ----------
+
+// assert(v [, message, ...])：第一个参数是assert表本身
+fn assert_call(state: &mut ExeState) -> Result<i32, LuaError> {
+    let v = state.check_any(2, "assert")?;
+    if !v.is_false() {
+        return Ok(state.get_args_count() as i32 - 1);
+    }
+    if state.get_args_count() >= 3 {
+        return Err(LuaError::Runtime(state.get_arg(3).clone()));
+    }
+    Err(state.error("assertion failed!"))
+}
+
+// 失败信息中的值，表最多展开两层
+fn show(v: &Value, depth: usize) -> String {
+    match v {
+        Value::String(s) => format!("{:?}", String::from_utf8_lossy(s)),
+        Value::Table(t) if depth > 0 => {
+            let t = t.borrow();
+            let mut fields = Vec::new();
+            let mut key = Value::Nil;
+            let mut next_index = 1;
+            while let Ok(Some((k, v))) = t.next(&key) {
+                if k == Value::Integer(next_index) {
+                    fields.push(show(&v, depth - 1));
+                    next_index += 1;
+                } else {
+                    let is_name = matches!(&k, Value::String(s) if s.first().is_some_and(|c| !c.is_ascii_digit())
+                        && s.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'_'));
+                    let key = if is_name { k.to_string() } else { format!("[{}]", show(&k, 0)) };
+                    fields.push(format!("{} = {}", key, show(&v, depth - 1)));
+                }
+                key = k;
+            }
+            format!("{{{}}}", fields.join(", "))
+        }
+        _ => v.to_string(),
+    }
+}
+
+fn assert_failure(state: &ExeState, i: usize, msg: String) -> LuaError {
+    match state.get_arg(i) {
+        Value::Nil => state.error(&msg),
+        custom => state.error(&format!("{}: {}", custom, msg)),
+    }
+}
+
+// assert.equals(expected, actual [, message])：按==比较
+fn assert_equals(state: &mut ExeState) -> Result<i32, LuaError> {
+    let expected = state.check_any(1, "equals")?;
+    let actual = state.check_any(2, "equals")?;
+    if state.equals(&expected, &actual)? {
+        return Ok(0);
+    }
+    Err(assert_failure(state, 3, format!("expected {}, got {}", show(&expected, 2), show(&actual, 2))))
+}
+
+// 表逐字段递归比较，元表不参与比较；seen记录正在比较的表对，处理循环引用
+fn deep_equal(a: &Value, b: &Value, seen: &mut Vec<(*const RefCell<Table>, *const RefCell<Table>)>) -> bool {
+    match (a, b) {
+        (Value::Table(ta), Value::Table(tb)) => {
+            let pair = (Rc::as_ptr(ta), Rc::as_ptr(tb));
+            if Rc::ptr_eq(ta, tb) || seen.contains(&pair) {
+                return true;
+            }
+            seen.push(pair);
+            let (ta, tb) = (ta.borrow(), tb.borrow());
+            let mut count = 0;
+            let mut key = Value::Nil;
+            while let Ok(Some((k, va))) = ta.next(&key) {
+                if !deep_equal(&va, &tb.get(&k), seen) {
+                    return false;
+                }
+                count += 1;
+                key = k;
+            }
+            let mut key = Value::Nil;
+            while let Ok(Some((k, _))) = tb.next(&key) {
+                count -= 1;
+                key = k;
+            }
+            count == 0
+        }
+        (Value::Integer(_) | Value::Float(_), Value::Integer(_) | Value::Float(_)) => match (a, b) {
+            (Value::Integer(x), Value::Integer(y)) => x == y,
+            _ => a.to_float() == b.to_float(),
+        },
+        _ => a == b,
+    }
+}
+
+// assert.same(expected, actual [, message])：深度比较
+fn assert_same(state: &mut ExeState) -> Result<i32, LuaError> {
+    let expected = state.check_any(1, "same")?;
+    let actual = state.check_any(2, "same")?;
+    if deep_equal(&expected, &actual, &mut Vec::new()) {
+        return Ok(0);
+    }
+    Err(assert_failure(state, 3, format!("expected {}, got {}", show(&expected, 2), show(&actual, 2))))
+}
+
+// 同string.find的模式查找
+fn pattern_find(s: &[u8], p: &[u8]) -> Result<bool, String> {
+    let anchor = p.first() == Some(&b'^');
+    let mut ms = MatchState::new(s, p);
+    for start in 0..=s.len() {
+        if ms.find(start, anchor as usize)?.is_some() {
+            return Ok(true);
+        }
+        if anchor {
+            break;
+        }
+    }
+    Ok(false)
+}
+
+// assert.error_matches(f, pattern [, message])：f必须出错，且错误消息匹配模式
+fn assert_error_matches(state: &mut ExeState) -> Result<i32, LuaError> {
+    let f = check_function(state, 1, "error_matches")?;
+    let pattern = state.check_string(2, "error_matches")?;
+    let err = match state.call_function(f, Vec::new()) {
+        Ok(_) => return Err(assert_failure(state, 3, "expected an error, but none was raised".to_string())),
+        Err(e) if !e.is_catchable() => return Err(e),
+        Err(e) => e.into_value(),
+    };
+    let msg = match &err {
+        Value::String(s) => s.clone(),
+        Value::Integer(_) | Value::Float(_) => err.to_string().into_bytes().into(),
+        _ => return Err(assert_failure(state, 3, format!("expected an error message, got {}", show(&err, 2)))),
+    };
+    if pattern_find(&msg, &pattern).map_err(|e| state.error(&e))? {
+        return Ok(0);
+    }
+    Err(assert_failure(state, 3, format!("error {} does not match {}", show(&err, 0), show(&Value::String(pattern), 0))))
+}
@@ -0,0 +1,235 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::error::LuaError;
+use crate::lex::utf8_encode;
+use crate::value::{RustFn, Table, Value};
+use crate::vm::ExeState;
+
+const MAX_UNICODE: u32 = 0x10FFFF;
+const MAX_UTF: u32 = 0x7FFFFFFF;
+const MSG_INVALID: &str = "invalid UTF-8 code";
+
+// 匹配一个UTF-8字节序列的模式
+const CHAR_PATTERN: &[u8] = b"[\x00-\x7F\xC2-\xFD][\x80-\xBF]*";
+
+pub fn open(state: &mut ExeState) {
+    let functions: [(&str, RustFn); 5] = [
+        ("char", utf8_char),
+        ("codepoint", utf8_codepoint),
+        ("codes", utf8_codes),
+        ("len", utf8_len),
+        ("offset", utf8_offset),
+    ];
+    let mut utf8 = Table::new(0, 8);
+    for (name, f) in functions {
+        utf8.set_str(name, Value::Function(f));
+    }
+    utf8.set_str("charpattern", Value::String(CHAR_PATTERN.into()));
+    state.set_global("utf8", Value::Table(Rc::new(RefCell::new(utf8))));
+}
+
+// 负数位置从字符串末尾倒数
+fn posrelat(pos: i64, len: usize) -> i64 {
+    if pos >= 0 {
+        pos
+    } else if pos.unsigned_abs() > len as u64 {
+        0
+    } else {
+        len as i64 + pos + 1
+    }
+}
+
+// 是否为后续字节；越过末尾视为'\0'
+fn is_cont(s: &[u8], i: usize) -> bool {
+    s.get(i).is_some_and(|&c| c & 0xC0 == 0x80)
+}
+
+// 解码位置i开始的字符，返回码点和下一个字符的位置；
+// strict时拒绝超出Unicode范围的码点和代理对
+fn utf8_decode(s: &[u8], i: usize, strict: bool) -> Option<(u32, usize)> {
+    const LIMITS: [u32; 6] = [!0, 0x80, 0x800, 0x10000, 0x200000, 0x4000000];
+    let mut c = s.get(i).copied().unwrap_or(0) as u32;
+    let mut res = 0u32;
+    let mut count = 0;
+    if c < 0x80 {
+        res = c;
+    } else {
+        while c & 0x40 != 0 {
+            count += 1;
+            let cc = s.get(i + count).copied().unwrap_or(0) as u32;
+            if cc & 0xC0 != 0x80 {
+                return None;
+            }
+            res = (res << 6) | (cc & 0x3F);
+            c <<= 1;
+        }
+        if count > 5 {
+            return None;
+        }
+        res |= (c & 0x7F) << (count * 5);
+        if res > MAX_UTF || res < LIMITS[count] {
+            return None;
+        }
+    }
+    if strict && (res > MAX_UNICODE || (0xD800..=0xDFFF).contains(&res)) {
+        return None;
+    }
+    Some((res, i + count + 1))
+}
+
+// utf8.len(s [, i [, j [, lax]]])：遇到无效字节时返回fail和其位置
+fn utf8_len(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "len")?;
+    let len = s.len() as i64;
+    let mut posi = posrelat(state.opt_integer(2, "len", 1)?, s.len());
+    let posj = posrelat(state.opt_integer(3, "len", -1)?, s.len()) - 1;
+    let lax = !state.get_arg(4).is_false();
+    if posi < 1 || posi - 1 > len {
+        return Err(state.arg_error(2, "len", "initial position out of bounds"));
+    }
+    posi -= 1;
+    if posj >= len {
+        return Err(state.arg_error(3, "len", "final position out of bounds"));
+    }
+    let mut n = 0;
+    while posi <= posj {
+        match utf8_decode(&s, posi as usize, !lax) {
+            Some((_, next)) => posi = next as i64,
+            None => {
+                state.push(Value::Nil);
+                state.push(Value::Integer(posi + 1));
+                return Ok(2);
+            }
+        }
+        n += 1;
+    }
+    state.push(Value::Integer(n));
+    Ok(1)
+}
+
+// utf8.codepoint(s [, i [, j [, lax]]])
+fn utf8_codepoint(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "codepoint")?;
+    let posi = posrelat(state.opt_integer(2, "codepoint", 1)?, s.len());
+    let pose = posrelat(state.opt_integer(3, "codepoint", posi)?, s.len());
+    let lax = !state.get_arg(4).is_false();
+    if posi < 1 {
+        return Err(state.arg_error(2, "codepoint", "out of bounds"));
+    }
+    if pose > s.len() as i64 {
+        return Err(state.arg_error(3, "codepoint", "out of bounds"));
+    }
+    if posi > pose {
+        return Ok(0);
+    }
+    if pose - posi >= i32::MAX as i64 {
+        return Err(state.error("string slice too long"));
+    }
+    let mut codes = Vec::new();
+    let mut i = posi as usize - 1;
+    while i < pose as usize {
+        let (code, next) = utf8_decode(&s, i, !lax).ok_or_else(|| state.error(MSG_INVALID))?;
+        codes.push(Value::Integer(code as i64));
+        i = next;
+    }
+    Ok(state.push_all(codes))
+}
+
+// utf8.char(...)
+fn utf8_char(state: &mut ExeState) -> Result<i32, LuaError> {
+    let mut buf = Vec::new();
+    for i in 1..=state.get_args_count() {
+        let code = state.check_integer(i, "char")? as u64;
+        if code > MAX_UTF as u64 {
+            return Err(state.arg_error(i, "char", "value out of range"));
+        }
+        utf8_encode(code as u32, &mut buf);
+    }
+    state.push(Value::String(buf.into()));
+    Ok(1)
+}
+
+// utf8.offset(s, n [, i])：第n个字符开始的字节位置，n为0时找i所在字符的开头
+fn utf8_offset(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "offset")?;
+    let len = s.len() as i64;
+    let mut n = state.check_integer(2, "offset")?;
+    let default = if n >= 0 { 1 } else { len + 1 };
+    let mut posi = posrelat(state.opt_integer(3, "offset", default)?, s.len());
+    if posi < 1 || posi - 1 > len {
+        return Err(state.arg_error(3, "offset", "position out of bounds"));
+    }
+    posi -= 1;
+    if n == 0 {
+        while posi > 0 && is_cont(&s, posi as usize) {
+            posi -= 1;
+        }
+    } else {
+        if is_cont(&s, posi as usize) {
+            return Err(state.error("initial position is a continuation byte"));
+        }
+        if n < 0 {
+            while n < 0 && posi > 0 {
+                posi -= 1;
+                while posi > 0 && is_cont(&s, posi as usize) {
+                    posi -= 1;
+                }
+                n += 1;
+            }
+        } else {
+            n -= 1;
+            while n > 0 && posi < len {
+                posi += 1;
+                while is_cont(&s, posi as usize) {
+                    posi += 1;
+                }
+                n -= 1;
+            }
+        }
+    }
+    state.push(if n == 0 { Value::Integer(posi + 1) } else { Value::Nil });
+    Ok(1)
+}
+
+// utf8.codes(s [, lax])：泛型for的迭代器，控制变量为上一个字符的位置
+fn utf8_codes(state: &mut ExeState) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "codes")?;
+    let lax = !state.get_arg(2).is_false();
+    if is_cont(&s, 0) {
+        return Err(state.arg_error(1, "codes", MSG_INVALID));
+    }
+    state.push(Value::Function(if lax { iter_aux_lax } else { iter_aux_strict }));
+    state.push(Value::String(s));
+    state.push(Value::Integer(0));
+    Ok(3)
+}
+
+fn iter_aux(state: &mut ExeState, strict: bool) -> Result<i32, LuaError> {
+    let s = state.check_string(1, "for iterator")?;
+    let mut n = state.get_arg(2).to_integer().unwrap_or(0) as u64;
+    let len = s.len() as u64;
+    // 跳过上一个字符的后续字节
+    while n < len && is_cont(&s, n as usize) {
+        n += 1;
+    }
+    if n >= len {
+        return Ok(0);
+    }
+    match utf8_decode(&s, n as usize, strict) {
+        Some((code, next)) if !is_cont(&s, next) => {
+            state.push(Value::Integer(n as i64 + 1));
+            state.push(Value::Integer(code as i64));
+            Ok(2)
+        }
+        _ => Err(state.error(MSG_INVALID)),
+    }
+}
+
+fn iter_aux_strict(state: &mut ExeState) -> Result<i32, LuaError> {
+    iter_aux(state, true)
+}
+
+fn iter_aux_lax(state: &mut ExeState) -> Result<i32, LuaError> {
+    iter_aux(state, false)
+}
@@ -0,0 +1,231 @@
+// `myLua test`：查找测试文件，每个文件在新的ExeState中运行，结果以TAP输出到标准输出，可另写JUnit XML
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::error::LuaError;
+use crate::stdlib::testlib::{self, TestResult, TestStatus};
+use crate::vm::ExeState;
+
+// 一个测试文件的结果，error为加载或收集测试时的错误
+pub struct FileResult {
+    pub path: String,
+    pub tests: Vec<TestResult>,
+    pub error: Option<String>,
+    pub time: Duration,
+}
+
+impl FileResult {
+    fn count(&self, pred: impl Fn(&TestStatus) -> bool) -> usize {
+        self.tests.iter().filter(|t| pred(&t.status)).count()
+    }
+
+    fn failures(&self) -> usize {
+        self.count(|s| matches!(s, TestStatus::Fail(_)))
+    }
+
+    fn skipped(&self) -> usize {
+        self.count(|s| matches!(s, TestStatus::Skip))
+    }
+}
+
+// 测试文件：名字以_test.lua结尾，或位于spec目录（含子目录）下的.lua文件
+fn is_test_file(path: &Path, in_spec: bool) -> bool {
+    let name = path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+    name.ends_with("_test.lua") || (in_spec && name.ends_with(".lua"))
+}
+
+fn walk(dir: &Path, in_spec: bool, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+        if name.starts_with('.') || name == "target" {
+            continue;
+        }
+        if path.is_dir() {
+            walk(&path, in_spec || name == "spec", files)?;
+        } else if is_test_file(&path, in_spec) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// 在给出的目录中查找测试文件，直接给出的文件总是运行。没有参数时查找当前目录
+pub fn discover(paths: &[String]) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let roots = if paths.is_empty() { vec![".".to_string()] } else { paths.to_vec() };
+    for root in &roots {
+        let path = Path::new(root);
+        if path.is_dir() {
+            let mut found = Vec::new();
+            walk(path, path.file_name().is_some_and(|n| n == "spec"), &mut found)?;
+            found.sort();
+            files.extend(found);
+        } else {
+            fs::metadata(path)?;
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+pub fn run_file(path: &Path) -> FileResult {
+    let name = path.strip_prefix("./").unwrap_or(path).display().to_string();
+    let start = Instant::now();
+    let mut state = ExeState::new();
+    testlib::open(&mut state);
+    let result = fs::read(path)
+        .map_err(|e| LuaError::runtime(format!("cannot open {}: {}", name, e)))
+        .and_then(|code| state.load_bytes(&code, &format!("@{}", name), None))
+        .and_then(|f| state.call_function(f, Vec::new()))
+        .and_then(|_| testlib::run_tests(&mut state));
+    let (tests, error) = match result {
+        Ok(tests) => (tests, None),
+        Err(e) => (Vec::new(), Some(e.to_string())),
+    };
+    FileResult { path: name, tests, error, time: start.elapsed() }
+}
+
+fn tap_diagnostic(out: &mut impl Write, msg: &str) -> io::Result<()> {
+    for line in msg.lines() {
+        writeln!(out, "# {}", line)?;
+    }
+    Ok(())
+}
+
+// TAP第13版，计划行放在最后，文件加载错误记为一个失败的测试
+pub fn write_tap_file(out: &mut impl Write, file: &FileResult, n: &mut usize) -> io::Result<()> {
+    if let Some(error) = &file.error {
+        *n += 1;
+        writeln!(out, "not ok {} - {}", n, file.path)?;
+        tap_diagnostic(out, error)?;
+    }
+    for test in &file.tests {
+        *n += 1;
+        match &test.status {
+            TestStatus::Pass => writeln!(out, "ok {} - {}: {}", n, file.path, test.name)?,
+            TestStatus::Skip => writeln!(out, "ok {} - {}: {} # SKIP pending", n, file.path, test.name)?,
+            TestStatus::Fail(msg) => {
+                writeln!(out, "not ok {} - {}: {}", n, file.path, test.name)?;
+                tap_diagnostic(out, msg)?;
+            }
+        }
+    }
+    out.flush()
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // XML 1.0不允许的控制字符
+            c if (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r') => out.push('?'),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// 每个文件一个testsuite，文件加载错误记为一个error
+pub fn write_junit(out: &mut impl Write, files: &[FileResult]) -> io::Result<()> {
+    let total = |f: fn(&FileResult) -> usize| files.iter().map(f).sum::<usize>();
+    let tests = total(|f| f.tests.len() + f.error.is_some() as usize);
+    let time: f64 = files.iter().map(|f| f.time.as_secs_f64()).sum();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<testsuites tests="{}" failures="{}" errors="{}" skipped="{}" time="{:.3}">"#,
+        tests, total(FileResult::failures), total(|f| f.error.is_some() as usize), total(FileResult::skipped), time)?;
+    for file in files {
+        let path = xml_escape(&file.path);
+        writeln!(out, r#"  <testsuite name="{}" tests="{}" failures="{}" errors="{}" skipped="{}" time="{:.3}">"#,
+            path, file.tests.len() + file.error.is_some() as usize, file.failures(), file.error.is_some() as usize,
+            file.skipped(), file.time.as_secs_f64())?;
+        if let Some(error) = &file.error {
+            writeln!(out, r#"    <testcase classname="{}" name="{}" time="0">"#, path, path)?;
+            writeln!(out, r#"      <error message="{}"/>"#, xml_escape(error))?;
+            writeln!(out, "    </testcase>")?;
+        }
+        for test in &file.tests {
+            let head = format!(r#"    <testcase classname="{}" name="{}" time="{:.3}""#, path, xml_escape(&test.name), test.time.as_secs_f64());
+            match &test.status {
+                TestStatus::Pass => writeln!(out, "{}/>", head)?,
+                TestStatus::Skip => writeln!(out, "{}>\n      <skipped/>\n    </testcase>", head)?,
+                TestStatus::Fail(msg) => {
+                    writeln!(out, "{}>", head)?;
+                    writeln!(out, r#"      <failure message="{}">{}</failure>"#, xml_escape(msg.lines().next().unwrap_or("")), xml_escape(msg))?;
+                    writeln!(out, "    </testcase>")?;
+                }
+            }
+        }
+        writeln!(out, "  </testsuite>")?;
+    }
+    writeln!(out, "</testsuites>")?;
+    out.flush()
+}
+
+fn usage(progname: &str) -> i32 {
+    eprintln!("usage: {} test [--junit file] [path ...]
+Runs *_test.lua files and .lua files under spec/ directories found in each
+path (default: the current directory), printing TAP to stdout.
+  --junit file  also write a JUnit XML report to 'file'", progname);
+    1
+}
+
+// 返回进程退出码：全部通过为0
+pub fn main(progname: &str, args: &[String]) -> i32 {
+    let mut junit = None;
+    let mut paths = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--junit" => {
+                i += 1;
+                match args.get(i) {
+                    Some(file) => junit = Some(file.clone()),
+                    None => return usage(progname),
+                }
+            }
+            "-h" | "--help" => return usage(progname),
+            arg if arg.starts_with('-') => return usage(progname),
+            arg => paths.push(arg.to_string()),
+        }
+        i += 1;
+    }
+
+    let files = match discover(&paths) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("{}: {}", progname, e);
+            return 1;
+        }
+    };
+    let mut out = io::stdout();
+    let mut results = Vec::new();
+    let mut n = 0;
+    let _ = writeln!(out, "TAP version 13");
+    for path in &files {
+        let result = run_file(path);
+        let _ = write_tap_file(&mut out, &result, &mut n);
+        results.push(result);
+    }
+    let failed: usize = results.iter().map(|f| f.failures() + f.error.is_some() as usize).sum();
+    let skipped: usize = results.iter().map(FileResult::skipped).sum();
+    let _ = writeln!(out, "1..{}", n);
+    let _ = writeln!(out, "# {} files, {} tests, {} passed, {} failed, {} skipped",
+        files.len(), n, n - failed - skipped, failed, skipped);
+
+    if let Some(file) = junit {
+        let written = fs::File::create(&file).and_then(|f| write_junit(&mut io::BufWriter::new(f), &results));
+        if let Err(e) = written {
+            eprintln!("{}: cannot write {}: {}", progname, file, e);
+            return 1;
+        }
+    }
+    if failed > 0 { 1 } else { 0 }
+}
@@ -1,25 +1,346 @@
-use std::cell::RefCell;
+use std::any::Any;
+use std::cell::{Ref, RefCell, RefMut};
 use std::collections::HashMap;
+use std::ffi::c_void;
 use std::fmt::{Debug, Formatter};
 use std::rc::Rc;
-use crate::vm::ExeState;
+use crate::error::LuaError;
+use crate::parse::ParseProto;
+use crate::vm::{Coroutine, ExeState};
 
+// Rust函数：参数在栈上，返回压入栈顶的结果个数
+pub type RustFn = fn (&mut ExeState) -> Result<i32, LuaError>;
+
+// 上值：被捕获的局部变量在其作用域内仍位于栈上（Open），离开作用域后转存（Closed）
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+// 上值列表可以被debug.upvaluejoin修改
+pub struct LuaClosure {
+    pub proto: Rc<ParseProto>,
+    pub upvalues: RefCell<Vec<Rc<RefCell<Upvalue>>>>,
+}
+
+// 带上值的Rust函数，上值可以在调用中修改（如string.gmatch的迭代位置）
+pub struct RustClosure {
+    pub f: RustFn,
+    pub upvalues: RefCell<Vec<Value>>,
+}
+
+// 哈希部分按插入顺序保存在entries中，index为键到位置的映射。
+// 删除的键保留为值为nil的墓碑，这样遍历中把字段置为nil不影响next
 pub struct Table {
     pub array: Vec<Value>,
-    pub map: HashMap<Value, Value>,
+    index: HashMap<Value, usize>,
+    entries: Vec<(Value, Value)>,
+    tombstones: usize,
+    pub metatable: Option<Rc<RefCell<Table>>>,
+}
+
+impl Table {
+    pub fn new(narray: usize, nmap: usize) -> Self {
+        Table {
+            array: Vec::with_capacity(narray),
+            index: HashMap::with_capacity(nmap),
+            entries: Vec::with_capacity(nmap),
+            tombstones: 0,
+            metatable: None,
+        }
+    }
+
+    // 原始读取，不触发元方法
+    pub fn get(&self, key: &Value) -> Value {
+        if let Value::Float(f) = key {
+            if let Some(i) = float_to_integer(*f) {
+                return self.get(&Value::Integer(i));
+            }
+        }
+        if let Value::Integer(i) = key {
+            if *i >= 1 && (*i as usize) <= self.array.len() {
+                return self.array[*i as usize - 1].clone();
+            }
+        }
+        self.map_get(key)
+    }
+
+    // 原始写入，不触发元方法
+    pub fn set(&mut self, key: Value, value: Value) {
+        if let Value::Integer(i) = key {
+            let len = self.array.len();
+            if i >= 1 && (i as usize) <= len {
+                self.array[i as usize - 1] = value;
+                return;
+            }
+            if i as usize == len + 1 && !matches!(value, Value::Nil) {
+                self.array.push(value);
+                self.map_set(key, Value::Nil);
+                // 把哈希部分中紧接着的整数键迁移到数组部分
+                let mut next = len as i64 + 2;
+                loop {
+                    let key = Value::Integer(next);
+                    let v = self.map_get(&key);
+                    if v == Value::Nil {
+                        break;
+                    }
+                    self.array.push(v);
+                    self.map_set(key, Value::Nil);
+                    next += 1;
+                }
+                return;
+            }
+        }
+        self.map_set(key, value);
+    }
+
+    fn map_get(&self, key: &Value) -> Value {
+        match self.index.get(key) {
+            Some(&i) => self.entries[i].1.clone(),
+            None => Value::Nil,
+        }
+    }
+
+    fn map_set(&mut self, key: Value, value: Value) {
+        if let Some(&i) = self.index.get(&key) {
+            let slot = &mut self.entries[i].1;
+            match (slot == &Value::Nil, value == Value::Nil) {
+                (false, true) => self.tombstones += 1,
+                (true, false) => self.tombstones -= 1,
+                _ => (),
+            }
+            *slot = value;
+            return;
+        }
+        if value == Value::Nil {
+            return;
+        }
+        // 新增键时才清理墓碑（遍历中新增键本来就是未定义行为）
+        if self.tombstones > 8 && self.tombstones * 2 > self.entries.len() {
+            self.entries.retain(|(_, v)| *v != Value::Nil);
+            self.index = self.entries.iter().enumerate().map(|(i, (k, _))| (k.clone(), i)).collect();
+            self.tombstones = 0;
+        }
+        self.index.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+    }
+
+    // 遍历：返回key之后的下一个非nil元素，key为nil时从头开始；key不存在时返回Err
+    #[allow(clippy::result_unit_err)]
+    pub fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, ()> {
+        let key = match key {
+            Value::Float(f) => float_to_integer(*f).map_or(key.clone(), Value::Integer),
+            _ => key.clone(),
+        };
+        // 先确定从数组部分还是哈希部分的哪个位置继续
+        let (mut ai, mut hi) = match &key {
+            Value::Nil => (0, 0),
+            Value::Integer(i) if *i >= 1 && (*i as usize) <= self.array.len() => (*i as usize, 0),
+            _ => match self.index.get(&key) {
+                Some(&i) => (self.array.len(), i + 1),
+                None => return Err(()),
+            },
+        };
+        while ai < self.array.len() {
+            if self.array[ai] != Value::Nil {
+                return Ok(Some((Value::Integer(ai as i64 + 1), self.array[ai].clone())));
+            }
+            ai += 1;
+        }
+        while hi < self.entries.len() {
+            let (k, v) = &self.entries[hi];
+            if *v != Value::Nil {
+                return Ok(Some((k.clone(), v.clone())));
+            }
+            hi += 1;
+        }
+        Ok(None)
+    }
+
+    // 长度：数组部分之后若哈希部分还有连续的整数键，继续向后查找边界
+    pub fn len(&self) -> i64 {
+        let mut n = self.array.len();
+        if n > 0 && self.array[n - 1] == Value::Nil {
+            // 数组部分有空洞，二分查找一个边界
+            let (mut lo, mut hi) = (0, n);
+            while hi - lo > 1 {
+                let m = (lo + hi) / 2;
+                if self.array[m - 1] == Value::Nil {
+                    hi = m;
+                } else {
+                    lo = m;
+                }
+            }
+            return lo as i64;
+        }
+        while self.map_get(&Value::Integer(n as i64 + 1)) != Value::Nil {
+            n += 1;
+        }
+        n as i64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.array.iter().all(|v| *v == Value::Nil) && self.entries.len() == self.tombstones
+    }
+
+    // 哈希部分中的元素个数
+    pub fn map_len(&self) -> usize {
+        self.entries.len() - self.tombstones
+    }
+
+    pub fn get_str(&self, key: &str) -> Value {
+        self.map_get(&Value::from(key))
+    }
+
+    pub fn set_str(&mut self, key: &str, value: Value) {
+        self.set(Value::from(key), value);
+    }
+}
+
+// 完全用户数据：包装任意Rust对象，生命周期由Rc管理。
+// 最后一个引用消失时，被包装对象的`Drop`即为其`__gc`终结器。
+pub struct UserData {
+    data: RefCell<Box<dyn Any>>,
+    pub metatable: RefCell<Option<Rc<RefCell<Table>>>>,
+}
+
+impl UserData {
+    pub fn new<T: Any>(data: T) -> Self {
+        UserData {
+            data: RefCell::new(Box::new(data)),
+            metatable: RefCell::new(None),
+        }
+    }
+
+    pub fn is<T: Any>(&self) -> bool {
+        self.data.borrow().is::<T>()
+    }
+
+    // 类型不符或已被可变借用时返回None
+    pub fn borrow<T: Any>(&self) -> Option<Ref<'_, T>> {
+        let data = self.data.try_borrow().ok()?;
+        Ref::filter_map(data, |b| b.downcast_ref::<T>()).ok()
+    }
+
+    pub fn borrow_mut<T: Any>(&self) -> Option<RefMut<'_, T>> {
+        let data = self.data.try_borrow_mut().ok()?;
+        RefMut::filter_map(data, |b| b.downcast_mut::<T>()).ok()
+    }
 }
 
 #[derive(Clone)]
 pub enum Value {
     Integer(i64),
     Float(f64),
-    String(String),
+    String(Rc<[u8]>),  // Lua字符串是任意字节序列
     Bool(bool),
-    Function(fn (&mut ExeState)->i32),
+    Function(RustFn),
+    LuaFunction(Rc<LuaClosure>),
+    RustClosure(Rc<RustClosure>),
     Nil,
 
     Table(Rc<RefCell<Table>>),
-    
+    UserData(Rc<UserData>),
+    LightUserData(*mut c_void),
+    Thread(Rc<Coroutine>),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Integer(_) | Value::Float(_) => "number",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::LuaFunction(_) | Value::RustClosure(_) => "function",
+            Value::Table(_) => "table",
+            Value::UserData(_) | Value::LightUserData(_) => "userdata",
+            Value::Thread(_) => "thread",
+        }
+    }
+
+    pub fn is_false(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    // 转为数字，字符串按Lua规则解析
+    pub fn to_number(&self) -> Option<Value> {
+        match self {
+            Value::Integer(_) | Value::Float(_) => Some(self.clone()),
+            Value::String(s) => crate::lex::str_to_number(std::str::from_utf8(s).ok()?),
+            _ => None,
+        }
+    }
+
+    pub fn to_float(&self) -> Option<f64> {
+        match self.to_number()? {
+            Value::Integer(i) => Some(i as f64),
+            Value::Float(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    // 转为整数，浮点数必须有精确的整数表示
+    pub fn to_integer(&self) -> Option<i64> {
+        match self.to_number()? {
+            Value::Integer(i) => Some(i),
+            Value::Float(f) => float_to_integer(f),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    // 引用类型的地址，用于tostring和%p；其他类型为空指针
+    pub fn to_pointer(&self) -> *const c_void {
+        match self {
+            Value::Function(f) => *f as *const c_void,
+            Value::LuaFunction(f) => Rc::as_ptr(f) as *const c_void,
+            Value::RustClosure(f) => Rc::as_ptr(f) as *const c_void,
+            Value::Table(t) => Rc::as_ptr(t) as *const c_void,
+            Value::UserData(u) => Rc::as_ptr(u) as *const c_void,
+            Value::LightUserData(p) => *p as *const c_void,
+            Value::Thread(co) => Rc::as_ptr(co) as *const c_void,
+            _ => std::ptr::null(),
+        }
+    }
+
+    pub fn metatable(&self) -> Option<Rc<RefCell<Table>>> {
+        match self {
+            Value::Table(t) => t.borrow().metatable.clone(),
+            Value::UserData(u) => u.metatable.borrow().clone(),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::String(s.as_bytes().into())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::String(s.into_bytes().into())
+    }
+}
+
+impl From<&[u8]> for Value {
+    fn from(s: &[u8]) -> Self {
+        Value::String(s.into())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(s: Vec<u8>) -> Self {
+        Value::String(s.into())
+    }
 }
 
 impl Debug for Value {
@@ -27,14 +348,19 @@ impl Debug for Value {
         match self {
             Value::Integer(i) => write!(f, "{}", i),
             Value::Float(float) => write!(f, "{}", float),
-            Value::String(s) => write!(f, "{}", s),
+            Value::String(s) => write!(f, "{:?}", String::from_utf8_lossy(s)),
             Value::Bool(b) => write!(f, "{}", b),
             Value::Function(_) => write!(f, "<function>"),
+            Value::LuaFunction(_) => write!(f, "<function>"),
+            Value::RustClosure(_) => write!(f, "<function>"),
             Value::Nil => write!(f, "nil"),
             Value::Table(t) => {
                 let t = t.borrow();
-                write!(f, "<table>: {} {}", t.array.len(), t.map.len())
+                write!(f, "<table>: {} {}", t.array.len(), t.map_len())
             },
+            Value::UserData(u) => write!(f, "<userdata>: {:?}", Rc::as_ptr(u)),
+            Value::LightUserData(p) => write!(f, "<userdata>: {:?}", p),
+            Value::Thread(co) => write!(f, "<thread>: {:?}", Rc::as_ptr(co)),
         }
     }
 }
@@ -46,9 +372,14 @@ impl PartialEq for Value {
             (Value::Float(f1), Value::Float(f2)) => *f1 == *f2,
             (Value::String(s1), Value::String(s2)) => *s1 == *s2,
             (Value::Bool(b1), Value::Bool(b2)) => *b1 == *b2,
-            (Value::Function(f1), Value::Function(f2)) => std::ptr::eq(f1, f2),
+            (Value::Function(f1), Value::Function(f2)) => std::ptr::fn_addr_eq(*f1, *f2),
+            (Value::LuaFunction(f1), Value::LuaFunction(f2)) => Rc::ptr_eq(f1, f2),
+            (Value::RustClosure(f1), Value::RustClosure(f2)) => Rc::ptr_eq(f1, f2),
             (Value::Nil, Value::Nil) => true,
             (Value::Table(t1), Value::Table(t2)) => Rc::ptr_eq(t1, t2),
+            (Value::UserData(u1), Value::UserData(u2)) => Rc::ptr_eq(u1, u2),
+            (Value::LightUserData(p1), Value::LightUserData(p2)) => *p1 == *p2,
+            (Value::Thread(c1), Value::Thread(c2)) => Rc::ptr_eq(c1, c2),
             _ => false,
         }
     }
@@ -60,12 +391,11 @@ impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Value::Integer(i) => write!(f, "{}", i),
-            Value::Float(n) => write!(f, "{:?}", n),
-            Value::String(s) => write!(f, "{}", s),
+            Value::Float(n) => write!(f, "{}", fmt_float(*n)),
+            Value::String(s) => write!(f, "{}", String::from_utf8_lossy(s)),
             Value::Bool(b) => write!(f, "{}", b),
-            Value::Function(_) => write!(f, "<function>"),
             Value::Nil => write!(f, "nil"),
-            Value::Table(t) => write!(f, "<table> : {:?}", Rc::as_ptr(t)),
+            _ => write!(f, "{}: {:p}", self.type_name(), self.to_pointer()),
         }
     }
 }
@@ -77,12 +407,65 @@ impl std::hash::Hash for Value {
             Value::Bool(b) => b.hash(state),
             Value::Integer(i) => i.hash(state),
             Value::String(s) => s.hash(state),
-            Value::Float(f) => unsafe {
-                std::mem::transmute::<f64, i64>(*f).hash(state);
-            },
+            Value::Float(f) => f.to_bits().hash(state),
             Value::Function(f) => (*f as *const usize).hash(state),
+            Value::LuaFunction(f) => Rc::as_ptr(f).hash(state),
+            Value::RustClosure(f) => Rc::as_ptr(f).hash(state),
             Value::Table(t) => Rc::as_ptr(t).hash(state),
+            Value::UserData(u) => Rc::as_ptr(u).hash(state),
+            Value::LightUserData(p) => p.hash(state),
+            Value::Thread(co) => Rc::as_ptr(co).hash(state),
         }
     }
 }
 
+// 按Lua的"%.14g"格式输出浮点数，看起来像整数时补上".0"
+pub fn fmt_float(f: f64) -> String {
+    if f.is_nan() {
+        return if f.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if f.is_infinite() {
+        return if f < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    let s = fmt_g(f, 14);
+    if s.bytes().all(|b| b.is_ascii_digit() || b == b'-') {
+        s + ".0"
+    } else {
+        s
+    }
+}
+
+// C语言printf的"%.<precision>g"
+pub fn fmt_g(f: f64, precision: usize) -> String {
+    let precision = precision.max(1);
+    if f == 0.0 {
+        return if f.is_sign_negative() { "-0" } else { "0" }.to_string();
+    }
+    let sci = format!("{:.*e}", precision - 1, f);
+    let (mantissa, exp) = sci.split_once('e').unwrap();
+    let exp: i32 = exp.parse().unwrap();
+    if exp < -4 || exp >= precision as i32 {
+        let mantissa = trim_fraction(mantissa);
+        format!("{}e{}{:02}", mantissa, if exp < 0 { '-' } else { '+' }, exp.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exp) as usize;
+        trim_fraction(&format!("{:.*}", decimals, f)).to_string()
+    }
+}
+
+fn trim_fraction(s: &str) -> &str {
+    if s.contains('.') {
+        s.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        s
+    }
+}
+
+// 浮点数有精确整数表示时转为整数
+pub fn float_to_integer(f: f64) -> Option<i64> {
+    if f.fract() == 0.0 && (-9223372036854775808.0..9223372036854775808.0).contains(&f) {
+        Some(f as i64)
+    } else {
+        None
+    }
+}
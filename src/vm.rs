@@ -1,132 +1,2175 @@
+use std::any::{Any, TypeId};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::ffi::c_void;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use std::time::Instant;
 
-use crate::value::Value;
+use crate::value::{float_to_integer, LuaClosure, RustClosure, RustFn, Table, Upvalue, UserData, Value};
 use crate::bytecode::ByteCode;
+use crate::error::LuaError;
+use crate::lex::Lex;
 use crate::parse::ParseProto;
+use crate::dump;
+use crate::debug::{self, DebugInfo, Hook, HookEvent, HOOK_COUNT, HOOK_LINE};
+use crate::stdlib::Lib;
+
+const MAX_FRAMES: usize = 200000;
+const MAX_RUST_CALLS: usize = 200;
+const LIMIT_CHECK_INTERVAL: u64 = 1000;  // 每执行这么多条指令检查一次时间、内存和中断请求
+
+// 宿主用来从其他线程中断执行的句柄，执行在下一次检查限制时以错误中止
+#[derive(Clone)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.0.store(true, AtomicOrdering::Relaxed);
+    }
+}
+
+// 宿主设置的执行限制
+#[derive(Default)]
+struct Limits {
+    fuel: Option<u64>,  // 剩余可执行的指令数
+    deadline: Option<Instant>,
+    memory: Option<isize>,  // 分配上限，见alloc::set_budget
+    entered: u32,  // 正在执行的入口调用数，其间线程的分配上限是本状态的
+    interval: u64,  // 上次检查后安排执行的指令数
+    countdown: u64,  // 距下次检查还剩的指令数
+}
+
+// Lua函数的调用帧
+struct CallFrame {
+    closure: Rc<LuaClosure>,
+    pc: usize,
+    base: usize,  // 寄存器0在栈中的位置，函数本身位于base-1
+    varargs: Vec<Value>,
+    nret: u8,  // 调用者期望的返回值个数+1，0表示全部
+    is_tail: bool,  // 由尾调用进入
+    hook_pc: usize,  // 上次检查line钩子时的pc，usize::MAX表示刚进入函数
+}
+
+// 正在执行的Rust函数，供调试接口列出调用栈
+#[derive(Clone, Copy)]
+struct RustFrame {
+    func_idx: usize,
+    depth: usize,  // 调用时的Lua帧数，即它位于frames[depth-1]之上
+}
+
+// 调用栈中的一层：frames或rust_frames的下标
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CallRef {
+    Lua(usize),
+    Rust(usize),
+}
+
+// 局部变量的位置：栈上，或某一帧的第i个可变参数
+enum LocalSlot {
+    Stack(usize),
+    Vararg(usize, usize),
+}
+
+#[derive(Default)]
+struct HookState {
+    func: Option<Hook>,
+    mask: u8,
+    count: u32,
+    countdown: u32,
+    running: bool,
+    depth: usize,  // 钩子开始执行时的Lua帧数，位于此处的帧即钩子函数
+    transfer: Option<(CallRef, usize, usize)>,  // call/return钩子中传递的值：所在的层、起始序号和个数
+}
+
+// Rust函数的延续：被调用的函数让出后，在协程恢复并完成调用时执行。
+// 第二个参数为被调用函数的返回值或错误，第三个为call_function_k传入的上下文
+pub type RustContinuation = fn(&mut ExeState, Result<Vec<Value>, LuaError>, Value) -> Result<i32, LuaError>;
+
+// 因让出而挂起的调用：Rust函数，或Lua指令触发的元方法
+struct Continuation {
+    func_idx: usize,  // Rust函数在栈中的位置
+    nret: u8,
+    k: ContKind,
+    depth: usize,  // 内层调用开始前的帧数
+    inner_idx: usize,  // 内层被调用函数在栈中的位置
+}
+
+// 挂起的调用完成后如何继续
+enum ContKind {
+    Yield,  // Rust函数本身让出，resume的参数即为其返回值
+    Rust(RustContinuation, Value),  // call_function_k的延续
+    Op(bool),  // 元方法返回后由finish_op完成触发它的指令，false表示丢弃返回值（`__newindex`）
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CoStatus {
+    Suspended,
+    Running,
+    Normal,  // 恢复了另一个协程，正在等待其让出
+    Dead,
+}
+
+// 线程的执行状态，不运行时保存在协程中
+#[derive(Default)]
+struct ThreadState {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    rust_frames: Vec<RustFrame>,
+    upvalues: Vec<(Rc<RefCell<Upvalue>>, usize)>,  // 换出时暂存的开放上值及其栈位置
+    tbc_list: Vec<usize>,
+    conts: Vec<Continuation>,
+    nny: usize,
+    func_index: usize,
+    errfunc: Option<Value>,
+    error: Option<Value>,  // 因出错而结束时的错误对象
+}
+
+// 协程，即Lua的thread类型
+pub struct Coroutine {
+    status: Cell<CoStatus>,
+    thread: RefCell<ThreadState>,
+}
+
+impl Coroutine {
+    fn new(status: CoStatus, stack: Vec<Value>) -> Self {
+        Coroutine {
+            status: Cell::new(status),
+            thread: RefCell::new(ThreadState { stack, ..Default::default() }),
+        }
+    }
+
+    pub fn status(&self) -> CoStatus {
+        self.status.get()
+    }
+}
 
 pub struct ExeState {
     stack: Vec<Value>,
-    globals: HashMap<String, Value>,
+    frames: Vec<CallFrame>,
+    rust_frames: Vec<RustFrame>,  // 当前线程中正在执行的Rust函数
+    globals: Rc<RefCell<Table>>,  // 全局表_G
+    func_index: usize,  // 当前被调用的Rust函数在栈中的位置
+    userdata_metatables: HashMap<TypeId, Rc<RefCell<Table>>>,  // 每种用户数据类型共享的元表
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,  // 仍指向栈上的上值
+    rust_calls: usize,  // Rust层嵌套调用深度，防止Rust栈溢出
+    pub(crate) warnings: bool,  // warn("@on")/warn("@off")
+    type_metatables: [Option<Rc<RefCell<Table>>>; 7],  // 表和用户数据之外各类型共享的元表，见type_slot
+    registry: Rc<RefCell<Table>>,  // 供库保存内部状态的注册表
+    tbc_list: Vec<usize>,  // 当前线程中待关闭变量的栈位置
+    nny: usize,  // 当前线程中不可让出的Rust调用层数
+    conts: Vec<Continuation>,  // 当前线程中因让出而挂起的Rust调用，由外到内
+    unwinding: Vec<Continuation>,  // 让出向外传递时记录的挂起调用，由内到外
+    pending_k: Option<(RustContinuation, Value, usize, usize)>,  // call_function_k让出时留给调用者的延续
+    transfer: Vec<Value>,  // yield传给resume的值
+    main_thread: Rc<Coroutine>,
+    current: Rc<Coroutine>,  // 正在运行的线程
+    limits: Limits,
+    interrupt: Arc<AtomicBool>,
+    hook: HookState,  // 钩子对所有线程生效
+    errfunc: Option<Value>,  // 当前线程的消息处理函数，由xpcall设置
+    raised: Option<Value>,   // 已经由消息处理函数处理、正在向外传递的错误对象
+}
+
+impl Default for ExeState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// 选择打开哪些标准库，以及是否构建沙箱环境
+pub struct ExeStateBuilder {
+    libs: Vec<Lib>,
+    sandbox: bool,
+    os_sandbox: bool,
+}
+
+impl ExeStateBuilder {
+    // 不打开任何库
+    pub fn new() -> Self {
+        ExeStateBuilder { libs: Vec::new(), sandbox: false, os_sandbox: false }
+    }
+
+    pub fn lib(mut self, lib: Lib) -> Self {
+        self.libs.push(lib);
+        self
+    }
+
+    pub fn libs(mut self, libs: &[Lib]) -> Self {
+        self.libs.extend_from_slice(libs);
+        self
+    }
+
+    pub fn all_libs(self) -> Self {
+        self.libs(&Lib::ALL)
+    }
+
+    // 执行不可信脚本的受限环境：不打开io和package，去掉可以访问文件和执行命令的函数，
+    // load不接受二进制chunk，库表只读
+    pub fn sandbox(mut self, sandbox: bool) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    // 只去掉os.execute、os.remove和os.exit，见stdlib::os::sandbox
+    pub fn os_sandbox(mut self, os_sandbox: bool) -> Self {
+        self.os_sandbox = os_sandbox;
+        self
+    }
+
+    pub fn build(self) -> ExeState {
+        let mut state = ExeState::bare();
+        crate::stdlib::open_libs(&mut state, &self.libs, self.sandbox);
+        if self.os_sandbox {
+            crate::stdlib::os::sandbox(&mut state);
+        }
+        state
+    }
 }
 
-impl ExeState {
-    pub fn new() -> Self {
-        let mut globals = HashMap::new();
+impl Default for ExeStateBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ExeState {
+    // 打开全部标准库
+    pub fn new() -> Self {
+        ExeStateBuilder::new().all_libs().build()
+    }
+
+    pub fn builder() -> ExeStateBuilder {
+        ExeStateBuilder::new()
+    }
+
+    fn bare() -> Self {
+        let main_thread = Rc::new(Coroutine::new(CoStatus::Running, Vec::new()));
+        ExeState {
+            stack: Vec::new(),
+            frames: Vec::new(),
+            rust_frames: Vec::new(),
+            globals: Rc::new(RefCell::new(Table::new(0, 64))),
+            func_index: 0,
+            userdata_metatables: HashMap::new(),
+            open_upvalues: Vec::new(),
+            rust_calls: 0,
+            warnings: false,
+            type_metatables: Default::default(),
+            registry: Rc::new(RefCell::new(Table::new(0, 8))),
+            tbc_list: Vec::new(),
+            nny: 0,
+            conts: Vec::new(),
+            unwinding: Vec::new(),
+            pending_k: None,
+            transfer: Vec::new(),
+            main_thread: main_thread.clone(),
+            current: main_thread,
+            limits: Limits { interval: LIMIT_CHECK_INTERVAL, countdown: LIMIT_CHECK_INTERVAL, ..Default::default() },
+            interrupt: Arc::new(AtomicBool::new(false)),
+            hook: HookState::default(),
+            errfunc: None,
+            raised: None,
+        }
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.borrow_mut().set_str(name, value);
+    }
+
+    pub fn globals(&self) -> Rc<RefCell<Table>> {
+        self.globals.clone()
+    }
+
+    // 注册表，Lua代码无法直接访问
+    pub fn registry(&self) -> Rc<RefCell<Table>> {
+        self.registry.clone()
+    }
+
+    pub fn get_global(&self, name: &str) -> Value {
+        self.globals.borrow().get_str(name)
+    }
+
+    // 编译并执行一个chunk，返回其全部返回值
+    // 在本状态的内存上限下执行f，结束后恢复线程原来的上限（可能属于其他状态）
+    fn enter<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        let saved = crate::alloc::set_budget(self.limits.memory);
+        self.limits.entered += 1;
+        let result = f(self);
+        self.limits.entered -= 1;
+        crate::alloc::set_budget(saved);
+        result
+    }
+
+    pub fn run(&mut self, proto: ParseProto) -> Result<Vec<Value>, LuaError> {
+        let f = self.new_closure(proto, None);
+        self.call_function(f, Vec::new())
+    }
+
+    // 把编译好的chunk包装成函数：第一个上值为_ENV，取env或全局表，其余上值初始为nil
+    pub fn new_closure(&self, proto: ParseProto, env: Option<Value>) -> Value {
+        let env = env.unwrap_or_else(|| Value::Table(self.globals.clone()));
+        let upvalues = proto.upvalues.iter().enumerate().map(|(i, _)| {
+            let v = if i == 0 { env.clone() } else { Value::Nil };
+            Rc::new(RefCell::new(Upvalue::Closed(v)))
+        }).collect();
+        Value::LuaFunction(Rc::new(LuaClosure { proto: Rc::new(proto), upvalues: RefCell::new(upvalues) }))
+    }
+
+    // 编译源码为函数
+    pub fn load(&mut self, lex: Lex, env: Option<Value>) -> Result<Value, LuaError> {
+        let mut proto = ParseProto::new(lex);
+        proto.compile()?;
+        Ok(self.new_closure(proto, env))
+    }
+
+    // 加载源码或预编译的二进制chunk
+    pub fn load_bytes(&mut self, code: &[u8], chunkname: &str, env: Option<Value>) -> Result<Value, LuaError> {
+        if code.starts_with(&dump::SIGNATURE[..1]) {
+            let proto = dump::undump(code, chunkname)?;
+            Ok(self.new_closure(proto, env))
+        } else {
+            self.load(Lex::from_bytes(code, chunkname), env)
+        }
+    }
+
+    // 从Rust调用Lua值（函数或带__call的对象），被调用的代码不能让出
+    pub fn call_function(&mut self, func: Value, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        self.enter(|state| {
+            state.nny += 1;
+            let result = state.call_yieldable(func, args);
+            state.nny -= 1;
+            result
+        })
+    }
+
+    // 可让出的调用，必须由Rust函数作为返回值直接返回。
+    // 被调用的代码让出时，协程恢复并完成调用后以其结果调用k；否则立即调用k
+    pub fn call_function_k(&mut self, func: Value, args: Vec<Value>, k: RustContinuation, ctx: Value) -> Result<i32, LuaError> {
+        let depth = self.frames.len();
+        let inner_idx = self.stack.len();
+        match self.call_yieldable(func, args) {
+            Err(LuaError::Yield) => {
+                self.pending_k = Some((k, ctx, depth, inner_idx));
+                Err(LuaError::Yield)
+            }
+            result => k(self, result, ctx),
+        }
+    }
+
+    fn call_yieldable(&mut self, func: Value, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        if self.rust_calls >= MAX_RUST_CALLS {
+            return Err(self.error("stack overflow"));
+        }
+        let func_idx = self.stack.len();
+        let depth = self.frames.len();
+        let nargs = args.len();
+        self.stack.push(func);
+        self.stack.extend(args);
+
+        self.rust_calls += 1;
+        let result = match self.call_value(func_idx, nargs, 0) {
+            Ok(true) => self.execute(depth + 1),
+            Ok(false) => Ok(()),
+            Err(e) => Err(e),
+        };
+        self.rust_calls -= 1;
+        match result {
+            Ok(()) => Ok(self.stack.drain(func_idx..).collect()),
+            // 让出时保留各帧，恢复后继续执行
+            Err(LuaError::Yield) => Err(LuaError::Yield),
+            Err(e) => {
+                // 出错时撤销本次调用的所有帧
+                let e = self.handle_error(e);
+                self.frames.truncate(depth);
+                let e = self.close_on_error(func_idx, e);
+                self.stack.truncate(func_idx);
+                Err(e)
+            }
+        }
+    }
+
+    // ---------- 执行限制 ----------
+
+    // 从现在起最多再执行limit条指令，None为不限制
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.limits.fuel = limit;
+        self.recheck_limits();
+    }
+
+    // 超过deadline后中止执行
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.limits.deadline = deadline;
+        self.recheck_limits();
+    }
+
+    // 从现在起最多再分配limit字节，超过时抛出可被pcall捕获的"not enough memory"。
+    // 上限在本状态执行期间由分配器执行，需要安装alloc::CountingAlloc，否则不起作用
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.limits.memory = limit.map(crate::alloc::budget_after);
+        if self.limits.entered > 0 {
+            crate::alloc::set_budget(self.limits.memory);
+        }
+        self.recheck_limits();
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle(self.interrupt.clone())
+    }
+
+    // 已安排但未执行的指令退还给fuel，下一条指令就检查
+    fn recheck_limits(&mut self) {
+        self.limits.interval = 0;
+        self.limits.countdown = 0;
+    }
+
+    fn check_limits(&mut self) -> Result<(), LuaError> {
+        let limits = &mut self.limits;
+        if let Some(fuel) = &mut limits.fuel {
+            *fuel = fuel.saturating_sub(limits.interval);
+        }
+        limits.interval = 0;
+        if limits.fuel == Some(0) {
+            return Err(LuaError::Interrupt("instruction limit exceeded"));
+        }
+        if self.interrupt.swap(false, AtomicOrdering::Relaxed) {
+            return Err(LuaError::Interrupt("interrupted"));
+        }
+        if limits.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(LuaError::Interrupt("deadline exceeded"));
+        }
+        limits.interval = limits.fuel.map_or(LIMIT_CHECK_INTERVAL, |fuel| fuel.min(LIMIT_CHECK_INTERVAL));
+        limits.countdown = limits.interval;
+        self.check_memory()
+    }
+
+    // 上次检查后有分配超出了上限。内存错误可以被捕获，处理代码在下次超限之前有机会释放内存
+    fn check_memory(&self) -> Result<(), LuaError> {
+        if self.limits.memory.is_some() && crate::alloc::take_exceeded() {
+            return Err(LuaError::runtime("not enough memory"));
+        }
+        Ok(())
+    }
+
+    // 供耗时长的Rust函数在循环中调用，检查中断请求和deadline
+    pub fn check_interrupt(&mut self) -> Result<(), LuaError> {
+        if self.interrupt.swap(false, AtomicOrdering::Relaxed) {
+            return Err(LuaError::Interrupt("interrupted"));
+        }
+        if self.limits.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(LuaError::Interrupt("deadline exceeded"));
+        }
+        self.check_memory()
+    }
+
+    // 为生成大字符串的Rust函数预留空间，超出内存上限时抛出"not enough memory"而不是终止程序
+    pub fn reserve(&self, buf: &mut Vec<u8>, additional: usize) -> Result<(), LuaError> {
+        crate::alloc::try_reserve(buf, additional).map_err(|_| LuaError::runtime("not enough memory"))
+    }
+
+    // ---------- 调试 ----------
+
+    // 设置钩子，mask为debug::HOOK_*的组合；count大于0时每执行count条指令触发一次count事件。
+    // hook为None或mask为0时关闭钩子
+    pub fn set_hook(&mut self, hook: Option<Hook>, mut mask: u8, count: u32) {
+        if count == 0 {
+            mask &= !HOOK_COUNT;
+        }
+        if hook.is_none() || mask == 0 {
+            self.hook.func = None;
+            self.hook.mask = 0;
+            self.hook.count = 0;
+        } else {
+            self.hook.func = hook;
+            self.hook.mask = mask;
+            self.hook.count = count;
+            self.hook.countdown = count;
+        }
+    }
+
+    pub fn hook_mask(&self) -> u8 {
+        self.hook.mask
+    }
+
+    pub fn hook_count(&self) -> u32 {
+        self.hook.count
+    }
+
+    fn run_hook(&mut self, event: HookEvent, transfer: Option<(CallRef, usize, usize)>) -> Result<(), LuaError> {
+        if self.hook.mask & event.mask() == 0 || self.hook.running {
+            return Ok(());
+        }
+        let Some(hook) = self.hook.func.clone() else {
+            return Ok(());
+        };
+        self.hook.running = true;
+        self.hook.depth = self.frames.len();
+        self.hook.transfer = transfer;
+        self.nny += 1;
+        let result = hook(self, event);
+        self.nny -= 1;
+        self.hook.running = false;
+        self.hook.transfer = None;
+        result
+    }
+
+    // Rust函数返回了栈顶的n个值
+    fn rust_return_hook(&mut self, n: i32) -> Result<i32, LuaError> {
+        let first = self.stack.len() - n as usize - self.func_index;
+        self.run_hook(HookEvent::Return, Some((CallRef::Rust(self.rust_frames.len() - 1), first, n as usize)))?;
+        Ok(n)
+    }
+
+    // 执行pc处的指令前触发count和line钩子。进入函数、换行或向后跳转时触发line事件
+    fn trace_exec(&mut self, pc: usize) -> Result<(), LuaError> {
+        if self.hook.running {
+            return Ok(());
+        }
+        if self.hook.mask & HOOK_COUNT != 0 {
+            self.hook.countdown -= 1;
+            if self.hook.countdown == 0 {
+                self.hook.countdown = self.hook.count;
+                self.run_hook(HookEvent::Count, None)?;
+            }
+        }
+        if self.hook.mask & HOOK_LINE != 0 {
+            let frame = self.frames.last_mut().unwrap();
+            let lineinfo = &frame.closure.proto.lineinfo;
+            let line = lineinfo.get(pc).copied().unwrap_or(0);
+            let last = std::mem::replace(&mut frame.hook_pc, pc);
+            let new_line = last == usize::MAX || pc <= last || lineinfo.get(last) != Some(&line);
+            if new_line && line != 0 {
+                self.run_hook(HookEvent::Line(line), None)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 在co（None为当前线程）的执行状态上调用f。恢复了其他协程而处于normal状态的线程看不到调用栈。
+    // 挂起的协程中被挂起的Rust调用也列在调用栈中，第0层为让出的函数（如coroutine.yield）
+    fn with_thread<R>(&self, co: Option<&Rc<Coroutine>>, f: impl FnOnce(&[Value], &[CallFrame], &[RustFrame]) -> R) -> R {
+        match co {
+            Some(co) if !Rc::ptr_eq(co, &self.current) => {
+                let thread = co.thread.borrow();
+                let suspended = thread.conts.iter().filter(|cont| !matches!(cont.k, ContKind::Op(_))).map(|cont| RustFrame {
+                    func_idx: cont.func_idx,
+                    depth: if matches!(cont.k, ContKind::Rust(..)) { cont.depth } else { thread.frames.len() },
+                });
+                let rust_frames: Vec<RustFrame> = suspended.chain(thread.rust_frames.iter().copied()).collect();
+                f(&thread.stack, &thread.frames, &rust_frames)
+            }
+            _ => f(&self.stack, &self.frames, &self.rust_frames),
+        }
+    }
+
+    // co的调用栈的层数
+    pub fn call_depth(&self, co: Option<&Rc<Coroutine>>) -> usize {
+        self.with_thread(co, |_, frames, rust_frames| frames.len() + rust_frames.len())
+    }
+
+    // 正在执行的Lua函数的原型和指令位置，供line和count钩子逐条指令统计。正在执行Rust函数时为None
+    pub fn current_instruction(&self) -> Option<(&Rc<ParseProto>, usize)> {
+        if self.rust_frames.last().is_some_and(|r| r.depth == self.frames.len()) {
+            return None;
+        }
+        let frame = self.frames.last()?;
+        Some((&frame.closure.proto, frame.pc.checked_sub(1)?))
+    }
+
+    // co的调用栈第level层的信息，第0层为正在执行的函数（如debug.getinfo自己）
+    pub fn get_info(&self, co: Option<&Rc<Coroutine>>, level: usize) -> Option<DebugInfo> {
+        let is_current = co.is_none_or(|co| Rc::ptr_eq(co, &self.current));
+        self.with_thread(co, |stack, frames, rust_frames| {
+            let calls = call_stack(frames, rust_frames);
+            let at = *calls.get(level)?;
+            let mut info = match at {
+                CallRef::Lua(i) => {
+                    let frame = &frames[i];
+                    let mut info = DebugInfo::of_function(&Value::LuaFunction(frame.closure.clone()));
+                    let line = frame.closure.proto.lineinfo.get(frame.pc.saturating_sub(1));
+                    info.current_line = line.map_or(-1, |&line| line as i64);
+                    info.is_tail_call = frame.is_tail;
+                    info
+                }
+                CallRef::Rust(i) => DebugInfo::of_function(&stack[rust_frames[i].func_idx]),
+            };
+            // 函数名由调用者正在执行的指令推断
+            let name = match at {
+                CallRef::Lua(i) if frames[i].is_tail => None,
+                CallRef::Lua(i) if is_current && self.hook.running && i == self.hook.depth => Some(("?".to_string(), "hook")),
+                _ => match calls.get(level + 1) {
+                    Some(&CallRef::Lua(i)) => debug::func_name_from_code(&frames[i].closure.proto, frames[i].pc.saturating_sub(1)),
+                    _ => None,
+                },
+            };
+            if let Some((name, namewhat)) = name {
+                info.name = Some(name);
+                info.namewhat = namewhat;
+            }
+            if let Some((t, first, n)) = self.hook.transfer {
+                if is_current && t == at {
+                    info.ftransfer = first;
+                    info.ntransfer = n;
+                }
+            }
+            Some(info)
+        })
+    }
+
+    // co的调用栈第level层函数的第n个局部变量的名字和值，n为负数时取第-n个可变参数
+    pub fn get_local(&self, co: Option<&Rc<Coroutine>>, level: usize, n: i64) -> Option<(String, Value)> {
+        self.with_thread(co, |stack, frames, rust_frames| {
+            let (name, slot) = local_slot(stack, frames, rust_frames, level, n)?;
+            let v = match slot {
+                LocalSlot::Stack(i) => stack[i].clone(),
+                LocalSlot::Vararg(f, i) => frames[f].varargs[i].clone(),
+            };
+            Some((name, v))
+        })
+    }
+
+    // 给局部变量赋值，返回其名字
+    pub fn set_local(&mut self, co: Option<&Rc<Coroutine>>, level: usize, n: i64, v: Value) -> Option<String> {
+        let (name, slot) = self.with_thread(co, |stack, frames, rust_frames| local_slot(stack, frames, rust_frames, level, n))?;
+        let mut thread;
+        let (stack, frames) = match co {
+            Some(co) if !Rc::ptr_eq(co, &self.current) => {
+                thread = co.thread.borrow_mut();
+                let thread = &mut *thread;
+                (&mut thread.stack, &mut thread.frames)
+            }
+            _ => (&mut self.stack, &mut self.frames),
+        };
+        match slot {
+            LocalSlot::Stack(i) => stack[i] = v,
+            LocalSlot::Vararg(f, i) => frames[f].varargs[i] = v,
+        }
+        Some(name)
+    }
+
+    // 函数f的第n个上值（从1开始）的名字和值，Rust函数的上值名字为空串
+    pub fn function_upvalue(&self, f: &Value, n: usize) -> Option<(String, Value)> {
+        let i = n.checked_sub(1)?;
+        match f {
+            Value::LuaFunction(c) => {
+                let up = c.upvalues.borrow().get(i)?.clone();
+                let v = match &*up.borrow() {
+                    Upvalue::Open(idx) => self.stack[*idx].clone(),
+                    Upvalue::Closed(v) => v.clone(),
+                };
+                let name = c.proto.upvalues.get(i).map_or("?", |desc| desc.name.as_str());
+                Some((if name.is_empty() { "?".to_string() } else { name.to_string() }, v))
+            }
+            Value::RustClosure(c) => Some((String::new(), c.upvalues.borrow().get(i)?.clone())),
+            _ => None,
+        }
+    }
+
+    // 给函数f的第n个上值赋值，返回其名字
+    pub fn set_function_upvalue(&mut self, f: &Value, n: usize, v: Value) -> Option<String> {
+        let (name, _) = self.function_upvalue(f, n)?;
+        match f {
+            Value::LuaFunction(c) => {
+                let up = c.upvalues.borrow()[n - 1].clone();
+                let mut up = up.borrow_mut();
+                match &mut *up {
+                    Upvalue::Open(idx) => self.stack[*idx] = v,
+                    Upvalue::Closed(old) => *old = v,
+                }
+            }
+            Value::RustClosure(c) => c.upvalues.borrow_mut()[n - 1] = v,
+            _ => unreachable!(),
+        }
+        Some(name)
+    }
+
+    // ---------- 协程 ----------
+
+    pub fn new_coroutine(&self, func: Value) -> Rc<Coroutine> {
+        Rc::new(Coroutine::new(CoStatus::Suspended, vec![func]))
+    }
+
+    pub fn running(&self) -> Rc<Coroutine> {
+        self.current.clone()
+    }
+
+    pub fn is_main_thread(&self, co: &Rc<Coroutine>) -> bool {
+        Rc::ptr_eq(co, &self.main_thread)
+    }
+
+    // 协程能否让出：主线程和不可让出的Rust调用中都不能
+    pub fn is_yieldable(&self, co: &Rc<Coroutine>) -> bool {
+        if self.is_main_thread(co) {
+            false
+        } else if Rc::ptr_eq(co, &self.current) {
+            self.nny == 0
+        } else {
+            co.thread.borrow().nny == 0
+        }
+    }
+
+    // 让出当前协程，values成为resume的返回值。返回的错误需要原样向外传递
+    pub fn yield_values(&mut self, values: Vec<Value>) -> LuaError {
+        if self.is_main_thread(&self.current) {
+            return LuaError::runtime("attempt to yield from outside a coroutine".to_string());
+        }
+        if self.nny > 0 {
+            return LuaError::runtime("attempt to yield across a C-call boundary".to_string());
+        }
+        self.transfer = values;
+        LuaError::Yield
+    }
+
+    // 恢复协程，返回其让出的值或主函数的返回值
+    pub fn resume(&mut self, co: &Rc<Coroutine>, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        self.enter(|state| state.resume_thread(co, args))
+    }
+
+    fn resume_thread(&mut self, co: &Rc<Coroutine>, args: Vec<Value>) -> Result<Vec<Value>, LuaError> {
+        match co.status.get() {
+            CoStatus::Suspended => (),
+            CoStatus::Dead => return Err(LuaError::runtime("cannot resume dead coroutine".to_string())),
+            _ => return Err(LuaError::runtime("cannot resume non-suspended coroutine".to_string())),
+        }
+        if self.rust_calls >= MAX_RUST_CALLS {
+            return Err(LuaError::runtime("C stack overflow".to_string()));
+        }
+        let (mut saved, prev) = self.enter_thread(co, CoStatus::Running);
+        self.rust_calls += 1;
+        let result = self.run_coroutine(args);
+        self.rust_calls -= 1;
+        let result = match result {
+            Ok(()) => {
+                co.status.set(CoStatus::Dead);
+                Ok(self.stack.drain(..).collect())
+            }
+            Err(LuaError::Yield) => {
+                co.status.set(CoStatus::Suspended);
+                Ok(std::mem::take(&mut self.transfer))
+            }
+            Err(e) => {
+                co.status.set(CoStatus::Dead);
+                self.raised = None;
+                saved.error = Some(e.clone().into_value());
+                Err(e)
+            }
+        };
+        self.leave_thread(co, saved, prev);
+        result
+    }
+
+    // 关闭挂起或已结束的协程，调用其中所有待关闭变量的`__close`。
+    // 协程因出错而结束或`__close`出错时返回错误对象
+    pub fn close_coroutine(&mut self, co: &Rc<Coroutine>) -> Result<(), Value> {
+        let status = co.status.get();
+        let (mut saved, prev) = self.enter_thread(co, status);
+        let mut err = saved.error.take();
+        self.rust_calls += 1;
+        while let Some(idx) = self.tbc_list.pop() {
+            let v = self.stack[idx].clone();
+            if let Err(e) = self.call_close(v, err.clone().unwrap_or(Value::Nil)) {
+                err = Some(e.into_value());
+            }
+        }
+        self.rust_calls -= 1;
+        self.close_upvalues(0);
+        self.frames.clear();
+        self.rust_frames.clear();
+        self.conts.clear();
+        self.stack.clear();
+        self.nny = 0;
+        co.status.set(CoStatus::Dead);
+        self.leave_thread(co, saved, prev);
+        err.map_or(Ok(()), Err)
+    }
+
+    // 切换到协程co，返回换出的调用者线程。返回的状态中只有error属于co
+    fn enter_thread(&mut self, co: &Rc<Coroutine>, status: CoStatus) -> (ThreadState, Rc<Coroutine>) {
+        self.current.status.set(CoStatus::Normal);
+        co.status.set(status);
+        let mut thread = std::mem::take(&mut *co.thread.borrow_mut());
+        self.swap_thread(&mut thread);
+        (thread, std::mem::replace(&mut self.current, co.clone()))
+    }
+
+    fn leave_thread(&mut self, co: &Rc<Coroutine>, mut thread: ThreadState, prev: Rc<Coroutine>) {
+        self.swap_thread(&mut thread);
+        *co.thread.borrow_mut() = thread;
+        self.current = prev;
+        self.current.status.set(CoStatus::Running);
+    }
+
+    // 交换当前线程与saved中的执行状态。开放上值指向当前的栈，换出前暂存其值，换入时放回
+    fn swap_thread(&mut self, saved: &mut ThreadState) {
+        let stack = &self.stack;
+        let parked = self.open_upvalues.drain(..).map(|up| {
+            let idx = match *up.borrow() {
+                Upvalue::Open(idx) => idx,
+                Upvalue::Closed(_) => unreachable!(),
+            };
+            *up.borrow_mut() = Upvalue::Closed(stack.get(idx).cloned().unwrap_or(Value::Nil));
+            (up, idx)
+        }).collect();
+        std::mem::swap(&mut self.stack, &mut saved.stack);
+        std::mem::swap(&mut self.frames, &mut saved.frames);
+        std::mem::swap(&mut self.rust_frames, &mut saved.rust_frames);
+        std::mem::swap(&mut self.tbc_list, &mut saved.tbc_list);
+        std::mem::swap(&mut self.conts, &mut saved.conts);
+        std::mem::swap(&mut self.nny, &mut saved.nny);
+        std::mem::swap(&mut self.func_index, &mut saved.func_index);
+        std::mem::swap(&mut self.errfunc, &mut saved.errfunc);
+        for (up, idx) in std::mem::replace(&mut saved.upvalues, parked) {
+            let v = std::mem::replace(&mut *up.borrow_mut(), Upvalue::Open(idx));
+            if let Upvalue::Closed(v) = v {
+                self.stack[idx] = v;
+            }
+            self.open_upvalues.push(up);
+        }
+    }
+
+    fn run_coroutine(&mut self, args: Vec<Value>) -> Result<(), LuaError> {
+        let status = match self.conts.pop() {
+            // 首次运行：栈上只有主函数
+            None => {
+                let nargs = args.len();
+                self.stack.extend(args);
+                self.call_value(0, nargs, 0).map(|_| ())
+            }
+            // 从让出处继续：resume的参数作为yield的返回值
+            Some(cont) => {
+                let n = args.len();
+                let start = self.stack.len();
+                self.stack.extend(args);
+                self.place_results(cont.func_idx, start, n, cont.nret);
+                self.resize_caller(cont.nret);
+                Ok(())
+            }
+        };
+        self.unroll(status)
+    }
+
+    // 继续执行协程，直到主函数返回、再次让出或因出错而结束。
+    // 被挂起的Rust调用在其内层调用完成后以结果调用延续
+    fn unroll(&mut self, mut status: Result<(), LuaError>) -> Result<(), LuaError> {
+        loop {
+            status = match status {
+                Ok(()) => {
+                    let depth = self.conts.last().map_or(0, |c| c.depth);
+                    if self.frames.len() > depth {
+                        self.execute(depth + 1)
+                    } else {
+                        match self.conts.pop() {
+                            None => return Ok(()),
+                            Some(cont) => {
+                                let results = self.stack.drain(cont.inner_idx..).collect();
+                                self.finish_continuation(cont, Ok(results))
+                            }
+                        }
+                    }
+                }
+                Err(LuaError::Yield) => {
+                    let unwinding = std::mem::take(&mut self.unwinding);
+                    self.conts.extend(unwinding.into_iter().rev());
+                    return Err(LuaError::Yield);
+                }
+                Err(e) => match self.conts.pop() {
+                    None => return Err(e),
+                    // 由最内层的延续处理错误，比如pcall
+                    Some(cont) => {
+                        let e = self.handle_error(e);
+                        self.frames.truncate(cont.depth);
+                        let e = self.close_on_error(cont.inner_idx, e);
+                        self.stack.truncate(cont.inner_idx);
+                        self.finish_continuation(cont, Err(e))
+                    }
+                },
+            };
+        }
+    }
+
+    fn finish_continuation(&mut self, cont: Continuation, result: Result<Vec<Value>, LuaError>) -> Result<(), LuaError> {
+        let (k, ctx) = match cont.k {
+            ContKind::Rust(k, ctx) => (k, ctx),
+            ContKind::Op(store) => return self.finish_op(store, result?),
+            ContKind::Yield => unreachable!(),
+        };
+        let saved = self.func_index;
+        self.func_index = cont.func_idx;
+        self.rust_frames.push(RustFrame { func_idx: cont.func_idx, depth: self.frames.len() });
+        let n = k(self, result, ctx).and_then(|n| self.rust_return_hook(n)).map_err(|e| self.handle_error(e));
+        self.rust_frames.pop();
+        self.func_index = saved;
+        self.finish_rust_call(cont.func_idx, cont.nret, n)?;
+        self.resize_caller(cont.nret);
+        Ok(())
+    }
+
+    // ---------- Rust函数的参数和返回值 ----------
+
+    // 当前Rust函数的第i个参数（从1开始），缺省为nil
+    pub fn get_arg(&self, i: usize) -> &Value {
+        self.stack.get(self.func_index + i).unwrap_or(&Value::Nil)
+    }
+
+    pub fn get_args_count(&self) -> usize {
+        self.stack.len() - self.func_index - 1
+    }
+
+    pub fn push(&mut self, v: Value) {
+        self.stack.push(v);
+    }
+
+    // 新建带上值的Rust函数
+    pub fn new_rust_closure(&self, f: RustFn, upvalues: Vec<Value>) -> Value {
+        Value::RustClosure(Rc::new(RustClosure { f, upvalues: RefCell::new(upvalues) }))
+    }
+
+    // 当前Rust闭包的第i个上值（从1开始）
+    pub fn get_upvalue(&self, i: usize) -> Value {
+        match &self.stack[self.func_index] {
+            Value::RustClosure(c) => c.upvalues.borrow().get(i - 1).cloned().unwrap_or(Value::Nil),
+            _ => Value::Nil,
+        }
+    }
+
+    pub fn set_upvalue(&self, i: usize, v: Value) {
+        if let Value::RustClosure(c) = &self.stack[self.func_index] {
+            if let Some(up) = c.upvalues.borrow_mut().get_mut(i - 1) {
+                *up = v;
+            }
+        }
+    }
+
+    // 运行时错误，附加位置信息：Lua指令出错时为当前函数，Rust函数出错时为其调用者
+    pub fn error(&self, msg: &str) -> LuaError {
+        let level = if self.current_instruction().is_some() { 0 } else { 1 };
+        LuaError::runtime(format!("{}{}", self.where_(level), msg))
+    }
+
+    // 调用栈第level层（第0层为正在执行的函数）的"chunk:line:"前缀，Rust函数没有位置
+    pub fn where_(&self, level: usize) -> String {
+        let Some(&CallRef::Lua(i)) = call_stack(&self.frames, &self.rust_frames).get(level) else {
+            return String::new();
+        };
+        let frame = &self.frames[i];
+        let proto = &frame.closure.proto;
+        // 去掉了调试信息的函数不知道行号
+        let Some(line) = proto.lineinfo.get(frame.pc.saturating_sub(1)) else {
+            return String::new();
+        };
+        format!("{}:{}: ", crate::lex::chunkid(&proto.source), line)
+    }
+
+    // ---------- 用户数据 ----------
+
+    // 注册一种用户数据类型：元表带有`__name`，并以`methods`作为`__index`方法表
+    pub fn register_userdata_type<T: Any>(&mut self, name: &str, methods: &[(&str, RustFn)]) -> Rc<RefCell<Table>> {
+        let mut index = Table::new(0, methods.len());
+        for (method, f) in methods {
+            index.set_str(method, Value::Function(*f));
+        }
+
+        let mut meta = Table::new(0, 2);
+        meta.set_str("__name", Value::from(name));
+        meta.set_str("__index", Value::Table(Rc::new(RefCell::new(index))));
+
+        let meta = Rc::new(RefCell::new(meta));
+        self.userdata_metatables.insert(TypeId::of::<T>(), meta.clone());
+        meta
+    }
+
+    pub fn userdata_metatable<T: Any>(&self) -> Option<Rc<RefCell<Table>>> {
+        self.userdata_metatables.get(&TypeId::of::<T>()).cloned()
+    }
+
+    // 新建完全用户数据，自动关联该类型注册过的元表
+    pub fn new_userdata<T: Any>(&self, data: T) -> Value {
+        let ud = UserData::new(data);
+        *ud.metatable.borrow_mut() = self.userdata_metatable::<T>();
+        Value::UserData(Rc::new(ud))
+    }
+
+    pub fn new_light_userdata(&self, p: *mut c_void) -> Value {
+        Value::LightUserData(p)
+    }
+
+    // 取第i个参数，要求是类型为T的用户数据
+    pub fn get_userdata<T: Any>(&self, i: usize) -> Option<Rc<UserData>> {
+        match self.get_arg(i) {
+            Value::UserData(u) if u.is::<T>() => Some(u.clone()),
+            _ => None,
+        }
+    }
+
+    // ---------- 元表操作 ----------
+
+    pub fn get_metatable(&self, v: &Value) -> Option<Rc<RefCell<Table>>> {
+        match type_slot(v) {
+            Some(i) => self.type_metatables[i].clone(),
+            None => v.metatable(),
+        }
+    }
+
+    pub fn set_string_metatable(&mut self, meta: Option<Rc<RefCell<Table>>>) {
+        self.set_type_metatable(&Value::from(""), meta);
+    }
+
+    // 设置v所属类型共享的元表，v为表或用户数据时只设置它自己的元表
+    pub fn set_type_metatable(&mut self, v: &Value, meta: Option<Rc<RefCell<Table>>>) {
+        match (type_slot(v), v) {
+            (Some(i), _) => self.type_metatables[i] = meta,
+            (None, Value::Table(t)) => t.borrow_mut().metatable = meta,
+            (None, Value::UserData(u)) => *u.metatable.borrow_mut() = meta,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn get_metamethod(&self, v: &Value, event: &str) -> Value {
+        match self.get_metatable(v) {
+            Some(meta) => meta.borrow().get_str(event),
+            None => Value::Nil,
+        }
+    }
+
+    fn call_metamethod(&mut self, mm: Value, args: Vec<Value>) -> Result<Value, LuaError> {
+        self.call_metamethod_k(mm, args, true)
+    }
+
+    // 由Lua指令触发的元方法可以让出，协程恢复、元方法返回后由finish_op完成该指令；
+    // Rust函数中触发的不能让出
+    fn call_metamethod_k(&mut self, mm: Value, args: Vec<Value>, store: bool) -> Result<Value, LuaError> {
+        if self.current_instruction().is_none() {
+            let results = self.call_function(mm, args)?;
+            return Ok(results.into_iter().next().unwrap_or(Value::Nil));
+        }
+        let depth = self.frames.len();
+        let inner_idx = self.stack.len();
+        match self.call_yieldable(mm, args) {
+            Ok(results) => Ok(results.into_iter().next().unwrap_or(Value::Nil)),
+            Err(LuaError::Yield) => {
+                self.unwinding.push(Continuation { func_idx: inner_idx, nret: 0, k: ContKind::Op(store), depth, inner_idx });
+                Err(LuaError::Yield)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // 元方法让出后返回：把返回值写入触发它的指令的目标
+    fn finish_op(&mut self, store: bool, results: Vec<Value>) -> Result<(), LuaError> {
+        if !store {
+            return Ok(());
+        }
+        let v = results.into_iter().next().unwrap_or(Value::Nil);
+        let frame = self.frames.last().unwrap();
+        let closure = frame.closure.clone();
+        let base = frame.base;
+        let proto = &closure.proto;
+        let dst = match proto.instructions[frame.pc - 1] {
+            ByteCode::SetGlobalGlobal(env, name, _) => {
+                return self.set_global_value(&closure, env, &proto.constants[name as usize], v);
+            }
+            ByteCode::Method(dst, obj, _) => {
+                self.stack[base + dst as usize + 1] = self.stack[base + obj as usize].clone();
+                dst
+            }
+            ByteCode::Eq(dst, _, _) | ByteCode::Lt(dst, _, _) | ByteCode::Le(dst, _, _) => {
+                self.stack[base + dst as usize] = Value::Bool(!v.is_false());
+                return Ok(());
+            }
+            ByteCode::GetGlobal(dst, _, _) | ByteCode::GetTable(dst, _, _) | ByteCode::GetField(dst, _, _)
+            | ByteCode::GetInt(dst, _, _) | ByteCode::Add(dst, _, _) | ByteCode::Sub(dst, _, _)
+            | ByteCode::Mul(dst, _, _) | ByteCode::Div(dst, _, _) | ByteCode::Idiv(dst, _, _)
+            | ByteCode::Mod(dst, _, _) | ByteCode::Pow(dst, _, _) | ByteCode::BitAnd(dst, _, _)
+            | ByteCode::BitOr(dst, _, _) | ByteCode::BitXor(dst, _, _) | ByteCode::ShiftL(dst, _, _)
+            | ByteCode::ShiftR(dst, _, _) | ByteCode::Concat(dst, _, _) | ByteCode::Neg(dst, _)
+            | ByteCode::BitNot(dst, _) | ByteCode::Len(dst, _) => dst,
+            _ => unreachable!(),
+        };
+        self.stack[base + dst as usize] = v;
+        Ok(())
+    }
+
+    // tostring：优先使用`__tostring`元方法，其次用`__name`作为类型名
+    pub fn tostring(&mut self, v: &Value) -> Result<Value, LuaError> {
+        let mm = self.get_metamethod(v, "__tostring");
+        if mm != Value::Nil {
+            return match self.call_metamethod(mm, vec![v.clone()])? {
+                s @ Value::String(_) => Ok(s),
+                _ => Err(self.error("'__tostring' must return a string")),
+            };
+        }
+        if let Value::String(name) = self.get_metamethod(v, "__name") {
+            let name = String::from_utf8_lossy(&name);
+            return Ok(Value::from(format!("{}: {:p}", name, v.to_pointer())));
+        }
+        Ok(match v {
+            Value::String(_) => v.clone(),
+            _ => Value::from(v.to_string()),
+        })
+    }
+
+    // 索引操作 t[k]，支持元表的`__index`（表或函数）
+    pub fn index(&mut self, t: &Value, key: &Value) -> Result<Value, LuaError> {
+        let mut t = t.clone();
+        for _ in 0..2000 {
+            if let Value::Table(table) = &t {
+                let v = table.borrow().get(key);
+                if v != Value::Nil {
+                    return Ok(v);
+                }
+            }
+
+            let handler = self.get_metamethod(&t, "__index");
+            match handler {
+                Value::Nil => {
+                    if let Value::Table(_) = t {
+                        return Ok(Value::Nil);
+                    }
+                    return Err(self.error(&format!("attempt to index a {} value", t.type_name())));
+                }
+                Value::Function(_) | Value::LuaFunction(_) | Value::RustClosure(_) => {
+                    return self.call_metamethod(handler, vec![t, key.clone()]);
+                }
+                h => t = h,
+            }
+        }
+        Err(self.error("'__index' chain too long; possible loop"))
+    }
+
+    // 赋值操作 t[k] = v，支持元表的`__newindex`
+    pub fn set_index(&mut self, t: &Value, key: Value, value: Value) -> Result<(), LuaError> {
+        let mut t = t.clone();
+        for _ in 0..2000 {
+            if let Value::Table(table) = &t {
+                let exists = table.borrow().get(&key) != Value::Nil;
+                if exists || table.borrow().metatable.is_none() {
+                    return self.raw_set(table, key, value);
+                }
+            }
+
+            let handler = self.get_metamethod(&t, "__newindex");
+            match handler {
+                Value::Nil => {
+                    if let Value::Table(table) = &t {
+                        return self.raw_set(table, key, value);
+                    }
+                    return Err(self.error(&format!("attempt to index a {} value", t.type_name())));
+                }
+                Value::Function(_) | Value::LuaFunction(_) | Value::RustClosure(_) => {
+                    self.call_metamethod_k(handler, vec![t, key, value], false)?;
+                    return Ok(());
+                }
+                h => t = h,
+            }
+        }
+        Err(self.error("'__newindex' chain too long; possible loop"))
+    }
+
+    // 不触发元方法的赋值，检查非法键并把整数值的浮点键规范为整数
+    pub fn raw_set(&self, table: &Rc<RefCell<Table>>, key: Value, value: Value) -> Result<(), LuaError> {
+        let key = match key {
+            Value::Nil => return Err(self.error("index is nil")),
+            Value::Float(f) if f.is_nan() => return Err(self.error("index is NaN")),
+            Value::Float(f) => match float_to_integer(f) {
+                Some(i) => Value::Integer(i),
+                None => Value::Float(f),
+            },
+            k => k,
+        };
+        table.borrow_mut().set(key, value);
+        Ok(())
+    }
 
-        globals.insert("print".to_string(), Value::Function(|state| {
-            let value = state.stack.pop().unwrap();
-            println!("{}", value);
-            0
-        }));
-        
+    pub fn equals(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match (a, b) {
+            (Value::Integer(i), Value::Float(f)) | (Value::Float(f), Value::Integer(i)) => Ok(*i as f64 == *f && float_to_integer(*f) == Some(*i)),
+            (Value::Table(t1), Value::Table(t2)) if !Rc::ptr_eq(t1, t2) => self.eq_metamethod(a, b),
+            (Value::UserData(u1), Value::UserData(u2)) if !Rc::ptr_eq(u1, u2) => self.eq_metamethod(a, b),
+            _ => Ok(a == b),
+        }
+    }
 
-        ExeState {
-            stack: Vec::new(),
-            globals,
+    fn eq_metamethod(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        let mut mm = self.get_metamethod(a, "__eq");
+        if mm == Value::Nil {
+            mm = self.get_metamethod(b, "__eq");
+        }
+        if mm == Value::Nil {
+            return Ok(false);
+        }
+        Ok(!self.call_metamethod(mm, vec![a.clone(), b.clone()])?.is_false())
+    }
+
+    pub fn less_than(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match (a, b) {
+            (Value::String(s1), Value::String(s2)) => Ok(s1 < s2),
+            _ => match num_cmp(a, b) {
+                Some(ord) => Ok(ord == Some(Ordering::Less)),
+                None => self.compare_metamethod(a, b, "__lt"),
+            },
+        }
+    }
+
+    pub fn less_equal(&mut self, a: &Value, b: &Value) -> Result<bool, LuaError> {
+        match (a, b) {
+            (Value::String(s1), Value::String(s2)) => Ok(s1 <= s2),
+            _ => match num_cmp(a, b) {
+                Some(ord) => Ok(matches!(ord, Some(Ordering::Less | Ordering::Equal))),
+                None => self.compare_metamethod(a, b, "__le"),
+            },
+        }
+    }
+
+    fn compare_metamethod(&mut self, a: &Value, b: &Value, event: &str) -> Result<bool, LuaError> {
+        let mut mm = self.get_metamethod(a, event);
+        if mm == Value::Nil {
+            mm = self.get_metamethod(b, event);
+        }
+        if mm == Value::Nil {
+            let (t1, t2) = (a.type_name(), b.type_name());
+            return Err(if t1 == t2 {
+                self.error(&format!("attempt to compare two {} values", t1))
+            } else {
+                self.error(&format!("attempt to compare {} with {}", t1, t2))
+            });
+        }
+        Ok(!self.call_metamethod(mm, vec![a.clone(), b.clone()])?.is_false())
+    }
+
+    pub fn len(&mut self, v: &Value) -> Result<Value, LuaError> {
+        if let Value::String(s) = v {
+            return Ok(Value::Integer(s.len() as i64));
+        }
+        let mm = self.get_metamethod(v, "__len");
+        if mm != Value::Nil {
+            return self.call_metamethod(mm, vec![v.clone()]);
+        }
+        match v {
+            Value::Table(t) => Ok(Value::Integer(t.borrow().len())),
+            _ => Err(self.error(&format!("attempt to get length of a {} value", v.type_name()))),
+        }
+    }
+
+    pub fn concat(&mut self, a: &Value, b: &Value) -> Result<Value, LuaError> {
+        if let (Some(s1), Some(s2)) = (concat_str(a), concat_str(b)) {
+            let mut s = s1;
+            self.reserve(&mut s, s2.len())?;
+            s.extend_from_slice(&s2);
+            return Ok(Value::from(s));
+        }
+        let mut mm = self.get_metamethod(a, "__concat");
+        if mm == Value::Nil {
+            mm = self.get_metamethod(b, "__concat");
+        }
+        if mm == Value::Nil {
+            let bad = if concat_str(a).is_none() { a } else { b };
+            return Err(self.error(&format!("attempt to concatenate a {} value", bad.type_name())));
+        }
+        self.call_metamethod(mm, vec![a.clone(), b.clone()])
+    }
+
+    // 算术和位运算，先尝试数字运算，再查找元方法
+    fn arith(&mut self, op: ArithOp, a: &Value, b: &Value) -> Result<Value, LuaError> {
+        let result = match op {
+            ArithOp::Add => arith_add(a, b),
+            ArithOp::Sub => arith_sub(a, b),
+            ArithOp::Mul => arith_mul(a, b),
+            ArithOp::Div => arith_div(a, b),
+            ArithOp::Pow => arith_pow(a, b),
+            ArithOp::Idiv | ArithOp::Mod => {
+                if let (Some(Value::Integer(_)), Some(Value::Integer(0))) = (a.to_number(), b.to_number()) {
+                    let name = if op == ArithOp::Idiv { "'n//0'" } else { "'n%0'" };
+                    return Err(self.error(&format!("attempt to perform {}", name)));
+                }
+                if op == ArithOp::Idiv { arith_idiv(a, b) } else { arith_mod(a, b) }
+            }
+            ArithOp::Unm => match a.to_number() {
+                Some(Value::Integer(i)) => Some(Value::Integer(i.wrapping_neg())),
+                Some(Value::Float(f)) => Some(Value::Float(-f)),
+                _ => None,
+            },
+            _ => {
+                if let (Some(x), Some(y)) = (a.to_integer(), b.to_integer()) {
+                    Some(Value::Integer(match op {
+                        ArithOp::BAnd => x & y,
+                        ArithOp::BOr => x | y,
+                        ArithOp::BXor => x ^ y,
+                        ArithOp::Shl => shift_left(x, y),
+                        ArithOp::Shr => shift_left(x, y.wrapping_neg()),
+                        ArithOp::BNot => !x,
+                        _ => unreachable!(),
+                    }))
+                } else {
+                    None
+                }
+            }
+        };
+        if let Some(v) = result {
+            return Ok(v);
+        }
+
+        let event = op.event();
+        let mut mm = self.get_metamethod(a, event);
+        if mm == Value::Nil {
+            mm = self.get_metamethod(b, event);
+        }
+        if mm != Value::Nil {
+            return self.call_metamethod(mm, vec![a.clone(), b.clone()]);
+        }
+
+        if op.is_bitwise() {
+            let bad = if a.to_number().is_none() { a } else if b.to_number().is_none() { b } else {
+                return Err(self.error("number has no integer representation"));
+            };
+            return Err(self.error(&format!("attempt to perform bitwise operation on a {} value", bad.type_name())));
         }
+        let bad = if a.to_number().is_none() { a } else { b };
+        Err(self.error(&format!("attempt to perform arithmetic on a {} value", bad.type_name())))
+    }
+
+    // ---------- 调用 ----------
+
+    // 调用位于func_idx的函数，参数紧随其后。Lua函数压入新帧后返回true，由execute执行
+    fn call_value(&mut self, func_idx: usize, nargs: usize, nret: u8) -> Result<bool, LuaError> {
+        self.precall(func_idx, nargs, nret, false)
     }
 
-    pub fn run(&mut self, proto: &ParseProto) {
-        for instruction in &proto.instructions {
-            match *instruction {
-                ByteCode::GetGlobal(index, name) => {
-                    let name = &proto.constants[name as usize];
-                    if let Value::String(name) = name {
-                        let value = self.globals.get(name)
-                                        .unwrap_or(&Value::Nil)
-                                        .clone();
-                        self.set_stack(index, value);
-                        
+    fn precall(&mut self, func_idx: usize, nargs: usize, nret: u8, is_tail: bool) -> Result<bool, LuaError> {
+        match self.stack[func_idx].clone() {
+            Value::Function(f) => self.call_rust(f, func_idx, nargs, nret),
+            Value::RustClosure(c) => self.call_rust(c.f, func_idx, nargs, nret),
+            Value::LuaFunction(closure) => {
+                let proto = &closure.proto;
+                let base = func_idx + 1;
+                self.stack.truncate(base + nargs);
+                let varargs = if nargs > proto.num_params {
+                    if proto.is_vararg {
+                        self.stack.split_off(base + proto.num_params)
                     } else {
-                        panic!("Expected string, got {:?}", name);
+                        self.stack.truncate(base + proto.num_params);
+                        Vec::new()
                     }
-                },
-                ByteCode::LoadConstant(index, c) => {
-                    let value = proto.constants[c as usize].clone();
-                    self.set_stack(index, value);
+                } else {
+                    Vec::new()
+                };
+                self.stack.resize(base + proto.max_stack, Value::Nil);
+                if self.frames.len() >= MAX_FRAMES {
+                    return Err(self.error("stack overflow"));
+                }
+                let nparams = proto.num_params;
+                self.frames.push(CallFrame { closure, pc: 0, base, varargs, nret, is_tail, hook_pc: usize::MAX });
+                let event = if is_tail { HookEvent::TailCall } else { HookEvent::Call };
+                self.run_hook(event, Some((CallRef::Lua(self.frames.len() - 1), 1, nparams)))?;
+                Ok(true)
+            }
+            v => {
+                // __call元方法：把被调用对象作为第一个参数
+                let mm = self.get_metamethod(&v, "__call");
+                if mm == Value::Nil {
+                    return Err(self.error(&format!("attempt to call a {} value", v.type_name())));
                 }
-                ByteCode::LoadInt(index, i) => {
-                    self.set_stack(index, Value::Integer(i as i64));
+                self.stack.insert(func_idx, mm);
+                self.precall(func_idx, nargs + 1, nret, is_tail)
+            }
+        }
+    }
+
+    // Rust函数直接在当前栈上执行，参数从func_idx+1开始
+    fn call_rust(&mut self, f: RustFn, func_idx: usize, nargs: usize, nret: u8) -> Result<bool, LuaError> {
+        self.stack.truncate(func_idx + 1 + nargs);
+        let saved = self.func_index;
+        self.func_index = func_idx;
+        self.rust_frames.push(RustFrame { func_idx, depth: self.frames.len() });
+        let n = self.run_hook(HookEvent::Call, Some((CallRef::Rust(self.rust_frames.len() - 1), 1, nargs)))
+            .and_then(|()| f(self))
+            .and_then(|n| self.rust_return_hook(n))
+            .and_then(|n| self.check_memory().map(|()| n))
+            .map_err(|e| self.handle_error(e));
+        self.rust_frames.pop();
+        self.func_index = saved;
+        self.finish_rust_call(func_idx, nret, n)?;
+        Ok(false)
+    }
+
+    // 把Rust函数的返回值移到func_idx；函数让出时记录这个被挂起的调用
+    fn finish_rust_call(&mut self, func_idx: usize, nret: u8, n: Result<i32, LuaError>) -> Result<(), LuaError> {
+        match n {
+            Ok(n) => {
+                let n = n as usize;
+                let start = self.stack.len() - n;
+                self.place_results(func_idx, start, n, nret);
+                Ok(())
+            }
+            Err(LuaError::Yield) => {
+                let (k, depth, inner_idx) = match self.pending_k.take() {
+                    Some((k, ctx, depth, inner_idx)) => (ContKind::Rust(k, ctx), depth, inner_idx),
+                    None => (ContKind::Yield, 0, 0),
+                };
+                self.unwinding.push(Continuation { func_idx, nret, k, depth, inner_idx });
+                Err(LuaError::Yield)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // 把从start开始的n个返回值移到dst，nret为0时保留全部并以栈顶标记个数
+    fn place_results(&mut self, dst: usize, start: usize, n: usize, nret: u8) {
+        if dst != start {
+            for i in 0..n {
+                self.stack[dst + i] = std::mem::replace(&mut self.stack[start + i], Value::Nil);
+            }
+        }
+        if nret == 0 {
+            self.stack.truncate(dst + n);
+        } else {
+            let want = nret as usize - 1;
+            self.stack.truncate(dst + n.min(want));
+            self.stack.resize(dst + want, Value::Nil);
+        }
+    }
+
+    fn close_upvalues(&mut self, level: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|up| {
+            let mut up = up.borrow_mut();
+            match *up {
+                Upvalue::Open(idx) if idx >= level => {
+                    *up = Upvalue::Closed(stack.get(idx).cloned().unwrap_or(Value::Nil));
+                    false
                 }
-                
-                ByteCode::LoadBool(index, b) => {
-                    self.set_stack(index, Value::Bool(b));
+                _ => true,
+            }
+        });
+    }
+
+    // 离开作用域：关闭level及以上的上值，并按声明的逆序调用待关闭变量的`__close`
+    fn close_level(&mut self, level: usize) -> Result<(), LuaError> {
+        self.close_upvalues(level);
+        while let Some(&idx) = self.tbc_list.last() {
+            if idx < level {
+                break;
+            }
+            self.tbc_list.pop();
+            let v = self.stack[idx].clone();
+            self.call_close(v, Value::Nil)?;
+        }
+        Ok(())
+    }
+
+    // 出错时关闭level及以上的变量，`__close`再出错时以新的错误代替原来的
+    fn close_on_error(&mut self, level: usize, mut e: LuaError) -> LuaError {
+        self.close_upvalues(level);
+        let raised = self.raised.take();
+        while let Some(&idx) = self.tbc_list.last() {
+            if idx < level {
+                break;
+            }
+            self.tbc_list.pop();
+            let v = self.stack[idx].clone();
+            if let Err(e2) = self.call_close(v, e.clone().into_value()) {
+                if e.is_catchable() {
+                    e = e2;
                 }
-                ByteCode::LoadNil(index) => {
-                    self.set_stack(index, Value::Nil);
+            }
+        }
+        if raised.is_some() {
+            self.raised = Some(e.clone().into_value());
+        }
+        e
+    }
+
+    // 设置当前线程的消息处理函数，nil为没有，返回原来的。pcall和xpcall在调用前设置、结束后恢复
+    pub fn set_error_handler(&mut self, handler: Value) -> Value {
+        self.raised = None;
+        let handler = if handler == Value::Nil { None } else { Some(handler) };
+        std::mem::replace(&mut self.errfunc, handler).unwrap_or(Value::Nil)
+    }
+
+    // 错误第一次向外传递、出错处的各帧还在时，以消息处理函数处理错误对象，
+    // 使处理函数中的debug.traceback等能看到出错的位置
+    fn handle_error(&mut self, e: LuaError) -> LuaError {
+        if matches!(e, LuaError::Yield) || !e.is_catchable() || self.errfunc.is_none() {
+            return e;
+        }
+        let v = e.into_value();
+        if self.raised.as_ref().is_some_and(|r| same_object(r, &v)) {
+            return LuaError::Runtime(v);
+        }
+        // 处理函数中的错误不再处理
+        let handler = self.errfunc.take();
+        let v = match self.call_function(handler.clone().unwrap(), vec![v]) {
+            Ok(results) => results.into_iter().next().unwrap_or(Value::Nil),
+            Err(e) => e.into_value(),
+        };
+        self.errfunc = handler;
+        self.raised = Some(v.clone());
+        LuaError::Runtime(v)
+    }
+
+    fn call_close(&mut self, v: Value, err: Value) -> Result<(), LuaError> {
+        let mm = self.get_metamethod(&v, "__close");
+        self.call_function(mm, vec![v, err])?;
+        Ok(())
+    }
+
+    fn find_upvalue(&mut self, idx: usize) -> Rc<RefCell<Upvalue>> {
+        for up in &self.open_upvalues {
+            if let Upvalue::Open(i) = *up.borrow() {
+                if i == idx {
+                    return up.clone();
                 }
+            }
+        }
+        let up = Rc::new(RefCell::new(Upvalue::Open(idx)));
+        self.open_upvalues.push(up.clone());
+        up
+    }
 
-                ByteCode::SetGlobal(dst, src) => {
-                    let name = proto.constants[dst as usize].clone();
-                    if let Value::String(key) = name {
-                        let value = self.stack[src as usize].clone();
-                        self.globals.insert(key, value);
-                    } else {
-                        panic!("Expected string, got {:?}", name);
+    // 执行Lua帧，直到帧数降到depth以下
+    fn execute(&mut self, depth: usize) -> Result<(), LuaError> {
+        loop {
+            if self.limits.countdown == 0 {
+                self.check_limits()?;
+            }
+            self.limits.countdown -= 1;
+            let frame = self.frames.last_mut().unwrap();
+            let closure = frame.closure.clone();
+            let proto = &closure.proto;
+            let base = frame.base;
+            let pc = frame.pc;
+            frame.pc += 1;
+            if self.hook.mask & (HOOK_LINE | HOOK_COUNT) != 0 {
+                self.trace_exec(pc)?;
+            }
+            let instruction = proto.instructions[pc];
+
+            match instruction {
+                ByteCode::GetGlobal(dst, env, name) => {
+                    let env = self.upvalue_value(&closure, env);
+                    let value = self.index(&env, &proto.constants[name as usize])?;
+                    self.stack[base + dst as usize] = value;
+                }
+                ByteCode::SetGlobal(env, name, src) => {
+                    let value = self.stack[base + src as usize].clone();
+                    self.set_global_value(&closure, env, &proto.constants[name as usize], value)?;
+                }
+                ByteCode::SetGlobalConst(env, name, src) => {
+                    let value = proto.constants[src as usize].clone();
+                    self.set_global_value(&closure, env, &proto.constants[name as usize], value)?;
+                }
+                ByteCode::SetGlobalGlobal(env, name, src) => {
+                    let t = self.upvalue_value(&closure, env);
+                    let value = self.index(&t, &proto.constants[src as usize])?;
+                    self.set_global_value(&closure, env, &proto.constants[name as usize], value)?;
+                }
+                ByteCode::LoadConstant(dst, c) => {
+                    self.stack[base + dst as usize] = proto.constants[c as usize].clone();
+                }
+                ByteCode::LoadNil(dst, n) => {
+                    for i in 0..n as usize {
+                        self.stack[base + dst as usize + i] = Value::Nil;
                     }
                 }
+                ByteCode::LoadBool(dst, b) => {
+                    self.stack[base + dst as usize] = Value::Bool(b);
+                }
+                ByteCode::LoadInt(dst, i) => {
+                    self.stack[base + dst as usize] = Value::Integer(i as i64);
+                }
+                ByteCode::Move(dst, src) => {
+                    self.stack[base + dst as usize] = self.stack[base + src as usize].clone();
+                }
 
-                ByteCode::SetGlobalConst(dst, src) => {
-                    let name = proto.constants[dst as usize].clone();
-                    if let Value::String(name) = name {
-                        let value = proto.constants[src as usize].clone();
-                        self.globals.insert(name, value);
-                    } else {
-                        panic!("Expected string, got {:?}", name);
+                ByteCode::GetUpvalue(dst, i) => {
+                    self.stack[base + dst as usize] = self.upvalue_value(&closure, i);
+                }
+                ByteCode::SetUpvalue(i, src) => {
+                    let value = self.stack[base + src as usize].clone();
+                    let up = closure.upvalues.borrow()[i as usize].clone();
+                    let mut up = up.borrow_mut();
+                    match &mut *up {
+                        Upvalue::Open(idx) => self.stack[*idx] = value,
+                        Upvalue::Closed(v) => *v = value,
+                    }
+                }
+                ByteCode::Close(a) => {
+                    self.close_level(base + a as usize)?;
+                }
+                ByteCode::Tbc(a) => {
+                    let idx = base + a as usize;
+                    let v = &self.stack[idx];
+                    if !v.is_false() {
+                        if self.get_metamethod(v, "__close") == Value::Nil {
+                            let name = proto.local_name(a as usize, pc).unwrap_or("?");
+                            return Err(self.error(&format!("variable '{}' got a non-closable value", name)));
+                        }
+                        self.tbc_list.push(idx);
                     }
                 }
 
-                ByteCode::SetGlobalGlobal(dst, src) => {
-                    let name = proto.constants[dst as usize].clone();
-                    if let Value::String(name) = name {
-                        let src = &proto.constants[src as usize];
-                        if let Value::String(src) = src {
-                            let value = self.globals.get(src)
-                                .unwrap_or(&Value::Nil)
-                                .clone();
-                            self.globals.insert(name, value);
-                        } else {
-                            panic!("Expected string, got {:?}", src);
+                ByteCode::NewTable(dst, narray, nmap) => {
+                    let table = Table::new(narray as usize, nmap as usize);
+                    self.stack[base + dst as usize] = Value::Table(Rc::new(RefCell::new(table)));
+                }
+                ByteCode::GetTable(dst, t, k) => {
+                    let t = self.stack[base + t as usize].clone();
+                    let k = self.stack[base + k as usize].clone();
+                    let v = self.get_table(&t, &k)?;
+                    self.stack[base + dst as usize] = v;
+                }
+                ByteCode::GetField(dst, t, k) => {
+                    let t = self.stack[base + t as usize].clone();
+                    let v = self.get_table(&t, &proto.constants[k as usize])?;
+                    self.stack[base + dst as usize] = v;
+                }
+                ByteCode::GetInt(dst, t, i) => {
+                    let t = self.stack[base + t as usize].clone();
+                    let v = self.get_table(&t, &Value::Integer(i as i64))?;
+                    self.stack[base + dst as usize] = v;
+                }
+                ByteCode::SetTable(t, k, v) => {
+                    let t = self.stack[base + t as usize].clone();
+                    let k = self.stack[base + k as usize].clone();
+                    let v = self.stack[base + v as usize].clone();
+                    self.set_table(&t, k, v)?;
+                }
+                ByteCode::SetField(t, k, v) => {
+                    let t = self.stack[base + t as usize].clone();
+                    let v = self.stack[base + v as usize].clone();
+                    self.set_table(&t, proto.constants[k as usize].clone(), v)?;
+                }
+                ByteCode::SetInt(t, i, v) => {
+                    let t = self.stack[base + t as usize].clone();
+                    let v = self.stack[base + v as usize].clone();
+                    self.set_table(&t, Value::Integer(i as i64), v)?;
+                }
+                ByteCode::SetList(t, n, offset) => {
+                    let start = base + t as usize + 1;
+                    let end = if n == 0 { self.stack.len() } else { start + n as usize - 1 };
+                    if let Value::Table(table) = &self.stack[base + t as usize] {
+                        let mut table = table.borrow_mut();
+                        for (i, v) in self.stack[start..end].iter().enumerate() {
+                            table.set(Value::Integer(offset as i64 + i as i64 + 1), v.clone());
                         }
+                    }
+                    if n == 0 {
+                        self.stack.resize(base + proto.max_stack, Value::Nil);
+                    }
+                }
+                ByteCode::Method(dst, obj, k) => {
+                    let obj = self.stack[base + obj as usize].clone();
+                    let method = self.get_table(&obj, &proto.constants[k as usize])?;
+                    self.stack[base + dst as usize + 1] = obj;
+                    self.stack[base + dst as usize] = method;
+                }
 
-                    } else {
-                        panic!("Expected string, got {:?}", name);
+                ByteCode::Add(dst, a, b) => self.arith_op(base, ArithOp::Add, dst, a, b)?,
+                ByteCode::Sub(dst, a, b) => self.arith_op(base, ArithOp::Sub, dst, a, b)?,
+                ByteCode::Mul(dst, a, b) => self.arith_op(base, ArithOp::Mul, dst, a, b)?,
+                ByteCode::Div(dst, a, b) => self.arith_op(base, ArithOp::Div, dst, a, b)?,
+                ByteCode::Idiv(dst, a, b) => self.arith_op(base, ArithOp::Idiv, dst, a, b)?,
+                ByteCode::Mod(dst, a, b) => self.arith_op(base, ArithOp::Mod, dst, a, b)?,
+                ByteCode::Pow(dst, a, b) => self.arith_op(base, ArithOp::Pow, dst, a, b)?,
+                ByteCode::BitAnd(dst, a, b) => self.arith_op(base, ArithOp::BAnd, dst, a, b)?,
+                ByteCode::BitOr(dst, a, b) => self.arith_op(base, ArithOp::BOr, dst, a, b)?,
+                ByteCode::BitXor(dst, a, b) => self.arith_op(base, ArithOp::BXor, dst, a, b)?,
+                ByteCode::ShiftL(dst, a, b) => self.arith_op(base, ArithOp::Shl, dst, a, b)?,
+                ByteCode::ShiftR(dst, a, b) => self.arith_op(base, ArithOp::Shr, dst, a, b)?,
+                ByteCode::Neg(dst, a) => self.arith_op(base, ArithOp::Unm, dst, a, a)?,
+                ByteCode::BitNot(dst, a) => self.arith_op(base, ArithOp::BNot, dst, a, a)?,
+                ByteCode::Concat(dst, a, b) => {
+                    let a = self.stack[base + a as usize].clone();
+                    let b = self.stack[base + b as usize].clone();
+                    let v = self.concat(&a, &b)?;
+                    self.stack[base + dst as usize] = v;
+                }
+                ByteCode::Eq(dst, a, b) => {
+                    let a = self.stack[base + a as usize].clone();
+                    let b = self.stack[base + b as usize].clone();
+                    let v = self.equals(&a, &b)?;
+                    self.stack[base + dst as usize] = Value::Bool(v);
+                }
+                ByteCode::Lt(dst, a, b) => {
+                    let a = self.stack[base + a as usize].clone();
+                    let b = self.stack[base + b as usize].clone();
+                    let v = self.less_than(&a, &b)?;
+                    self.stack[base + dst as usize] = Value::Bool(v);
+                }
+                ByteCode::Le(dst, a, b) => {
+                    let a = self.stack[base + a as usize].clone();
+                    let b = self.stack[base + b as usize].clone();
+                    let v = self.less_equal(&a, &b)?;
+                    self.stack[base + dst as usize] = Value::Bool(v);
+                }
+                ByteCode::Not(dst, a) => {
+                    let v = self.stack[base + a as usize].is_false();
+                    self.stack[base + dst as usize] = Value::Bool(v);
+                }
+                ByteCode::Len(dst, a) => {
+                    let a = self.stack[base + a as usize].clone();
+                    let v = self.len(&a)?;
+                    self.stack[base + dst as usize] = v;
+                }
+
+                ByteCode::Jump(offset) => self.jump(offset),
+                ByteCode::TestAndJump(a, offset) => {
+                    if self.stack[base + a as usize].is_false() {
+                        self.jump(offset);
+                    }
+                }
+                ByteCode::TestOrJump(a, offset) => {
+                    if !self.stack[base + a as usize].is_false() {
+                        self.jump(offset);
                     }
                 }
 
-                ByteCode::Move(dst, src) => {
-                    self.set_stack(dst, self.stack[src as usize].clone());
+                ByteCode::ForPrepare(a, offset) => {
+                    if !self.for_prepare(base + a as usize)? {
+                        self.jump(offset);
+                    }
+                }
+                ByteCode::ForLoop(a, offset) => {
+                    if self.for_loop(base + a as usize) {
+                        self.jump(offset);
+                    }
+                }
+                ByteCode::ForCall(a, nvars) => {
+                    let a = base + a as usize;
+                    for i in 0..3 {
+                        self.stack[a + 4 + i] = self.stack[a + i].clone();
+                    }
+                    if self.call_value(a + 4, 2, nvars + 1)? {
+                        continue;
+                    }
+                    self.stack.resize(base + proto.max_stack, Value::Nil);
+                }
+                ByteCode::ForGenLoop(a, offset) => {
+                    let a = base + a as usize;
+                    if self.stack[a + 4] != Value::Nil {
+                        self.stack[a + 2] = self.stack[a + 4].clone();
+                        self.jump(offset);
+                    }
                 }
 
-                ByteCode::Call(index, _) => {
-                    // println!("{}", index);
-                    // dbg!(&self.stack);
-                    let function = &self.stack[index as usize];
-                    if let Value::Function(f) = function {
-                        let result = f(self);
-                        if result != 0 {
-                            panic!("Function returned error code: {}", result);
+                ByteCode::Closure(dst, i) => {
+                    let proto = proto.protos[i as usize].clone();
+                    let upvalues = proto.upvalues.iter().map(|desc| {
+                        if desc.in_stack {
+                            self.find_upvalue(base + desc.index as usize)
+                        } else {
+                            closure.upvalues.borrow()[desc.index as usize].clone()
+                        }
+                    }).collect();
+                    let f = LuaClosure { proto, upvalues: RefCell::new(upvalues) };
+                    self.stack[base + dst as usize] = Value::LuaFunction(Rc::new(f));
+                }
+                ByteCode::Call(func, narg, nret) => {
+                    let func = base + func as usize;
+                    let nargs = if narg == 0 { self.stack.len() - func - 1 } else { narg as usize - 1 };
+                    if !self.call_value(func, nargs, nret)? && nret != 0 {
+                        self.stack.resize(base + proto.max_stack, Value::Nil);
+                    }
+                }
+                ByteCode::TailCall(func, narg) => {
+                    let func = base + func as usize;
+                    let nargs = if narg == 0 { self.stack.len() - func - 1 } else { narg as usize - 1 };
+                    if matches!(self.stack[func], Value::Function(_) | Value::RustClosure(_)) {
+                        // Rust函数像普通调用一样执行，以便看到调用它的Lua函数（如error的层数），
+                        // 结果由下一条Return返回
+                        self.call_value(func, nargs, 0)?;
+                        continue;
+                    }
+                    self.close_upvalues(base);
+                    // 把函数和参数移到当前函数的位置，复用调用者的帧
+                    let frame = self.frames.pop().unwrap();
+                    let dst = base - 1;
+                    for i in 0..=nargs {
+                        self.stack[dst + i] = std::mem::replace(&mut self.stack[func + i], Value::Nil);
+                    }
+                    if !self.precall(dst, nargs, frame.nret, true)? {
+                        if self.frames.len() < depth {
+                            return Ok(());
                         }
+                        self.resize_caller(frame.nret);
+                    }
+                }
+                ByteCode::Return(a, n) => {
+                    let start = base + a as usize;
+                    let count = if n == 0 { self.stack.len() - start } else { n as usize - 1 };
+                    self.close_level(base)?;
+                    self.run_hook(HookEvent::Return, Some((CallRef::Lua(self.frames.len() - 1), start - base + 1, count)))?;
+                    let frame = self.frames.pop().unwrap();
+                    self.place_results(base - 1, start, count, frame.nret);
+                    if self.frames.len() < depth {
+                        return Ok(());
+                    }
+                    self.resize_caller(frame.nret);
+                }
+                ByteCode::VarArgs(dst, n) => {
+                    let dst = base + dst as usize;
+                    let frame = self.frames.last().unwrap();
+                    if n == 0 {
+                        let varargs = frame.varargs.clone();
+                        self.stack.truncate(dst);
+                        self.stack.extend(varargs);
                     } else {
-                        panic!("Expected function, got {:?}", function);
+                        for i in 0..n as usize - 1 {
+                            let v = frame.varargs.get(i).cloned().unwrap_or(Value::Nil);
+                            self.stack[dst + i] = v;
+                        }
                     }
                 }
+            }
+        }
+    }
 
-                _ => panic!("Unimplemented instruction: {:?}", instruction),
+    // 返回到调用者后，恢复调用者的栈空间
+    fn resize_caller(&mut self, nret: u8) {
+        if nret != 0 {
+            let frame = self.frames.last().unwrap();
+            let top = frame.base + frame.closure.proto.max_stack;
+            if self.stack.len() < top {
+                self.stack.resize(top, Value::Nil);
             }
         }
     }
 
-    fn set_stack(&mut self, index: u8, value: Value) {
-        let index = index as usize;
-        match index.cmp(&self.stack.len()) {
-            std::cmp::Ordering::Equal => self.stack.push(value),
-            std::cmp::Ordering::Less => self.stack[index] = value,
-            std::cmp::Ordering::Greater => {
-                dbg!(&self.stack);
-                panic!("Invalid stack index: {}", index)
-            },
-            
+    fn jump(&mut self, offset: i32) {
+        let frame = self.frames.last_mut().unwrap();
+        frame.pc = (frame.pc as isize + offset as isize) as usize;
+    }
+
+    fn upvalue_value(&self, closure: &LuaClosure, i: u8) -> Value {
+        match &*closure.upvalues.borrow()[i as usize].borrow() {
+            Upvalue::Open(idx) => self.stack[*idx].clone(),
+            Upvalue::Closed(v) => v.clone(),
+        }
+    }
+
+    // 全局变量赋值：_ENV[key] = value
+    fn set_global_value(&mut self, closure: &LuaClosure, env: u8, key: &Value, value: Value) -> Result<(), LuaError> {
+        let env = self.upvalue_value(closure, env);
+        self.set_index(&env, key.clone(), value)
+    }
+
+    fn get_table(&mut self, t: &Value, k: &Value) -> Result<Value, LuaError> {
+        self.index(t, k)
+    }
+
+    fn set_table(&mut self, t: &Value, k: Value, v: Value) -> Result<(), LuaError> {
+        self.set_index(t, k, v)
+    }
+
+    fn arith_op(&mut self, base: usize, op: ArithOp, dst: u8, a: u8, b: u8) -> Result<(), LuaError> {
+        let a = self.stack[base + a as usize].clone();
+        let b = self.stack[base + b as usize].clone();
+        let v = self.arith(op, &a, &b)?;
+        self.stack[base + dst as usize] = v;
+        Ok(())
+    }
+
+    // 数值for的初始化，返回是否执行循环体。整数循环预先算出迭代次数，避免溢出
+    fn for_prepare(&mut self, a: usize) -> Result<bool, LuaError> {
+        let (init, limit, step) = (self.stack[a].clone(), self.stack[a + 1].clone(), self.stack[a + 2].clone());
+        if let (Value::Integer(i), Value::Integer(s)) = (&init, &step) {
+            let (i, s) = (*i, *s);
+            if s == 0 {
+                return Err(self.error("'for' step is zero"));
+            }
+            let Some(limit) = self.for_limit(&limit, s)? else { return Ok(false) };
+            if if s > 0 { i > limit } else { i < limit } {
+                return Ok(false);
+            }
+            let count = if s > 0 {
+                (limit as u64).wrapping_sub(i as u64) / s as u64
+            } else {
+                (i as u64).wrapping_sub(limit as u64) / ((s.wrapping_add(1)).wrapping_neg() as u64 + 1)
+            };
+            self.stack[a + 1] = Value::Integer(count as i64);
+            self.stack[a + 3] = Value::Integer(i);
+            return Ok(true);
+        }
+
+        let to_f = |v: &Value, what: &str| -> Result<f64, String> {
+            match v {
+                Value::Integer(i) => Ok(*i as f64),
+                Value::Float(f) => Ok(*f),
+                _ => Err(format!("'for' {} value must be a number", what)),
+            }
+        };
+        let l = to_f(&limit, "limit").map_err(|m| self.error(&m))?;
+        let s = to_f(&step, "step").map_err(|m| self.error(&m))?;
+        let i = to_f(&init, "initial").map_err(|m| self.error(&m))?;
+        if s == 0.0 {
+            return Err(self.error("'for' step is zero"));
+        }
+        if if s > 0.0 { i > l } else { i < l } {
+            return Ok(false);
+        }
+        self.stack[a] = Value::Float(i);
+        self.stack[a + 1] = Value::Float(l);
+        self.stack[a + 2] = Value::Float(s);
+        self.stack[a + 3] = Value::Float(i);
+        Ok(true)
+    }
+
+    // 把循环上限转为整数，None表示循环不执行
+    fn for_limit(&self, limit: &Value, step: i64) -> Result<Option<i64>, LuaError> {
+        match limit {
+            Value::Integer(l) => Ok(Some(*l)),
+            Value::Float(f) => {
+                let f = if step > 0 { f.floor() } else { f.ceil() };
+                if f.is_nan() {
+                    return Ok(None);
+                }
+                if let Some(l) = float_to_integer(f) {
+                    Ok(Some(l))
+                } else if f > 0.0 {
+                    // 超出整数范围
+                    if step < 0 { Ok(None) } else { Ok(Some(i64::MAX)) }
+                } else if step > 0 {
+                    Ok(None)
+                } else {
+                    Ok(Some(i64::MIN))
+                }
+            }
+            _ => Err(self.error("'for' limit must be a number")),
+        }
+    }
+
+    fn for_loop(&mut self, a: usize) -> bool {
+        match (&self.stack[a], &self.stack[a + 1], &self.stack[a + 2]) {
+            (Value::Integer(i), Value::Integer(count), Value::Integer(step)) => {
+                let count = *count as u64;
+                if count == 0 {
+                    return false;
+                }
+                let i = i.wrapping_add(*step);
+                self.stack[a] = Value::Integer(i);
+                self.stack[a + 1] = Value::Integer((count - 1) as i64);
+                self.stack[a + 3] = Value::Integer(i);
+                true
+            }
+            (Value::Float(i), Value::Float(limit), Value::Float(step)) => {
+                let i = i + step;
+                let go_on = if *step > 0.0 { i <= *limit } else { i >= *limit };
+                if go_on {
+                    self.stack[a] = Value::Float(i);
+                    self.stack[a + 3] = Value::Float(i);
+                }
+                go_on
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ArithOp {
+    Add, Sub, Mul, Div, Idiv, Mod, Pow, Unm,
+    BAnd, BOr, BXor, Shl, Shr, BNot,
+}
+
+impl ArithOp {
+    fn event(self) -> &'static str {
+        match self {
+            ArithOp::Add => "__add",
+            ArithOp::Sub => "__sub",
+            ArithOp::Mul => "__mul",
+            ArithOp::Div => "__div",
+            ArithOp::Idiv => "__idiv",
+            ArithOp::Mod => "__mod",
+            ArithOp::Pow => "__pow",
+            ArithOp::Unm => "__unm",
+            ArithOp::BAnd => "__band",
+            ArithOp::BOr => "__bor",
+            ArithOp::BXor => "__bxor",
+            ArithOp::Shl => "__shl",
+            ArithOp::Shr => "__shr",
+            ArithOp::BNot => "__bnot",
+        }
+    }
+
+    fn is_bitwise(self) -> bool {
+        matches!(self, ArithOp::BAnd | ArithOp::BOr | ArithOp::BXor | ArithOp::Shl | ArithOp::Shr | ArithOp::BNot)
+    }
+}
+
+// 从栈顶向下列出调用栈。Rust函数位于调用它时的最后一个Lua帧之上
+fn call_stack(frames: &[CallFrame], rust_frames: &[RustFrame]) -> Vec<CallRef> {
+    let mut calls = Vec::with_capacity(frames.len() + rust_frames.len());
+    let (mut fi, mut ri) = (frames.len(), rust_frames.len());
+    loop {
+        if ri > 0 && rust_frames[ri - 1].depth >= fi {
+            ri -= 1;
+            calls.push(CallRef::Rust(ri));
+        } else if fi > 0 {
+            fi -= 1;
+            calls.push(CallRef::Lua(fi));
+        } else {
+            return calls;
+        }
+    }
+}
+
+// 第level层函数的第n个局部变量。没有名字但在该层栈空间内的为临时值
+fn local_slot(stack: &[Value], frames: &[CallFrame], rust_frames: &[RustFrame], level: usize, n: i64) -> Option<(String, LocalSlot)> {
+    let calls = call_stack(frames, rust_frames);
+    let at = *calls.get(level)?;
+    // 该层的栈空间到上一层被调用的函数为止
+    let limit = match level.checked_sub(1).map(|l| calls[l]) {
+        None => stack.len(),
+        Some(CallRef::Lua(i)) => frames[i].base - 1,
+        Some(CallRef::Rust(i)) => rust_frames[i].func_idx,
+    };
+    match at {
+        CallRef::Lua(i) => {
+            let frame = &frames[i];
+            if n < 0 {
+                let vi = (-n - 1) as usize;
+                return (vi < frame.varargs.len()).then(|| ("(vararg)".to_string(), LocalSlot::Vararg(i, vi)));
+            }
+            let n = usize::try_from(n).ok().filter(|&n| n > 0)?;
+            let idx = frame.base + n - 1;
+            match frame.closure.proto.local_name(n - 1, frame.pc.saturating_sub(1)) {
+                Some(name) => Some((name.to_string(), LocalSlot::Stack(idx))),
+                None => (idx < limit).then(|| ("(temporary)".to_string(), LocalSlot::Stack(idx))),
+            }
+        }
+        CallRef::Rust(i) => {
+            let n = usize::try_from(n).ok().filter(|&n| n > 0)?;
+            let idx = rust_frames[i].func_idx + n;
+            (idx < limit).then(|| ("(C temporary)".to_string(), LocalSlot::Stack(idx)))
         }
     }
-}
\ No newline at end of file
+}
+
+// 共享元表的类型在type_metatables中的位置
+// 是否为同一个对象，字符串比较地址
+fn same_object(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::String(s1), Value::String(s2)) => Rc::ptr_eq(s1, s2),
+        _ => a == b,
+    }
+}
+
+fn type_slot(v: &Value) -> Option<usize> {
+    match v {
+        Value::Table(_) | Value::UserData(_) => None,
+        Value::Nil => Some(0),
+        Value::Bool(_) => Some(1),
+        Value::Integer(_) | Value::Float(_) => Some(2),
+        Value::String(_) => Some(3),
+        Value::Function(_) | Value::LuaFunction(_) | Value::RustClosure(_) => Some(4),
+        Value::LightUserData(_) => Some(5),
+        Value::Thread(_) => Some(6),
+    }
+}
+
+fn shift_left(x: i64, n: i64) -> i64 {
+    if n <= -64 || n >= 64 {
+        0
+    } else if n >= 0 {
+        ((x as u64) << n) as i64
+    } else {
+        ((x as u64) >> -n) as i64
+    }
+}
+
+// 数字比较，两边都是数字时返回Some(比较结果)，NaN的比较结果为None
+fn num_cmp(a: &Value, b: &Value) -> Option<Option<Ordering>> {
+    match (a, b) {
+        (Value::Integer(x), Value::Integer(y)) => Some(Some(x.cmp(y))),
+        (Value::Float(x), Value::Float(y)) => Some(x.partial_cmp(y)),
+        (Value::Integer(i), Value::Float(f)) => Some(int_float_cmp(*i, *f)),
+        (Value::Float(f), Value::Integer(i)) => Some(int_float_cmp(*i, *f).map(Ordering::reverse)),
+        _ => None,
+    }
+}
+
+// 整数与浮点数的精确比较
+fn int_float_cmp(i: i64, f: f64) -> Option<Ordering> {
+    if f.is_nan() {
+        return None;
+    }
+    if f >= 9223372036854775808.0 {
+        return Some(Ordering::Less);
+    }
+    if f < -9223372036854775808.0 {
+        return Some(Ordering::Greater);
+    }
+    let fi = f.floor();
+    match i.cmp(&(fi as i64)) {
+        Ordering::Equal if f > fi => Some(Ordering::Less),
+        ord => Some(ord),
+    }
+}
+
+fn concat_str(v: &Value) -> Option<Vec<u8>> {
+    match v {
+        Value::String(s) => Some(s.to_vec()),
+        Value::Integer(_) | Value::Float(_) => Some(v.to_string().into_bytes()),
+        _ => None,
+    }
+}
+
+fn arith_float(a: &Value, b: &Value, f: fn(f64, f64) -> f64) -> Option<Value> {
+    Some(Value::Float(f(a.to_float()?, b.to_float()?)))
+}
+
+fn arith_int_float(a: &Value, b: &Value, i: fn(i64, i64) -> i64, f: fn(f64, f64) -> f64) -> Option<Value> {
+    match (a.to_number()?, b.to_number()?) {
+        (Value::Integer(x), Value::Integer(y)) => Some(Value::Integer(i(x, y))),
+        (x, y) => arith_float(&x, &y, f),
+    }
+}
+
+pub fn arith_add(a: &Value, b: &Value) -> Option<Value> {
+    arith_int_float(a, b, i64::wrapping_add, |x, y| x + y)
+}
+
+pub fn arith_sub(a: &Value, b: &Value) -> Option<Value> {
+    arith_int_float(a, b, i64::wrapping_sub, |x, y| x - y)
+}
+
+pub fn arith_mul(a: &Value, b: &Value) -> Option<Value> {
+    arith_int_float(a, b, i64::wrapping_mul, |x, y| x * y)
+}
+
+pub fn arith_div(a: &Value, b: &Value) -> Option<Value> {
+    arith_float(a, b, |x, y| x / y)
+}
+
+pub fn arith_pow(a: &Value, b: &Value) -> Option<Value> {
+    arith_float(a, b, |x, y| if y == 2.0 { x * x } else { x.powf(y) })
+}
+
+// 整数除数为0时由调用者报错
+pub fn arith_idiv(a: &Value, b: &Value) -> Option<Value> {
+    arith_int_float(a, b, |x, y| {
+        if y == -1 {
+            return x.wrapping_neg();
+        }
+        let q = x / y;
+        if (x % y != 0) && ((x ^ y) < 0) { q - 1 } else { q }
+    }, |x, y| (x / y).floor())
+}
+
+pub fn arith_mod(a: &Value, b: &Value) -> Option<Value> {
+    arith_int_float(a, b, |x, y| {
+        if y == -1 {
+            return 0;
+        }
+        let r = x % y;
+        if r != 0 && (r ^ y) < 0 { r + y } else { r }
+    }, |x, y| {
+        if y.is_infinite() && x.is_finite() {
+            if (x >= 0.0) == (y > 0.0) { x } else { y }
+        } else {
+            let r = x % y;
+            if r != 0.0 && (r < 0.0) != (y < 0.0) { r + y } else { r }
+        }
+    })
+}
@@ -0,0 +1,35 @@
+-- myLua test test_lua
+describe("assert", function()
+  it("equals compares with ==", function()
+    assert.equals(4, 2 + 2)
+    assert.equals(1, 1.0)
+    assert.error_matches(function() assert.equals(1, 2) end, "expected 1, got 2")
+  end)
+
+  it("same compares tables deeply", function()
+    assert.same({1, 2, {x = "y"}}, {1, 2, {x = "y"}})
+    local a, b = {}, {}
+    a.self, b.self = a, b
+    assert.same(a, b)
+    assert.error_matches(function() assert.same({1}, {1, 2}) end, "expected {1}, got {1, 2}")
+  end)
+
+  it("is still callable", function()
+    assert.equals(3, select("#", assert(1, 2, 3)))
+    assert.error_matches(function() assert(false, "message") end, "^message$")
+  end)
+end)
+
+describe("hooks", function()
+  local calls = {}
+  before_each(function() calls[#calls + 1] = "before" end)
+  after_each(function() calls[#calls + 1] = "after" end)
+
+  it("runs before_each first", function()
+    assert.same({"before"}, calls)
+  end)
+
+  it("runs after_each after each test", function()
+    assert.same({"before", "after", "before"}, calls)
+  end)
+end)
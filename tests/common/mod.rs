@@ -0,0 +1,26 @@
+// 各集成测试共用的辅助函数：执行一段代码，返回值以空格连接
+#![allow(dead_code)]
+use my_lua::vm::ExeState;
+
+pub fn run(state: &mut ExeState, code: &str) -> Result<String, String> {
+    let f = state.load_bytes(code.as_bytes(), "=test", None).map_err(|e| e.to_string())?;
+    match state.call_function(f, Vec::new()) {
+        Ok(values) => Ok(values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" ")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub fn eval_in(state: &mut ExeState, code: &str) -> String {
+    run(state, code).unwrap_or_else(|e| panic!("{}: {}", code, e))
+}
+
+pub fn eval(code: &str) -> String {
+    eval_in(&mut ExeState::new(), code)
+}
+
+pub fn eval_err(code: &str) -> String {
+    match run(&mut ExeState::new(), code) {
+        Ok(_) => panic!("no error: {}", code),
+        Err(e) => e,
+    }
+}
@@ -0,0 +1,212 @@
+// 编译器和虚拟机：执行小段代码，检查返回值
+mod common;
+
+use common::{eval, eval_err};
+use my_lua::vm::ExeState;
+
+// 常量左操作数与会占用临时寄存器的右操作数
+#[test]
+fn constant_op_global_field() {
+    assert_eq!(eval("g = {x = 5}; return 1 + g.x, 10 * g.x, 2 ^ g.x, 'a' .. g.x, 1 < g.x"), "6 50 32.0 a5 true");
+}
+
+#[test]
+fn constant_op_nested_field() {
+    assert_eq!(eval("local l = {y = {z = 7}}; return 2 * l.y.z, 1 + l.y.z, 8 // l.y.z"), "14 8 1");
+}
+
+#[test]
+fn constant_op_indexed_global() {
+    assert_eq!(eval("t = {3, 4}; local r = {} for i = 1, 2 do r[i] = tostring(10 * t[i]) end return r[1], r[2], 10 * t[1]"), "30 40 30");
+}
+
+#[test]
+fn constant_op_upvalue_field() {
+    assert_eq!(eval("local up = {v = 3}; local function f() return 4 - up.v, 1 .. up.v end return f()"), "1 13");
+}
+
+#[test]
+fn constant_op_in_lists() {
+    assert_eq!(eval("g = {x = 5}; local a, b = 1 + g.x, 2 + g.x; return a, b, math.max(1 + g.x, 2), ({1 + g.x})[1]"), "6 7 6 6");
+}
+
+#[test]
+fn integer_division_by_zero() {
+    assert_eq!(eval_err("local z = 0; return 1 % z"), "test:1: attempt to perform 'n%0'");
+    assert_eq!(eval_err("local z = 0; return 1 // z"), "test:1: attempt to perform 'n//0'");
+    assert_eq!(eval("local z = 0; return 1 // 0.0, -1 // (z + 0.0)"), "inf -inf");
+}
+
+#[test]
+fn lexer_literals() {
+    assert_eq!(eval("return 0x10, 0xA.8p1, 1e2, .5, 3., 0xffffffffffffffff, 9223372036854775808"),
+        "16 21.0 100.0 0.5 3.0 -1 9.2233720368548e+18");
+    assert_eq!(eval(r#"return "\65\x42\u{43}\z
+        D", #"\0\n", [==[a]]b]==]"#), "ABCD 2 a]]b");
+    assert_eq!(eval("--[[ long\ncomment ]] return 1 -- tail"), "1");
+}
+
+#[test]
+fn control_flow() {
+    assert_eq!(eval("local s = 0 for i = 10, 1, -3 do s = s * 10 + i end return s"), "10741");
+    assert_eq!(eval("local n = 0 for i = 1.0, 2.0, 0.5 do n = n + i end return n"), "4.5");
+    assert_eq!(eval("local i = 0 while true do i = i + 1 if i == 3 then break end end return i"), "3");
+    assert_eq!(eval("local i = 5 repeat local j = i i = i - 1 until j <= 2 return i"), "1");
+    assert_eq!(eval("local s = '' for i = 1, 3 do if i == 2 then goto next end s = s .. i ::next:: end return s"), "13");
+    assert_eq!(eval("local t = {} for k, v in ipairs({'a', 'b'}) do t[#t + 1] = k .. v end return table.concat(t)"), "1a2b");
+    assert_eq!(eval("if nil then return 1 elseif false then return 2 else return 3 end"), "3");
+}
+
+#[test]
+fn closures_and_upvalues() {
+    assert_eq!(eval("local function c() local n = 0 return function() n = n + 1 return n end end
+        local a, b = c(), c() a() return a(), b()"), "2 1");
+    assert_eq!(eval("local fs = {} for i = 1, 3 do fs[i] = function() return i end end return fs[1](), fs[3]()"), "1 3");
+    assert_eq!(eval("local x = 1 local function set() x = 2 end set() return x"), "2");
+    assert_eq!(eval("local function f(n) if n == 0 then return 'done' end return f(n - 1) end return f(100000)"), "done");
+}
+
+#[test]
+fn varargs_and_multiple_results() {
+    assert_eq!(eval("local function f(...) return select('#', ...), ... end return f(1, nil, 3)"), "3 1 nil 3");
+    assert_eq!(eval("local function f() return 1, 2 end return f(), f()"), "1 1 2");
+    assert_eq!(eval("local function f() return 1, 2 end return (f())"), "1");
+    assert_eq!(eval("local function f() return 1, 2, 3 end local t = {f(), f()} return #t"), "4");
+    assert_eq!(eval("local a, b, c = (function() return 1 end)() return a, b, c"), "1 nil nil");
+}
+
+#[test]
+fn integer_and_float_arithmetic() {
+    assert_eq!(eval("return 7 // 2, 7.0 // 2, -7 // 2, 7 % -3, -7 % 3, 5.5 % 2, 3 / 2"), "3 3.0 -4 -2 2 1.5 1.5");
+    assert_eq!(eval("return 3 | 5, 3 & 5, 3 ~ 5, ~0, 1 << 64, -1 >> 60, '3' | 0"), "7 1 6 -1 0 15 3");
+    assert_eq!(eval("return math.maxinteger + 1 == math.mininteger, 1 == 1.0, 2^53, '10' + 1, 10 .. 20"),
+        "true true 9.007199254741e+15 11 1020");
+}
+
+#[test]
+fn metamethods() {
+    assert_eq!(eval("local V = {} V.__index = V
+        V.__add = function(a, b) return setmetatable({x = a.x + b.x}, V) end
+        V.__eq = function(a, b) return a.x == b.x end
+        V.__lt = function(a, b) return a.x < b.x end
+        V.__len = function() return 42 end
+        V.__concat = function(a, b) return 'cat' end
+        V.__call = function(self, y) return self.x + y end
+        local a, b = setmetatable({x = 1}, V), setmetatable({x = 2}, V)
+        return (a + b).x, a == setmetatable({x = 1}, V), a < b, #a, a .. 'x', a(10)"), "3 true true 42 cat 11");
+    assert_eq!(eval("local log = {}
+        local p = setmetatable({}, {__index = function(t, k) return k .. '!' end,
+            __newindex = function(t, k, v) log[#log + 1] = k rawset(t, k, v) end})
+        p.a = 1 p.a = 2 return p.b, p.a, #log"), "b! 2 1");
+}
+
+#[test]
+fn syntax_errors() {
+    let error = |code: &str| {
+        let mut state = ExeState::new();
+        match state.load_bytes(code.as_bytes(), "=test", None) {
+            Ok(_) => panic!("compiled: {}", code),
+            Err(e) => e.to_string(),
+        }
+    };
+    assert_eq!(error("x = = 1"), "test:1: unexpected symbol near '='");
+    assert_eq!(error("for i = 1 do end"), "test:1: ',' expected near 'do'");
+    assert_eq!(error("goto nowhere"), "test:1: no visible label 'nowhere' for <goto> at line 1");
+    assert_eq!(error("local x <const> = 1; x = 2"), "test:1: attempt to assign to const variable 'x'");
+    assert_eq!(error("return 'unfinished"), "test:1: unfinished string near <eof>");
+    assert_eq!(error("return 'a\nb'"), "test:1: unfinished string near ''a'");
+}
+
+#[test]
+fn load_and_dofile() {
+    assert_eq!(eval("return load('return 1 + ...')(2)"), "3");
+    assert_eq!(eval("local f, err = load('return +') return f, err"), "nil [string \"return +\"]:1: unexpected symbol near '+'");
+    assert_eq!(eval("local parts = {'return ', '4', '2'} local i = 0
+        return load(function() i = i + 1 return parts[i] end)()"), "42");
+    assert_eq!(eval("local env = {y = 5} return load('x = 1 return y', 'chunk', 't', env)(), env.x, x"), "5 1 nil");
+    assert_eq!(eval("return pcall(load, 'return 1', 'chunk', 'b')"), "true nil attempt to load a text chunk (mode is 'b')");
+
+    let path = std::env::temp_dir().join(format!("mylua_dofile_{}.lua", std::process::id()));
+    std::fs::write(&path, "local a = ... return (a or 0) + 1, 'file'").unwrap();
+    let path = path.to_str().unwrap().to_string();
+    assert_eq!(eval(&format!("return dofile({:?})", path)), "1 file");
+    assert_eq!(eval(&format!("return loadfile({:?})(41)", path)), "42 file");
+    std::fs::remove_file(&path).unwrap();
+    assert!(eval("return loadfile('/nonexistent/file.lua')").starts_with("nil cannot open /nonexistent/file.lua: "));
+}
+
+// 全局变量即_ENV的字段，_ENV可以是局部变量、参数或load指定的环境
+#[test]
+fn env_variable() {
+    assert_eq!(eval("do local _ENV = {x = 1} y = x + 1 return y, _ENV.y end"), "2 2");
+    assert_eq!(eval("do local _ENV = {} y = 1 end return y"), "nil");
+    assert_eq!(eval("local function f(_ENV) return a + b end return f({a = 1, b = 2})"), "3");
+    assert_eq!(eval("local function mk(_ENV) return function() w = 'set' return v end end
+        local e = {v = 'inner'} return mk(e)(), e.w, w"), "inner set nil");
+    assert_eq!(eval_err("local _ENV = nil return x"), "test:1: attempt to index a nil value");
+
+    assert_eq!(eval("local env = {} local f = load('a = 1 return _ENV', 'c', 't', env) return f() == env, env.a, a"), "true 1 nil");
+    // 显式传入nil时环境为nil，省略时为全局表
+    assert_eq!(eval("return pcall(load('return x', 'c', 't', nil))"), "false [string \"c\"]:1: attempt to index a nil value");
+    assert_eq!(eval("return load('return print', 'c', 't')() == print"), "true");
+
+    // 以_G的元表禁止访问未声明的全局变量
+    let strict = "setmetatable(_G, {__newindex = function(t, k) error('assign to undeclared global ' .. k, 2) end,
+            __index = function(_, k) error('undeclared global ' .. k, 2) end})
+        rawset(_G, 'declared', 1) declared = 2\n";
+    assert_eq!(eval(&format!("{}return pcall(function() return undeclared end)", strict)), "false test:4: undeclared global undeclared");
+    assert_eq!(eval(&format!("{}return pcall(function() newvar = 1 end)", strict)), "false test:4: assign to undeclared global newvar");
+    assert_eq!(eval(&format!("{}return declared, rawget(_G, 'newvar')", strict)), "2 nil");
+}
+
+// 出错位置按调用栈的层数计算，Rust函数也算一层但没有位置
+#[test]
+fn error_positions() {
+    assert_eq!(eval("return pcall(error, 'msg')"), "false msg");
+    assert_eq!(eval("return pcall(assert, false)"), "false assertion failed!");
+    assert_eq!(eval("return pcall(table.concat, {{}})"), "false invalid value (at index 1) in table for 'concat'");
+    assert_eq!(eval_err("local function f() error('up', 2) end\nf()"), "test:2: up");
+    assert_eq!(eval_err("table.sort({1, 2}, function() error('in sort', 2) end)"), "in sort");
+    // 方法调用时参数序号不计self
+    assert_eq!(eval_err("return ('x'):rep({})"), "test:1: bad argument #1 to 'rep' (number expected, got table)");
+    assert_eq!(eval_err("return string.rep('x', {})"), "test:1: bad argument #2 to 'rep' (number expected, got table)");
+    assert_eq!(eval_err("return setmetatable({}, {__index = string}):rep(2)"),
+        "test:1: calling 'rep' on bad self (string expected, got table)");
+}
+
+// xpcall的消息处理函数在出错处调用，能看到出错的各帧
+#[test]
+fn xpcall_handler_at_raise_point() {
+    let traceback = eval("local function f() error('boom') end return select(2, xpcall(f, debug.traceback))");
+    assert!(traceback.starts_with("test:1: boom\nstack traceback:\n\t[C]: in function 'error'\n\ttest:1: in "), "{}", traceback);
+    assert_eq!(eval("return xpcall(function() error('x') end, function() return debug.getinfo(2, 'n').name end)"), "false error");
+    assert_eq!(eval("return xpcall(function() local t = nil return t.x end, function(m) return 'h: ' .. m end)"),
+        "false h: test:1: attempt to index a nil value");
+    // 内层pcall捕获的错误不经过外层的处理函数
+    assert_eq!(eval("return xpcall(function() return pcall(error, 'in', 0) end, function() return 'outer' end)"), "true false in");
+    assert_eq!(eval("return pcall(xpcall, error, function(m) return 'h: ' .. m end, 'e', 0)"), "true false h: e");
+    assert_eq!(eval("local co = coroutine.wrap(function()
+            return xpcall(function() coroutine.yield(1) error('late', 0) end, function(m) return 'h: ' .. m end)
+        end) co() return co()"), "false h: late");
+    assert_eq!(eval("return xpcall(function() local t <close> = setmetatable({}, {__close = function() end}) error('c', 0) end,
+        function(m) return 'h: ' .. m end)"), "false h: c");
+}
+
+// 挂起的协程的第0层是让出的Rust函数
+#[test]
+fn suspended_coroutine_levels() {
+    let setup = "local co = coroutine.create(function(a, b) local x = a + b coroutine.yield(x) return x end)
+        coroutine.resume(co, 1, 2) ";
+    assert_eq!(eval(&format!("{} local i = debug.getinfo(co, 0, 'Sn') return i.what, i.name, i.short_src", setup)), "C yield [C]");
+    assert_eq!(eval(&format!("{} local i = debug.getinfo(co, 1, 'Sl') return i.what, i.currentline", setup)), "Lua 1");
+    assert_eq!(eval(&format!("{} return debug.getlocal(co, 1, 1), debug.getlocal(co, 1, 3), debug.getinfo(co, 2)", setup)), "a x nil");
+    assert_eq!(eval(&format!("{} debug.setlocal(co, 1, 3, 10) return coroutine.resume(co)", setup)), "true 10");
+    assert_eq!(eval(&format!("{} return debug.traceback(co)", setup)),
+        "stack traceback:\n\t[C]: in function 'coroutine.yield'\n\ttest:1: in function <test:1>");
+    assert_eq!(eval(&format!("{} return debug.traceback(co, 'msg', 1)", setup)), "msg\nstack traceback:\n\ttest:1: in function <test:1>");
+    // 被挂起的pcall也在调用栈中；未开始的协程没有调用栈
+    assert_eq!(eval("local co = coroutine.create(function() pcall(coroutine.yield) end) coroutine.resume(co)
+        return debug.traceback(co)"),
+        "stack traceback:\n\t[C]: in function 'coroutine.yield'\n\t[C]: in function 'pcall'\n\ttest:1: in function <test:1>");
+    assert_eq!(eval("return debug.traceback(coroutine.create(print)), debug.getinfo(coroutine.create(print), 0)"), "stack traceback: nil");
+}
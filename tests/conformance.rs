@@ -0,0 +1,202 @@
+// 一致性测试：运行test_lua和tests/golden中自己编写的脚本，与同名的黄金文件比较：
+// .out为标准输出，.err为标准错误（没有时应为空），.status为退出码（没有时应为0）。
+// 设置环境变量UPDATE_GOLDEN=1时改为按实际结果重写黄金文件，重写后要按Lua 5.4的行为逐行核对。
+// 已知与lua.c的不同：未捕获错误的调用栈最后没有"[C]: in ?"一行（脚本不是从C函数pmain中调用的）。
+// tests/lua-5.4-tests中收录官方测试集的文件，passing.txt中列出的必须通过
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MYLUA: &str = env!("CARGO_BIN_EXE_myLua");
+const ROOT: &str = env!("CARGO_MANIFEST_DIR");
+
+// 每个脚本的运行时间上限
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+// 官方测试集的全局开关：跳过不可移植的和耗时长的测试
+const LUA_TESTS_PRELUDE: &str = "_port = true; _soft = true";
+
+struct Output {
+    stdout: String,
+    stderr: String,
+    status: i32,
+}
+
+// 在脚本所在目录运行，使错误信息中的chunk名与位置无关
+fn run(dir: &Path, args: &[&str]) -> Output {
+    let mut child = Command::new(MYLUA)
+        .args(args)
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("cannot run myLua");
+    let read = |mut pipe: Box<dyn Read + Send>| thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = pipe.read_to_end(&mut buf);
+        buf
+    });
+    let stdout = read(Box::new(child.stdout.take().unwrap()));
+    let stderr = read(Box::new(child.stderr.take().unwrap()));
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status.code().unwrap_or(-1);
+        }
+        if start.elapsed() > TIMEOUT {
+            let _ = child.kill();
+            let _ = child.wait();
+            break -1;
+        }
+        thread::sleep(Duration::from_millis(5));
+    };
+    let text = |h: thread::JoinHandle<Vec<u8>>| normalize(&String::from_utf8_lossy(&h.join().unwrap()));
+    Output { stdout: text(stdout), stderr: text(stderr), status }
+}
+
+// 去掉每次运行都不同的部分：程序路径和对象地址
+fn normalize(s: &str) -> String {
+    let s = s.replace(MYLUA, "myLua");
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s.as_str();
+    while let Some(i) = rest.find("0x") {
+        let after_type = rest[..i].ends_with(": ");
+        out.push_str(&rest[..i + 2]);
+        rest = &rest[i + 2..];
+        let digits = rest.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(rest.len());
+        // 只替换tostring输出的"类型: 0x..."中的地址，其他十六进制数（如%q的输出）保留
+        if after_type && digits >= 8 {
+            out.push_str("ADDR");
+        } else {
+            out.push_str(&rest[..digits]);
+        }
+        rest = &rest[digits..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// 第一处不同的行
+fn diff(expected: &str, actual: &str) -> String {
+    let (mut e, mut a) = (expected.lines(), actual.lines());
+    for n in 1.. {
+        match (e.next(), a.next()) {
+            (None, None) => break,
+            (el, al) if el == al => continue,
+            (el, al) => return format!("line {}:\n  expected: {}\n  actual:   {}", n, el.unwrap_or("<eof>"), al.unwrap_or("<eof>")),
+        }
+    }
+    "trailing newline differs".to_string()
+}
+
+fn lua_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("cannot read {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|p| p.extension().is_some_and(|e| e == "lua"))
+        .collect();
+    files.sort();
+    files
+}
+
+// 比较或重写一个脚本的黄金文件，返回不同之处
+fn check_golden(script: &Path, update: bool) -> Vec<String> {
+    let dir = script.parent().unwrap();
+    let name = script.file_name().unwrap().to_str().unwrap();
+    let output = run(dir, &[name]);
+    let status = if output.status == 0 { String::new() } else { format!("{}\n", output.status) };
+    let parts = [("out", &output.stdout), ("err", &output.stderr), ("status", &status)];
+
+    let mut failures = Vec::new();
+    for (ext, actual) in parts {
+        let golden = script.with_extension(ext);
+        if update {
+            if actual.is_empty() && ext != "out" {
+                let _ = fs::remove_file(&golden);
+            } else {
+                fs::write(&golden, actual).unwrap();
+            }
+            continue;
+        }
+        let expected = match fs::read_to_string(&golden) {
+            Ok(expected) => expected,
+            Err(_) if ext != "out" => String::new(),
+            Err(e) => {
+                failures.push(format!("{}: cannot read {}: {}", name, golden.display(), e));
+                continue;
+            }
+        };
+        if &expected != actual {
+            failures.push(format!("{}: {} differs at {}", name, ext, diff(&expected, actual)));
+        }
+    }
+    failures
+}
+
+#[test]
+fn golden() {
+    let update = std::env::var_os("UPDATE_GOLDEN").is_some();
+    let mut failures = Vec::new();
+    let mut count = 0;
+    for dir in ["test_lua", "tests/golden"] {
+        // *_test.lua由`myLua test`运行
+        for script in lua_files(&Path::new(ROOT).join(dir)) {
+            if !script.to_str().unwrap().ends_with("_test.lua") {
+                failures.extend(check_golden(&script, update));
+                count += 1;
+            }
+        }
+    }
+    assert!(count > 0, "no golden scripts found");
+    assert!(failures.is_empty(), "{} of {} golden scripts failed:\n{}", failures.len(), count, failures.join("\n"));
+}
+
+#[test]
+fn test_subcommand() {
+    let output = run(Path::new(ROOT), &["test", "test_lua"]);
+    assert_eq!(output.status, 0, "myLua test failed:\n{}{}", output.stdout, output.stderr);
+}
+
+// passing.txt每行一个文件名，#开始注释
+fn pass_list(dir: &Path) -> Vec<String> {
+    fs::read_to_string(dir.join("passing.txt"))
+        .expect("cannot read passing.txt")
+        .lines()
+        .map(|l| l.split('#').next().unwrap().trim())
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn lua_test_suite() {
+    let dir = Path::new(ROOT).join("tests/lua-5.4-tests");
+    let passing = pass_list(&dir);
+    let scripts = lua_files(&dir);
+    if scripts.is_empty() {
+        eprintln!("no official Lua test files in {}; see passing.txt", dir.display());
+    }
+    let mut failures = Vec::new();
+    for script in scripts {
+        let name = script.file_name().unwrap().to_str().unwrap();
+        let listed = passing.iter().any(|p| p == name);
+        let output = run(&dir, &["-e", LUA_TESTS_PRELUDE, name]);
+        match (output.status == 0, listed) {
+            (false, true) => failures.push(format!("{}: exit status {}\n{}", name, output.status, output.stderr.trim_end())),
+            // 新通过的文件不算失败，提示加入列表
+            (true, false) => eprintln!("{} now passes; add it to passing.txt", name),
+            _ => (),
+        }
+    }
+    for name in &passing {
+        if !dir.join(name).exists() {
+            failures.push(format!("{}: listed in passing.txt but not vendored", name));
+        }
+    }
+    assert!(failures.is_empty(), "official Lua tests regressed:\n{}", failures.join("\n"));
+}
@@ -0,0 +1,80 @@
+// --coverage：两次运行的结果合并到同一个lcov.info
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+const MYLUA: &str = env!("CARGO_BIN_EXE_myLua");
+
+const SCRIPT: &str = "local helper = require('helper')
+local function sign(x)
+  if x > 0 then
+    return 'pos'
+  end
+  return 'neg'
+end
+for i = 1, 3 do sign(i) end
+sign(-1)
+helper.twice()
+";
+
+const HELPER: &str = "local M = {}
+function M.twice()
+  return 2
+end
+function M.never()
+  return 0
+end
+return M
+";
+
+// 每个文件的DA行：行号到执行次数
+fn line_counts(lcov: &str) -> BTreeMap<String, BTreeMap<u32, u64>> {
+    let mut files = BTreeMap::new();
+    let mut current = None;
+    for line in lcov.lines() {
+        if let Some(path) = line.strip_prefix("SF:") {
+            let name = Path::new(path).file_name().unwrap().to_str().unwrap().to_string();
+            current = Some(files.entry(name).or_insert_with(BTreeMap::new));
+        } else if let (Some(da), Some(cov)) = (line.strip_prefix("DA:"), current.as_mut()) {
+            let (line, count) = da.split_once(',').unwrap();
+            cov.insert(line.parse().unwrap(), count.parse().unwrap());
+        }
+    }
+    files
+}
+
+fn run_with_coverage(dir: &Path) -> String {
+    let status = Command::new(MYLUA).args(["--coverage", "main.lua"]).current_dir(dir).status().unwrap();
+    assert!(status.success());
+    fs::read_to_string(dir.join("lcov.info")).unwrap()
+}
+
+#[test]
+fn merge_runs() {
+    let dir = std::env::temp_dir().join(format!("mylua_coverage_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.lua"), SCRIPT).unwrap();
+    fs::write(dir.join("helper.lua"), HELPER).unwrap();
+
+    let first = line_counts(&run_with_coverage(&dir));
+    let main = &first["main.lua"];
+    assert_eq!((main[&4], main[&6], main[&9]), (3, 1, 1), "{:?}", main);
+    // require加载的文件也统计，没有执行的函数记为0
+    let helper = &first["helper.lua"];
+    assert_eq!((helper[&3], helper[&6]), (1, 0), "{:?}", helper);
+
+    let lcov = run_with_coverage(&dir);
+    let second = line_counts(&lcov);
+    assert_eq!(second.keys().collect::<Vec<_>>(), ["helper.lua", "main.lua"]);
+    for (file, lines) in &first {
+        let doubled: BTreeMap<u32, u64> = lines.iter().map(|(&line, &n)| (line, n * 2)).collect();
+        assert_eq!(second[file], doubled, "{}", file);
+    }
+    // 分支计数同样合并：if的两个去向分别执行了2次和6次
+    let branches: Vec<&str> = lcov.lines().filter(|l| l.starts_with("BRDA:3,")).collect();
+    assert_eq!(branches, ["BRDA:3,0,0,2", "BRDA:3,0,1,6"]);
+    assert!(fs::read_to_string(dir.join("coverage.txt")).unwrap().contains("main.lua"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
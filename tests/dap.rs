@@ -0,0 +1,119 @@
+// 调试适配器：以脚本化的请求序列驱动`myLua --dap`，检查响应和事件
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const MYLUA: &str = env!("CARGO_BIN_EXE_myLua");
+
+const SCRIPT: &str = "local function f(a)
+  local x = a * 2
+  print('x', x)
+  return x
+end
+print(f(21))
+";
+
+// 一次把全部请求写入标准输入，返回输出中的各条消息
+fn session(requests: &[String]) -> Vec<String> {
+    let mut child = Command::new(MYLUA)
+        .arg("--dap")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("cannot run myLua");
+    let mut input = child.stdin.take().unwrap();
+    for (seq, body) in requests.iter().enumerate() {
+        let msg = format!("{{\"seq\":{},\"type\":\"request\",{}}}", seq + 1, body);
+        write!(input, "Content-Length: {}\r\n\r\n{}", msg.len(), msg).unwrap();
+    }
+    drop(input);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let out = String::from_utf8(output.stdout).unwrap();
+    let mut messages = Vec::new();
+    let mut rest = out.as_str();
+    while let Some(i) = rest.find("\r\n\r\n") {
+        let len: usize = rest[..i].trim_start_matches("Content-Length: ").parse().unwrap();
+        messages.push(rest[i + 4..i + 4 + len].to_string());
+        rest = &rest[i + 4 + len..];
+    }
+    messages
+}
+
+fn request(command: &str, arguments: &str) -> String {
+    format!("\"command\":\"{}\",\"arguments\":{}", command, arguments)
+}
+
+// 第n个（从0开始）对command的响应
+fn response<'a>(messages: &'a [String], command: &str, n: usize) -> &'a str {
+    let cmd = format!("\"command\":\"{}\"", command);
+    messages.iter().filter(|m| m.contains("\"type\":\"response\"") && m.contains(&cmd)).nth(n)
+        .unwrap_or_else(|| panic!("no response {} to {}:\n{}", n, command, messages.join("\n")))
+}
+
+fn events<'a>(messages: &'a [String], event: &str) -> Vec<&'a str> {
+    let tag = format!("\"event\":\"{}\"", event);
+    messages.iter().filter(|m| m.contains(&tag)).map(String::as_str).collect()
+}
+
+#[test]
+fn scripted_session() {
+    let path = std::env::temp_dir().join(format!("mylua_dap_{}.lua", std::process::id()));
+    std::fs::write(&path, SCRIPT).unwrap();
+    let program = format!("{:?}", path.to_str().unwrap());
+    // 断点之后的请求在程序运行时就已到达，应留到停下时处理
+    let messages = session(&[
+        request("initialize", "{\"adapterID\":\"mylua\"}"),
+        request("launch", &format!("{{\"program\":{}}}", program)),
+        request("setBreakpoints", &format!("{{\"source\":{{\"path\":{}}},\"breakpoints\":[{{\"line\":3}}]}}", program)),
+        request("configurationDone", "{}"),
+        request("threads", "{}"),
+        request("stackTrace", "{\"threadId\":1}"),
+        request("scopes", "{\"frameId\":1}"),
+        request("variables", "{\"variablesReference\":1}"),
+        request("evaluate", "{\"expression\":\"x + 1\",\"frameId\":1}"),
+        request("next", "{\"threadId\":1}"),
+        request("stackTrace", "{\"threadId\":1,\"levels\":1}"),
+        request("continue", "{\"threadId\":1}"),
+        request("disconnect", "{}"),
+    ]);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(messages.iter().all(|m| !m.contains("\"success\":false")), "{}", messages.join("\n"));
+    let stopped = events(&messages, "stopped");
+    assert_eq!(stopped.len(), 2, "{}", messages.join("\n"));
+    assert!(stopped[0].contains("\"reason\":\"breakpoint\""), "{}", stopped[0]);
+    assert!(stopped[1].contains("\"reason\":\"step\""), "{}", stopped[1]);
+
+    let trace = response(&messages, "stackTrace", 0);
+    assert!(trace.contains("\"name\":\"f\",\"line\":3"), "{}", trace);
+    assert!(trace.contains("\"name\":\"main chunk\",\"line\":6"), "{}", trace);
+    let vars = response(&messages, "variables", 0);
+    assert!(vars.contains("\"name\":\"a\",\"value\":\"21\"") && vars.contains("\"name\":\"x\",\"value\":\"42\""), "{}", vars);
+    assert!(response(&messages, "evaluate", 0).contains("\"result\":\"43\""));
+    assert!(response(&messages, "stackTrace", 1).contains("\"name\":\"f\",\"line\":4"));
+    response(&messages, "continue", 0);
+    response(&messages, "disconnect", 0);
+
+    // 管道中的输出可能合并成一个事件
+    let output: String = events(&messages, "output").concat();
+    assert!(output.contains("x\\t42\\n") && output.matches("42\\n").count() == 2, "{}", output);
+    let exited = events(&messages, "exited");
+    assert!(exited.len() == 1 && exited[0].contains("\"exitCode\":0"), "{}", messages.join("\n"));
+}
+
+#[test]
+fn terminate_while_running() {
+    let path = std::env::temp_dir().join(format!("mylua_dap_loop_{}.lua", std::process::id()));
+    std::fs::write(&path, "while true do end\n").unwrap();
+    let program = format!("{:?}", path.to_str().unwrap());
+    let messages = session(&[
+        request("initialize", "{}"),
+        request("launch", &format!("{{\"program\":{}}}", program)),
+        request("configurationDone", "{}"),
+        request("terminate", "{}"),
+    ]);
+    std::fs::remove_file(&path).unwrap();
+    response(&messages, "terminate", 0);
+    assert!(events(&messages, "exited").is_empty(), "{}", messages.join("\n"));
+}
@@ -0,0 +1,123 @@
+// 二进制chunk的保存和加载
+use my_lua::bytecode::ByteCode;
+use my_lua::dump::{dump, undump};
+use my_lua::lex::Lex;
+use my_lua::parse::ParseProto;
+use my_lua::vm::ExeState;
+
+const CODE: &str = "
+local function fib(n) if n < 2 then return n end return fib(n - 1) + fib(n - 2) end
+local t = {1.5, 'x', true, nil, 2^62, -7}
+local up = 0
+local function inc() up = up + 1 return up end
+inc()
+return fib(10), t[1], t[2], t[3], t[4], t[5], t[6], inc(), select('#', ...)
+";
+
+fn compile(code: &str) -> ParseProto {
+    let mut proto = ParseProto::new(Lex::from_bytes(code.as_bytes(), "=test"));
+    proto.compile().unwrap();
+    proto
+}
+
+fn run(proto: ParseProto) -> String {
+    let mut state = ExeState::new();
+    match state.run(proto) {
+        Ok(values) => values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" "),
+        Err(e) => format!("error: {}", e),
+    }
+}
+
+fn undump_err(data: &[u8]) -> String {
+    match undump(data, "=test") {
+        Ok(_) => panic!("loaded a bad chunk"),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn round_trip() {
+    let expected = run(compile(CODE));
+    assert_eq!(expected, "55 1.5 x true nil 4.6116860184274e+18 -7 2 0");
+    let data = dump(&compile(CODE), false);
+    let loaded = undump(&data, "=test").unwrap();
+    assert_eq!(loaded.source, "=test");
+    assert_eq!(loaded.listing(), compile(CODE).listing());
+    assert_eq!(dump(&loaded, false), data);
+    assert_eq!(run(loaded), expected);
+}
+
+#[test]
+fn round_trip_stripped() {
+    let data = dump(&compile(CODE), true);
+    assert!(data.len() < dump(&compile(CODE), false).len());
+    let loaded = undump(&data, "=test").unwrap();
+    assert!(loaded.lineinfo.is_empty() && loaded.locals_info.is_empty());
+    assert!(loaded.protos.iter().all(|p| p.lineinfo.is_empty() && p.locals_info.is_empty()));
+    assert_eq!(dump(&loaded, true), data);
+    assert_eq!(run(loaded), run(compile(CODE)));
+
+    // 没有行号信息时错误信息不带位置
+    let loaded = undump(&dump(&compile("error('boom')"), true), "=test").unwrap();
+    assert_eq!(run(loaded), "error: boom");
+}
+
+#[test]
+fn bad_header() {
+    let data = dump(&compile("return 1"), false);
+    let corrupt = |pos: usize| {
+        let mut bad = data.clone();
+        bad[pos] ^= 0xff;
+        undump_err(&bad)
+    };
+    assert_eq!(corrupt(0), "test: bad binary format (not a binary chunk)");
+    assert_eq!(corrupt(4), "test: bad binary format (version mismatch)");
+    assert_eq!(corrupt(5), "test: bad binary format (format mismatch)");
+    assert_eq!(corrupt(6), "test: bad binary format (corrupted chunk)");
+    assert_eq!(corrupt(12), "test: bad binary format (integer size mismatch)");
+    assert_eq!(corrupt(13), "test: bad binary format (float size mismatch)");
+    assert_eq!(corrupt(14), "test: bad binary format (integer format mismatch)");
+    assert_eq!(corrupt(22), "test: bad binary format (float format mismatch)");
+
+    let mut extra = data.clone();
+    extra.push(0);
+    assert_eq!(undump_err(&extra), "test: bad binary format (extra bytes after chunk)");
+}
+
+#[test]
+fn truncated() {
+    for strip in [false, true] {
+        let data = dump(&compile(CODE), strip);
+        for len in 0..data.len() {
+            let msg = undump_err(&data[..len]);
+            assert!(msg.starts_with("test: bad binary format ("), "{} bytes: {}", len, msg);
+        }
+    }
+}
+
+#[test]
+fn verify_rejects_bad_operands() {
+    let reject = |code: &str, edit: &dyn Fn(&mut ParseProto)| {
+        let mut proto = compile(code);
+        edit(&mut proto);
+        undump_err(&dump(&proto, false))
+    };
+    let bad = |why: &str| format!("test: bad binary format ({})", why);
+
+    assert_eq!(reject("local a = 1", &|p| p.instructions[0] = ByteCode::Move(200, 0)),
+        bad("register out of range at instruction 1"));
+    assert_eq!(reject("local a = 1", &|p| p.instructions[0] = ByteCode::LoadConstant(0, 99)),
+        bad("invalid constant at instruction 1"));
+    assert_eq!(reject("local a = 1", &|p| p.instructions[0] = ByteCode::GetUpvalue(0, 5)),
+        bad("invalid upvalue at instruction 1"));
+    assert_eq!(reject("local a = 1", &|p| p.instructions[0] = ByteCode::Closure(0, 3)),
+        bad("invalid function index at instruction 1"));
+    assert_eq!(reject("local a = 1", &|p| p.instructions[0] = ByteCode::Jump(100)),
+        bad("invalid jump at instruction 1"));
+    assert_eq!(reject("local a = 1", &|p| p.instructions[0] = ByteCode::Jump(-2)),
+        bad("invalid jump at instruction 1"));
+    assert_eq!(reject("local a = 1", &|p| { p.instructions.pop(); p.lineinfo.pop(); }),
+        bad("function does not end with a return"));
+    assert_eq!(reject("local a = 1", &|p| p.max_stack = 300), bad("invalid stack size"));
+    assert_eq!(reject("local a = 1", &|p| { p.lineinfo.pop(); }), bad("invalid line information"));
+}
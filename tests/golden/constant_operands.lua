@@ -0,0 +1,16 @@
+-- 常量左操作数与读取表字段的右操作数：右操作数的临时寄存器不能被常量覆盖
+g = {x = 5, y = {z = 7}}
+t = {3, 4}
+print(1 + g.x, 10 * g.x, 2 ^ g.x, 20 // g.x, 7 % g.x, 1 < g.x, "a" .. g.x)
+print(2 * g.y.z, 1 + g.y.z, 100 - g.y.z)
+for i = 1, 2 do
+  print(i, 10 * t[i], tostring(1 + t[i]))
+end
+local a, b = 1 + g.x, 2 + g.x
+print(a, b, math.max(1 + g.x, 2), ({1 + g.x, 2 * g.x})[2], #{1 + g.x})
+local up = {v = 3}
+local function f() return 4 - up.v, 1 .. up.v, 2 * up.v end
+print(f())
+local function args(...) return select("#", ...), ... end
+print(args(1 + g.x, 1 + g.y.z, 10 * t[2]))
+print(string.format("%d %s", 1 + g.x, "b" .. g.y.z))
@@ -0,0 +1,74 @@
+-- 协程
+local co = coroutine.create(function(a, b)
+  print("start", a, b)
+  local c = coroutine.yield(a + b)
+  print("got", c)
+  local d, e = coroutine.yield(c * 2)
+  return d + e
+end)
+print(coroutine.status(co))
+print(coroutine.resume(co, 1, 2))
+print(coroutine.status(co))
+print(coroutine.resume(co, 10))
+print(coroutine.resume(co, 3, 4))
+print(coroutine.status(co), coroutine.resume(co))
+
+local function gen(n)
+  return coroutine.wrap(function() for i = 1, n do coroutine.yield(i) end end)
+end
+local out = {}
+for v in gen(5) do out[#out + 1] = v end
+print(table.concat(out, " "))
+
+local bad = coroutine.create(function() error("oops", 0) end)
+print(coroutine.resume(bad))
+print(coroutine.status(bad))
+
+print(coroutine.isyieldable(), coroutine.running())
+local inner = coroutine.create(function()
+  local _, main = coroutine.running()
+  print(coroutine.isyieldable(), main)
+  coroutine.yield()
+end)
+coroutine.resume(inner)
+
+-- 嵌套协程和pcall中的yield
+local outer = coroutine.wrap(function()
+  local c = coroutine.wrap(function() coroutine.yield("inner") end)
+  coroutine.yield(c())
+  local ok, v = pcall(function() return coroutine.yield("in pcall") + 1 end)
+  coroutine.yield(tostring(ok) .. " " .. v)
+end)
+print(outer(), outer(), outer(41))
+
+local wrapped = coroutine.wrap(function() error("wrapped error", 0) end)
+print(pcall(function() return wrapped() end))
+local dead = coroutine.create(function() end)
+coroutine.resume(dead)
+print(coroutine.resume(dead))
+print(coroutine.close(coroutine.create(print)))
+
+-- 元方法中的yield
+local lazy = setmetatable({}, {__index = function(_, k) return coroutine.yield(k) end})
+print(coroutine.wrap(function() return lazy.foo end)())
+local get = coroutine.wrap(function() local v = lazy.foo; return "got", v end)
+print(get(), get(42))
+local store = setmetatable({}, {__newindex = function(t, k, v) coroutine.yield("set " .. k); rawset(t, k, v * 2) end})
+local set = coroutine.wrap(function() store.x = 5; return store.x end)
+print(set(), set())
+local yielding = {}
+for _, e in ipairs({"__add", "__lt", "__le", "__concat", "__len"}) do
+  yielding[e] = function() return coroutine.yield(e) end
+end
+local a, b = setmetatable({}, yielding), setmetatable({}, yielding)
+local ops = coroutine.wrap(function()
+  local s = a + 1
+  local lt, le = a < b, a <= b
+  return s, lt, le, a .. "x", #a
+end)
+print(ops(), ops(10), ops(nil), ops(1), ops("ax"), ops(3))
+local failing = coroutine.wrap(function()
+  return pcall(function() local x = a + 1; error("after " .. x, 0) end)
+end)
+print(failing(), failing(7))
+print(coroutine.wrap(function() return pcall(table.sort, {a, b}) end)())
@@ -0,0 +1,25 @@
+-- 错误处理，最后一个错误未被捕获，退出码为1
+local function none() end
+print(pcall(error, "msg", 0))
+print(pcall(error, {code = 1}))
+print(select(2, pcall(error)))
+print(pcall(function() error("with position") end))
+print(pcall(function() local function f() error("level 2", 2) end f() end))
+print(pcall(function() return none().field end))
+print(pcall(function() return 1 + {} end))
+print(pcall(function() return "a" < 1 end))
+print(pcall(function() return #5 end))
+print(pcall(function() none()() end))
+print(pcall(function() string.rep() end))
+print(pcall(function() string.sub("x", "y") end))
+print(pcall(function() setmetatable(1, {}) end))
+print(pcall(function() return 1 // 0 end))
+print(pcall(function() return 1 < nil end))
+print(pcall(function() local t = setmetatable({}, {__index = function() error("deep", 0) end}); return t.x end))
+print(xpcall(function() error("handled") end, function(m) return "handler: " .. m end))
+print(xpcall(function(a, b) return a + b end, print, 1, 2))
+print(select("#", pcall(error)))
+print(tostring(nil) .. "", pcall(function() tostring() end))
+print(assert(1, "unused"), pcall(assert, false), pcall(assert, nil, "custom"), pcall(assert, false, {}))
+none().y = 1
+print("not reached")
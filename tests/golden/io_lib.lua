@@ -0,0 +1,74 @@
+-- io库：文件句柄、读取格式、lines、seek和默认输入输出
+local name = os.tmpname()
+
+local f = assert(io.open(name, "w"))
+print(io.type(f), io.type(io.stdout), io.type(42))
+print(f:write("12 3.5 0x10", "\n", "second line\n", 42, " ", 1.5, "\n") == f)
+print(f:seek("cur"), f:seek("set", 3), f:seek("end"))
+print(f:setvbuf("full", 1024), f:flush())
+print(f:close())
+print(io.type(f), tostring(f))
+print(pcall(f.write, f, "x"))
+
+f = assert(io.open(name))
+print(f:read("n", "n", "n"))
+print(f:read("l"))
+print(f:read("L"))
+print(f:read(2), f:read(0), f:read("a"))
+print(f:read("a"), f:read("l"), f:read(0), f:read("n"))
+f:close()
+
+-- lines：每次迭代按给出的格式读取，文件结束时io.lines关闭文件
+for a, b in io.lines(name, 1, "l") do print(a, b) end
+local n = 0
+for line in io.lines(name, "L") do n = n + #line end
+print(n)
+f = io.open(name)
+for line in f:lines() do io.write("[", line, "]") end
+print()
+print(io.type(f), f:read("a"), f:close())
+
+-- 默认输入输出
+io.output(name)
+io.write("one\n", "two\n")
+print(io.output() ~= io.stdout)
+io.close()
+io.output(io.stdout)
+io.input(name)
+print(io.read(), io.read("L"), io.read())
+io.input():close()
+print(pcall(io.read))
+io.input(io.stdin)
+
+-- 追加和读写模式
+f = io.open(name, "a+")
+f:write("three\n")
+f:seek("set")
+print(f:read("a"))
+f:close()
+f = io.open(name, "r+")
+f:write("ONE")
+f:seek("set")
+print(f:read("l"))
+f:close()
+
+local t = io.tmpfile()
+t:write("temp")
+t:seek("set")
+print(t:read("a"))
+t:close()
+
+local p = io.popen("echo from popen")
+print(p:read("l"), p:close())
+print(io.popen("exit 3"):close())
+
+-- 错误
+print(io.open("/nonexistent/file"))
+print(pcall(function() return io.open(name, "rw") end))
+print(pcall(io.lines, "/nonexistent/file"))
+f = io.open(name)
+print(pcall(function() return f:read("x") end))
+print(pcall(function() return f:seek("middle") end))
+f:close()
+
+print(os.remove(name))
@@ -0,0 +1,54 @@
+-- 元表和元方法
+local V = {}
+V.__index = V
+local function vec(x, y) return setmetatable({x = x, y = y}, V) end
+V.__add = function(a, b) return vec(a.x + b.x, a.y + b.y) end
+V.__eq = function(a, b) return a.x == b.x and a.y == b.y end
+V.__lt = function(a, b) return a.x < b.x end
+V.__le = function(a, b) return a.x <= b.x end
+V.__unm = function(a) return vec(-a.x, -a.y) end
+V.__len = function() return 2 end
+V.__tostring = function(a) return "(" .. a.x .. "," .. a.y .. ")" end
+V.__concat = function(a, b) return tostring(a) .. tostring(b) end
+function V:dot(o) return self.x * o.x + self.y * o.y end
+local a, b = vec(1, 2), vec(3, 4)
+print(tostring(a + b), a == vec(1, 2), a ~= b, a < b, a <= b, a > b, #a, tostring(-a), a .. b, a .. "!")
+print(a:dot(b), getmetatable(a) == V, rawequal(a, vec(1, 2)))
+
+local log = {}
+local proxy = setmetatable({}, {
+  __index = function(t, k) log[#log + 1] = "get " .. k; return k .. "!" end,
+  __newindex = function(t, k, v) log[#log + 1] = "set " .. k; rawset(t, k, v) end,
+})
+print(proxy.a, proxy.a)
+proxy.b = 1
+proxy.b = 2
+print(proxy.b, table.concat(log, ","))
+
+local base = {greet = function() return "hi" end}
+local mid = setmetatable({}, {__index = base})
+local top = setmetatable({}, {__index = mid})
+print(top.greet(), top.missing)
+
+local arith = setmetatable({}, {
+  __sub = function() return "sub" end, __mul = function() return "mul" end,
+  __div = function() return "div" end, __mod = function() return "mod" end,
+  __pow = function() return "pow" end, __idiv = function() return "idiv" end,
+  __band = function() return "band" end, __shl = function() return "shl" end,
+  __bnot = function() return "bnot" end,
+})
+print(arith - 1, 2 * arith, arith / 1, arith % 1, arith ^ 1, arith // 1, arith & 1, 1 << arith, ~arith)
+
+print(getmetatable("abc").__index == string, ("x"):rep(2))
+local locked = setmetatable({}, {__metatable = "locked"})
+print(getmetatable(locked), pcall(function() setmetatable(locked, {}) end))
+
+local closed = {}
+do
+  local x <close> = setmetatable({}, {__close = function(_, err) closed[#closed + 1] = tostring(err) end})
+end
+print(pcall(function()
+  local y <close> = setmetatable({}, {__close = function(_, err) closed[#closed + 1] = err end})
+  error("boom", 0)
+end))
+print(table.concat(closed, ","))
@@ -0,0 +1,69 @@
+-- 运算符优先级、控制结构和局部变量
+print(2 + 3 * 4 ^ 2 / 8, (2 + 3) * 4, 2 ^ 3 ^ 2, -2 ^ 2, not 1 == 2)
+print(1 .. 2 .. 3, "a" .. "b" == "ab", 1 < 2 == true, 5 - 3 - 1, 2 * 3 % 4)
+print(7 // 2, 7.0 // 2, -7 // 2, 7 % -3, -7 % 3, 5.5 % 2, 3 / 2, 4 / 2)
+print(3 | 5, 3 & 5, 3 ~ 5, ~0, 1 << 62, 1 << 64, -1 >> 60, "3" | 0)
+print(1 and 2, nil and 1, false or "x", nil or false, 1 or error("not evaluated"))
+print(1 == 1.0, "1" == 1, 0.1 + 0.2 == 0.3, math.maxinteger + 1 == math.mininteger)
+print("a" < "b", "Z" < "a", "abc" < "abd", "" < "a", 1 < 1.5, -0.0 == 0.0)
+
+local sum = 0
+for i = 1, 10 do
+  if i % 2 == 0 then
+    sum = sum + i
+  elseif i == 5 then
+    sum = sum + 100
+  else
+    sum = sum - 1
+  end
+end
+print(sum)
+
+local t = {}
+for i = 10, 1, -3 do t[#t + 1] = i end
+print(table.concat(t, ","))
+local n = 0
+for i = 1.0, 2.0, 0.25 do n = n + i end
+print(n)
+for i = 1, 0 do error("empty loop ran") end
+for i = math.maxinteger - 1, math.maxinteger do io.write(i, " ") end
+print()
+
+local i = 0
+while true do
+  i = i + 1
+  if i > 5 then break end
+end
+repeat
+  local j = i
+  i = i - 1
+until j <= 3
+print(i)
+
+for i = 1, 3 do
+  for j = 1, 3 do
+    if j == 2 then goto continue end
+    io.write(i, j, " ")
+    ::continue::
+  end
+end
+print()
+
+do
+  local a, b, c = 1, 2
+  print(a, b, c)
+  a, b = b, a
+  print(a, b)
+  local a = a + 10
+  print(a)
+end
+
+local x <const> = 42
+do
+  local log = {}
+  do
+    local a <close> = setmetatable({}, {__close = function() log[#log + 1] = "a" end})
+    local b <close> = setmetatable({}, {__close = function() log[#log + 1] = "b" end})
+  end
+  print(x, table.concat(log))
+end
@@ -0,0 +1,56 @@
+-- os库：日期格式、时间表的规范化和其他函数
+local t0 = 86400 * 365 + 3 * 3600 + 4 * 60 + 5  -- 1971-01-01 03:04:05 UTC
+
+print(os.date("!%Y-%m-%d %H:%M:%S", t0))
+print(os.date("!%a %A %b %B %d %e %j %u %w %y %C", t0))
+print(os.date("!%D|%F|%T|%R|%r|%p|%I|%M|%S|%%|%n|%t|", t0))
+print(os.date("!%c", t0))
+print(os.date("!%x %X", t0))
+print(os.date("!%G %g %V %U %W", t0))
+print(os.date("!%Ey %EC %Od %OH", t0))
+print(os.date("!%h|%z|%Z", 0))
+print(pcall(function() return os.date("%s", 0) end))
+print(os.date("!plain text", t0), os.date("!", t0) == "")
+
+local function show(t)
+  local keys = {}
+  for k in pairs(t) do keys[#keys + 1] = k end
+  table.sort(keys)
+  local parts = {}
+  for _, k in ipairs(keys) do parts[#parts + 1] = k .. "=" .. tostring(t[k]) end
+  return table.concat(parts, " ")
+end
+print(show(os.date("!*t", t0)))
+print(show(os.date("!*t", 0)))
+
+-- os.time按本地时间解释表，超出范围的字段进位，并写回规范化的值
+local t = {year = 2024, month = 1, day = 32, hour = 25, min = -1, sec = 0}
+local secs = os.time(t)
+print(math.type(secs), t.year, t.month, t.day, t.hour, t.min, t.sec, t.wday, t.yday)
+print(os.date("%Y-%m-%d %H:%M:%S", secs))
+local d = os.date("*t", os.time({year = 2023, month = 14, day = 0, hour = 12}))
+d.isdst = nil  -- 与时区有关
+print(show(d))
+print(os.time({year = 2000, month = 1, day = 1, hour = 12}) - os.time({year = 1999, month = 12, day = 31, hour = 12}))
+print(os.date("%Y", os.time({year = 2020, month = 6, day = 15})), os.date("!%Y", 0) == "1970")
+print(os.difftime(t0, 0), math.type(os.difftime(1, 1)))
+print(math.type(os.time()), math.type(os.clock()), os.clock() >= 0)
+
+-- 错误
+print(pcall(function() return os.date("%Ez", 0) end))
+print(pcall(function() return os.date("%", 0) end))
+print(pcall(function() return os.time({year = 2000}) end))
+print(pcall(function() return os.time({year = 2000, month = "x", day = 1}) end))
+print(pcall(function() return os.time({year = 2000, month = 1.5, day = 1}) end))
+print(pcall(function() return os.date("*t", 2^60) end))
+
+-- 环境变量和文件
+print(type(os.getenv("PATH")), os.getenv("MYLUA_SURELY_UNSET_VARIABLE"))
+local name = os.tmpname()
+local renamed = name .. ".renamed"
+print(os.rename(name, renamed), io.open(name) == nil)
+print(os.remove(renamed))
+print(select("#", os.remove(renamed)), select(3, os.remove(renamed)))
+print(os.rename("/nonexistent/a", "/nonexistent/b"))
+print(os.execute() , os.execute("exit 2"))
+print(os.execute("kill -9 $$"))
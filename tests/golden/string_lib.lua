@@ -0,0 +1,30 @@
+-- 字符串字面量和string库
+print("tab\tnew\\line", 'single "quote"', [[long
+string]], [==[with ]] inside]==])
+print("\65\066\x43\u{48}\z
+       I", #"\0\1\2", "\u{7FF}" == "\xDF\xBF")
+print(#"hello", ("abc"):upper(), ("ABC"):lower(), ("hello"):sub(2, -2), ("hello"):sub(-3))
+print(("ab"):rep(3), ("ab"):rep(3, ","), ("x"):rep(0), ("hello"):reverse(), ("hello"):len())
+print(("ABC"):byte(), ("ABC"):byte(2, -1), string.char(72, 105), ("%d"):format(3.0))
+print(string.format("%5.2f|%d|%s|%x|%X|%o|%-5s|%5s|%%", 3.14159, -42, "hi", 255, 255, 8, "ab", "ab"))
+print(string.format("%q", "a\nb\"c\0"), string.format("%q", 1 / 3 * 3), string.format("%q", math.mininteger))
+print(string.format("%g %g %g %g", 1e20, 0.1, 100, 2 ^ 53), string.format("%.3s|%c", "abcdef", 65))
+print(string.find("hello world", "o w"), string.find("hello", "l+"), string.find("a.b", ".", 1, true))
+print(string.find("hello", "xyz"), string.find("hello", "", 10), string.find("hello", "l", -2))
+print(string.match("key = val", "(%w+)%s*=%s*(%w+)"), string.match("2024-01-15", "(%d+)-(%d+)-(%d+)"))
+print(string.match("hello", "()ll()"), string.match("[tag]", "%[(.-)%]"), string.match("  trim  ", "^%s*(.-)%s*$") .. "|")
+print(string.gsub("hello world", "o", "0"), string.gsub("abc", "%w", "%0%0"), string.gsub("abc", "", "-"))
+print(string.gsub("$name is $age", "%$(%w+)", {name = "Bob", age = 42}))
+print(string.gsub("1 2 3", "%d", function(d) return d * 2 end), string.gsub("aaa", "a", "b", 2))
+print(string.match("f(a(b)c)", "%b()"), string.find("THE (quick) fox", "%f[%a]%a+", 5))
+local words = {}
+for w in string.gmatch("one two  three", "%a+") do words[#words + 1] = w end
+print(#words, table.concat(words, "|"))
+for k, v in string.gmatch("a=1, b=2", "(%w+)=(%w+)") do io.write(k, v, ";") end
+print()
+print(tostring(12), tostring(1.5), tostring(-0.0), tostring(1e100), 10 .. "", 1e15, 2 ^ 63)
+print(tonumber("0x10"), tonumber("  12  "), tonumber("1e1"), tonumber("z", 36), tonumber("ff", 16), tonumber(""), tonumber("1x"))
+print("10" + 1, "3" * "4", 10 .. 20, "0x10" + 0, math.type("1" + 1), math.type("1.0" + 1))
+print(utf8.char(72, 228, 8364), utf8.len("häll€"), utf8.codepoint("€"), #utf8.char(0x10FFFF))
+for p, c in utf8.codes("aé") do io.write(p, ":", c, " ") end
+print()
@@ -0,0 +1,53 @@
+-- 表构造、长度、next/pairs/ipairs和table库
+local t = {1, 2, 3, nil, x = "x", [10] = 10, ["y z"] = true; 4}
+print(t[1], t[4], t.x, t[10], t["y z"], #{1, 2, 3, nil})
+local function f() return 1, 2, 3 end
+print(#{f()}, #{f(), f()}, #{(f())}, #{f(), nil})
+
+local keys = {}
+for k in pairs({a = 1, b = 2, c = 3}) do keys[#keys + 1] = k end
+table.sort(keys)
+print(table.concat(keys))
+for i, v in ipairs({"a", "b", nil, "d"}) do io.write(i, v, " ") end
+print()
+print(next({}), next({5}), rawlen({1, 2}), rawlen("abc"), rawequal(t, t), rawget(t, "x"))
+
+local n = 0
+local big = {}
+for i = 1, 1000 do big[i] = i end
+for k, v in pairs(big) do n = n + v end
+print(#big, n)
+for i = 1, 1000, 2 do big[i] = nil end
+n = 0
+for k in pairs(big) do n = n + 1 end
+print(n)
+
+-- 遍历时可以清除字段
+local m = {}
+for i = 1, 20 do m["k" .. i] = i end
+for k in pairs(m) do m[k] = nil end
+print(next(m))
+
+-- 浮点数键规范化为整数
+local fk = {}
+fk[1.0] = "one"; fk[2] = "two"; fk[2^53] = "big"
+print(fk[1], fk[2.0], fk[2^53 | 0], math.type(next(fk)))
+
+local s = {5, 2, 8, 1, 9, 3}
+table.sort(s)
+print(table.concat(s, " "))
+table.sort(s, function(a, b) return a > b end)
+print(table.concat(s, " "))
+local w = {"banana", "apple", "Cherry"}
+table.sort(w)
+print(table.concat(w, " "))
+
+local u = {1, 2, 3}
+table.insert(u, 4)
+table.insert(u, 1, 0)
+print(table.concat(u, ","), table.remove(u), table.remove(u, 1), table.concat(u, ","))
+print(table.concat({}, ","), table.concat({1, 2.5, "x"}, "-", 2, 3), table.unpack({1, 2, 3}, 2))
+local p = table.pack(1, nil, 3)
+print(p.n, p[1], p[2], p[3], select("#", table.unpack(p, 1, p.n)))
+print(table.concat(table.move({1, 2, 3}, 1, 3, 2), ","), select(-1, 1, 2, 3), select(2, "a", "b", "c"))
+print(pcall(function() table.insert({}, 1, 2, 3) end))
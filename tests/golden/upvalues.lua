@@ -0,0 +1,58 @@
+-- 闭包、上值和可变参数
+local function counter()
+  local n = 0
+  return function() n = n + 1; return n end, function() return n end
+end
+local inc, get = counter()
+inc(); inc()
+local inc2 = counter()
+inc2()
+print(get(), inc2())
+
+local fs = {}
+for i = 1, 3 do fs[i] = function() return i end end
+print(fs[1](), fs[2](), fs[3]())
+local gs = {}
+local j = 1
+while j <= 3 do
+  local k = j
+  gs[j] = function() k = k + 10; return k end
+  j = j + 1
+end
+print(gs[1](), gs[1](), gs[2]())
+
+local function shared()
+  local v = 0
+  local function a() v = v + 1 end
+  local function b() return v end
+  return a, b
+end
+local a, b = shared()
+a(); a(); a()
+print(b())
+
+local function fact(n) if n <= 1 then return 1 end return n * fact(n - 1) end
+print(fact(20), fact(21) < 0)
+
+local function va(...)
+  local x, y = ...
+  return select("#", ...), x, y, ...
+end
+print(va())
+print(va(1, nil, 3))
+print((va(1, 2)))
+local function pack2(...) return {...}, select("#", ...) end
+local t, n = pack2(nil, nil)
+print(#t, n)
+
+-- 尾调用不增长栈
+local function loop(n) if n == 0 then return "done" end return loop(n - 1) end
+print(loop(100000))
+
+local obj = {v = 1}
+function obj:add(d) self.v = self.v + d; return self end
+function obj.static(x) return x * 2 end
+print(obj:add(2):add(3).v, obj.static(4))
+
+local mt = {__call = function(self, x) return "called " .. x end}
+print(setmetatable({}, mt)(5))
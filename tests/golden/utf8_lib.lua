@@ -0,0 +1,52 @@
+-- utf8库：编码、解码、长度、偏移和遍历，lax放宽对码点范围的检查
+local s = "héllo, 世界!"
+
+print(#s, utf8.len(s), utf8.len(s, 3), utf8.len(s, -4), utf8.len(s, 1, 0), utf8.len(""))
+print(utf8.char(72, 233, 0x4E16, 0x754C, 0x1F600), utf8.char())
+print(utf8.codepoint(s), utf8.codepoint(s, 2), utf8.codepoint(s, 8, -1))
+print(utf8.codepoint(s, 4, 3), select("#", utf8.codepoint(s, 4, 3)))
+print(utf8.charpattern == "[\0-\x7F\xC2-\xFD][\x80-\xBF]*")
+for w in s:gmatch(utf8.charpattern) do io.write("[", w, "]") end
+print()
+
+-- codes：位置和码点
+for p, c in utf8.codes(s) do io.write(p, ":", c, " ") end
+print()
+for p, c in utf8.codes("") do print("never") end
+
+-- offset：第n个字符的开始位置，n为0时找i所在字符的开头，负数从末尾数
+print(utf8.offset(s, 1), utf8.offset(s, 3), utf8.offset(s, 9), utf8.offset(s, 10), utf8.offset(s, 11), utf8.offset(s, 12))
+print(utf8.offset(s, -1), utf8.offset(s, -2), utf8.offset(s, -3), utf8.offset(s, -12), utf8.offset(s, -13))
+print(utf8.offset(s, 0, 3), utf8.offset(s, 0, 10), utf8.offset(s, 0, 11), utf8.offset(s, 2, 9), utf8.offset(s, -1, 9))
+print(utf8.offset("", 1), utf8.offset("", 0), utf8.offset("", -1))
+
+-- 无效序列：len返回fail和位置
+local bad = {
+  "\xFF", "\x80", "a\xC3", "\xC0\x80", "\xE0\x80\x80", "\xED\xA0\x80",
+  "\xF4\x90\x80\x80", "\xF8\x88\x80\x80\x80", "\xFD\xBF\xBF\xBF\xBF\xBF",
+}
+for _, b in ipairs(bad) do
+  print(utf8.len(b), utf8.len(b, 1, -1, true), pcall(utf8.codepoint, b, 1, -1, true))
+end
+
+-- lax允许代理对和超出Unicode范围直到2^31-1的码点
+print(utf8.char(0xD800) == "\xED\xA0\x80", utf8.char(0x7FFFFFFF) == "\xFD\xBF\xBF\xBF\xBF\xBF")
+print(utf8.codepoint("\u{7FFFFFFF}", 1, 1, true), utf8.len("\u{D7FF}\u{E000}\u{10FFFF}"))
+for _, c in utf8.codes("\u{D800}\u{110000}", true) do io.write(c, " ") end
+print()
+
+-- 错误
+print(pcall(function() return utf8.codepoint("\xFF") end))
+print(pcall(function() return utf8.codepoint("\u{D800}") end))
+print(pcall(function() for _ in utf8.codes("ab\xFF") do end end))
+print(pcall(function() for _ in utf8.codes("a\u{D800}") do end end))
+print(pcall(function() for _ in utf8.codes("\xE4\xB8\x96\x80") do end end))
+print(pcall(function() return utf8.codes("\x80") end))
+print(pcall(function() return utf8.offset(s, 1, 3) end))
+print(pcall(function() return utf8.offset(s, 1, 100) end))
+print(pcall(function() return utf8.char(-1) end))
+print(pcall(function() return utf8.char(0x80000000) end))
+print(pcall(function() return utf8.len(s, 100) end))
+print(pcall(function() return utf8.len(s, 1, 100) end))
+print(pcall(function() return utf8.codepoint(s, 0) end))
+print(pcall(function() return utf8.codepoint(s, 1, 100) end))
@@ -0,0 +1,121 @@
+// 宿主设置的执行限制：指令数、deadline、中断和内存
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+use common::run;
+use my_lua::alloc::CountingAlloc;
+use my_lua::vm::ExeState;
+
+// 内存上限由分配器执行
+#[global_allocator]
+static ALLOC: CountingAlloc = CountingAlloc::new();
+
+#[test]
+fn instruction_limit() {
+    let mut state = ExeState::new();
+    state.set_instruction_limit(Some(10_000));
+    assert_eq!(run(&mut state, "while true do end"), Err("instruction limit exceeded".to_string()));
+    // 不能被pcall捕获
+    state.set_instruction_limit(Some(10_000));
+    assert_eq!(run(&mut state, "pcall(function() while true do end end) return 'caught'"),
+        Err("instruction limit exceeded".to_string()));
+    state.set_instruction_limit(Some(10_000));
+    assert_eq!(run(&mut state, "local n = 0 for i = 1, 100 do n = n + i end return n"), Ok("5050".to_string()));
+    state.set_instruction_limit(None);
+    assert_eq!(run(&mut state, "local n = 0 for i = 1, 100000 do n = n + 1 end return n"), Ok("100000".to_string()));
+}
+
+#[test]
+fn deadline() {
+    let mut state = ExeState::new();
+    state.set_deadline(Some(Instant::now() + Duration::from_millis(100)));
+    assert_eq!(run(&mut state, "while true do end"), Err("deadline exceeded".to_string()));
+
+    // 耗时长的库函数中也会停下
+    for code in ["return #string.rep('ab', 3e8)", "return #string.rep('x', 2^20, 'y'):rep(1000)",
+        "local t = {} for i = 1, 100 do t[i] = string.rep('x', 2^20) end
+         local s = 0 while true do s = s + #table.concat(t) end"] {
+        let start = Instant::now();
+        state.set_deadline(Some(start + Duration::from_millis(100)));
+        assert_eq!(run(&mut state, code), Err("deadline exceeded".to_string()), "{}", code);
+        assert!(start.elapsed() < Duration::from_secs(2), "{}: took {:?}", code, start.elapsed());
+    }
+    state.set_deadline(None);
+    assert_eq!(run(&mut state, "return 1"), Ok("1".to_string()));
+}
+
+#[test]
+fn interrupt() {
+    let mut state = ExeState::new();
+    let handle = state.interrupt_handle();
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        handle.interrupt();
+    });
+    let start = Instant::now();
+    assert_eq!(run(&mut state, "while true do end"), Err("interrupted".to_string()));
+    interrupter.join().unwrap();
+
+    let handle = state.interrupt_handle();
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        handle.interrupt();
+    });
+    assert_eq!(run(&mut state, "return #string.rep('ab', 3e8)"), Err("interrupted".to_string()));
+    interrupter.join().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2), "took {:?}", start.elapsed());
+
+    // 请求只中断一次
+    assert_eq!(run(&mut state, "return 2"), Ok("2".to_string()));
+}
+
+#[test]
+fn memory_limit() {
+    let mut state = ExeState::new();
+    state.set_memory_limit(Some(10 << 20));
+    // 大块分配在分配前被拒绝
+    assert_eq!(run(&mut state, "return #string.rep('x', 1 << 30)"), Err("not enough memory".to_string()));
+    assert_eq!(run(&mut state, "return pcall(string.rep, 'x', 1 << 30)"), Ok("false not enough memory".to_string()));
+    assert_eq!(run(&mut state, "local s = 'x' while true do s = s .. s end"), Err("not enough memory".to_string()));
+    assert_eq!(run(&mut state, "local t = {} for i = 1, 20 do t[i] = string.rep('x', 1 << 20) end return #table.concat(t)"),
+        Err("not enough memory".to_string()));
+    // 许多小的分配
+    assert_eq!(run(&mut state, "local t = {} for i = 1, 1e7 do t[i] = {} end"), Err("not enough memory".to_string()));
+    assert_eq!(run(&mut state, "return #string.rep('x', 1 << 20)"), Ok("1048576".to_string()));
+
+    let path = std::env::temp_dir().join(format!("mylua_limits_{}.txt", std::process::id()));
+    std::fs::write(&path, vec![b'x'; 16 << 20]).unwrap();
+    let code = format!("local f = io.open({:?}) local ok, err = pcall(f.read, f, 'a') f:close() return ok, err", path);
+    assert_eq!(run(&mut state, &code), Ok("false not enough memory".to_string()));
+    std::fs::remove_file(&path).unwrap();
+
+    state.set_memory_limit(None);
+    assert_eq!(run(&mut state, "return #string.rep('x', 20 << 20)"), Ok("20971520".to_string()));
+}
+
+
+// 内存上限属于各个状态，不受同一线程上其他状态的影响
+#[test]
+fn memory_limit_per_state() {
+    let big = "return #string.rep('x', 64 << 20)";
+    let mut a = ExeState::new();
+    a.set_memory_limit(Some(10 << 20));
+    assert_eq!(run(&mut a, big), Err("not enough memory".to_string()));
+
+    let mut b = ExeState::new();
+    b.set_memory_limit(Some(100 << 20));
+    assert_eq!(run(&mut b, big), Ok("67108864".to_string()));
+    assert_eq!(run(&mut a, big), Err("not enough memory".to_string()));
+    drop(b);
+    assert_eq!(run(&mut a, big), Err("not enough memory".to_string()));
+
+    // 没有上限的状态不受限制，也不影响其他状态
+    let mut c = ExeState::new();
+    assert_eq!(run(&mut c, big), Ok("67108864".to_string()));
+    assert_eq!(run(&mut a, big), Err("not enough memory".to_string()));
+    // 上限只在执行期间生效
+    let mut buf: Vec<u8> = Vec::new();
+    assert!(my_lua::alloc::try_reserve(&mut buf, 64 << 20).is_ok());
+}
@@ -0,0 +1,51 @@
+// 字节码清单
+use my_lua::lex::Lex;
+use my_lua::parse::ParseProto;
+
+#[test]
+fn small_chunk() {
+    let code = "local x = 1\nprint(x + 2.5, \"s\")\nlocal function f(a) return a end\n";
+    let mut proto = ParseProto::new(Lex::from_bytes(code.as_bytes(), "=test"));
+    proto.compile().unwrap();
+    let expected = "\
+main <test:0,0> (9 instructions)
+0+ params, 4 slots, 1 upvalue, 2 locals, 3 constants, 1 function
+\t1\t[1]\tLOADINT       \t0 1
+\t2\t[2]\tGETGLOBAL     \t1 0 0\t; _ENV print
+\t3\t[2]\tLOADCONSTANT  \t2 1\t; 2.5
+\t4\t[2]\tADD           \t2 0 2
+\t5\t[2]\tLOADCONSTANT  \t3 2\t; \"s\"
+\t6\t[2]\tCALL          \t1 3 1
+\t7\t[3]\tCLOSURE       \t2 0\t; function <test:3>
+\t8\t[3]\tMOVE          \t1 2
+\t9\t[3]\tRETURN        \t0 1
+constants (3):
+\t0\tS\t\"print\"
+\t1\tF\t2.5
+\t2\tS\t\"s\"
+locals (2):
+\t0\tx\t2\t10
+\t1\tf\t7\t10
+upvalues (1):
+\t0\t_ENV\t0\t0
+
+function <test:3,3> (2 instructions)
+1 param, 2 slots, 0 upvalues, 1 local, 0 constants, 0 functions
+\t1\t[3]\tRETURN        \t0 2
+\t2\t[3]\tRETURN        \t0 1
+constants (0):
+locals (1):
+\t0\ta\t1\t3
+upvalues (0):
+";
+    assert_eq!(proto.listing(), expected);
+}
+
+#[test]
+fn jump_targets() {
+    let mut proto = ParseProto::new(Lex::from_bytes(b"for i = 1, 2 do end", "=test"));
+    proto.compile().unwrap();
+    let listing = proto.listing();
+    assert!(listing.contains("FORPREPARE    \t0 1\t; to 6\n"), "{}", listing);
+    assert!(listing.contains("FORLOOP       \t0 -1\t; to 5\n"), "{}", listing);
+}
@@ -0,0 +1,16 @@
+// math库
+mod common;
+
+use common::eval;
+
+// 同一种子得到与Lua 5.4相同的序列（xoshiro256**，种子{n, 0xff, 0, 0}并丢弃最初16个值）
+#[test]
+fn randomseed_sequence() {
+    assert_eq!(eval("math.randomseed(42)
+        local a, b, c = math.random(1, 100), math.random(1, 100), math.random(1, 100)
+        return a, b, c, math.random() == 0.6173176359584727, math.random(0)"), "50 76 86 true 177101407732369983");
+    // randomseed返回使用的种子，重设后序列重复
+    assert_eq!(eval("local s1, s2 = math.randomseed(7, 3) local x = math.random(0)
+        math.randomseed(7, 3) return s1, s2, x == math.random(0)"), "7 3 true");
+    assert_eq!(eval("math.randomseed(42) return math.random(10), math.random(3, 3), math.random(-2, -1)"), "6 3 -1");
+}
@@ -0,0 +1,107 @@
+// require和package库
+mod common;
+
+use common::{eval_in, run};
+use my_lua::error::LuaError;
+use my_lua::value::{Table, Value};
+use my_lua::vm::ExeState;
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+
+// 以package.path为dir/?.lua;dir/?/init.lua的状态，cpath只指向dir中不存在的动态库
+fn state_with_path(dir: &str) -> ExeState {
+    let mut state = ExeState::new();
+    let code = format!("package.path = '{0}/?.lua;{0}/?/init.lua' package.cpath = '{0}/?.so'", dir);
+    run(&mut state, &code).unwrap();
+    state
+}
+
+#[test]
+fn require_lua_module() {
+    let dir = std::env::temp_dir().join(format!("mylua_package_{}", std::process::id()));
+    fs::create_dir_all(dir.join("pkg")).unwrap();
+    fs::write(dir.join("counter.lua"), "loads = (loads or 0) + 1\nreturn {name = ..., file = select(2, ...)}\n").unwrap();
+    fs::write(dir.join("pkg/init.lua"), "return 'pkg init'").unwrap();
+    fs::write(dir.join("pkg/inner.lua"), "return 'inner'").unwrap();
+    fs::write(dir.join("quiet.lua"), "x = 1").unwrap();
+    fs::write(dir.join("broken.lua"), "return +").unwrap();
+    let d = dir.to_str().unwrap();
+    let mut state = state_with_path(d);
+
+    // 加载函数以(名字, 文件名)调用，结果缓存在package.loaded中
+    let file = format!("{}/counter.lua", d);
+    assert_eq!(eval_in(&mut state, "local m, f = require('counter') return m.name, m.file, f"), format!("counter {0} {0}", file));
+    assert_eq!(eval_in(&mut state, "require('counter') return loads, package.loaded.counter == require('counter')"), "1 true");
+    // 点号换成目录分隔符，也查找?/init.lua
+    assert_eq!(eval_in(&mut state, "return require('pkg'), (require('pkg.inner'))"), "pkg init inner");
+    // 没有返回值的模块记为true
+    assert_eq!(eval_in(&mut state, "return require('quiet'), package.loaded.quiet"), "true true");
+    let err = run(&mut state, "require('broken')").unwrap_err();
+    assert!(err.starts_with(&format!("error loading module 'broken' from file '{}/broken.lua':\n\t", d)), "{}", err);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn native_loader(state: &mut ExeState) -> Result<i32, LuaError> {
+    let mut t = Table::new(0, 2);
+    t.set_str("name", state.get_arg(1).clone());
+    t.set_str("extra", state.get_arg(2).clone());
+    state.push(Value::Table(Rc::new(RefCell::new(t))));
+    Ok(1)
+}
+
+#[test]
+fn require_native_and_preload() {
+    let mut state = ExeState::new();
+    state.register_module("native", native_loader);
+    assert_eq!(eval_in(&mut state, "local m, extra = require('native') return m.name, m.extra, extra, package.loaded.native == m"),
+        "native :native: :native: true");
+
+    // preload优先于其他搜索器
+    assert_eq!(eval_in(&mut state, "package.preload.native = function(...) return table.concat({...}, ',') end
+        package.loaded.native = nil
+        return require('native')"), "native,:preload: :preload:");
+    assert_eq!(eval_in(&mut state, "package.preload.nothing = function() end return require('nothing')"), "true :preload:");
+}
+
+#[test]
+fn module_not_found() {
+    let mut state = state_with_path("/nonexistent");
+    let err = run(&mut state, "require('a.b')").unwrap_err();
+    assert_eq!(err, "test:1: module 'a.b' not found:
+\tno field package.preload['a.b']
+\tno file '/nonexistent/a/b.lua'
+\tno file '/nonexistent/a/b/init.lua'
+\tno native module 'a.b'
+\tno file '/nonexistent/a/b.so'");
+}
+
+#[test]
+fn searchers_and_searchpath() {
+    let mut state = ExeState::new();
+    assert_eq!(eval_in(&mut state, "return #package.searchers"), "3");
+    // 自定义搜索器：返回字符串时拼入找不到的错误信息
+    assert_eq!(eval_in(&mut state, "table.insert(package.searchers, 1, function(name)
+            if name == 'virtual' then return function() return 'from searcher' end, 'extra' end
+            return 'not virtual'
+        end)
+        return require('virtual')"), "from searcher extra");
+    let err = run(&mut state, "package.path = '' package.cpath = '' require('missing')").unwrap_err();
+    assert!(err.starts_with("test:1: module 'missing' not found:\n\tnot virtual\n\tno field package.preload['missing']"), "{}", err);
+    let err = run(&mut state, "package.searchers = nil require('other')").unwrap_err();
+    assert_eq!(err, "test:1: 'package.searchers' must be a table");
+
+    // searchpath按分号分隔的模板依次替换'?'
+    let file = std::env::temp_dir().join(format!("mylua_searchpath_{}.lua", std::process::id()));
+    fs::write(&file, "").unwrap();
+    let tmp = std::env::temp_dir();
+    let tmp = tmp.to_str().unwrap();
+    let code = format!("return package.searchpath('mylua_searchpath_{}', '/nonexistent/?.x;{}/?.lua')", std::process::id(), tmp);
+    assert_eq!(eval_in(&mut state, &code), file.to_str().unwrap());
+    assert_eq!(eval_in(&mut state, "return package.searchpath('a.b.c', '/no/?.lua;/no/?/x')"),
+        "nil no file '/no/a/b/c.lua'\n\tno file '/no/a/b/c/x'");
+    assert_eq!(eval_in(&mut state, "return select(2, package.searchpath('a.b', '/no/?', '.', '_'))"), "no file '/no/a_b'");
+    assert_eq!(eval_in(&mut state, "return select(2, package.searchpath('a.b', '/no/?', ''))"), "no file '/no/a.b'");
+    fs::remove_file(&file).unwrap();
+}
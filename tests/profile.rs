@@ -0,0 +1,49 @@
+// 性能分析报告：协程让出和恢复时总时间的统计
+use my_lua::profile::Profiler;
+use my_lua::vm::ExeState;
+
+const CODE: &str = "
+local function work(n) local s = 0 for i = 1, n do s = s + i end return s end
+local function producer()
+  for i = 1, 20 do work(2000) coroutine.yield(i) end
+  -- 最后挂起的协程不再恢复
+  work(2000) coroutine.yield()
+end
+local function consumer()
+  local co = coroutine.create(producer)
+  local sum = 0
+  for i = 1, 21 do local _, v = coroutine.resume(co) sum = sum + (v or 0) work(500) end
+  return sum
+end
+local gen = coroutine.wrap(function() while true do work(1000) coroutine.yield() end end)
+for i = 1, 10 do gen() end
+local sum = consumer()
+return sum
+";
+
+// 报告中每个函数的(自身时间, 总时间, 名字)
+fn functions(report: &str) -> Vec<(f64, f64, String)> {
+    report.split("\n\n").nth(1).unwrap().lines().skip(1).map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        (fields[0].parse().unwrap(), fields[1].parse().unwrap(), fields[4..].join(" "))
+    }).collect()
+}
+
+#[test]
+fn total_time_with_coroutines() {
+    let mut state = ExeState::new();
+    let f = state.load_bytes(CODE.as_bytes(), "=test", None).unwrap();
+    let profiler = Profiler::start(&mut state);
+    assert_eq!(state.call_function(f, Vec::new()).unwrap()[0].to_string(), "210");
+    let report = profiler.stop(&mut state).report();
+
+    let funcs = functions(&report);
+    assert!(funcs.len() >= 5, "{}", report);
+    for (self_ms, total_ms, name) in &funcs {
+        // 报告保留三位小数
+        assert!(total_ms + 0.0015 >= *self_ms, "{}: total {} < self {}\n{}", name, total_ms, self_ms, report);
+    }
+    // 恢复协程的函数的总时间包括协程运行的时间
+    let total = |prefix: &str| funcs.iter().find(|f| f.2.starts_with(prefix)).unwrap_or_else(|| panic!("{}\n{}", prefix, report)).1;
+    assert!(total("consumer") >= total("function <test:3>"), "{}", report);
+}
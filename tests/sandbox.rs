@@ -0,0 +1,41 @@
+// ExeStateBuilder的沙箱选项
+mod common;
+
+use common::{eval_in, run};
+use my_lua::stdlib::Lib;
+use my_lua::vm::ExeState;
+
+const PROBE: &str = "return type(os.execute), type(os.remove), type(os.exit), type(os.getenv), type(os.time),
+    type(io), type(debug), type(rawset), pcall(function() string.x = 1 end)";
+
+#[test]
+fn os_sandbox() {
+    // 只去掉os中危险的函数，其他库照常
+    let mut state = ExeState::builder().all_libs().os_sandbox(true).build();
+    assert_eq!(eval_in(&mut state, PROBE), "nil nil nil function function table table function true");
+    assert_eq!(eval_in(&mut state, "return package.loaded.os.execute"), "nil");
+
+    // 没有打开os时不做任何事
+    let mut state = ExeState::builder().lib(Lib::Base).os_sandbox(true).build();
+    assert_eq!(eval_in(&mut state, "return os"), "nil");
+}
+
+#[test]
+fn full_sandbox() {
+    let mut state = ExeState::builder().all_libs().sandbox(true).build();
+    let probe = eval_in(&mut state, PROBE);
+    assert!(probe.starts_with("nil nil nil nil function nil nil nil false "), "{}", probe);
+    assert!(probe.ends_with("attempt to modify a read-only table"), "{}", probe);
+    // pairs不交出原表
+    assert_eq!(eval_in(&mut state, "local _, raw = pairs(math) return raw == math, pcall(function() raw.floor = function() return 'pwned' end end)"),
+        "true false test:1: attempt to modify a read-only table");
+    assert_eq!(eval_in(&mut state, "local _, raw = pairs(string) return pcall(function() raw.rep = nil end)"),
+        "false test:1: attempt to modify a read-only table");
+    assert_eq!(run(&mut state, "local _, raw = pairs(math); raw.floor = function() return 'pwned' end"),
+        Err("test:1: attempt to modify a read-only table".to_string()));
+    assert_eq!(eval_in(&mut state, "return math.floor(2.5), ('x'):rep(2)"), "2 xx");
+    assert_eq!(eval_in(&mut state, "local n = 0 for k, v in pairs(math) do n = n + 1 end return n > 20, next(math)"), "true nil");
+
+    let mut state = ExeState::new();
+    assert_eq!(eval_in(&mut state, PROBE), "function function function function function table table function true");
+}
@@ -0,0 +1,45 @@
+// string.pack/unpack/packsize的边界和错误信息，与官方实现一致
+mod common;
+
+use common::{eval, eval_err};
+
+#[test]
+fn integer_overflow() {
+    assert_eq!(eval("return string.unpack('i1i1', string.pack('i1i1', 127, -128))"), "127 -128 3");
+    assert_eq!(eval_err("return string.pack('i1', 128)"), "test:1: bad argument #2 to 'pack' (integer overflow)");
+    assert_eq!(eval_err("return string.pack('i1', -129)"), "test:1: bad argument #2 to 'pack' (integer overflow)");
+    assert_eq!(eval_err("return string.pack('>i2i2', 1, 32768)"), "test:1: bad argument #3 to 'pack' (integer overflow)");
+    assert_eq!(eval("return string.unpack('I1', string.pack('I1', 255))"), "255 2");
+    assert_eq!(eval_err("return string.pack('I1', 256)"), "test:1: bad argument #2 to 'pack' (unsigned overflow)");
+    assert_eq!(eval_err("return string.pack('I1', -1)"), "test:1: bad argument #2 to 'pack' (unsigned overflow)");
+}
+
+#[test]
+fn data_string_too_short() {
+    assert_eq!(eval_err("return string.unpack('i4', 'abc')"), "test:1: bad argument #2 to 'unpack' (data string too short)");
+    assert_eq!(eval_err("return string.unpack('i4', 'abcd', 2)"), "test:1: bad argument #2 to 'unpack' (data string too short)");
+    assert_eq!(eval_err("return string.unpack('s1', '\\5ab')"), "test:1: bad argument #2 to 'unpack' (data string too short)");
+    assert_eq!(eval_err("return string.unpack('c3', 'ab')"), "test:1: bad argument #2 to 'unpack' (data string too short)");
+    assert_eq!(eval_err("return string.unpack('z', 'abc')"), "test:1: bad argument #2 to 'unpack' (unfinished string for format 'z')");
+    assert_eq!(eval_err("return string.unpack('i4', 'abcd', 6)"), "test:1: bad argument #3 to 'unpack' (initial position out of string)");
+}
+
+#[test]
+fn integral_size_limits() {
+    assert_eq!(eval_err("return string.pack('i17', 1)"), "test:1: integral size (17) out of limits [1,16]");
+    assert_eq!(eval_err("return string.pack('I0', 1)"), "test:1: integral size (0) out of limits [1,16]");
+    assert_eq!(eval_err("return string.pack('!17')"), "test:1: integral size (17) out of limits [1,16]");
+    // 超过8字节的整数按符号扩展，读出时高位必须是符号位
+    assert_eq!(eval("local s = string.pack('i16', -3) return #s, string.unpack('i16', s)"), "16 -3 17");
+    assert_eq!(eval("return string.unpack('<I9', string.pack('<I9', 7))"), "7 10");
+    assert_eq!(eval_err("return string.unpack('<i9', ('\\0'):rep(8) .. '\\1')"), "test:1: 9-byte integer does not fit into Lua Integer");
+    assert_eq!(eval_err("return string.unpack('<i16', ('\\255'):rep(8) .. ('\\1'):rep(8))"),
+        "test:1: 16-byte integer does not fit into Lua Integer");
+}
+
+#[test]
+fn packsize() {
+    assert_eq!(eval("return string.packsize('i4i8'), string.packsize('!8i1i8'), string.packsize('c10')"), "12 16 10");
+    assert_eq!(eval_err("return string.packsize('s')"), "test:1: bad argument #1 to 'packsize' (variable-length format)");
+    assert_eq!(eval_err("return string.packsize('i4z')"), "test:1: bad argument #1 to 'packsize' (variable-length format)");
+}
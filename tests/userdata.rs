@@ -0,0 +1,119 @@
+// 用户数据：从Rust注册类型和方法，在Lua中使用
+mod common;
+
+use common::{eval_in, run};
+use my_lua::error::LuaError;
+use my_lua::value::{UserData, Value};
+use my_lua::vm::ExeState;
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::rc::Rc;
+
+struct Counter {
+    n: i64,
+    drops: Rc<Cell<u32>>,
+}
+
+impl Drop for Counter {
+    fn drop(&mut self) {
+        self.drops.set(self.drops.get() + 1);
+    }
+}
+
+fn check_counter(state: &ExeState, fname: &str) -> Result<Rc<UserData>, LuaError> {
+    state.get_userdata::<Counter>(1).ok_or_else(|| state.arg_error(1, fname, "Counter expected"))
+}
+
+fn counter_inc(state: &mut ExeState) -> Result<i32, LuaError> {
+    let ud = check_counter(state, "inc")?;
+    let by = state.opt_integer(2, "inc", 1)?;
+    ud.borrow_mut::<Counter>().unwrap().n += by;
+    Ok(0)
+}
+
+fn counter_get(state: &mut ExeState) -> Result<i32, LuaError> {
+    let ud = check_counter(state, "get")?;
+    let n = ud.borrow::<Counter>().unwrap().n;
+    state.push(Value::Integer(n));
+    Ok(1)
+}
+
+fn counter_state(drops: &Rc<Cell<u32>>) -> ExeState {
+    let mut state = ExeState::new();
+    state.register_userdata_type::<Counter>("Counter", &[("inc", counter_inc), ("get", counter_get)]);
+    let c = state.new_userdata(Counter { n: 0, drops: drops.clone() });
+    state.set_global("c", c);
+    state
+}
+
+#[test]
+fn registered_type_methods() {
+    let drops = Rc::new(Cell::new(0));
+    let mut state = counter_state(&drops);
+    assert_eq!(eval_in(&mut state, "c:inc(5) c:inc() return c:get(), type(c), tostring(c):match('^Counter: ') ~= nil"), "6 userdata true");
+    assert_eq!(eval_in(&mut state, "return getmetatable(c).__name, c.missing"), "Counter nil");
+    assert_eq!(run(&mut state, "c.get({})").unwrap_err(), "test:1: bad argument #1 to 'get' (Counter expected)");
+
+    // 没有注册类型的用户数据没有元表
+    let plain = state.new_userdata(String::from("plain"));
+    state.set_global("plain", plain);
+    assert_eq!(eval_in(&mut state, "return getmetatable(plain), type(plain)"), "nil userdata");
+    assert_eq!(run(&mut state, "return plain.x").unwrap_err(), "test:1: attempt to index a userdata value");
+    assert_eq!(drops.get(), 0);
+}
+
+// 最后一个引用消失时运行Drop，相当于__gc
+#[test]
+fn drop_as_finalizer() {
+    let drops = Rc::new(Cell::new(0));
+    let mut state = counter_state(&drops);
+    eval_in(&mut state, "local alias = c c = nil t = {alias}");
+    assert_eq!(drops.get(), 0);
+    eval_in(&mut state, "t = nil");
+    assert_eq!(drops.get(), 1);
+
+    // 从Rust持有的引用同样计数
+    let d = state.new_userdata(Counter { n: 0, drops: drops.clone() });
+    state.set_global("d", d.clone());
+    eval_in(&mut state, "d = nil");
+    assert_eq!(drops.get(), 1);
+    drop(d);
+    assert_eq!(drops.get(), 2);
+}
+
+#[test]
+fn typed_borrow() {
+    let ud = UserData::new(5i32);
+    assert!(ud.is::<i32>() && !ud.is::<u32>());
+    assert_eq!(*ud.borrow::<i32>().unwrap(), 5);
+    assert!(ud.borrow::<u32>().is_none());
+    assert!(ud.borrow_mut::<String>().is_none());
+
+    // 共享借用期间可以再共享借用，不能可变借用
+    let shared = ud.borrow::<i32>().unwrap();
+    assert!(ud.borrow::<i32>().is_some());
+    assert!(ud.borrow_mut::<i32>().is_none());
+    drop(shared);
+
+    // 可变借用期间两者都不行
+    let mut exclusive = ud.borrow_mut::<i32>().unwrap();
+    *exclusive += 1;
+    assert!(ud.borrow::<i32>().is_none());
+    assert!(ud.borrow_mut::<i32>().is_none());
+    drop(exclusive);
+    assert_eq!(*ud.borrow::<i32>().unwrap(), 6);
+}
+
+#[test]
+fn light_userdata() {
+    let mut x = 0u8;
+    let p = &mut x as *mut u8 as *mut c_void;
+    let mut state = ExeState::new();
+    let (a, b) = (state.new_light_userdata(p), state.new_light_userdata(p));
+    state.set_global("a", a);
+    state.set_global("b", b);
+    state.set_global("other", state.new_light_userdata(std::ptr::null_mut()));
+    assert_eq!(eval_in(&mut state, "local t = {[a] = 1} return type(a), a == b, a == other, t[b], getmetatable(a)"),
+        "userdata true false 1 nil");
+    assert!(matches!(state.get_global("a"), Value::LightUserData(q) if q == p));
+}